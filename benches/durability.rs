@@ -0,0 +1,59 @@
+//! Times a run of single-row inserts, each its own commit, at every
+//! durability level in both journal modes. Run with `cargo bench`.
+
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const ROWS: usize = 2000;
+
+fn main() {
+    let dir = env::temp_dir().join(format!("bugdb-durability-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    println!("{} inserts, each its own commit", ROWS);
+    for journal_mode in ["rollback", "wal"] {
+        for durability in ["off", "normal", "full"] {
+            let path = dir.join(format!("{}-{}.db", journal_mode, durability));
+            let elapsed = run(path.to_str().unwrap(), journal_mode, durability);
+            println!(
+                "journal {:<8} durability {:<6} {:>6} ms",
+                journal_mode,
+                durability,
+                elapsed.as_millis()
+            );
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn run(path: &str, journal_mode: &str, durability: &str) -> Duration {
+    let mut script = format!(
+        "create table t (id integer primary key, name text)\n\
+         pragma journal_mode = {}\n\
+         pragma durability = {}\n",
+        journal_mode, durability
+    );
+    for i in 0..ROWS {
+        script.push_str(&format!("insert into t values ({}, 'row {}')\n", i, i));
+    }
+    script.push_str(".exit\n");
+
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_BugDB"))
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+    start.elapsed()
+}
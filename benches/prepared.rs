@@ -0,0 +1,57 @@
+//! Times a bulk insert run through the library twice: once parsing every
+//! statement, and once preparing the INSERT and binding each row's values
+//! to it. Run with `cargo bench`.
+
+use std::{
+    env, fs,
+    time::{Duration, Instant},
+};
+
+use bugdb::{
+    sql_parser::{PreparedStatement, Value},
+    Database,
+};
+
+const ROWS: i64 = 20_000;
+
+fn main() {
+    let dir = env::temp_dir().join(format!("bugdb-prepared-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    println!("{} inserts in one transaction", ROWS);
+    for prepared in [false, true] {
+        let path = dir.join(format!("prepared-{}.db", prepared));
+        let elapsed = run(path.to_str().unwrap(), prepared);
+        println!(
+            "{:<10} {:>6} ms",
+            if prepared { "prepared" } else { "re-parsed" },
+            elapsed.as_millis()
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn run(path: &str, prepared: bool) -> Duration {
+    let mut db = Database::open(path).unwrap();
+    db.execute("create table t (id integer primary key, name text, n integer)")
+        .unwrap();
+    let insert = PreparedStatement::new("insert into t values (?, ?, ?)").unwrap();
+
+    // Inside one transaction the rows are written once, at COMMIT, so the
+    // time is mostly spent getting each statement ready to run
+    let start = Instant::now();
+    db.execute("begin").unwrap();
+    for i in 0..ROWS {
+        let name = format!("row {}", i);
+        if prepared {
+            let params = [Value::Integer(i), Value::Text(name), Value::Integer(i % 7)];
+            db.execute_prepared(&insert, &params).unwrap();
+        } else {
+            let sql = format!("insert into t values ({}, '{}', {})", i, name, i % 7);
+            db.execute(&sql).unwrap();
+        }
+    }
+    db.execute("commit").unwrap();
+    let elapsed = start.elapsed();
+    db.close().unwrap();
+    elapsed
+}
@@ -0,0 +1,150 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::{
+    executor::{collation_of, compare_values},
+    functions,
+    sql_parser::{Collation, Expr, Value},
+};
+
+/// Whether an expression is a call to an aggregate function. `min` and
+/// `max` are aggregates with one argument and scalar functions with two.
+pub fn is_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function { name, args, .. } => functions::is_aggregate_name(name, args.len()),
+        _ => false,
+    }
+}
+
+/// The running state of one aggregate call as rows are fed to it.
+pub enum Accumulator {
+    Count(i64),
+    /// NULL until the first non-NULL value.
+    Sum(Option<i64>),
+    Avg {
+        sum: i64,
+        count: i64,
+    },
+    Min(Value),
+    Max(Value),
+    /// For `DISTINCT`: passes each value on to the aggregate only the first
+    /// time it is seen, remembering the canonical encoding of each.
+    Distinct {
+        seen: HashSet<Vec<u8>>,
+        collation: Collation,
+        inner: Box<Accumulator>,
+    },
+}
+
+impl Accumulator {
+    /// Starts the accumulator for an aggregate call, checking its
+    /// arguments. Only `count` accepts `*`.
+    pub fn new(call: &Expr) -> Result<Accumulator, String> {
+        let (name, args, distinct) = match call {
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => (name.as_str(), args, *distinct),
+            _ => return Err(format!("{} is not an aggregate function", call)),
+        };
+        if distinct && args.len() != 1 {
+            return Err("DISTINCT aggregates must have exactly one argument".to_string());
+        }
+        if args.len() != 1 {
+            return Err(format!("wrong number of arguments to function {}()", name));
+        }
+        if args[0].is_star() && name != "count" {
+            return Err(format!("wrong number of arguments to function {}()", name));
+        }
+        let accumulator = match name {
+            "count" => Accumulator::Count(0),
+            "sum" => Accumulator::Sum(None),
+            "avg" => Accumulator::Avg { sum: 0, count: 0 },
+            "min" => Accumulator::Min(Value::Null),
+            _ => Accumulator::Max(Value::Null),
+        };
+        if !distinct {
+            return Ok(accumulator);
+        }
+        Ok(Accumulator::Distinct {
+            seen: HashSet::new(),
+            collation: collation_of(&args[0]).unwrap_or_default(),
+            inner: Box::new(accumulator),
+        })
+    }
+
+    /// Feeds the argument's value for one row. NULLs are skipped, which is
+    /// why `count(*)` is fed a non-NULL value for every row.
+    pub fn add(&mut self, value: &Value) -> Result<(), String> {
+        if let Accumulator::Distinct {
+            seen,
+            collation,
+            inner,
+        } = self
+        {
+            if value == &Value::Null {
+                return Ok(());
+            }
+            let mut key = Vec::new();
+            canonical(&collation.fold(value)).encode_key(&mut key);
+            if seen.insert(key) {
+                inner.add(value)?;
+            }
+            return Ok(());
+        }
+        let integer = match value {
+            Value::Null => return Ok(()),
+            Value::Integer(i) => Some(*i),
+            Value::Text(_) => None,
+        };
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => {
+                let i = integer.ok_or("sum() expects a number, not text")?;
+                *sum = Some(sum.unwrap_or(0).checked_add(i).ok_or("integer overflow")?);
+            }
+            Accumulator::Avg { sum, count } => {
+                let i = integer.ok_or("avg() expects a number, not text")?;
+                *sum = sum.checked_add(i).ok_or("integer overflow")?;
+                *count += 1;
+            }
+            Accumulator::Min(best) => {
+                if best == &Value::Null || compare_values(value, best) == Some(Ordering::Less) {
+                    *best = value.clone();
+                }
+            }
+            Accumulator::Max(best) => {
+                if best == &Value::Null || compare_values(value, best) == Some(Ordering::Greater) {
+                    *best = value.clone();
+                }
+            }
+            Accumulator::Distinct { .. } => unreachable!("handled above"),
+        }
+        Ok(())
+    }
+
+    /// The aggregate's result over every row fed so far. Until REAL values
+    /// exist, `avg` divides as integers and rounds toward zero.
+    pub fn finish(&self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::Integer(*count),
+            Accumulator::Sum(sum) => sum.map_or(Value::Null, Value::Integer),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Integer(sum / count),
+            Accumulator::Min(best) | Accumulator::Max(best) => best.clone(),
+            Accumulator::Distinct { inner, .. } => inner.finish(),
+        }
+    }
+}
+
+/// Text spelling an integer exactly as the integer would be written, such
+/// as `'42'`, stands for that integer, so DISTINCT counts the two once.
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Text(s) => match s.parse::<i64>() {
+            Ok(i) if i.to_string() == *s => Value::Integer(i),
+            _ => value.clone(),
+        },
+        _ => value.clone(),
+    }
+}
@@ -0,0 +1,304 @@
+use crate::pager::PAGE_SIZE;
+
+/// A page holding rows. A page of zeros is an empty leaf, so a table needs
+/// no page written before its first row.
+pub const LEAF: u8 = 0;
+/// A page pointing to the pages below it, each of which holds the rows up
+/// to a key.
+pub const INTERNAL: u8 = 1;
+/// A page outside the tree holding part of a row too big for its leaf.
+pub const OVERFLOW: u8 = 2;
+/// A page no longer in use, on the free list for the next to be needed.
+pub const FREE: u8 = 3;
+
+/// Each page starts with its node type and its number of cells, and a leaf
+/// then the page of the next leaf in key order. Page 0 holds the file's
+/// header and is never a leaf, so 0 there means there is none. Overflow
+/// and free pages keep the next page of their chain in the same place,
+/// and an overflow page's part of a row follows.
+const NODE_TYPE_OFFSET: usize = 0;
+const CELL_COUNT_OFFSET: usize = 2;
+const NEXT_PAGE_OFFSET: usize = 4;
+const HEADER_SIZE: usize = 8;
+
+/// A slotted node's header goes on to where its cells start. Its cells
+/// are packed against the end of the usable page, and an array of pointers
+/// to them, in key order, follows the header, so the space between the two
+/// is free. 0 stands for the end of the page, so a page of zeros is still
+/// an empty leaf.
+const CONTENT_START_OFFSET: usize = 8;
+const SLOTTED_HEADER_SIZE: usize = 10;
+/// A pointer is the offset of its cell and the cell's length.
+const POINTER_SIZE: usize = 4;
+
+/// An internal cell holds a child's page, the number of rows under it and
+/// the greatest key under it, which an insert compares against to choose
+/// where to go. The row counts let a row be found by its position too.
+const CHILD_PAGE_OFFSET: usize = 0;
+const CHILD_ROWS_OFFSET: usize = 4;
+const CHILD_KEY_OFFSET: usize = 8;
+
+/// The layout of one kind of node. Both kinds are slotted: a leaf's cells,
+/// a key followed by a row, and an internal node's cells, a child and its
+/// key, vary in size with the text they hold and are found through the
+/// node's pointers. Cells stop short of the page's end by whatever the
+/// pager keeps there.
+pub struct Node {
+    /// The fraction of the page the largest cell may take.
+    cells_per_page: usize,
+    usable_size: usize,
+}
+
+impl Node {
+    pub fn leaf(usable_size: usize) -> Node {
+        Node {
+            cells_per_page: 4,
+            usable_size,
+        }
+    }
+
+    /// An internal node's cells are kept smaller than a leaf's, as each
+    /// insert may need room in it for several.
+    pub fn internal(usable_size: usize) -> Node {
+        Node {
+            cells_per_page: 16,
+            usable_size,
+        }
+    }
+
+    /// The largest cell the node takes. Half a node's cells by size are
+    /// moved out when it splits, which leaves room for another this big
+    /// on either side.
+    pub fn max_cell_size(&self) -> usize {
+        (self.usable_size - SLOTTED_HEADER_SIZE) / self.cells_per_page - POINTER_SIZE
+    }
+
+    /// The largest key an internal cell holds within the node.
+    pub fn max_key_size(&self) -> usize {
+        self.max_cell_size() - CHILD_KEY_OFFSET
+    }
+
+    /// Whether a page has room for one more cell of `size` bytes.
+    pub fn has_room(&self, page: &[u8], size: usize) -> bool {
+        self.free_space(page) >= size + POINTER_SIZE
+    }
+
+    pub fn cell<'p>(&self, page: &'p [u8], cell_num: usize) -> &'p [u8] {
+        let (offset, size) = self.locate(page, cell_num);
+        &page[offset..offset + size]
+    }
+
+    /// A cell to change in place, keeping its size.
+    pub fn cell_mut<'p>(&self, page: &'p mut [u8], cell_num: usize) -> &'p mut [u8] {
+        let (offset, size) = self.locate(page, cell_num);
+        &mut page[offset..offset + size]
+    }
+
+    /// Puts a cell at `cell_num`, moving the cells from there on along by
+    /// one. The caller checks there is room.
+    pub fn insert(&self, page: &mut [u8], cell_num: usize, cell: &[u8]) {
+        let count = cell_count(page);
+        let start = self.content_start(page) - cell.len();
+        page[start..start + cell.len()].copy_from_slice(cell);
+        set_content_start(page, start);
+        let pointer = pointer_offset(cell_num);
+        page.copy_within(pointer..pointer_offset(count), pointer + POINTER_SIZE);
+        set_pointer(page, cell_num, start, cell.len());
+        set_cell_count(page, count + 1);
+    }
+
+    /// Puts a cell in place of the one at `cell_num`, or returns false,
+    /// changing nothing, when the page has no room for it.
+    pub fn replace(&self, page: &mut [u8], cell_num: usize, cell: &[u8]) -> bool {
+        let (_, size) = self.locate(page, cell_num);
+        if self.free_space(page) + size < cell.len() {
+            return false;
+        }
+        self.remove(page, cell_num);
+        self.insert(page, cell_num, cell);
+        true
+    }
+
+    /// Takes out the cell at `cell_num`, moving the later cells back by
+    /// one. The other cells close up the space it leaves.
+    pub fn remove(&self, page: &mut [u8], cell_num: usize) {
+        let count = cell_count(page);
+        let (offset, size) = self.locate(page, cell_num);
+        let start = self.content_start(page);
+        page.copy_within(start..offset, start + size);
+        set_content_start(page, start + size);
+        for other in 0..count {
+            let (other_offset, other_size) = self.locate(page, other);
+            if other_offset < offset {
+                set_pointer(page, other, other_offset + size, other_size);
+            }
+        }
+        let pointer = pointer_offset(cell_num);
+        page.copy_within(pointer + POINTER_SIZE..pointer_offset(count), pointer);
+        set_cell_count(page, count - 1);
+    }
+
+    /// Moves the upper half of a page's cells, by their size rather than
+    /// their number, to a new page, which is returned to be stored as page
+    /// `new_page_num`. A leaf is linked to the new one as its next.
+    pub fn split(&self, page: &mut [u8], new_page_num: usize) -> Vec<u8> {
+        let count = cell_count(page);
+        let mut new_page = vec![0; PAGE_SIZE];
+        new_page[NODE_TYPE_OFFSET] = node_type(page);
+        let cells: Vec<Vec<u8>> = (0..count).map(|i| self.cell(page, i).to_vec()).collect();
+        let total: usize = cells.iter().map(Vec::len).sum();
+        let mut kept = 0;
+        let mut kept_size = 0;
+        while kept < count - 1 && kept_size + cells[kept].len() <= total / 2 {
+            kept_size += cells[kept].len();
+            kept += 1;
+        }
+        let kept = kept.max(1);
+        page[CONTENT_START_OFFSET..].fill(0);
+        set_cell_count(page, 0);
+        for (i, cell) in cells[..kept].iter().enumerate() {
+            self.insert(page, i, cell);
+        }
+        for (i, cell) in cells[kept..].iter().enumerate() {
+            self.insert(&mut new_page, i, cell);
+        }
+        if node_type(page) == LEAF {
+            set_next_leaf(&mut new_page, next_leaf(page));
+            set_next_leaf(page, new_page_num);
+        }
+        new_page
+    }
+
+    /// Where a cell starts and how long it is.
+    fn locate(&self, page: &[u8], cell_num: usize) -> (usize, usize) {
+        let pointer = pointer_offset(cell_num);
+        (read_u16(page, pointer), read_u16(page, pointer + 2))
+    }
+
+    fn content_start(&self, page: &[u8]) -> usize {
+        match read_u16(page, CONTENT_START_OFFSET) {
+            0 => self.usable_size,
+            start => start,
+        }
+    }
+
+    fn free_space(&self, page: &[u8]) -> usize {
+        self.content_start(page) - pointer_offset(cell_count(page))
+    }
+}
+
+fn pointer_offset(cell_num: usize) -> usize {
+    SLOTTED_HEADER_SIZE + cell_num * POINTER_SIZE
+}
+
+fn set_pointer(page: &mut [u8], cell_num: usize, offset: usize, size: usize) {
+    let pointer = pointer_offset(cell_num);
+    write_u16(page, pointer, offset);
+    write_u16(page, pointer + 2, size);
+}
+
+fn set_content_start(page: &mut [u8], start: usize) {
+    write_u16(page, CONTENT_START_OFFSET, start);
+}
+
+/// A new, empty internal page.
+pub fn internal_page() -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    page[NODE_TYPE_OFFSET] = INTERNAL;
+    page
+}
+
+pub fn node_type(page: &[u8]) -> u8 {
+    page[NODE_TYPE_OFFSET]
+}
+
+pub fn cell_count(page: &[u8]) -> usize {
+    u16::from_le_bytes([page[CELL_COUNT_OFFSET], page[CELL_COUNT_OFFSET + 1]]) as usize
+}
+
+fn set_cell_count(page: &mut [u8], count: usize) {
+    page[CELL_COUNT_OFFSET..CELL_COUNT_OFFSET + 2].copy_from_slice(&(count as u16).to_le_bytes());
+}
+
+/// The leaf after this one, or 0 for the last.
+pub fn next_leaf(page: &[u8]) -> usize {
+    read_u32(page, NEXT_PAGE_OFFSET)
+}
+
+fn set_next_leaf(page: &mut [u8], page_num: usize) {
+    write_u32(page, NEXT_PAGE_OFFSET, page_num);
+}
+
+/// A new overflow page holding part of a row, followed in its chain by
+/// `next_page`, or by nothing when that is 0.
+pub fn overflow_page(part: &[u8], next_page: usize) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    page[NODE_TYPE_OFFSET] = OVERFLOW;
+    write_u32(&mut page, NEXT_PAGE_OFFSET, next_page);
+    page[HEADER_SIZE..HEADER_SIZE + part.len()].copy_from_slice(part);
+    page
+}
+
+/// A page let go, followed on the free list by `next_page`.
+pub fn free_page(next_page: usize) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    page[NODE_TYPE_OFFSET] = FREE;
+    write_u32(&mut page, NEXT_PAGE_OFFSET, next_page);
+    page
+}
+
+/// The page after an overflow or free page in its chain, or 0 for the last.
+pub fn next_page(page: &[u8]) -> usize {
+    read_u32(page, NEXT_PAGE_OFFSET)
+}
+
+/// The first `len` bytes of the part of a row an overflow page holds.
+pub fn overflow_part(page: &[u8], len: usize) -> &[u8] {
+    &page[HEADER_SIZE..HEADER_SIZE + len]
+}
+
+/// How many bytes of a row fit on an overflow page.
+pub fn overflow_capacity(usable_size: usize) -> usize {
+    usable_size - HEADER_SIZE
+}
+
+/// An internal cell for a child.
+pub fn child_cell(page_num: usize, rows: usize, key: &[u8]) -> Vec<u8> {
+    let mut cell = vec![0; CHILD_KEY_OFFSET];
+    write_u32(&mut cell, CHILD_PAGE_OFFSET, page_num);
+    write_u32(&mut cell, CHILD_ROWS_OFFSET, rows);
+    cell.extend_from_slice(key);
+    cell
+}
+
+pub fn child_page(cell: &[u8]) -> usize {
+    read_u32(cell, CHILD_PAGE_OFFSET)
+}
+
+pub fn child_rows(cell: &[u8]) -> usize {
+    read_u32(cell, CHILD_ROWS_OFFSET)
+}
+
+pub fn set_child_rows(cell: &mut [u8], rows: usize) {
+    write_u32(cell, CHILD_ROWS_OFFSET, rows);
+}
+
+pub fn child_key(cell: &[u8]) -> &[u8] {
+    &cell[CHILD_KEY_OFFSET..]
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+}
@@ -0,0 +1,65 @@
+/// The bytes at the end of each page of a file with checksums, holding the
+/// CRC-32 of the rest of the page.
+pub const CHECKSUM_SIZE: usize = 4;
+
+/// The CRC-32 lookup table for the reflected polynomial 0xEDB88320, the
+/// one zip and PNG use.
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Whether a whole page, checksum included, holds the checksum of its
+/// contents.
+pub fn verify(page: &[u8]) -> bool {
+    let (contents, stored) = page.split_at(page.len() - CHECKSUM_SIZE);
+    crc32(contents).to_le_bytes() == stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn verify_catches_any_changed_byte() {
+        let mut page: Vec<u8> = (0..64).collect();
+        let checksum = crc32(&page[..64 - CHECKSUM_SIZE]);
+        page[64 - CHECKSUM_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(verify(&page));
+
+        for i in 0..page.len() {
+            page[i] ^= 1;
+            assert!(!verify(&page), "byte {}", i);
+            page[i] ^= 1;
+        }
+    }
+}
@@ -0,0 +1,68 @@
+/// Splits one CSV record into fields following RFC 4180: a field may be
+/// wrapped in double quotes, inside which commas are literal and a doubled
+/// quote stands for a single one. Unquoted empty fields come back as None so
+/// callers can treat them as NULL, while `""` is an empty string.
+pub fn parse_record(line: &str) -> Result<Vec<Option<String>>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        let mut field = String::new();
+        let quoted = chars.peek() == Some(&'"');
+
+        if quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(ch) => field.push(ch),
+                    None => return Err("Unterminated quoted field".to_string()),
+                }
+            }
+        }
+
+        // Consume the rest of the field up to the next separator
+        let mut last_field = true;
+        for ch in chars.by_ref() {
+            if ch == ',' {
+                last_field = false;
+                break;
+            }
+            if quoted {
+                return Err(format!("Unexpected '{}' after closing quote", ch));
+            }
+            field.push(ch);
+        }
+
+        if quoted || !field.is_empty() {
+            fields.push(Some(field));
+        } else {
+            fields.push(None);
+        }
+
+        if last_field {
+            return Ok(fields);
+        }
+    }
+}
+
+/// Joins fields into one CSV record, the inverse of `parse_record`. A field
+/// is quoted only when it has to be: when it holds a comma, quote or line
+/// break, or is an empty string, which would otherwise read back as None.
+pub fn format_record(fields: &[Option<String>]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| match field {
+            None => String::new(),
+            Some(text) if text.is_empty() || text.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            }
+            Some(text) => text.clone(),
+        })
+        .collect();
+    fields.join(",")
+}
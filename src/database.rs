@@ -0,0 +1,1028 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    executor::{
+        canonicalize, check_columns_exist, check_indexable, execute_statement, expand_views_within,
+        find_table, remove_table_files, ExecResult,
+    },
+    functions::{self, UserFunctions},
+    header::{Header, FORMAT_VERSION},
+    index::Index,
+    output::OutputMode,
+    page_cache::DEFAULT_CACHE_PAGES,
+    pager::{Durability, HEADER_PAGE, PAGE_SIZE},
+    result_cache::ResultCache,
+    slow_log::SlowQueryLog,
+    sort,
+    sql_parser::{
+        parse_schema, prepare_statement, quote_name, ColumnDef, DataType, Expr, Pragma,
+        PreparedStatement, Statement, StatementType, Value,
+    },
+    table::Table,
+    wal::Wal,
+};
+
+/// An open database: its tables, views and triggers, and the settings of
+/// the session using it.
+///
+/// A transaction's writes stay in this Database's memory until COMMIT, so
+/// its own statements read them and no other Database on the same file
+/// can. Another's COMMIT is only seen once the database is opened again.
+pub struct Database {
+    pub(crate) filename: String,
+    /// Every table, in catalog order. The first keeps its rows in the
+    /// database file itself; the others use `<db>.<table>`.
+    pub(crate) tables: Vec<Table>,
+    pub(crate) views: Vec<View>,
+    pub(crate) triggers: Vec<Trigger>,
+    /// How many triggers are running inside one another.
+    pub(crate) trigger_depth: usize,
+    /// False while the database holds only the built-in default table and
+    /// no catalog has been written.
+    cataloged: bool,
+    /// Set between BEGIN and COMMIT/ROLLBACK.
+    pub(crate) in_transaction: bool,
+    /// Inside a transaction, the schema as it was at BEGIN.
+    schema_at_begin: Option<Schema>,
+    /// Tables dropped inside a transaction. Their files are deleted at
+    /// COMMIT, and ROLLBACK puts them back.
+    dropped: Vec<Table>,
+    /// Set while an INSERT and the triggers it sets off run, which are
+    /// committed or undone together rather than one by one.
+    pub(crate) in_statement: bool,
+    /// Set by `.bulk on`: writes are held in memory like a transaction's,
+    /// but kept rather than rolled back when the REPL exits.
+    pub(crate) bulk: bool,
+    /// Applied to every table; see `Table::implicit_key`.
+    implicit_key: bool,
+    /// The most pages each table keeps in memory, set with
+    /// `PRAGMA cache_size`.
+    pub(crate) cache_size: usize,
+    /// Whether tables write through a write-ahead log rather than a
+    /// rollback journal, as set by `PRAGMA journal_mode` or `--wal`.
+    wal: bool,
+    /// Given to every table's pager; `PRAGMA durability`.
+    durability: Durability,
+    pub(crate) slow_log: SlowQueryLog,
+    /// How SELECT rows are printed as they are produced, in the shell. A
+    /// library caller has none, and gets them in the `ExecResult` instead.
+    pub(crate) output_mode: Option<OutputMode>,
+    pub(crate) result_cache: ResultCache,
+    /// Counters for the statement currently executing.
+    pub(crate) stats: StatementStats,
+    /// Functions added with `register_function`.
+    pub(crate) user_functions: Rc<UserFunctions>,
+}
+
+#[derive(Default)]
+pub struct StatementStats {
+    pub rows_examined: usize,
+    /// Rows examined that passed the WHERE clause.
+    pub rows_matched: usize,
+    pub pages_read: usize,
+    pub pages_written: usize,
+    pub rows_returned: usize,
+    pub index_used: bool,
+    /// Passes made over every row of a table.
+    pub table_scans: usize,
+}
+
+/// A named SELECT that other SELECTs can read from as if it were a table.
+#[derive(Clone)]
+pub struct View {
+    pub name: String,
+    pub query: Statement,
+    /// The SELECT as written, for the catalog.
+    sql: String,
+}
+
+/// An INSERT run after every row inserted into a table.
+#[derive(Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    pub statement: Statement,
+    /// The INSERT as written, for the catalog.
+    sql: String,
+}
+
+/// The schema as BEGIN found it, for ROLLBACK to put back. The catalog
+/// is only written at COMMIT, so its file needs nothing undone.
+struct Schema {
+    tables: Vec<TableSchema>,
+    views: Vec<View>,
+    triggers: Vec<Trigger>,
+    cataloged: bool,
+    /// Whether the schema has changed since, for COMMIT to write it.
+    changed: bool,
+}
+
+/// A table in a `Schema`, known by the file its rows are in.
+struct TableSchema {
+    path: String,
+    name: String,
+    columns: Vec<ColumnDef>,
+    /// Each index's name and what it indexes.
+    indexes: Vec<(String, Expr)>,
+}
+
+/// Facts about a database file that can be gathered without opening it.
+pub struct DbInfo {
+    pub format_version: u32,
+    pub page_size: usize,
+    pub wal: bool,
+    pub page_count: usize,
+    pub table_count: usize,
+    /// The change counter of the database file's header.
+    pub change_counter: u64,
+    /// What the next open would recover, for each table file that needs
+    /// it.
+    pub recovery: Vec<Recovery>,
+}
+
+/// Work left behind by a session that did not close cleanly, which the
+/// next open of the table carries out.
+pub enum Recovery {
+    /// Committed frames in a write-ahead log, to be copied into the file.
+    Wal { path: String, frames: usize },
+    /// A rollback journal, whose pages are put back into the file.
+    Journal { path: String },
+}
+
+/// How `Database::open_with` opens a database.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// How hard writes are pushed to the disk. `PRAGMA durability` changes
+    /// it later.
+    pub durability: Durability,
+    /// Switches the database to write-ahead logging. A database already in
+    /// WAL mode stays in it either way, as the mode is kept in its header.
+    pub wal: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            durability: Durability::Full,
+            wal: false,
+        }
+    }
+}
+
+impl Database {
+    /// Opens the database in a file with the default options, creating it
+    /// if there is none.
+    pub fn open(filename: &str) -> io::Result<Database> {
+        Self::open_with(filename, Options::default())
+    }
+
+    /// Opens the database in a file, creating it if there is none. Until a
+    /// table is created it holds a placeholder `users` table, which the
+    /// first CREATE TABLE replaces while it is empty.
+    pub fn open_with(filename: &str, options: Options) -> io::Result<Database> {
+        let mut tables = Vec::new();
+        let mut views = Vec::new();
+        let mut triggers = Vec::new();
+        match fs::read_to_string(Self::catalog_path(filename)) {
+            Ok(catalog) => {
+                for sql in catalog.lines().filter(|line| !line.trim().is_empty()) {
+                    let statement = parse_schema(sql)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    if statement.statement_type == StatementType::CreateIndex {
+                        let table = find_table(&mut tables, &statement.table_name)?;
+                        let path = table.index_path(&statement.index_name);
+                        let indexed = statement.projection[0].clone();
+                        let index = Index::new(&statement.index_name, indexed, path);
+                        table.attach_index(index)?;
+                        continue;
+                    }
+                    if statement.statement_type == StatementType::CreateTrigger {
+                        triggers.push(Trigger {
+                            name: statement.trigger_name,
+                            table_name: statement.table_name,
+                            statement: *statement.query.expect("triggers have a statement"),
+                            sql: statement.query_sql,
+                        });
+                        continue;
+                    }
+                    if let Some(query) = statement.query {
+                        views.push(View {
+                            name: statement.table_name,
+                            query: *query,
+                            sql: statement.query_sql,
+                        });
+                        continue;
+                    }
+                    let path = Self::table_path(filename, &statement.table_name, tables.is_empty());
+                    tables.push(Table::new(
+                        &path,
+                        &statement.table_name,
+                        statement.column_defs,
+                    )?);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let cataloged = !tables.is_empty();
+        if !cataloged {
+            let columns = vec![
+                ColumnDef::new("id", DataType::Integer),
+                ColumnDef::new("username", DataType::Text),
+                ColumnDef::new("email", DataType::Text),
+            ];
+            tables.push(Table::new(filename, "users", columns)?);
+        }
+
+        let wal = tables[0].pager.wal.is_some();
+        let mut db = Database {
+            filename: filename.to_string(),
+            wal,
+            durability: Durability::Full,
+            tables,
+            views,
+            triggers,
+            trigger_depth: 0,
+            cataloged,
+            in_transaction: false,
+            schema_at_begin: None,
+            dropped: Vec::new(),
+            in_statement: false,
+            bulk: false,
+            implicit_key: false,
+            cache_size: DEFAULT_CACHE_PAGES,
+            slow_log: SlowQueryLog::new(format!("{}-slow.log", filename)),
+            output_mode: None,
+            result_cache: ResultCache::new(),
+            stats: StatementStats::default(),
+            user_functions: Rc::default(),
+        };
+        if options.wal {
+            db.set_wal(true)?;
+        }
+        db.set_durability(options.durability);
+        Ok(db)
+    }
+
+    /// Parses and runs one SQL statement. What it returns, rows included,
+    /// comes back in the result, and nothing is printed. A statement slower
+    /// than `PRAGMA slow_query_ms` is logged, as in the shell.
+    pub fn execute(&mut self, sql: &str) -> io::Result<ExecResult> {
+        let statement =
+            prepare_statement(sql).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let started = Instant::now();
+        let result = execute_statement(&statement, self)?;
+        self.slow_log.record(sql, started.elapsed(), &self.stats)?;
+        Ok(result)
+    }
+
+    /// Runs a prepared statement with its placeholders bound to `params`,
+    /// without parsing it again, and otherwise as `execute` does. The slow
+    /// query log shows it with its placeholders.
+    pub fn execute_prepared(
+        &mut self,
+        prepared: &PreparedStatement,
+        params: &[Value],
+    ) -> io::Result<ExecResult> {
+        let statement = prepared
+            .bind(params)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let started = Instant::now();
+        let result = execute_statement(&statement, self)?;
+        self.slow_log
+            .record(prepared.sql(), started.elapsed(), &self.stats)?;
+        Ok(result)
+    }
+
+    /// Makes a Rust closure callable from this database's SQL as
+    /// `name(arg, ...)` with exactly `arity` arguments, replacing any
+    /// function registered before under the same name and arity. Built-in
+    /// functions and aggregates cannot be replaced. An error the closure
+    /// returns fails the statement, named after the function. Calls are
+    /// never cached, since nothing is known about what the closure depends
+    /// on.
+    ///
+    /// ```no_run
+    /// use bugdb::{sql_parser::Value, Database};
+    ///
+    /// let mut db = Database::open("users.db")?;
+    /// db.register_function("slugify", 1, |args| match &args[0] {
+    ///     Value::Text(s) => Ok(Value::Text(s.to_lowercase().replace(' ', "-"))),
+    ///     other => Ok(other.clone()),
+    /// })?;
+    /// db.execute("select slugify(username) from users")?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn register_function<F>(&mut self, name: &str, arity: usize, call: F) -> io::Result<()>
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        Rc::make_mut(&mut self.user_functions)
+            .register(name, arity, Rc::new(call))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// The catalog lists the CREATE TABLE statement of every table, one per
+    /// line.
+    fn catalog_path(filename: &str) -> String {
+        format!("{}.schema", filename)
+    }
+
+    fn table_path(filename: &str, table_name: &str, first: bool) -> String {
+        if first {
+            filename.to_string()
+        } else {
+            format!("{}.{}", filename, table_name)
+        }
+    }
+
+    pub(crate) fn create_table(&mut self, name: &str, columns: Vec<ColumnDef>) -> io::Result<()> {
+        // The name becomes part of a file name
+        if name.contains('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("table names cannot contain '/': {}", name),
+            ));
+        }
+        self.check_not_view(name)?;
+        if let Some(existing) = self
+            .tables
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+        {
+            if self.cataloged || existing.num_rows > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("table {} already exists", existing.name),
+                ));
+            }
+        }
+        // Its files are still there, for ROLLBACK
+        if let Some(dropped) = self
+            .dropped
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "table {} was dropped in this transaction; commit before creating it again",
+                    dropped.name
+                ),
+            ));
+        }
+
+        // Until something is stored in it, the built-in default table is
+        // only a placeholder that the first CREATE TABLE replaces.
+        if !self.cataloged && self.tables[0].num_rows == 0 {
+            self.tables[0].name = name.to_string();
+            self.tables[0].columns = columns;
+            self.tables[0].indexes.clear();
+        } else {
+            // A table dropped in the transaction may still hold the database
+            // file, so the new one moves there at COMMIT
+            let first = self.tables.is_empty() && self.dropped.is_empty();
+            let path = Self::table_path(&self.filename, name, first);
+            let mut table = Table::new(&path, name, columns)?;
+            table.implicit_key = self.implicit_key;
+            table.pager.cache.limit = self.cache_size;
+            table.pager.durability = self.durability;
+            table.set_wal(self.wal)?;
+            if self.in_transaction {
+                table.begin()?;
+            }
+            self.tables.push(table);
+        }
+
+        self.cataloged = true;
+        self.save_catalog()
+    }
+
+    /// Removes a table and deletes its files, or inside a transaction sets
+    /// it aside until COMMIT. When the first table goes, the next one moves
+    /// into the database file to take its place.
+    pub(crate) fn drop_table(&mut self, name: &str) -> io::Result<()> {
+        if self.find_view(name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("use DROP VIEW to delete view {}", name),
+            ));
+        }
+        let index = self
+            .tables
+            .iter()
+            .position(|t| t.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no such table: {}", name))
+            })?;
+        let table = self.tables.remove(index);
+        self.triggers
+            .retain(|t| !t.table_name.eq_ignore_ascii_case(&table.name));
+        self.cataloged = true;
+        if self.in_transaction {
+            self.dropped.push(table);
+            return self.save_catalog();
+        }
+        remove_table_files(&table)?;
+        self.move_first_table()?;
+        self.save_catalog()
+    }
+
+    /// Moves the first table into the database file, where the catalog
+    /// says the first table is, once the table that was there is gone.
+    fn move_first_table(&mut self) -> io::Result<()> {
+        let next = match self.tables.first_mut() {
+            Some(next) if next.path != self.filename => next,
+            _ => return Ok(()),
+        };
+        next.close()?;
+        // Rebuilt under the new path when the table is reopened
+        next.remove_index_files()?;
+        fs::rename(&next.path, &self.filename)?;
+        if Path::new(&next.sequence_path).exists() {
+            fs::rename(&next.sequence_path, format!("{}.sequence", self.filename))?;
+        }
+        let (name, columns) = (next.name.clone(), next.columns.clone());
+        let named: Vec<(String, Expr)> = next
+            .indexes
+            .iter()
+            .map(|index| (index.name.clone(), index.expr.clone()))
+            .collect();
+        self.tables[0] = Table::new(&self.filename, &name, columns)?;
+        self.tables[0].implicit_key = self.implicit_key;
+        self.tables[0].pager.cache.limit = self.cache_size;
+        self.tables[0].pager.durability = self.durability;
+        for (index_name, expr) in named {
+            let path = self.tables[0].index_path(&index_name);
+            self.tables[0].attach_index(Index::new(&index_name, expr, path))?;
+        }
+        Ok(())
+    }
+
+    /// Starts a transaction on every table, noting the schema for
+    /// ROLLBACK.
+    pub(crate) fn begin(&mut self) -> io::Result<()> {
+        for table in &mut self.tables {
+            table.begin()?;
+        }
+        self.schema_at_begin = Some(Schema {
+            tables: self
+                .tables
+                .iter()
+                .map(|table| TableSchema {
+                    path: table.path.clone(),
+                    name: table.name.clone(),
+                    columns: table.columns.clone(),
+                    indexes: table
+                        .indexes
+                        .iter()
+                        .map(|index| (index.name.clone(), index.expr.clone()))
+                        .collect(),
+                })
+                .collect(),
+            views: self.views.clone(),
+            triggers: self.triggers.clone(),
+            cataloged: self.cataloged,
+            changed: false,
+        });
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    /// Commits every table, then deletes the files of the tables dropped
+    /// and writes the catalog if the schema changed.
+    pub(crate) fn commit(&mut self) -> io::Result<()> {
+        self.in_transaction = false;
+        let schema = self.schema_at_begin.take();
+        for table in &mut self.tables {
+            table.commit()?;
+        }
+        for table in std::mem::take(&mut self.dropped) {
+            remove_table_files(&table)?;
+        }
+        if schema.is_some_and(|schema| schema.changed) {
+            self.move_first_table()?;
+            self.save_catalog()?;
+        }
+        Ok(())
+    }
+
+    /// Rolls every table back and puts the schema back as it was at BEGIN:
+    /// dropped tables return, created ones are deleted, and indexes made
+    /// or dropped since are dropped or built again.
+    pub(crate) fn rollback(&mut self) -> io::Result<()> {
+        self.in_transaction = false;
+        let schema = self.schema_at_begin.take();
+        self.tables.append(&mut self.dropped);
+        for table in &mut self.tables {
+            table.rollback()?;
+        }
+        let schema = match schema {
+            Some(schema) if schema.changed => schema,
+            _ => return Ok(()),
+        };
+
+        let mut tables = Vec::new();
+        for table in std::mem::take(&mut self.tables) {
+            match schema.tables.iter().position(|t| t.path == table.path) {
+                Some(position) => tables.push((position, table)),
+                None => remove_table_files(&table)?,
+            }
+        }
+        tables.sort_by_key(|(position, _)| *position);
+        for (position, mut table) in tables {
+            let saved = &schema.tables[position];
+            table.name = saved.name.clone();
+            table.columns = saved.columns.clone();
+            let position = |index: &Index| {
+                saved
+                    .indexes
+                    .iter()
+                    .position(|(name, expr)| *name == index.name && *expr == index.expr)
+            };
+            for index in std::mem::take(&mut table.indexes) {
+                match position(&index) {
+                    Some(_) => table.indexes.push(index),
+                    None => index.remove_file()?,
+                }
+            }
+            for (name, expr) in &saved.indexes {
+                if !table.indexes.iter().any(|index| index.name == *name) {
+                    let path = table.index_path(name);
+                    table.attach_index(Index::new(name, expr.clone(), path))?;
+                }
+            }
+            table.indexes.sort_by_key(position);
+            self.tables.push(table);
+        }
+        self.views = schema.views;
+        self.triggers = schema.triggers;
+        self.cataloged = schema.cataloged;
+        Ok(())
+    }
+
+    /// Saves a view, after checking that it reads from something that
+    /// exists and not, even through other views, from itself. Views that
+    /// group, sort, limit, use UNION or DISTINCT are not supported.
+    pub(crate) fn create_view(
+        &mut self,
+        name: &str,
+        query: &Statement,
+        sql: &str,
+    ) -> io::Result<()> {
+        self.check_not_view(name)?;
+        if let Some(table) = self
+            .tables
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("table {} already exists", table.name),
+            ));
+        }
+        if !query.compound.is_empty()
+            || !query.order_by.is_empty()
+            || query.limit.is_some()
+            || !query.group_by.is_empty()
+            || query.distinct
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "views cannot use UNION, ORDER BY, LIMIT, GROUP BY or DISTINCT",
+            ));
+        }
+        let mut expanded = query.clone();
+        expand_views_within(&self.views, &self.tables, &mut expanded, &[name])?;
+        for table_name in std::iter::once(&expanded.table_name)
+            .chain(expanded.join.iter().map(|join| &join.table_name))
+            .filter(|name| !name.is_empty())
+        {
+            find_table(&mut self.tables, table_name)?;
+        }
+
+        self.views.push(View {
+            name: name.to_string(),
+            query: query.clone(),
+            sql: sql.to_string(),
+        });
+        self.cataloged = true;
+        self.save_catalog()
+    }
+
+    /// Turns bulk mode on or off. While it is on, writes reach the file only
+    /// when it is turned off again, which `.exit` also does; if the process
+    /// dies first, everything written since `.bulk on` is lost.
+    pub(crate) fn set_bulk(&mut self, on: bool) -> io::Result<()> {
+        if on == self.bulk {
+            return Ok(());
+        }
+        if self.in_transaction {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot change bulk mode inside a transaction",
+            ));
+        }
+        let writes_before: usize = self.tables.iter().map(|t| t.pager.writes).sum();
+        for table in &mut self.tables {
+            if on {
+                table.begin()?;
+            } else {
+                table.commit()?;
+            }
+        }
+        self.bulk = on;
+        if !on {
+            let writes: usize = self.tables.iter().map(|t| t.pager.writes).sum();
+            println!("Wrote {} pages", writes - writes_before);
+        }
+        Ok(())
+    }
+
+    /// Moves every table to WAL mode or back to the rollback journal.
+    pub(crate) fn set_wal(&mut self, on: bool) -> io::Result<()> {
+        if self.in_transaction || self.bulk {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot change the journal mode inside a transaction",
+            ));
+        }
+        for table in &mut self.tables {
+            table.set_wal(on)?;
+        }
+        self.wal = on;
+        Ok(())
+    }
+
+    pub(crate) fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+        for table in &mut self.tables {
+            table.pager.durability = durability;
+        }
+    }
+
+    /// Copies every table's write-ahead log into its file.
+    pub(crate) fn checkpoint(&mut self) -> io::Result<()> {
+        for table in &mut self.tables {
+            table.pager.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Indexes a column of a table, building the index from its rows.
+    /// Indexes a column, or an expression over the table's columns. The
+    /// expression is computed once per row as the row is written, so it
+    /// must give the same value every time: functions such as random()
+    /// are refused, as are subqueries, aggregates and parameters.
+    pub(crate) fn create_index(
+        &mut self,
+        name: &str,
+        table_name: &str,
+        indexed: &Expr,
+    ) -> io::Result<()> {
+        if self.find_index(name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("index {} already exists", name),
+            ));
+        }
+        let table = find_table(&mut self.tables, table_name)?;
+        check_columns_exist(&[table], indexed.column_refs().into_iter())?;
+        check_indexable(indexed).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut indexed = indexed.clone();
+        canonicalize(&mut indexed);
+
+        // A file left behind under the same name describes other rows
+        let index = Index::new(name, indexed, table.index_path(name));
+        index.remove_file()?;
+        table.attach_index(index)?;
+        self.cataloged = true;
+        self.save_catalog()
+    }
+
+    pub(crate) fn drop_index(&mut self, name: &str) -> io::Result<()> {
+        let (table, position) = self.find_index(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such index: {}", name))
+        })?;
+        let index = self.tables[table].indexes.remove(position);
+        index.remove_file()?;
+        self.save_catalog()
+    }
+
+    /// The table and position of the index with the given name.
+    fn find_index(&self, name: &str) -> Option<(usize, usize)> {
+        self.tables.iter().enumerate().find_map(|(i, table)| {
+            let position = table
+                .indexes
+                .iter()
+                .position(|index| index.name.eq_ignore_ascii_case(name))?;
+            Some((i, position))
+        })
+    }
+
+    pub(crate) fn drop_view(&mut self, name: &str) -> io::Result<()> {
+        let index = self
+            .views
+            .iter()
+            .position(|v| v.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no such view: {}", name))
+            })?;
+        self.views.remove(index);
+        self.save_catalog()
+    }
+
+    /// Saves a trigger, after checking that the table it watches and the
+    /// table its INSERT writes to both exist.
+    pub(crate) fn create_trigger(
+        &mut self,
+        name: &str,
+        table_name: &str,
+        statement: &Statement,
+        sql: &str,
+    ) -> io::Result<()> {
+        if let Some(trigger) = self
+            .triggers
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("trigger {} already exists", trigger.name),
+            ));
+        }
+        for table_name in [table_name, &statement.table_name] {
+            if self.find_view(table_name).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot use view {} in a trigger", table_name),
+                ));
+            }
+        }
+        let table_name = find_table(&mut self.tables, table_name)?.name.clone();
+        find_table(&mut self.tables, &statement.table_name)?;
+
+        self.triggers.push(Trigger {
+            name: name.to_string(),
+            table_name,
+            statement: statement.clone(),
+            sql: sql.to_string(),
+        });
+        self.cataloged = true;
+        self.save_catalog()
+    }
+
+    pub(crate) fn drop_trigger(&mut self, name: &str) -> io::Result<()> {
+        let index = self
+            .triggers
+            .iter()
+            .position(|t| t.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no such trigger: {}", name),
+                )
+            })?;
+        self.triggers.remove(index);
+        self.save_catalog()
+    }
+
+    pub(crate) fn find_view(&self, name: &str) -> Option<&View> {
+        self.views
+            .iter()
+            .find(|v| v.name.eq_ignore_ascii_case(name))
+    }
+
+    fn check_not_view(&self, name: &str) -> io::Result<()> {
+        match self.find_view(name) {
+            Some(view) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("view {} already exists", view.name),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn save_catalog(&mut self) -> io::Result<()> {
+        // Inside a transaction the catalog waits for COMMIT
+        if let Some(schema) = &mut self.schema_at_begin {
+            schema.changed = true;
+            return Ok(());
+        }
+        let catalog: String = self
+            .tables
+            .iter()
+            .flat_map(|table| std::iter::once(table.schema_sql()).chain(table.index_sql()))
+            .chain(self.views.iter().map(View::schema_sql))
+            .chain(self.triggers.iter().map(Trigger::schema_sql))
+            .map(|sql| format!("{}\n", sql))
+            .collect();
+        fs::write(Self::catalog_path(&self.filename), catalog)
+    }
+
+    /// Writes what is still held in memory to the files. A transaction
+    /// still open is rolled back.
+    pub fn close(&mut self) -> io::Result<()> {
+        if self.bulk {
+            self.set_bulk(false)?;
+        }
+        if self.in_transaction {
+            // Like a connection that goes away, leaving abandons the changes
+            println!("Rolling back the open transaction");
+            self.rollback()?;
+        }
+        for table in &mut self.tables {
+            table.close()?;
+        }
+        Ok(())
+    }
+
+    /// Changes a setting, or returns its value when the pragma gives none.
+    pub(crate) fn pragma(&mut self, pragma: &Pragma) -> io::Result<Option<String>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let on_off = |flag: bool| if flag { "on" } else { "off" }.to_string();
+        let log = &mut self.slow_log;
+        let value = match (pragma.name.as_str(), &pragma.value) {
+            ("slow_query_ms", None) => Some(match log.threshold {
+                Some(threshold) => threshold.as_millis().to_string(),
+                None => "off".to_string(),
+            }),
+            ("slow_query_ms", Some(Value::Integer(ms))) if *ms >= 0 => {
+                log.threshold = Some(Duration::from_millis(*ms as u64));
+                None
+            }
+            ("slow_query_ms", Some(Value::Text(off))) if off == "off" => {
+                log.threshold = None;
+                None
+            }
+            ("slow_query_log", None) => Some(log.path.clone()),
+            ("slow_query_log", Some(Value::Text(path))) => {
+                log.path = path.clone();
+                None
+            }
+            ("slow_query_log_literals", None) => Some(on_off(log.include_literals)),
+            ("slow_query_log_literals", Some(Value::Text(flag)))
+                if flag == "on" || flag == "off" =>
+            {
+                log.include_literals = flag == "on";
+                None
+            }
+            ("slow_query_log_max_bytes", Some(Value::Integer(bytes))) if *bytes > 0 => {
+                log.max_bytes = *bytes as u64;
+                None
+            }
+            ("random_seed", Some(Value::Integer(seed))) => {
+                functions::seed_random(*seed);
+                None
+            }
+            ("sort_memory", None) => Some(sort::memory_budget().to_string()),
+            ("sort_memory", Some(Value::Integer(bytes))) if *bytes > 0 => {
+                sort::set_memory_budget(*bytes as usize);
+                None
+            }
+            ("implicit_key", None) => Some(on_off(self.implicit_key)),
+            ("implicit_key", Some(Value::Text(flag))) if flag == "on" || flag == "off" => {
+                self.implicit_key = flag == "on";
+                for table in &mut self.tables {
+                    table.implicit_key = self.implicit_key;
+                }
+                None
+            }
+            ("cache_size", None) => Some(self.cache_size.to_string()),
+            ("cache_size", Some(Value::Integer(pages))) if *pages > 0 => {
+                self.cache_size = *pages as usize;
+                for table in &mut self.tables {
+                    table.pager.cache.limit = self.cache_size;
+                }
+                None
+            }
+            ("durability", None) => Some(self.durability.name().to_string()),
+            ("durability", Some(Value::Text(level))) => {
+                self.set_durability(Durability::parse(level)?);
+                None
+            }
+            ("journal_mode", None) => Some(if self.wal { "wal" } else { "rollback" }.to_string()),
+            ("journal_mode", Some(Value::Text(mode))) if mode == "wal" || mode == "rollback" => {
+                self.set_wal(mode == "wal")?;
+                None
+            }
+            (
+                "slow_query_ms"
+                | "slow_query_log"
+                | "slow_query_log_literals"
+                | "slow_query_log_max_bytes"
+                | "random_seed"
+                | "sort_memory"
+                | "implicit_key"
+                | "cache_size"
+                | "durability"
+                | "journal_mode",
+                _,
+            ) => return Err(invalid(format!("invalid value for pragma {}", pragma.name))),
+            _ => return Err(invalid(format!("unknown pragma: {}", pragma.name))),
+        };
+        Ok(value)
+    }
+
+    /// Inspects a database file using only its metadata, its header and
+    /// the catalog, logs and journals beside it. None of them is created,
+    /// written or locked, and no recovery is run, so this is safe to call
+    /// on a database that another process has open or that crashed.
+    pub(crate) fn peek(filename: &str) -> io::Result<DbInfo> {
+        let metadata = fs::metadata(filename)?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a database file", filename),
+            ));
+        }
+
+        let file_length = metadata.len() as usize;
+        // In WAL mode the latest header may still be in the log
+        let header_page = match Wal::peek(&format!("{}-wal", filename), HEADER_PAGE)? {
+            (_, Some(logged)) => logged,
+            (_, None) => {
+                let mut first_page = vec![0; PAGE_SIZE];
+                let read = File::open(filename)?.read(&mut first_page)?;
+                first_page.truncate(read);
+                first_page
+            }
+        };
+        let header = Header::read(&header_page).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, e))
+        })?;
+
+        // A database without a catalog has had no table created in it yet
+        let mut table_names = Vec::new();
+        match fs::read_to_string(Self::catalog_path(filename)) {
+            Ok(catalog) => {
+                for sql in catalog.lines().filter(|line| !line.trim().is_empty()) {
+                    let statement = parse_schema(sql)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    if statement.statement_type == StatementType::CreateTable {
+                        table_names.push(statement.table_name);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let mut recovery = pending_recovery(filename)?;
+        for name in table_names.iter().skip(1) {
+            recovery.extend(pending_recovery(&Self::table_path(filename, name, false))?);
+        }
+
+        Ok(DbInfo {
+            format_version: FORMAT_VERSION,
+            page_size: header.page_size,
+            wal: header.wal,
+            page_count: file_length.div_ceil(header.page_size.max(1)),
+            table_count: table_names.len(),
+            change_counter: header.change_counter,
+            recovery,
+        })
+    }
+}
+
+/// What opening a table's file would have to recover, found by reading
+/// its log and looking for its journal, neither of which is touched.
+fn pending_recovery(path: &str) -> io::Result<Vec<Recovery>> {
+    let mut recovery = Vec::new();
+    let wal_path = format!("{}-wal", path);
+    let (frames, _) = Wal::peek(&wal_path, HEADER_PAGE)?;
+    if frames > 0 {
+        recovery.push(Recovery::Wal {
+            path: wal_path,
+            frames,
+        });
+    }
+    let journal_path = format!("{}-journal", path);
+    if Path::new(&journal_path).exists() {
+        recovery.push(Recovery::Journal { path: journal_path });
+    }
+    Ok(recovery)
+}
+
+impl View {
+    pub fn schema_sql(&self) -> String {
+        format!("create view {} as {}", quote_name(&self.name), self.sql)
+    }
+}
+
+impl Trigger {
+    pub fn schema_sql(&self) -> String {
+        format!(
+            "create trigger {} after insert on {} begin {}; end",
+            quote_name(&self.name),
+            quote_name(&self.table_name),
+            self.sql
+        )
+    }
+}
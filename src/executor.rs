@@ -0,0 +1,2926 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs, io,
+    rc::Rc,
+};
+
+use crate::{
+    aggregates::{self, Accumulator},
+    database::{Database, StatementStats, View},
+    functions, output,
+    pipeline::{self, Rows},
+    planner,
+    result_cache::CachedResult,
+    sort,
+    sql_parser::{
+        BinaryOp, Collation, ColumnDef, ColumnRef, DataType, Expr, Join, OnConflict, OrderBy, Row,
+        SortKey, Statement, StatementType, UnaryOp, Value,
+    },
+    table::{Cursor, Table},
+};
+
+/// What a statement did: the rows it returned, how many it changed, and
+/// what the shell reports for it.
+pub struct ExecResult {
+    pub kind: StatementType,
+    /// Rows a SELECT output.
+    pub rows_returned: usize,
+    /// Rows an INSERT wrote, an UPDATE changed, or a DELETE or TRUNCATE
+    /// removed.
+    pub rows_affected: usize,
+    /// For a statement with a WHERE clause, the rows that matched it and
+    /// the rows scanned.
+    pub scanned: Option<(usize, usize)>,
+    /// The names of the columns of `rows`.
+    pub columns: Vec<String>,
+    /// The rows a SELECT returned, or an INSERT's RETURNING clause. The
+    /// shell prints a SELECT's as they are produced, and they are not kept.
+    pub rows: Vec<Vec<Value>>,
+    /// What a statement has to say besides its rows, a line at a time: that
+    /// a table was created, a pragma's value, a query plan.
+    pub messages: Vec<String>,
+}
+
+impl ExecResult {
+    pub(crate) fn new(kind: StatementType) -> ExecResult {
+        ExecResult {
+            kind,
+            rows_returned: 0,
+            rows_affected: 0,
+            scanned: None,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// A line such as "3 rows returned" or "1 row inserted", for statements
+    /// that return or change rows.
+    pub fn summary(&self) -> Option<String> {
+        let (count, verb) = match self.kind {
+            StatementType::Select => (self.rows_returned, "returned"),
+            StatementType::Insert => (self.rows_affected, "inserted"),
+            StatementType::Update => (self.rows_affected, "updated"),
+            StatementType::Delete | StatementType::Truncate => (self.rows_affected, "deleted"),
+            _ => return None,
+        };
+        let noun = if count == 1 { "row" } else { "rows" };
+        Some(match self.scanned {
+            Some((matched, scanned)) => format!(
+                "{} {} {} ({} of {} rows scanned matched)",
+                count, noun, verb, matched, scanned
+            ),
+            None => format!("{} {} {}", count, noun, verb),
+        })
+    }
+}
+
+pub fn execute_statement(statement: &Statement, db: &mut Database) -> io::Result<ExecResult> {
+    let statement = &mut statement.clone();
+    expand_views(&db.views, &db.tables, statement)?;
+    if db.bulk
+        && matches!(
+            statement.statement_type,
+            StatementType::CreateTable
+                | StatementType::DropTable
+                | StatementType::CreateView
+                | StatementType::DropView
+                | StatementType::CreateIndex
+                | StatementType::DropIndex
+                | StatementType::CreateTrigger
+                | StatementType::DropTrigger
+        )
+    {
+        // Bulk mode holds the rows back until `.bulk off`, while the catalog
+        // would be written straight away, so a crash could keep one and lose
+        // the other
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot change the schema in bulk mode",
+        ));
+    }
+    if matches!(
+        statement.statement_type,
+        StatementType::Insert
+            | StatementType::Update
+            | StatementType::Delete
+            | StatementType::Truncate
+    ) && db.find_view(&statement.table_name).is_some()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot modify {} because it is a view",
+                statement.table_name
+            ),
+        ));
+    }
+
+    if statement.statement_type != StatementType::Select {
+        db.result_cache.clear();
+    }
+    db.stats = StatementStats::default();
+    let pages_read = |tables: &[Table]| tables.iter().map(|t| t.pager.reads).sum::<usize>();
+    let pages_written = |tables: &[Table]| tables.iter().map(|t| t.pager.writes).sum::<usize>();
+    let reads_before = pages_read(&db.tables);
+    let writes_before = pages_written(&db.tables);
+    let user_functions = db.user_functions.clone();
+    let result = functions::with_user_functions(&user_functions, || run_statement(statement, db));
+    db.stats.pages_read = pages_read(&db.tables).saturating_sub(reads_before);
+    db.stats.pages_written = pages_written(&db.tables).saturating_sub(writes_before);
+    result
+}
+
+/// How deeply triggers may set each other off, through INSERTs into tables
+/// with triggers of their own, before the statement fails.
+const MAX_TRIGGER_DEPTH: usize = 32;
+
+/// Runs an INSERT on a table with triggers as one statement with them, so
+/// that a trigger that fails undoes the rows along with whatever the
+/// triggers before it wrote. Outside a transaction every table begins one,
+/// committed at the end; inside one, every table takes a savepoint.
+fn run_triggering_insert(statement: &Statement, db: &mut Database) -> io::Result<ExecResult> {
+    let autocommit = !db.in_transaction && !db.bulk;
+    let mut savepoints = Vec::new();
+    for table in &mut db.tables {
+        if autocommit {
+            table.begin()?;
+        } else {
+            savepoints.push(table.savepoint());
+        }
+    }
+    db.in_statement = true;
+    let result = run_statement(statement, db);
+    db.in_statement = false;
+
+    if autocommit {
+        for table in &mut db.tables {
+            match result {
+                Ok(_) => table.commit()?,
+                Err(_) => table.rollback()?,
+            }
+        }
+    } else {
+        for (table, savepoint) in db.tables.iter_mut().zip(savepoints) {
+            match result {
+                Ok(_) => table.release(savepoint),
+                Err(_) => table.restore(savepoint),
+            }
+        }
+    }
+    result
+}
+
+/// Runs each trigger on a table once for every row an INSERT wrote. The
+/// error of one that fails reports which it was.
+fn fire_triggers(db: &mut Database, table_name: &str, rows: usize) -> io::Result<()> {
+    let triggers: Vec<(String, Statement)> = db
+        .triggers
+        .iter()
+        .filter(|t| t.table_name.eq_ignore_ascii_case(table_name))
+        .map(|t| (t.name.clone(), t.statement.clone()))
+        .collect();
+    if triggers.is_empty() || rows == 0 {
+        return Ok(());
+    }
+    if db.trigger_depth >= MAX_TRIGGER_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many levels of trigger recursion",
+        ));
+    }
+    db.trigger_depth += 1;
+    let mut result = Ok(());
+    'rows: for _ in 0..rows {
+        for (name, statement) in &triggers {
+            let mut statement = statement.clone();
+            result = expand_views(&db.views, &db.tables, &mut statement)
+                .and_then(|()| run_statement(&statement, db).map(|_| ()))
+                .map_err(|e| match db.trigger_depth {
+                    // Named once, by the trigger the user's INSERT set off
+                    1 => io::Error::new(e.kind(), format!("trigger {}: {}", name, e)),
+                    _ => e,
+                });
+            if result.is_err() {
+                break 'rows;
+            }
+        }
+    }
+    db.trigger_depth -= 1;
+    result
+}
+
+fn run_statement(statement: &Statement, db: &mut Database) -> io::Result<ExecResult> {
+    let triggered = statement.statement_type == StatementType::Insert
+        && db
+            .triggers
+            .iter()
+            .any(|t| t.table_name.eq_ignore_ascii_case(&statement.table_name));
+    if triggered && !db.in_statement {
+        return run_triggering_insert(statement, db);
+    }
+    let autocommit = !db.in_transaction && !db.bulk && !db.in_statement;
+    let stats = &mut db.stats;
+    let tables = &mut db.tables;
+    let mut result = ExecResult::new(statement.statement_type);
+    match statement.statement_type {
+        StatementType::Insert => {
+            let rows = match &statement.query {
+                Some(query) => insert_query_rows(tables, statement, query, stats)?,
+                None => statement
+                    .values
+                    .iter()
+                    .map(|tuple| project(&Row::new(), tuple))
+                    .collect::<io::Result<_>>()?,
+            };
+            let table = find_table(tables, &statement.table_name)?;
+            let returning_refs = statement.returning.iter().flat_map(|e| e.column_refs());
+            check_qualifiers(&[&table.name], returning_refs.clone())?;
+            check_columns_exist(&[table], returning_refs)?;
+            let returning = expand_stars(&statement.returning, &[table])?;
+            // Every row is built, and so type-checked, before any is written
+            let rows = rows
+                .iter()
+                .map(|values| build_insert_row(table, &statement.columns, values))
+                .collect::<io::Result<Vec<Row>>>()?;
+
+            // The statement's rows are written together or not at all. Inside
+            // a transaction that takes a savepoint, though a single plain
+            // insert fails before anything is written and needs none.
+            let count = rows.len();
+            let savepoint = if autocommit {
+                table.begin()?;
+                None
+            } else if count > 1 || statement.on_conflict.is_some() {
+                Some(table.savepoint())
+            } else {
+                None
+            };
+            match write_insert_rows(table, statement, rows, &returning, &mut result) {
+                Ok(inserted) => {
+                    if let Some(savepoint) = savepoint {
+                        table.release(savepoint);
+                    } else if autocommit {
+                        table.commit()?;
+                    }
+                    result.rows_affected = inserted;
+                    fire_triggers(db, &statement.table_name, inserted)?;
+                }
+                Err(e) => {
+                    if let Some(savepoint) = savepoint {
+                        table.restore(savepoint);
+                    } else if autocommit {
+                        table.rollback()?;
+                    }
+                    if count == 1 {
+                        return Err(e);
+                    }
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}; no rows were inserted", e),
+                    ));
+                }
+            }
+        }
+        StatementType::Select => {
+            let cache = &mut db.result_cache;
+            let key = format!("{:?}", statement);
+            if let Some(hit) = cache.get(&key) {
+                result.columns = hit.columns.clone();
+                stats.rows_returned = match db.output_mode {
+                    Some(mode) => {
+                        output::print_rows(mode, &hit.columns, hit.rows.iter().cloned().map(Ok))?
+                    }
+                    None => {
+                        result.rows = hit.rows.clone();
+                        hit.rows.len()
+                    }
+                };
+                result.rows_returned = stats.rows_returned;
+                return Ok(result);
+            }
+            functions::take_nondeterministic_call();
+            let columns = result_columns(tables, statement)?;
+            // The shell prints rows as they are produced; a copy is kept for
+            // the cache only while the result is small enough to be cached
+            let mut kept = cache.enabled.then(Vec::new);
+            let rows = select_stream(tables, statement, stats)?.inspect(|row| {
+                if let (Some(rows), Ok(values)) = (&mut kept, row) {
+                    if rows.len() < cache.max_rows {
+                        rows.push(values.clone());
+                    } else {
+                        kept = None;
+                    }
+                }
+            });
+            stats.rows_returned = match db.output_mode {
+                Some(mode) => output::print_rows(mode, &columns, rows)?,
+                None => {
+                    result.rows = rows.collect::<io::Result<_>>()?;
+                    result.rows.len()
+                }
+            };
+            result.rows_returned = stats.rows_returned;
+            if statement.where_clause.is_some() {
+                result.scanned = Some((stats.rows_matched, stats.rows_examined));
+            }
+            if let Some(rows) = kept.filter(|_| !functions::take_nondeterministic_call()) {
+                cache.insert(
+                    key,
+                    CachedResult {
+                        columns: columns.clone(),
+                        rows,
+                    },
+                );
+            }
+            result.columns = columns;
+        }
+        StatementType::CreateTable => {
+            match db.create_table(&statement.table_name, statement.column_defs.clone()) {
+                Ok(()) => result
+                    .messages
+                    .push(format!("Created table {}", statement.table_name)),
+                Err(e) if statement.if_exists && e.kind() == io::ErrorKind::AlreadyExists => {
+                    result.messages.push(format!(
+                        "Table {} already exists, skipped",
+                        statement.table_name
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        StatementType::DropTable => match db.drop_table(&statement.table_name) {
+            Ok(()) => result
+                .messages
+                .push(format!("Dropped table {}", statement.table_name)),
+            Err(e) if statement.if_exists && e.kind() == io::ErrorKind::NotFound => {
+                result.messages.push(format!(
+                    "Table {} does not exist, skipped",
+                    statement.table_name
+                ))
+            }
+            Err(e) => return Err(e),
+        },
+        StatementType::CreateView => {
+            let query = statement.query.as_deref().expect("views have a query");
+            match db.create_view(&statement.table_name, query, &statement.query_sql) {
+                Ok(()) => result
+                    .messages
+                    .push(format!("Created view {}", statement.table_name)),
+                Err(e) if statement.if_exists && e.kind() == io::ErrorKind::AlreadyExists => {
+                    result.messages.push(format!(
+                        "View {} already exists, skipped",
+                        statement.table_name
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        StatementType::DropView => match db.drop_view(&statement.table_name) {
+            Ok(()) => result
+                .messages
+                .push(format!("Dropped view {}", statement.table_name)),
+            Err(e) if statement.if_exists && e.kind() == io::ErrorKind::NotFound => {
+                result.messages.push(format!(
+                    "View {} does not exist, skipped",
+                    statement.table_name
+                ))
+            }
+            Err(e) => return Err(e),
+        },
+        StatementType::CreateIndex => {
+            let (name, indexed) = (&statement.index_name, &statement.projection[0]);
+            match db.create_index(name, &statement.table_name, indexed) {
+                Ok(()) => result.messages.push(format!("Created index {}", name)),
+                Err(e) if statement.if_exists && e.kind() == io::ErrorKind::AlreadyExists => result
+                    .messages
+                    .push(format!("Index {} already exists, skipped", name)),
+                Err(e) => return Err(e),
+            }
+        }
+        StatementType::DropIndex => match db.drop_index(&statement.index_name) {
+            Ok(()) => result
+                .messages
+                .push(format!("Dropped index {}", statement.index_name)),
+            Err(e) if statement.if_exists && e.kind() == io::ErrorKind::NotFound => {
+                result.messages.push(format!(
+                    "Index {} does not exist, skipped",
+                    statement.index_name
+                ))
+            }
+            Err(e) => return Err(e),
+        },
+        StatementType::CreateTrigger => {
+            let name = &statement.trigger_name;
+            let insert = statement
+                .query
+                .as_deref()
+                .expect("triggers have a statement");
+            match db.create_trigger(name, &statement.table_name, insert, &statement.query_sql) {
+                Ok(()) => result.messages.push(format!("Created trigger {}", name)),
+                Err(e) if statement.if_exists && e.kind() == io::ErrorKind::AlreadyExists => result
+                    .messages
+                    .push(format!("Trigger {} already exists, skipped", name)),
+                Err(e) => return Err(e),
+            }
+        }
+        StatementType::DropTrigger => match db.drop_trigger(&statement.trigger_name) {
+            Ok(()) => result
+                .messages
+                .push(format!("Dropped trigger {}", statement.trigger_name)),
+            Err(e) if statement.if_exists && e.kind() == io::ErrorKind::NotFound => {
+                result.messages.push(format!(
+                    "Trigger {} does not exist, skipped",
+                    statement.trigger_name
+                ))
+            }
+            Err(e) => return Err(e),
+        },
+        StatementType::Update => {
+            let statement = &resolve_subqueries(tables, statement)?;
+            let table = find_table(tables, &statement.table_name)?;
+            let updates = update_rows(table, statement, stats)?;
+
+            // As with INSERT, the rows change together or not at all. Even a
+            // single row needs a savepoint, as one whose key changes is
+            // taken out before it is put back in its new place.
+            let count = updates.len();
+            let savepoint = if autocommit {
+                table.begin()?;
+                None
+            } else {
+                Some(table.savepoint())
+            };
+            let written = write_updates(table, updates);
+            if let Err(e) = written {
+                if let Some(savepoint) = savepoint {
+                    table.restore(savepoint);
+                } else if autocommit {
+                    table.rollback()?;
+                }
+                if count == 1 {
+                    return Err(e);
+                }
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}; no rows were updated", e),
+                ));
+            }
+            match savepoint {
+                Some(savepoint) => table.release(savepoint),
+                None => table.commit()?,
+            }
+            result.rows_affected = count;
+            if statement.where_clause.is_some() {
+                result.scanned = Some((stats.rows_matched, stats.rows_examined));
+            }
+        }
+        StatementType::Delete | StatementType::Truncate => {
+            let statement = &resolve_subqueries(tables, statement)?;
+            let table = find_table(tables, &statement.table_name)?;
+            // Without WHERE, and for TRUNCATE, every row goes, so the file
+            // is cut back rather than the rows visited one by one. The
+            // schema and AUTOINCREMENT sequence remain
+            let deleted = match statement.where_clause {
+                Some(_) => Some(matching_rows(table, statement, stats)?),
+                None => None,
+            };
+            let count = deleted.as_ref().map_or(table.num_rows, Vec::len);
+
+            let savepoint = if autocommit {
+                table.begin()?;
+                None
+            } else {
+                Some(table.savepoint())
+            };
+            let written = match &deleted {
+                Some(rows) => table.delete_rows(rows),
+                None => table.clear_rows(),
+            };
+            if let Err(e) = written {
+                if let Some(savepoint) = savepoint {
+                    table.restore(savepoint);
+                } else {
+                    table.rollback()?;
+                }
+                return Err(e);
+            }
+            match savepoint {
+                Some(savepoint) => table.release(savepoint),
+                None => table.commit()?,
+            }
+            result.rows_affected = count;
+            if statement.where_clause.is_some() {
+                result.scanned = Some((stats.rows_matched, stats.rows_examined));
+            }
+        }
+        StatementType::Begin => {
+            if db.in_transaction {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot start a transaction within a transaction",
+                ));
+            }
+            if db.bulk {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot start a transaction in bulk mode",
+                ));
+            }
+            db.begin()?;
+            result.messages.push("Transaction started".to_string());
+        }
+        StatementType::Commit | StatementType::Rollback => {
+            if !db.in_transaction {
+                let verb = match statement.statement_type {
+                    StatementType::Commit => "commit",
+                    _ => "rollback",
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot {} - no transaction is active", verb),
+                ));
+            }
+            match statement.statement_type {
+                StatementType::Commit => db.commit()?,
+                _ => db.rollback()?,
+            }
+            match statement.statement_type {
+                StatementType::Commit => result.messages.push("Transaction committed".to_string()),
+                _ => result.messages.push("Transaction rolled back".to_string()),
+            }
+        }
+        StatementType::Pragma => {
+            if let Some(pragma) = &statement.pragma {
+                result.messages.extend(db.pragma(pragma)?);
+            }
+        }
+        StatementType::Explain => {
+            if let Some(query) = &statement.query {
+                let lines = explain(tables, query)?;
+                result.messages.push("QUERY PLAN".to_string());
+                for (i, line) in lines.iter().enumerate() {
+                    let branch = if i + 1 == lines.len() { "`--" } else { "|--" };
+                    result.messages.push(format!("{}{}", branch, line));
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Rewrites every SELECT in a statement that reads from a view, including
+/// subqueries and UNION arms, to read from the view's own tables instead.
+/// The view's projection is substituted for references to its columns, and
+/// its WHERE is ANDed with the outer one.
+fn expand_views(views: &[View], tables: &[Table], statement: &mut Statement) -> io::Result<()> {
+    expand_views_within(views, tables, statement, &[])
+}
+
+/// How many views deep a SELECT may read, counting each view read from
+/// another view's definition.
+const MAX_VIEW_DEPTH: usize = 8;
+
+/// Expands views in a statement that is part of the definitions of the
+/// `within` views, the outermost first. Errors in a definition name the
+/// view it belongs to, and a view that reads from itself, directly or
+/// through others, is rejected.
+pub fn expand_views_within(
+    views: &[View],
+    tables: &[Table],
+    statement: &mut Statement,
+    within: &[&str],
+) -> io::Result<()> {
+    for arm in &mut statement.compound {
+        expand_views_within(views, tables, &mut arm.select, within)?;
+    }
+    // A view's own query is expanded when the view is used
+    if statement.statement_type != StatementType::CreateView {
+        if let Some(query) = &mut statement.query {
+            expand_views_within(views, tables, query, within)?;
+        }
+    }
+    for expr in statement.exprs_mut() {
+        expand_expr_views(views, tables, expr, within)?;
+    }
+
+    let names = std::iter::once(&statement.table_name)
+        .chain(statement.join.iter().map(|join| &join.table_name))
+        .filter(|name| !name.is_empty());
+    for name in names {
+        if let Some(start) = within.iter().position(|v| v.eq_ignore_ascii_case(name)) {
+            let cycle: Vec<&str> = within[start..]
+                .iter()
+                .copied()
+                .chain([name.as_str()])
+                .collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("circular view definition: {}", cycle.join(" -> ")),
+            ));
+        }
+        let known = || {
+            views.iter().any(|v| v.name.eq_ignore_ascii_case(name))
+                || tables.iter().any(|t| t.name.eq_ignore_ascii_case(name))
+        };
+        if let Some(view) = within.last().filter(|_| !known()) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("view {}: no such table: {}", view, name),
+            ));
+        }
+    }
+
+    let view = match views
+        .iter()
+        .find(|v| v.name.eq_ignore_ascii_case(&statement.table_name))
+    {
+        Some(view) if statement.statement_type == StatementType::Select => view,
+        _ => return Ok(()),
+    };
+    if within.len() >= MAX_VIEW_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "view {}: views are nested more than {} deep",
+                within[0], MAX_VIEW_DEPTH
+            ),
+        ));
+    }
+    if statement.join.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot join view {}", view.name),
+        ));
+    }
+    let mut base = view.query.clone();
+    let within: Vec<&str> = within.iter().copied().chain([view.name.as_str()]).collect();
+    expand_views_within(views, tables, &mut base, &within)?;
+
+    // A view over `*` passes its table's columns straight through
+    let columns: Option<Vec<(String, Expr)>> = if base.projection[0].is_star() {
+        None
+    } else {
+        Some(
+            base.projection
+                .iter()
+                .map(|expr| {
+                    let name = match expr {
+                        Expr::Column(column) => column.column.clone(),
+                        _ => expr.to_string(),
+                    };
+                    (name, expr.clone())
+                })
+                .collect(),
+        )
+    };
+    let substitute = |expr: &mut Expr| {
+        substitute_view_columns(expr, &view.name, &base.table_name, columns.as_deref())
+    };
+
+    if statement.projection[0].is_star() {
+        statement.projection = base.projection.clone();
+    } else {
+        for expr in &mut statement.projection {
+            substitute(expr)?;
+        }
+    }
+    for expr in statement.group_by.iter_mut().chain(&mut statement.having) {
+        substitute(expr)?;
+    }
+    for term in &mut statement.order_by {
+        if let SortKey::Expr(expr) = &mut term.key {
+            substitute(expr)?;
+        }
+    }
+    statement.where_clause = match (base.where_clause.take(), statement.where_clause.take()) {
+        (Some(inner), Some(mut outer)) => {
+            substitute(&mut outer)?;
+            Some(Expr::Binary {
+                left: Box::new(inner),
+                op: BinaryOp::And,
+                right: Box::new(outer),
+            })
+        }
+        (Some(inner), None) => Some(inner),
+        (None, Some(mut outer)) => {
+            substitute(&mut outer)?;
+            Some(outer)
+        }
+        (None, None) => None,
+    };
+    statement.table_name = base.table_name;
+    statement.join = base.join;
+    Ok(())
+}
+
+/// Expands views inside the subqueries of an expression.
+fn expand_expr_views(
+    views: &[View],
+    tables: &[Table],
+    expr: &mut Expr,
+    within: &[&str],
+) -> io::Result<()> {
+    match expr {
+        Expr::Column(_) | Expr::Literal(_) => Ok(()),
+        Expr::InSelect { expr, query, .. } => {
+            expand_expr_views(views, tables, expr, within)?;
+            expand_views_within(views, tables, query, within)
+        }
+        Expr::Exists { query, .. } => expand_views_within(views, tables, query, within),
+        _ => {
+            for child in expr.children_mut() {
+                expand_expr_views(views, tables, child, within)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Replaces references to a view's columns with the expressions that
+/// compute them, or just retargets them at the base table when the view
+/// selects `*`.
+fn substitute_view_columns(
+    expr: &mut Expr,
+    view_name: &str,
+    base_table: &str,
+    columns: Option<&[(String, Expr)]>,
+) -> io::Result<()> {
+    if let Expr::Column(column) = expr {
+        if column.is_star()
+            || column
+                .table
+                .as_ref()
+                .is_some_and(|table| !table.eq_ignore_ascii_case(view_name))
+        {
+            return Ok(());
+        }
+        match columns {
+            None => {
+                if column.table.is_some() {
+                    column.table = Some(base_table.to_string());
+                }
+            }
+            Some(columns) => {
+                let (_, replacement) = columns
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&column.column))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("no such column: {}", column),
+                        )
+                    })?;
+                *expr = replacement.clone();
+            }
+        }
+        return Ok(());
+    }
+    // Subqueries read their own tables, so only their outer operand is
+    // rewritten
+    for child in expr.children_mut() {
+        substitute_view_columns(child, view_name, base_table, columns)?;
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes a table's file and every file kept beside it.
+pub fn remove_table_files(table: &Table) -> io::Result<()> {
+    remove_if_exists(&table.path)?;
+    remove_if_exists(&table.pager.wal_path)?;
+    remove_if_exists(&table.pager.journal_path)?;
+    remove_if_exists(&table.sequence_path)?;
+    table.remove_index_files()
+}
+
+/// Runs the SELECT of an INSERT ... SELECT. Its columns are matched to the
+/// target columns by position, so the widths must agree before anything is
+/// written.
+fn insert_query_rows(
+    tables: &mut [Table],
+    statement: &Statement,
+    query: &Statement,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<Vec<Value>>> {
+    let width = output_width(tables, query)?;
+    let table = find_table(tables, &statement.table_name)?;
+    let message = if statement.columns.is_empty() {
+        (width != table.columns.len()).then(|| {
+            format!(
+                "table {} has {} columns but {} values were supplied",
+                table.name,
+                table.columns.len(),
+                width
+            )
+        })
+    } else {
+        (width != statement.columns.len())
+            .then(|| format!("{} values for {} columns", width, statement.columns.len()))
+    };
+    if let Some(message) = message {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    select(tables, query, stats)
+}
+
+/// Looks up a table by name, ignoring case.
+pub fn find_table<'a>(tables: &'a mut [Table], name: &str) -> io::Result<&'a mut Table> {
+    tables
+        .iter_mut()
+        .find(|t| t.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no such table: {}", name)))
+}
+
+/// Runs a SELECT, including any UNION arms, and returns its output rows.
+fn select(
+    tables: &mut [Table],
+    statement: &Statement,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<Vec<Value>>> {
+    let rows = select_stream(tables, statement, stats)?.collect::<io::Result<Vec<_>>>()?;
+    stats.rows_returned = rows.len();
+    Ok(rows)
+}
+
+/// Runs a SELECT, producing its output rows as they are read. Only a SELECT
+/// that sorts or has UNION arms holds its rows in memory first, as do
+/// joins and aggregates within `select_core`.
+fn select_stream<'a>(
+    tables: &'a mut [Table],
+    statement: &Statement,
+    stats: &'a mut StatementStats,
+) -> io::Result<Rows<'a>> {
+    let statement = &apply_collations(tables, statement);
+    // ORDER BY rowid is the order rows are stored in, so the scan itself
+    // produces it and LIMIT can stop the scan early
+    let rowid_order = rowid_order(tables, statement)?;
+    let unordered;
+    let core = match rowid_order {
+        Some(_) => {
+            unordered = Statement {
+                order_by: Vec::new(),
+                ..statement.clone()
+            };
+            &unordered
+        }
+        None => statement,
+    };
+    // Without sorting, UNION or DISTINCT the scan itself can skip the rows
+    // OFFSET drops, rather than producing them to be thrown away
+    let skip = if core.order_by.is_empty() && statement.compound.is_empty() && !statement.distinct {
+        statement.offset
+    } else {
+        0
+    };
+    let offset = statement.offset - skip;
+    let sorted = rowid_order.is_none() && !statement.order_by.is_empty();
+    if !sorted && statement.compound.is_empty() {
+        let rows = select_core(tables, core, rowid_order == Some(true), skip, stats)?;
+        let rows = pipeline::offset(rows, offset);
+        return Ok(Box::new(pipeline::limit(rows, statement.limit)));
+    }
+
+    let mut rows =
+        select_core(tables, core, false, skip, stats)?.collect::<io::Result<Vec<_>>>()?;
+    let width = output_width(tables, statement)?;
+    for arm in &statement.compound {
+        if output_width(tables, &arm.select)? != width {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SELECTs to the left and right of UNION do not have the same number of result columns",
+            ));
+        }
+        let arm_rows = select_core(tables, &arm.select, false, 0, stats)?;
+        rows.extend(arm_rows.collect::<io::Result<Vec<_>>>()?);
+        if !arm.all {
+            rows = dedup_rows(statement, rows);
+        }
+    }
+    if sorted {
+        rows = sort_rows(statement, width, rows, offset)?;
+    } else {
+        rows.drain(..offset.min(rows.len()));
+        if let Some(limit) = statement.limit {
+            rows.truncate(limit);
+        }
+    }
+    Ok(buffered(rows))
+}
+
+/// Rows already held in memory, as a stream.
+fn buffered<'a>(rows: Vec<Vec<Value>>) -> Rows<'a> {
+    Box::new(rows.into_iter().map(Ok))
+}
+
+/// Whether a SELECT is ordered by nothing but `rowid`, the position rows
+/// were inserted at, and if so whether descending. Only a plain scan of a
+/// table without its own rowid column qualifies.
+fn rowid_order(tables: &mut [Table], statement: &Statement) -> io::Result<Option<bool>> {
+    let descending = match statement.order_by.as_slice() {
+        [OrderBy {
+            key: SortKey::Expr(Expr::Column(column)),
+            descending,
+            ..
+        }] if column.column.eq_ignore_ascii_case("rowid") => *descending,
+        _ => return Ok(None),
+    };
+    if statement.table_name.is_empty() || statement.join.is_some() || !statement.compound.is_empty()
+    {
+        return Ok(None);
+    }
+    let table = find_table(tables, &statement.table_name)?;
+    let has_rowid_column = table
+        .columns
+        .iter()
+        .any(|c| c.name.eq_ignore_ascii_case("rowid"));
+    Ok((!has_rowid_column).then_some(descending))
+}
+
+/// The lines EXPLAIN QUERY PLAN prints for a statement: how each table it
+/// reads is read, as decided by the same planning the statement runs with,
+/// and whether its rows are sorted.
+fn explain(tables: &mut [Table], statement: &Statement) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    match statement.statement_type {
+        StatementType::Select => {
+            let statement = &apply_collations(tables, statement);
+            explain_core(tables, statement, &mut lines)?;
+            for arm in &statement.compound {
+                lines.push(if arm.all { "UNION ALL" } else { "UNION" }.to_string());
+                explain_core(tables, &arm.select, &mut lines)?;
+            }
+            if !statement.order_by.is_empty() && rowid_order(tables, statement)?.is_none() {
+                lines.push("SORT FOR ORDER BY".to_string());
+            }
+        }
+        StatementType::Update | StatementType::Delete => {
+            let statement = &resolve_subqueries(tables, statement)?;
+            let table = find_table(tables, &statement.table_name)?;
+            if statement.statement_type == StatementType::Delete && statement.where_clause.is_none()
+            {
+                lines.push(format!("TRUNCATE {}", table.name));
+            } else {
+                let mut where_clause = statement.where_clause.clone();
+                if let Some(condition) = &mut where_clause {
+                    collate_columns(condition, &[table]);
+                }
+                let plan = planner::plan_access(table, &where_clause);
+                lines.push(plan.describe(&table.name));
+            }
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "EXPLAIN QUERY PLAN only describes SELECT, UPDATE and DELETE",
+            ))
+        }
+    }
+    Ok(lines)
+}
+
+/// Explains a single SELECT, without its UNION arms, as `select_core`
+/// runs it.
+fn explain_core(
+    tables: &mut [Table],
+    statement: &Statement,
+    lines: &mut Vec<String>,
+) -> io::Result<()> {
+    let statement = &resolve_subqueries(tables, statement)?;
+    if statement.table_name.is_empty() {
+        lines.push("SCAN CONSTANT ROW".to_string());
+        return Ok(());
+    }
+    if let Some(join) = &statement.join {
+        let (outer, inner) = table_pair(tables, &statement.table_name, &join.table_name)?;
+        let scan = |table: &Table, detail: String| {
+            format!(
+                "SCAN {}{} ({})",
+                table.name,
+                detail,
+                planner::estimate(table.num_rows)
+            )
+        };
+        match join_key(&join.on, outer, inner) {
+            Some(key) => {
+                let (build, build_column, probe, probe_column) = if builds_outer(outer, inner) {
+                    (outer, &key.outer_column, inner, &key.inner_column)
+                } else {
+                    (inner, &key.inner_column, outer, &key.outer_column)
+                };
+                lines.push(scan(build, format!(" INTO HASH TABLE ON {}", build_column)));
+                lines.push(scan(
+                    probe,
+                    format!(" PROBING HASH TABLE ON {}", probe_column),
+                ));
+            }
+            None => {
+                lines.push(scan(outer, String::new()));
+                lines.push(scan(inner, format!(" FOR EACH ROW OF {}", outer.name)));
+            }
+        }
+        return Ok(());
+    }
+    let table = find_table(tables, &statement.table_name)?;
+    let plan = if is_aggregated(statement) && !counts_rows_only(statement) {
+        planner::AccessPlan::scan(table)
+    } else {
+        planner::plan_access(table, &statement.where_clause)
+    };
+    lines.push(plan.describe(&table.name));
+    Ok(())
+}
+
+/// Whether a SELECT computes aggregates, outputting a row per group.
+fn is_aggregated(statement: &Statement) -> bool {
+    !statement.group_by.is_empty()
+        || statement.having.is_some()
+        || statement
+            .projection
+            .iter()
+            .chain(sort_exprs(statement))
+            .any(contains_aggregate)
+}
+
+/// Whether a SELECT is a lone count(*), which needs no values, and often
+/// no rows at all.
+fn counts_rows_only(statement: &Statement) -> bool {
+    matches!(statement.projection.as_slice(), [p] if is_count_star(p))
+        && statement.group_by.is_empty()
+        && statement.having.is_none()
+        && statement.order_by.is_empty()
+}
+
+/// Runs a single SELECT, without its UNION arms, leaving out its first
+/// `skip` output rows. A reverse scan reads the table from its last row
+/// back to its first.
+fn select_core<'a>(
+    tables: &'a mut [Table],
+    statement: &Statement,
+    reverse: bool,
+    skip: usize,
+    stats: &'a mut StatementStats,
+) -> io::Result<Rows<'a>> {
+    let statement = &resolve_subqueries(tables, statement)?;
+    if !statement.group_by.is_empty()
+        && (statement.join.is_some() || statement.table_name.is_empty())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "GROUP BY is only supported on a single table",
+        ));
+    }
+    if statement.having.is_some() && (statement.join.is_some() || statement.table_name.is_empty()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "HAVING is only supported on a single table",
+        ));
+    }
+    if statement.table_name.is_empty() {
+        if statement.projection[0].is_star() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no tables specified",
+            ));
+        }
+        // Constant expressions see a row with no columns, so any column
+        // reference fails with "no such column"
+        let row = Row::new();
+        if skip > 0 || !matches_where_clause(&row, &statement.where_clause)? {
+            return Ok(buffered(Vec::new()));
+        }
+        return Ok(buffered(vec![output_row(&row, statement)?]));
+    }
+    if let Some(join) = &statement.join {
+        let mut rows = select_join(tables, statement, join, stats)?;
+        rows.drain(..skip.min(rows.len()));
+        return Ok(buffered(rows));
+    }
+
+    let table = find_table(tables, &statement.table_name)?;
+    let expanded = Statement {
+        projection: expand_stars(&statement.projection, &[table])?,
+        ..statement.clone()
+    };
+    let statement = &expanded;
+    let refs = statement
+        .projection
+        .iter()
+        .chain(statement.where_clause.iter())
+        .chain(&statement.group_by)
+        .chain(statement.having.iter())
+        .chain(sort_exprs(statement))
+        .flat_map(|e| e.column_refs())
+        .collect::<Vec<_>>();
+    check_qualifiers(&[&table.name], refs.iter().copied())?;
+    // Expressions are only evaluated for rows that reach them, so check the
+    // columns up front to fail even when no row matches
+    check_columns_exist(&[table], refs.into_iter())?;
+
+    if is_aggregated(statement) {
+        let mut rows = if counts_rows_only(statement) {
+            let count = count_rows(table, &statement.where_clause, stats)?;
+            vec![vec![Value::Integer(count as i64)]]
+        } else {
+            aggregate_rows(table, statement, stats)?
+        };
+        let mut seen = HashSet::new();
+        rows.retain(|row| is_distinct(statement, &mut seen, row));
+        rows.drain(..skip.min(rows.len()));
+        return Ok(buffered(rows));
+    }
+
+    let row_nums: Box<dyn Iterator<Item = usize>> =
+        match planner::plan_access(table, &statement.where_clause).row_nums(table)? {
+            Some(row_nums) => {
+                stats.index_used = true;
+                if reverse {
+                    Box::new(row_nums.into_iter().rev())
+                } else {
+                    Box::new(row_nums.into_iter())
+                }
+            }
+            None => {
+                stats.table_scans += 1;
+                if reverse {
+                    Box::new((0..table.num_rows).rev())
+                } else {
+                    Box::new(0..table.num_rows)
+                }
+            }
+        };
+    // Every row is output when there is no WHERE clause, so the rows OFFSET
+    // skips need not even be read
+    let (row_nums, skip): (Box<dyn Iterator<Item = usize>>, usize) =
+        if statement.where_clause.is_none() {
+            (Box::new(row_nums.skip(skip)), 0)
+        } else {
+            (row_nums, skip)
+        };
+    let statement = Rc::new(statement.clone());
+    let where_clause = statement.where_clause.clone();
+    let rows = pipeline::Filter::new(pipeline::Scan::new(table, row_nums), where_clause, stats);
+    let rows = pipeline::offset(rows, skip);
+    let rows = pipeline::project(rows, statement.clone());
+    Ok(Box::new(pipeline::distinct(rows, statement)))
+}
+
+/// Whether a scan can stop early, having produced (or skipped) the rows
+/// OFFSET skips and LIMIT keeps. Only a SELECT without UNION arms can
+/// stop; with them, deduplication may still discard rows.
+fn reached_limit(statement: &Statement, produced: usize) -> bool {
+    statement.compound.is_empty()
+        && statement.order_by.is_empty()
+        && statement
+            .limit
+            .is_some_and(|limit| produced >= limit.saturating_add(statement.offset))
+}
+
+/// Whether an output row is kept: always, unless the SELECT is DISTINCT
+/// and an earlier row had the same output values, compared with each
+/// column's collation. Sort values appended by `output_row` are not
+/// compared.
+pub fn is_distinct(statement: &Statement, seen: &mut HashSet<Vec<u8>>, row: &[Value]) -> bool {
+    if !statement.distinct {
+        return true;
+    }
+    let width = row.len() - sort_exprs(statement).count();
+    seen.insert(distinct_key(statement, &row[..width]))
+}
+
+/// Output values encoded for telling rows apart, each folded by the
+/// collation of its column of the SELECT.
+fn distinct_key(statement: &Statement, values: &[Value]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let collation = statement.projection.get(i).and_then(collation_of);
+        collation
+            .unwrap_or_default()
+            .fold(value)
+            .encode_key(&mut key);
+    }
+    key
+}
+
+/// Projects a row and, for a SELECT without UNION arms, appends the values
+/// of its ORDER BY expressions so that `sort_rows` can sort on them.
+pub fn output_row(row: &Row, statement: &Statement) -> io::Result<Vec<Value>> {
+    let mut values = project(row, &statement.projection)?;
+    for expr in sort_exprs(statement) {
+        values
+            .push(evaluate(expr, row).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?);
+    }
+    Ok(values)
+}
+
+/// The ORDER BY expressions evaluated against each source row. With UNION
+/// arms there are none, since terms must name a result column instead.
+fn sort_exprs(statement: &Statement) -> impl Iterator<Item = &Expr> {
+    statement
+        .order_by
+        .iter()
+        .filter(|_| statement.compound.is_empty())
+        .filter_map(|term| match &term.key {
+            SortKey::Expr(expr) => Some(expr),
+            SortKey::Position(_) => None,
+        })
+}
+
+/// Sorts output rows by a statement's ORDER BY terms, then drops the sort
+/// values `output_row` appended after the first `width` columns. Positions
+/// sort on the already projected values. The sort is stable, and NULLs go
+/// first or last as each term says. Rows past the sort's memory budget are
+/// spilled to disk, and only those within OFFSET and LIMIT are kept as the
+/// sorted runs are merged.
+fn sort_rows(
+    statement: &Statement,
+    width: usize,
+    rows: Vec<Vec<Value>>,
+    offset: usize,
+) -> io::Result<Vec<Vec<Value>>> {
+    let mut keys = Vec::new();
+    let mut appended = width;
+    for (i, term) in statement.order_by.iter().enumerate() {
+        let collation = match &term.key {
+            SortKey::Position(position) => position
+                .checked_sub(1)
+                .and_then(|i| statement.projection.get(i))
+                .and_then(collation_of),
+            SortKey::Expr(expr) => collation_of(expr),
+        };
+        let index = match &term.key {
+            SortKey::Position(position) if (1..=width).contains(position) => position - 1,
+            SortKey::Position(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} ORDER BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        width
+                    ),
+                ))
+            }
+            SortKey::Expr(_) if statement.compound.is_empty() => {
+                appended += 1;
+                appended - 1
+            }
+            SortKey::Expr(expr) => statement
+                .projection
+                .iter()
+                .position(|item| item.to_string() == expr.to_string())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} ORDER BY term does not match any column in the result set",
+                            ordinal(i + 1)
+                        ),
+                    )
+                })?,
+        };
+        keys.push((index, term, collation.unwrap_or_default()));
+    }
+
+    let mut sorter = sort::Sorter::new(|a: &[Value], b: &[Value]| {
+        keys.iter()
+            .map(|&(index, term, collation)| match (&a[index], &b[index]) {
+                (Value::Null, Value::Null) => Ordering::Equal,
+                (Value::Null, _) if term.nulls_first => Ordering::Less,
+                (Value::Null, _) => Ordering::Greater,
+                (_, Value::Null) if term.nulls_first => Ordering::Greater,
+                (_, Value::Null) => Ordering::Less,
+                (left, right) => {
+                    let ordering =
+                        compare_collated(left, right, collation).unwrap_or(Ordering::Equal);
+                    if term.descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    for row in rows {
+        sorter.push(row)?;
+    }
+    let mut sorted = Vec::new();
+    for (i, row) in sorter.finish()?.enumerate() {
+        let mut row = row?;
+        if statement.limit.is_some_and(|limit| sorted.len() >= limit) {
+            break;
+        }
+        if i >= offset {
+            row.truncate(width);
+            sorted.push(row);
+        }
+    }
+    Ok(sorted)
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (1, 11) | (2, 12) | (3, 13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// The number of columns a SELECT outputs.
+fn output_width(tables: &mut [Table], statement: &Statement) -> io::Result<usize> {
+    Ok(result_columns(tables, statement)?.len())
+}
+
+/// The names of the columns a SELECT outputs: a column's own name, or the
+/// text of any other expression. `*` stands for the columns of each table
+/// it covers.
+fn result_columns(tables: &mut [Table], statement: &Statement) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for expr in &statement.projection {
+        match expr {
+            Expr::Column(column) if column.is_star() && !statement.table_name.is_empty() => {
+                let joined = statement.join.iter().map(|join| &join.table_name);
+                for source in std::iter::once(&statement.table_name).chain(joined) {
+                    let table = find_table(tables, source)?;
+                    let covered = column
+                        .table
+                        .as_ref()
+                        .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(&table.name));
+                    if covered {
+                        names.extend(table.columns.iter().map(|c| c.name.clone()));
+                    }
+                }
+            }
+            Expr::Column(column) => names.push(column.column.clone()),
+            _ => names.push(expr.to_string()),
+        }
+    }
+    Ok(names)
+}
+
+/// Drops repeated rows, keeping the first of each, telling them apart as
+/// DISTINCT does with the collations of the first SELECT's columns. Unlike
+/// in comparisons, NULLs are equal to each other here.
+fn dedup_rows(statement: &Statement, rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+    let mut seen = HashSet::new();
+    rows.into_iter()
+        .filter(|row| seen.insert(distinct_key(statement, row)))
+        .collect()
+}
+
+/// Runs a SELECT over two joined tables. When the ON clause equates a
+/// column of each, that is a hash join; otherwise a nested loop, where for
+/// every outer row the inner table's cursor is rewound and scanned again.
+/// Its pages stay cached in its pager, so each is read from disk only once.
+/// Combined rows key their values by `table.column`.
+fn select_join(
+    tables: &mut [Table],
+    statement: &Statement,
+    join: &Join,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<Vec<Value>>> {
+    let outer_name = find_table(tables, &statement.table_name)?.name.clone();
+    let inner_name = find_table(tables, &join.table_name)?.name.clone();
+    if outer_name == inner_name {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot join {} to itself", inner_name),
+        ));
+    }
+    let refs = statement
+        .projection
+        .iter()
+        .chain(statement.where_clause.iter())
+        .chain(std::iter::once(&join.on))
+        .chain(sort_exprs(statement))
+        .flat_map(|e| e.column_refs())
+        .collect::<Vec<_>>();
+    check_qualifiers(&[&outer_name, &inner_name], refs.iter().copied())?;
+
+    let (outer, inner) = table_pair(tables, &outer_name, &inner_name)?;
+    check_columns_exist(&[outer, inner], refs.into_iter())?;
+    let expanded = Statement {
+        projection: expand_stars(&statement.projection, &[outer, inner])?,
+        ..statement.clone()
+    };
+    let statement = &expanded;
+    if let Some(key) = join_key(&join.on, outer, inner) {
+        return hash_join(outer, inner, statement, join, &key, stats);
+    }
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    let mut cursor = Cursor::table_start(outer)?;
+    let mut inner_cursor = Cursor::table_start(inner)?;
+    stats.table_scans += 1;
+    while !cursor.end_of_table && !reached_limit(statement, rows.len()) {
+        if let Some(row) = cursor.value()? {
+            let outer_row = qualify_row(&outer_name, row);
+            inner_cursor.rewind()?;
+            stats.table_scans += 1;
+            while !inner_cursor.end_of_table {
+                if let Some(inner_row) = inner_cursor.value()? {
+                    let mut row = outer_row.clone();
+                    row.values
+                        .extend(qualify_row(&inner_name, inner_row).values);
+                    stats.rows_examined += 1;
+                    if matches_condition(&row, &join.on)?
+                        && matches_where_clause(&row, &statement.where_clause)?
+                    {
+                        stats.rows_matched += 1;
+                        let values = output_row(&row, statement)?;
+                        if is_distinct(statement, &mut seen, &values) {
+                            rows.push(values);
+                        }
+                    }
+                }
+                inner_cursor.advance()?;
+            }
+        }
+        cursor.advance()?;
+    }
+    Ok(rows)
+}
+
+/// The columns an equality join matches on: one of the outer table and one
+/// of the inner, compared with `collation`.
+struct JoinKey {
+    outer_column: String,
+    inner_column: String,
+    collation: Collation,
+}
+
+/// The join key when an ON clause is, or has among the terms it ANDs
+/// together, an equality between a column of each table.
+fn join_key(on: &Expr, outer: &Table, inner: &Table) -> Option<JoinKey> {
+    let (left, right) = match on {
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => {
+            return join_key(left, outer, inner).or_else(|| join_key(right, outer, inner));
+        }
+        Expr::Binary {
+            left,
+            op: BinaryOp::Eq,
+            right,
+        } => (left, right),
+        _ => return None,
+    };
+    let collation = comparison_collation(left, right);
+    let (left, right) = match (strip_collate(left), strip_collate(right)) {
+        (Expr::Column(left), Expr::Column(right)) => (left, right),
+        _ => return None,
+    };
+    let owner = |column: &ColumnRef| {
+        let in_table = |table: &Table| {
+            column
+                .table
+                .as_ref()
+                .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(&table.name))
+                && table.columns.iter().any(|c| c.name == column.column)
+        };
+        match (in_table(outer), in_table(inner)) {
+            (true, false) => Some(false),
+            (false, true) => Some(true),
+            _ => None,
+        }
+    };
+    let (outer_column, inner_column) = match (owner(left)?, owner(right)?) {
+        (false, true) => (left, right),
+        (true, false) => (right, left),
+        _ => return None,
+    };
+    Some(JoinKey {
+        outer_column: outer_column.column.clone(),
+        inner_column: inner_column.column.clone(),
+        collation,
+    })
+}
+
+/// Whether a hash join reads the outer table into its map, the smaller
+/// table being the one read.
+fn builds_outer(outer: &Table, inner: &Table) -> bool {
+    outer.num_rows < inner.num_rows
+}
+
+/// Joins two tables on an equality by reading the smaller into a map from
+/// its key value to its rows, then scanning the larger once and looking
+/// each of its rows up, so each table is scanned once rather than the
+/// inner once per outer row. Keys encode their type, as an integer never
+/// equals text here, and a NULL key matches nothing. The full ON clause
+/// is still checked for every pair found. Rows come out in the order of
+/// the larger table.
+fn hash_join(
+    outer: &mut Table,
+    inner: &mut Table,
+    statement: &Statement,
+    join: &Join,
+    key: &JoinKey,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<Vec<Value>>> {
+    let ((build, build_column), (probe, probe_column)) = if builds_outer(outer, inner) {
+        ((outer, &key.outer_column), (inner, &key.inner_column))
+    } else {
+        ((inner, &key.inner_column), (outer, &key.outer_column))
+    };
+    let encode = |row: &Row, table_name: &str, column: &str| {
+        let value = row.values.get(&format!("{}.{}", table_name, column))?;
+        if value == &Value::Null {
+            return None;
+        }
+        let mut encoded = Vec::new();
+        key.collation.fold(value).encode_key(&mut encoded);
+        Some(encoded)
+    };
+
+    let mut built: HashMap<Vec<u8>, Vec<Row>> = HashMap::new();
+    let mut cursor = Cursor::table_start(build)?;
+    stats.table_scans += 1;
+    while !cursor.end_of_table {
+        if let Some(row) = cursor.value()? {
+            let row = qualify_row(&cursor.table.name, row);
+            if let Some(encoded) = encode(&row, &cursor.table.name, build_column) {
+                built.entry(encoded).or_default().push(row);
+            }
+        }
+        cursor.advance()?;
+    }
+
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    let mut cursor = Cursor::table_start(probe)?;
+    stats.table_scans += 1;
+    while !cursor.end_of_table && !reached_limit(statement, rows.len()) {
+        if let Some(row) = cursor.value()? {
+            let probe_row = qualify_row(&cursor.table.name, row);
+            let matches = encode(&probe_row, &cursor.table.name, probe_column)
+                .and_then(|encoded| built.get(&encoded));
+            for build_row in matches.into_iter().flatten() {
+                let mut row = probe_row.clone();
+                row.values.extend(build_row.values.clone());
+                stats.rows_examined += 1;
+                if matches_condition(&row, &join.on)?
+                    && matches_where_clause(&row, &statement.where_clause)?
+                {
+                    stats.rows_matched += 1;
+                    let values = output_row(&row, statement)?;
+                    if is_distinct(statement, &mut seen, &values) {
+                        rows.push(values);
+                    }
+                }
+            }
+        }
+        cursor.advance()?;
+    }
+    Ok(rows)
+}
+
+/// Gives the columns of a SELECT, and of its UNION arms, their declared
+/// collations by wrapping them in a COLLATE where they are compared,
+/// sorted, grouped or output, so that all of those treat a NOCASE column
+/// as they treat an explicit `COLLATE nocase`. Output is unchanged, since
+/// a COLLATE has the value of what it wraps.
+fn apply_collations(tables: &[Table], statement: &Statement) -> Statement {
+    let mut statement = statement.clone();
+    collate_statement(tables, &mut statement);
+    for arm in &mut statement.compound {
+        collate_statement(tables, &mut arm.select);
+    }
+    statement
+}
+
+fn collate_statement(tables: &[Table], statement: &mut Statement) {
+    let joined = statement.join.iter().map(|join| &join.table_name);
+    let sources: Vec<&Table> = std::iter::once(&statement.table_name)
+        .chain(joined)
+        .filter_map(|name| tables.iter().find(|t| t.name.eq_ignore_ascii_case(name)))
+        .collect();
+    for expr in statement.exprs_mut() {
+        collate_column(expr, &sources);
+        collate_columns(expr, &sources);
+    }
+}
+
+/// Gives the column operands of every comparison inside an expression,
+/// and the argument of a DISTINCT aggregate, their collations. As in
+/// SQLite, a COLLATE written on either operand overrides them.
+fn collate_columns(expr: &mut Expr, sources: &[&Table]) {
+    let operands: Vec<&mut Expr> = match expr {
+        Expr::Binary {
+            left,
+            op:
+                BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq,
+            right,
+        } => match (left.as_mut(), right.as_mut()) {
+            (Expr::Tuple(left), Expr::Tuple(right)) => {
+                left.iter_mut().chain(right.iter_mut()).collect()
+            }
+            (left, right) => vec![left, right],
+        },
+        Expr::In { expr, list, .. } => std::iter::once(expr.as_mut()).chain(list).collect(),
+        Expr::Between {
+            expr, low, high, ..
+        } => vec![expr.as_mut(), low.as_mut(), high.as_mut()],
+        // DISTINCT tells values apart as a comparison would
+        Expr::Function {
+            args,
+            distinct: true,
+            ..
+        } => args.iter_mut().collect(),
+        _ => Vec::new(),
+    };
+    if !operands
+        .iter()
+        .any(|operand| collation_of(operand).is_some())
+    {
+        for operand in operands {
+            collate_column(operand, sources);
+        }
+    }
+    for child in expr.children_mut() {
+        collate_columns(child, sources);
+    }
+}
+
+/// Wraps a bare reference to a column with a collation other than BINARY
+/// in a COLLATE naming it.
+fn collate_column(expr: &mut Expr, sources: &[&Table]) {
+    let column = match expr {
+        Expr::Column(column) => column,
+        _ => return,
+    };
+    let collation = sources
+        .iter()
+        .filter(|table| {
+            column
+                .table
+                .as_ref()
+                .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(&table.name))
+        })
+        .flat_map(|table| &table.columns)
+        .find(|c| c.name == column.column)
+        .map_or(Collation::Binary, |c| c.collation);
+    if collation != Collation::Binary {
+        *expr = Expr::Collate {
+            expr: Box::new(expr.clone()),
+            collation,
+        };
+    }
+}
+
+/// Replaces `*` and `t.*` in a projection with the columns they stand for,
+/// in the order the tables declare them, so every row outputs its values
+/// in that order. With more than one table the columns are qualified.
+fn expand_stars(projection: &[Expr], tables: &[&Table]) -> io::Result<Vec<Expr>> {
+    let mut expanded = Vec::new();
+    for expr in projection {
+        let star = match expr {
+            Expr::Column(column) if column.is_star() => column,
+            _ => {
+                expanded.push(expr.clone());
+                continue;
+            }
+        };
+        let mut matched = false;
+        for table in tables.iter().filter(|t| {
+            star.table
+                .as_ref()
+                .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(&t.name))
+        }) {
+            matched = true;
+            expanded.extend(table.columns.iter().map(|c| {
+                let mut column = Expr::Column(ColumnRef {
+                    table: (tables.len() > 1).then(|| table.name.clone()),
+                    column: c.name.clone(),
+                });
+                collate_column(&mut column, &[*table]);
+                column
+            }));
+        }
+        if !matched {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no such table: {}", star.table.as_deref().unwrap_or("")),
+            ));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Borrows two different tables at once, as a join scans both.
+fn table_pair<'a>(
+    tables: &'a mut [Table],
+    first: &str,
+    second: &str,
+) -> io::Result<(&'a mut Table, &'a mut Table)> {
+    let index = |name: &str| {
+        tables
+            .iter()
+            .position(|t| t.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no such table: {}", name))
+            })
+    };
+    let (i, j) = (index(first)?, index(second)?);
+    if i < j {
+        let (left, right) = tables.split_at_mut(j);
+        Ok((&mut left[i], &mut right[0]))
+    } else {
+        let (left, right) = tables.split_at_mut(i);
+        Ok((&mut right[0], &mut left[j]))
+    }
+}
+
+/// Runs every `IN (SELECT ...)` in a statement ahead of its own scan and
+/// replaces it with a plain IN over the values the subquery produced.
+fn resolve_subqueries(tables: &mut [Table], statement: &Statement) -> io::Result<Statement> {
+    let mut statement = statement.clone();
+    for expr in statement
+        .projection
+        .iter_mut()
+        .chain(statement.where_clause.iter_mut())
+        .chain(statement.join.iter_mut().map(|join| &mut join.on))
+        .chain(statement.group_by.iter_mut())
+        .chain(statement.assignments.iter_mut().map(|(_, expr)| expr))
+        .chain(
+            statement
+                .order_by
+                .iter_mut()
+                .filter_map(|term| match &mut term.key {
+                    SortKey::Expr(expr) => Some(expr),
+                    SortKey::Position(_) => None,
+                }),
+        )
+    {
+        resolve_expr_subqueries(tables, expr)?;
+    }
+    Ok(statement)
+}
+
+fn resolve_expr_subqueries(tables: &mut [Table], expr: &mut Expr) -> io::Result<()> {
+    match expr {
+        Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) => {}
+        Expr::Tuple(items) | Expr::Function { args: items, .. } => {
+            for item in items {
+                resolve_expr_subqueries(tables, item)?;
+            }
+        }
+        Expr::Binary { left, right, .. } => {
+            resolve_expr_subqueries(tables, left)?;
+            resolve_expr_subqueries(tables, right)?;
+        }
+        Expr::In { expr, list, .. } => {
+            resolve_expr_subqueries(tables, expr)?;
+            for item in list {
+                resolve_expr_subqueries(tables, item)?;
+            }
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            for item in [expr, low, high] {
+                resolve_expr_subqueries(tables, item)?;
+            }
+        }
+        Expr::Exists { query, .. } => {
+            // Only emptiness matters, so the subquery stops at its first row
+            let mut query = query.as_ref().clone();
+            query.limit = Some(1);
+            let found = !select(tables, &query, &mut StatementStats::default())?.is_empty();
+            *expr = Expr::Literal(bool_value(Some(found)));
+        }
+        Expr::Unary { expr, .. } | Expr::Collate { expr, .. } => {
+            resolve_expr_subqueries(tables, expr)?
+        }
+        Expr::Case {
+            operand,
+            branches,
+            else_result,
+        } => {
+            let branch_exprs = branches.iter_mut().flat_map(|(when, then)| [when, then]);
+            for item in operand
+                .iter_mut()
+                .map(|e| e.as_mut())
+                .chain(branch_exprs)
+                .chain(else_result.iter_mut().map(|e| e.as_mut()))
+            {
+                resolve_expr_subqueries(tables, item)?;
+            }
+        }
+        Expr::InSelect {
+            expr: left,
+            query,
+            negated,
+            ..
+        } => {
+            resolve_expr_subqueries(tables, left)?;
+            let list = subquery_values(tables, query)?
+                .into_iter()
+                .map(Expr::Literal)
+                .collect();
+            let left = std::mem::replace(left, Box::new(Expr::Literal(Value::Null)));
+            *expr = Expr::In {
+                expr: left,
+                list,
+                negated: *negated,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Runs a subquery and collects its single output column.
+fn subquery_values(tables: &mut [Table], query: &Statement) -> io::Result<Vec<Value>> {
+    let width = output_width(tables, query)?;
+    if width != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("sub-select returns {} columns - expected 1", width),
+        ));
+    }
+    let rows = select(tables, query, &mut StatementStats::default())?;
+    Ok(rows.into_iter().flatten().collect())
+}
+
+fn qualify_row(table_name: &str, row: Row) -> Row {
+    let mut qualified = Row::new();
+    for (column, value) in row.values {
+        qualified
+            .values
+            .insert(format!("{}.{}", table_name, column), value);
+    }
+    qualified
+}
+
+/// Writes an INSERT's rows in order, stopping at the first that fails.
+/// Returns how many rows were written. Their RETURNING values, and any
+/// notes about them, go into the result.
+fn write_insert_rows(
+    table: &mut Table,
+    statement: &Statement,
+    rows: Vec<Row>,
+    returning: &[Expr],
+    result: &mut ExecResult,
+) -> io::Result<usize> {
+    result.columns = returning
+        .iter()
+        .map(|expr| match expr {
+            Expr::Column(column) => column.column.clone(),
+            _ => expr.to_string(),
+        })
+        .collect();
+    let mut inserted = 0;
+    for row in rows {
+        let (note, row) = match &statement.on_conflict {
+            Some(on_conflict) => upsert(table, row, on_conflict)?,
+            None => insert_row(table, row)?,
+        };
+        if let Some(row) = &row {
+            if !returning.is_empty() {
+                result.rows.push(project(row, returning)?);
+            }
+            inserted += 1;
+        }
+        if let Some(note) = note.filter(|_| statement.query.is_none()) {
+            result.messages.push(note);
+        }
+    }
+    Ok(inserted)
+}
+
+/// Inserts a row, returning a note worth printing about it, if any, and
+/// the row as written.
+fn insert_row(table: &mut Table, mut row: Row) -> io::Result<(Option<String>, Option<Row>)> {
+    let note = table
+        .insert(&mut row)?
+        .map(|key| format!("Inserted (id {})", key));
+    Ok((note, Some(row)))
+}
+
+/// Like `insert_row`, but resolves a uniqueness conflict according to the
+/// statement's policy. No row is returned when the insert was ignored.
+fn upsert(
+    table: &mut Table,
+    mut row: Row,
+    on_conflict: &OnConflict,
+) -> io::Result<(Option<String>, Option<Row>)> {
+    // Coerced first, so `'5'` collides with an existing 5
+    table.apply_types(&mut row)?;
+    let conflict = match table.find_conflict(&row, None)? {
+        Some(conflict) => conflict,
+        None => return insert_row(table, row),
+    };
+
+    match on_conflict {
+        OnConflict::DoNothing => Ok((Some("Ignored (row already exists)".to_string()), None)),
+        OnConflict::Replace => {
+            table.assign_autoincrement(&mut row)?;
+            table.replace_row(conflict.row_num, &mut row)?;
+            Ok((Some("Replaced".to_string()), Some(row)))
+        }
+        OnConflict::DoUpdate {
+            target,
+            assignments,
+        } => {
+            if !target.is_empty() && !target.contains(&conflict.column) {
+                // The collision is not the one the statement handles
+                return Err(table.unique_violation(&conflict));
+            }
+
+            // Every assignment sees the existing row as it was, and the
+            // row that was proposed as `excluded.col`
+            let mut existing = table.row_slot(conflict.row_num)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "conflicting row disappeared")
+            })?;
+            let mut context = existing.clone();
+            for (column, value) in row.values {
+                context.values.insert(format!("excluded.{}", column), value);
+            }
+            let mut updates = Vec::new();
+            for (column, expr) in assignments {
+                if !table.columns.iter().any(|c| &c.name == column) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("no such column: {}", column),
+                    ));
+                }
+                let value = evaluate(expr, &context)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                updates.push((column.clone(), value));
+            }
+            for (column, value) in updates {
+                existing.values.insert(column, value);
+            }
+
+            table.replace_row(conflict.row_num, &mut existing)?;
+            Ok((Some("Updated existing row".to_string()), Some(existing)))
+        }
+    }
+}
+
+/// Writes an UPDATE's rows, given by their row numbers before any was
+/// written. A row moved to keep key order shifts the rows between its old
+/// and new places, so the numbers still to come are shifted to match.
+fn write_updates(table: &mut Table, updates: Vec<(usize, Row)>) -> io::Result<()> {
+    let mut moves: Vec<(usize, usize)> = Vec::new();
+    for (row_num, mut row) in updates {
+        let row_num = moves.iter().fold(row_num, |n, &(from, to)| {
+            let n = if n > from { n - 1 } else { n };
+            if n >= to {
+                n + 1
+            } else {
+                n
+            }
+        });
+        let new_row_num = table.replace_row(row_num, &mut row)?;
+        if new_row_num != row_num {
+            moves.push((row_num, new_row_num));
+        }
+    }
+    Ok(())
+}
+
+/// The rows, by row number, that an UPDATE or DELETE's WHERE clause
+/// matches, found through an index when it allows.
+fn matching_rows(
+    table: &mut Table,
+    statement: &Statement,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<(usize, Row)>> {
+    let refs = statement
+        .where_clause
+        .iter()
+        .flat_map(|e| e.column_refs())
+        .collect::<Vec<_>>();
+    check_qualifiers(&[&table.name], refs.iter().copied())?;
+    check_columns_exist(&[table], refs.into_iter())?;
+
+    let mut where_clause = statement.where_clause.clone();
+    if let Some(condition) = &mut where_clause {
+        collate_columns(condition, &[table]);
+    }
+    let row_nums = match planner::plan_access(table, &where_clause).row_nums(table)? {
+        Some(row_nums) => {
+            stats.index_used = true;
+            row_nums
+        }
+        None => {
+            stats.table_scans += 1;
+            (0..table.num_rows).collect()
+        }
+    };
+    let mut rows = Vec::new();
+    for row_num in row_nums {
+        let row = match table.row_slot(row_num)? {
+            Some(row) => row,
+            None => continue,
+        };
+        stats.rows_examined += 1;
+        if matches_where_clause(&row, &where_clause)? {
+            stats.rows_matched += 1;
+            rows.push((row_num, row));
+        }
+    }
+    Ok(rows)
+}
+
+/// The new contents of every row an UPDATE matches, by row number. All of
+/// them are computed before any is written, so each SET expression sees
+/// the row as it was and an error in any row leaves the table untouched.
+fn update_rows(
+    table: &mut Table,
+    statement: &Statement,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<(usize, Row)>> {
+    for (column, _) in &statement.assignments {
+        if !table.columns.iter().any(|c| &c.name == column) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no such column: {}", column),
+            ));
+        }
+    }
+    let refs = statement
+        .assignments
+        .iter()
+        .flat_map(|(_, expr)| expr.column_refs())
+        .collect::<Vec<_>>();
+    check_qualifiers(&[&table.name], refs.iter().copied())?;
+    check_columns_exist(&[table], refs.into_iter())?;
+
+    let mut updates = matching_rows(table, statement, stats)?;
+    for (_, row) in &mut updates {
+        let old = row.clone();
+        for (column, expr) in &statement.assignments {
+            let value =
+                evaluate(expr, &old).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            row.values.insert(column.clone(), value);
+        }
+        table.apply_types(row)?;
+    }
+    Ok(updates)
+}
+
+/// Rejects a reference to a column that none of the tables a statement
+/// reads has, or that the table it is qualified with lacks. The error
+/// suggests the closest column name when one is near enough to be a typo.
+pub fn check_columns_exist<'a>(
+    tables: &[&Table],
+    columns: impl Iterator<Item = &'a ColumnRef>,
+) -> io::Result<()> {
+    for column in columns {
+        if column.is_star() {
+            continue;
+        }
+        let candidates: Vec<&ColumnDef> = tables
+            .iter()
+            .filter(|t| {
+                column
+                    .table
+                    .as_ref()
+                    .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(&t.name))
+            })
+            .flat_map(|t| &t.columns)
+            .collect();
+        if candidates.iter().any(|c| c.name == column.column) {
+            continue;
+        }
+        let suggestion = candidates
+            .iter()
+            .map(|c| (edit_distance(&c.name, &column.column), &c.name))
+            .filter(|&(distance, _)| distance <= 2 && distance < column.column.chars().count())
+            .min_by_key(|&(distance, _)| distance);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            match suggestion {
+                Some((_, name)) => format!("no such column: {} (did you mean {}?)", column, name),
+                None => format!("no such column: {}", column),
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// The Levenshtein distance between two strings: how many characters must
+/// be inserted, deleted or replaced to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(ca != *cb);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Rejects column references qualified with a table other than the ones
+/// the statement reads.
+fn check_qualifiers<'a>(
+    table_names: &[&str],
+    columns: impl Iterator<Item = &'a ColumnRef>,
+) -> io::Result<()> {
+    for column in columns {
+        match &column.table {
+            Some(table) if !table_names.iter().any(|t| t.eq_ignore_ascii_case(table)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no such column: {}", column),
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn build_insert_row(table: &Table, columns: &[String], values: &[Value]) -> io::Result<Row> {
+    // Without a column list, values fill the table's columns in order
+    let columns: Vec<String> = if columns.is_empty() {
+        if values.len() > table.columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "table {} has {} columns but {} values were supplied",
+                    table.name,
+                    table.columns.len(),
+                    values.len()
+                ),
+            ));
+        }
+        table.columns.iter().map(|c| c.name.clone()).collect()
+    } else {
+        for (i, column) in columns.iter().enumerate() {
+            if !table.columns.iter().any(|c| &c.name == column) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("table {} has no column named {}", table.name, column),
+                ));
+            }
+            if columns[..i].contains(column) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("column {} is listed more than once", column),
+                ));
+            }
+        }
+        columns.to_vec()
+    };
+
+    let mut row = Row::new();
+    for (column, value) in columns.iter().zip(values.iter()) {
+        row.values.insert(column.clone(), value.clone());
+    }
+
+    // Columns the statement left out take their declared default, or NULL
+    for column in &table.columns {
+        if !row.values.contains_key(&column.name) {
+            let value = match &column.default {
+                Some(default) => evaluate(default, &Row::new())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                None => Value::Null,
+            };
+            row.values.insert(column.name.clone(), value);
+        }
+    }
+
+    Ok(row)
+}
+
+/// Runs a SELECT with aggregates or GROUP BY in a single pass. Every row
+/// matching the WHERE clause is bucketed by its GROUP BY values and feeds
+/// that group's aggregate calls; each group then makes one output row, in
+/// the order groups were first seen. Without GROUP BY all rows form one
+/// group, which exists even when no row matches. Groups failing the HAVING
+/// condition are left out. Columns outside both an aggregate call and the
+/// GROUP BY have no single value to output or test, so they are rejected.
+fn aggregate_rows(
+    table: &mut Table,
+    statement: &Statement,
+    stats: &mut StatementStats,
+) -> io::Result<Vec<Vec<Value>>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    if let Some(call) = statement.group_by.iter().find(|e| contains_aggregate(e)) {
+        return Err(invalid(format!(
+            "aggregate functions are not allowed in GROUP BY: {}",
+            call
+        )));
+    }
+    let outputs: Vec<&Expr> = statement
+        .projection
+        .iter()
+        .chain(sort_exprs(statement))
+        .collect();
+    // HAVING is checked like an output value, though it is not one
+    let mut calls: Vec<Expr> = Vec::new();
+    for output in outputs.iter().copied().chain(statement.having.iter()) {
+        let mut output = output.clone();
+        replace_aggregates(&mut output, &mut |call| {
+            if let Expr::Function { args, .. } = call {
+                for arg in args {
+                    replace_aggregates(&mut arg.clone(), &mut |inner| {
+                        Err(invalid(format!(
+                            "misuse of aggregate function {}()",
+                            function_name(inner)
+                        )))
+                    })?;
+                }
+            }
+            if !calls.contains(call) {
+                calls.push(call.clone());
+            }
+            Ok(Value::Null)
+        })?;
+        let nulls = vec![Value::Null; statement.group_by.len()];
+        replace_group_exprs(&mut output, &statement.group_by, &nulls);
+        if let Some(column) = output.column_refs().first() {
+            return Err(invalid(if statement.group_by.is_empty() {
+                format!(
+                    "column {} must appear in an aggregate function without GROUP BY",
+                    column
+                )
+            } else {
+                format!(
+                    "column {} must appear in the GROUP BY clause or be used in an aggregate function",
+                    column
+                )
+            }));
+        }
+    }
+    let accumulators = || {
+        calls
+            .iter()
+            .map(Accumulator::new)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)
+    };
+
+    // Each group's GROUP BY values and aggregate state, found by the
+    // canonical encoding of those values
+    let mut groups: Vec<(Vec<Value>, Vec<Accumulator>)> = Vec::new();
+    let mut group_index: HashMap<Vec<u8>, usize> = HashMap::new();
+    if statement.group_by.is_empty() {
+        groups.push((Vec::new(), accumulators()?));
+        group_index.insert(Vec::new(), 0);
+    }
+
+    let needed: Vec<String> = statement
+        .where_clause
+        .iter()
+        .chain(&statement.group_by)
+        .chain(&calls)
+        .flat_map(|e| e.column_refs())
+        .map(|c| c.column.clone())
+        .collect();
+    let mut cursor = Cursor::table_start(table)?;
+    stats.table_scans += 1;
+    while !cursor.end_of_table {
+        if let Some(row) = cursor.partial_value(Some(&needed))? {
+            stats.rows_examined += 1;
+            if matches_where_clause(&row, &statement.where_clause)? {
+                stats.rows_matched += 1;
+                let values = statement
+                    .group_by
+                    .iter()
+                    .map(|e| evaluate(e, &row))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(invalid)?;
+                let mut key = Vec::new();
+                for (value, expr) in values.iter().zip(&statement.group_by) {
+                    let collation = collation_of(expr).unwrap_or_default();
+                    collation.fold(value).encode_key(&mut key);
+                }
+                let index = match group_index.get(&key) {
+                    Some(&index) => index,
+                    None => {
+                        groups.push((values, accumulators()?));
+                        group_index.insert(key, groups.len() - 1);
+                        groups.len() - 1
+                    }
+                };
+                for (call, accumulator) in calls.iter().zip(&mut groups[index].1) {
+                    let arg = match call {
+                        Expr::Function { args, .. } => &args[0],
+                        _ => unreachable!("aggregate calls are functions"),
+                    };
+                    let value = if arg.is_star() {
+                        Value::Integer(1)
+                    } else {
+                        evaluate(arg, &row).map_err(invalid)?
+                    };
+                    accumulator.add(&value).map_err(invalid)?;
+                }
+            }
+        }
+        cursor.advance()?;
+    }
+
+    let mut rows = Vec::with_capacity(groups.len());
+    for (values, accumulators) in &groups {
+        // An expression over the group's GROUP BY values and aggregates
+        let group_value = |expr: &Expr| -> io::Result<Value> {
+            let mut expr = expr.clone();
+            replace_aggregates(&mut expr, &mut |call| {
+                let index = calls.iter().position(|c| c == call).unwrap_or_default();
+                Ok(accumulators[index].finish())
+            })?;
+            replace_group_exprs(&mut expr, &statement.group_by, values);
+            evaluate(&expr, &Row::new()).map_err(invalid)
+        };
+        if let Some(having) = &statement.having {
+            // Groups are only kept when the condition is definitely true
+            if truth(&group_value(having)?) != Some(true) {
+                continue;
+            }
+        }
+        let row = outputs
+            .iter()
+            .map(|output| group_value(output))
+            .collect::<io::Result<Vec<_>>>()?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Replaces each part of an expression that is one of the GROUP BY
+/// expressions with that expression's value for the group.
+fn replace_group_exprs(expr: &mut Expr, group_by: &[Expr], values: &[Value]) {
+    // `GROUP BY x COLLATE nocase` still provides the value of `x`
+    if let Some(index) = group_by
+        .iter()
+        .position(|e| strip_collate(e) == strip_collate(expr))
+    {
+        *expr = Expr::Literal(values[index].clone());
+        return;
+    }
+    for child in expr.children_mut() {
+        replace_group_exprs(child, group_by, values);
+    }
+}
+
+/// Replaces every aggregate call in an expression with the value `value`
+/// gives for it. Calls nested in another call's arguments are left alone.
+fn replace_aggregates(
+    expr: &mut Expr,
+    value: &mut dyn FnMut(&Expr) -> io::Result<Value>,
+) -> io::Result<()> {
+    if aggregates::is_aggregate(expr) {
+        *expr = Expr::Literal(value(expr)?);
+        return Ok(());
+    }
+    for child in expr.children_mut() {
+        replace_aggregates(child, value)?;
+    }
+    Ok(())
+}
+
+fn contains_aggregate(expr: &Expr) -> bool {
+    let mut found = false;
+    let _ = replace_aggregates(&mut expr.clone(), &mut |_| {
+        found = true;
+        Ok(Value::Null)
+    });
+    found
+}
+
+fn function_name(expr: &Expr) -> &str {
+    match expr {
+        Expr::Function { name, .. } => name,
+        _ => "",
+    }
+}
+
+fn is_count_star(expr: &Expr) -> bool {
+    matches!(expr, Expr::Function { name, args, .. } if name == "count" && args.len() == 1 && args[0].is_star())
+}
+
+/// Answers `count(*)`. Without a WHERE clause the table's row count is
+/// enough and no page is read; otherwise only the columns the condition
+/// uses are deserialized.
+fn count_rows(
+    table: &mut Table,
+    where_clause: &Option<Expr>,
+    stats: &mut StatementStats,
+) -> io::Result<usize> {
+    let condition = match where_clause {
+        Some(condition) => condition,
+        None => return Ok(table.num_rows),
+    };
+    let needed: Vec<String> = condition
+        .column_refs()
+        .into_iter()
+        .map(|c| c.column.clone())
+        .collect();
+
+    let mut count = 0;
+    if let Some(row_nums) = planner::plan_access(table, where_clause).row_nums(table)? {
+        stats.index_used = true;
+        for row_num in row_nums {
+            if let Some(row) = table.read_row(row_num, Some(&needed))? {
+                stats.rows_examined += 1;
+                if matches_where_clause(&row, where_clause)? {
+                    stats.rows_matched += 1;
+                    count += 1;
+                }
+            }
+        }
+        return Ok(count);
+    }
+    let mut cursor = Cursor::table_start(table)?;
+    stats.table_scans += 1;
+    while !cursor.end_of_table {
+        if let Some(row) = cursor.partial_value(Some(&needed))? {
+            stats.rows_examined += 1;
+            if matches_where_clause(&row, where_clause)? {
+                stats.rows_matched += 1;
+                count += 1;
+            }
+        }
+        cursor.advance()?;
+    }
+    Ok(count)
+}
+
+pub fn matches_where_clause(row: &Row, where_clause: &Option<Expr>) -> io::Result<bool> {
+    match where_clause {
+        Some(condition) => matches_condition(row, condition),
+        None => Ok(true),
+    }
+}
+
+fn matches_condition(row: &Row, condition: &Expr) -> io::Result<bool> {
+    let value =
+        evaluate(condition, row).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // Rows are only kept when the condition is definitely true
+    Ok(truth(&value) == Some(true))
+}
+
+pub fn evaluate(expr: &Expr, row: &Row) -> Result<Value, String> {
+    match expr {
+        Expr::Column(column) => column_value(row, column),
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Param(number) => Err(format!("no value bound to parameter ?{}", number)),
+        Expr::Tuple(_) => Err("row value misused".to_string()),
+        Expr::Collate { expr, .. } => evaluate(expr, row),
+        Expr::Binary { left, op, right } => match op {
+            BinaryOp::And => {
+                let left = truth(&evaluate(left, row)?);
+                if left == Some(false) {
+                    return Ok(bool_value(Some(false)));
+                }
+                let right = truth(&evaluate(right, row)?);
+                Ok(bool_value(match (left, right) {
+                    (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }))
+            }
+            BinaryOp::Or => {
+                let left = truth(&evaluate(left, row)?);
+                if left == Some(true) {
+                    return Ok(bool_value(Some(true)));
+                }
+                let right = truth(&evaluate(right, row)?);
+                Ok(bool_value(match (left, right) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }))
+            }
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+                arithmetic(evaluate(left, row)?, *op, evaluate(right, row)?)
+            }
+            BinaryOp::Like | BinaryOp::NotLike => {
+                let (value, pattern) = match (evaluate(left, row)?, evaluate(right, row)?) {
+                    (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+                    (value, pattern) => (text_of(&value), text_of(&pattern)),
+                };
+                let matched = functions::like_matches(&value, &pattern, None);
+                Ok(bool_value(Some(matched != (*op == BinaryOp::NotLike))))
+            }
+            BinaryOp::Concat => match (evaluate(left, row)?, evaluate(right, row)?) {
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+                (left, right) => Ok(Value::Text(text_of(&left) + &text_of(&right))),
+            },
+            _ => Ok(bool_value(compare_rows(left, *op, right, row)?)),
+        },
+        Expr::Unary {
+            op: UnaryOp::Neg,
+            expr,
+        } => match evaluate(expr, row)? {
+            Value::Null => Ok(Value::Null),
+            Value::Integer(i) => i
+                .checked_neg()
+                .map(Value::Integer)
+                .ok_or_else(|| "integer overflow".to_string()),
+            Value::Text(_) => Err("cannot negate a text value".to_string()),
+        },
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => {
+            // NOT of an unknown result stays unknown
+            let inner = truth(&evaluate(expr, row)?);
+            Ok(bool_value(inner.map(|b| !b)))
+        }
+        Expr::Function { name, .. } if aggregates::is_aggregate(expr) => {
+            Err(format!("misuse of aggregate function {}()", name))
+        }
+        Expr::Function { distinct: true, .. } => {
+            Err("DISTINCT is only allowed in aggregate functions".to_string())
+        }
+        Expr::Function { name, args, .. } => {
+            let function = functions::lookup(name, args.len())?;
+            let args = args
+                .iter()
+                .map(|arg| evaluate(arg, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            function.invoke(&args)
+        }
+        Expr::InSelect { .. } | Expr::Exists { .. } => {
+            Err("subqueries are not supported here".to_string())
+        }
+        Expr::Case {
+            operand,
+            branches,
+            else_result,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, row)?),
+                None => None,
+            };
+            for (when, then) in branches {
+                let when = evaluate(when, row)?;
+                let matched = match &operand {
+                    // NULL never equals anything, so it falls through to ELSE
+                    Some(operand) => compare_values(operand, &when) == Some(Ordering::Equal),
+                    None => truth(&when) == Some(true),
+                };
+                if matched {
+                    return evaluate(then, row);
+                }
+            }
+            match else_result {
+                Some(else_result) => evaluate(else_result, row),
+                None => Ok(Value::Null),
+            }
+        }
+        Expr::In {
+            expr,
+            list,
+            negated,
+        } => {
+            // True on any match, unknown if a NULL prevented a definite
+            // answer, false otherwise. NOT IN inverts that, so it is also
+            // unknown rather than true when a NULL is involved.
+            let mut result = Some(false);
+            for item in list {
+                match compare_rows(expr, BinaryOp::Eq, item, row)? {
+                    Some(true) => {
+                        result = Some(true);
+                        break;
+                    }
+                    Some(false) => {}
+                    None => result = None,
+                }
+            }
+            Ok(bool_value(result.map(|found| found != *negated)))
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => {
+            let above = compare_rows(expr, BinaryOp::GtEq, low, row)?;
+            let below = compare_rows(expr, BinaryOp::LtEq, high, row)?;
+            let result = match (above, below) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            };
+            Ok(bool_value(result.map(|inside| inside != *negated)))
+        }
+    }
+}
+
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Null => String::new(),
+    }
+}
+
+/// Looks a column up in a row. Rows from a single table key their values
+/// by bare column name; rows from a join key them by `table.column`. A
+/// qualified reference prefers a qualified key, so an upsert's
+/// `excluded.col` is not mistaken for the existing row's `col`.
+fn column_value(row: &Row, column: &ColumnRef) -> Result<Value, String> {
+    if column.table.is_none() {
+        if let Some(value) = row.values.get(&column.column) {
+            return Ok(value.clone());
+        }
+    }
+
+    let mut matches = row
+        .values
+        .iter()
+        .filter(|(key, _)| match key.split_once('.') {
+            Some((table, name)) => {
+                name == column.column
+                    && column
+                        .table
+                        .as_ref()
+                        .is_none_or(|qualifier| qualifier.eq_ignore_ascii_case(table))
+            }
+            None => false,
+        });
+    match (matches.next(), matches.next()) {
+        (Some((_, value)), None) => Ok(value.clone()),
+        (Some(_), Some(_)) => Err(format!("ambiguous column name: {}", column)),
+        _ => row
+            .values
+            .get(&column.column)
+            .cloned()
+            .ok_or_else(|| format!("no such column: {}", column)),
+    }
+}
+
+/// Applies an arithmetic operator. NULL in gives NULL out; overflow and
+/// division by zero are errors rather than wrapping or panicking.
+fn arithmetic(left: Value, op: BinaryOp, right: Value) -> Result<Value, String> {
+    let (l, r) = match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        (Value::Integer(l), Value::Integer(r)) => (l, r),
+        _ => return Err(format!("cannot apply {} to a text value", op.symbol())),
+    };
+
+    if matches!(op, BinaryOp::Div | BinaryOp::Mod) && r == 0 {
+        return Err("division by zero".to_string());
+    }
+    let result = match op {
+        BinaryOp::Add => l.checked_add(r),
+        BinaryOp::Sub => l.checked_sub(r),
+        BinaryOp::Mul => l.checked_mul(r),
+        BinaryOp::Div => l.checked_div(r),
+        BinaryOp::Mod => l.checked_rem(r),
+        _ => unreachable!("not an arithmetic operator"),
+    };
+    result
+        .map(Value::Integer)
+        .ok_or_else(|| "integer overflow".to_string())
+}
+
+/// Compares two operands that are either scalars or row values of the same
+/// arity. Row values compare lexicographically, and a NULL element makes
+/// the result unknown unless an earlier element already decided it.
+fn compare_rows(
+    left: &Expr,
+    op: BinaryOp,
+    right: &Expr,
+    row: &Row,
+) -> Result<Option<bool>, String> {
+    let mut left_values = row_value(left, row)?;
+    let mut right_values = row_value(right, row)?;
+    if left_values.len() != right_values.len() {
+        return Err("row value misused".to_string());
+    }
+    // A column's value decides how the other side is read, as it would be
+    // stored in that column
+    for ((l_expr, l), (r_expr, r)) in operands(left)
+        .iter()
+        .zip(left_values.iter_mut())
+        .zip(operands(right).iter().zip(right_values.iter_mut()))
+    {
+        match (is_column(l_expr), is_column(r_expr)) {
+            (true, false) => *r = coerce_like(r, l),
+            (false, true) => *l = coerce_like(l, r),
+            _ => {}
+        }
+        let collation = comparison_collation(l_expr, r_expr);
+        if collation != Collation::Binary {
+            *l = collation.fold(l);
+            *r = collation.fold(r);
+        }
+    }
+    let (left, right) = (left_values, right_values);
+
+    if matches!(op, BinaryOp::Eq | BinaryOp::NotEq) {
+        let mut result = Some(true);
+        for (l, r) in left.iter().zip(right.iter()) {
+            match compare_values(l, r) {
+                Some(Ordering::Equal) => {}
+                Some(_) => {
+                    result = Some(false);
+                    break;
+                }
+                None => result = None,
+            }
+        }
+        return Ok(if op == BinaryOp::Eq {
+            result
+        } else {
+            result.map(|equal| !equal)
+        });
+    }
+
+    for (l, r) in left.iter().zip(right.iter()) {
+        match compare_values(l, r) {
+            Some(Ordering::Equal) => continue,
+            Some(ordering) => return Ok(Some(ordering_matches(ordering, op))),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(ordering_matches(Ordering::Equal, op)))
+}
+
+/// Whether an expression reads a column, possibly through COLLATE.
+fn is_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Column(_) => true,
+        Expr::Collate { expr, .. } => is_column(expr),
+        _ => false,
+    }
+}
+
+/// Checks that an expression gives the same value for the same row every
+/// time it is evaluated, as an index on it needs.
+pub fn check_indexable(expr: &Expr) -> Result<(), String> {
+    match expr {
+        Expr::Param(_) => return Err("parameters are not allowed in an index".to_string()),
+        Expr::InSelect { .. } | Expr::Exists { .. } => {
+            return Err("subqueries are not allowed in an index".to_string())
+        }
+        Expr::Function { name, .. } if aggregates::is_aggregate(expr) => {
+            return Err(format!("misuse of aggregate function {}()", name))
+        }
+        Expr::Function { name, args, .. }
+            if !functions::lookup(name, args.len())?.is_deterministic() =>
+        {
+            return Err(format!(
+                "non-deterministic function {}() is not allowed in an index",
+                name.to_lowercase()
+            ))
+        }
+        _ => {}
+    }
+    expr.children().into_iter().try_for_each(check_indexable)
+}
+
+/// Drops the table qualifier from every column an expression refers to,
+/// and lowercases the functions it calls, so that an indexed expression
+/// reads a row by column name alone and equals the same expression written
+/// another way in a WHERE clause.
+pub fn canonicalize(expr: &mut Expr) {
+    match expr {
+        Expr::Column(column) => column.table = None,
+        Expr::Function { name, .. } => *name = name.to_lowercase(),
+        _ => {}
+    }
+    for child in expr.children_mut() {
+        canonicalize(child);
+    }
+}
+
+/// The expression inside any COLLATE wrapped around it.
+pub fn strip_collate(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Collate { expr, .. } => strip_collate(expr),
+        _ => expr,
+    }
+}
+
+/// The collation of an expression's outermost COLLATE, if it has one.
+pub fn collation_of(expr: &Expr) -> Option<Collation> {
+    match expr {
+        Expr::Collate { collation, .. } => Some(*collation),
+        _ => None,
+    }
+}
+
+/// Two operands compare by the left one's collation, else the right one's,
+/// else bytewise.
+pub fn comparison_collation(left: &Expr, right: &Expr) -> Collation {
+    collation_of(left)
+        .or(collation_of(right))
+        .unwrap_or_default()
+}
+
+/// Like `compare_values`, but comparing text as the collation says.
+pub fn compare_collated(left: &Value, right: &Value, collation: Collation) -> Option<Ordering> {
+    match collation {
+        Collation::Binary => compare_values(left, right),
+        _ => compare_values(&collation.fold(left), &collation.fold(right)),
+    }
+}
+
+/// The scalar expressions making up an operand of `compare_rows`.
+fn operands(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Tuple(items) => items.iter().collect(),
+        _ => vec![expr],
+    }
+}
+
+fn row_value(expr: &Expr, row: &Row) -> Result<Vec<Value>, String> {
+    match expr {
+        Expr::Tuple(items) => items.iter().map(|item| evaluate(item, row)).collect(),
+        _ => Ok(vec![evaluate(expr, row)?]),
+    }
+}
+
+fn ordering_matches(ordering: Ordering, op: BinaryOp) -> bool {
+    match op {
+        BinaryOp::Eq => ordering == Ordering::Equal,
+        BinaryOp::NotEq => ordering != Ordering::Equal,
+        BinaryOp::Lt => ordering == Ordering::Less,
+        BinaryOp::LtEq => ordering != Ordering::Greater,
+        BinaryOp::Gt => ordering == Ordering::Greater,
+        BinaryOp::GtEq => ordering != Ordering::Less,
+        _ => false,
+    }
+}
+
+/// Orders two values, or returns None when either is NULL. Integers sort
+/// before text.
+pub fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Integer(l), Value::Integer(r)) => Some(l.cmp(r)),
+        (Value::Text(l), Value::Text(r)) => Some(l.cmp(r)),
+        (Value::Integer(_), Value::Text(_)) => Some(Ordering::Less),
+        (Value::Text(_), Value::Integer(_)) => Some(Ordering::Greater),
+    }
+}
+
+/// Converts a value for a column of the given type. Text converts to an
+/// integer only when it spells one, such as `'42'` or `'-7'`; an integer
+/// always converts to its decimal text. None when the text is not a
+/// number. NULL is left alone.
+pub fn coerce(value: &Value, data_type: DataType) -> Option<Value> {
+    match (value, data_type) {
+        (Value::Text(s), DataType::Integer) => s.parse().ok().map(Value::Integer),
+        (Value::Integer(i), DataType::Text) => Some(Value::Text(i.to_string())),
+        _ => Some(value.clone()),
+    }
+}
+
+/// Reads `value` as the type of a column value it is compared with, or
+/// leaves it as is when it does not convert, so `id = 'abc'` is false.
+fn coerce_like(value: &Value, column_value: &Value) -> Value {
+    let data_type = match column_value {
+        Value::Integer(_) => DataType::Integer,
+        Value::Text(_) => DataType::Text,
+        Value::Null => return value.clone(),
+    };
+    coerce(value, data_type).unwrap_or_else(|| value.clone())
+}
+
+/// Interprets a value as a condition result: NULL is unknown, integers are
+/// true when non-zero.
+pub fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        Value::Integer(i) => Some(*i != 0),
+        Value::Text(s) => Some(s.parse::<i64>().is_ok_and(|i| i != 0)),
+    }
+}
+
+fn bool_value(result: Option<bool>) -> Value {
+    match result {
+        Some(b) => Value::Integer(b as i64),
+        None => Value::Null,
+    }
+}
+
+/// Evaluates a projection over a row.
+/// Evaluates a projection against a row. `*` must already have been
+/// replaced by `expand_stars`.
+fn project(row: &Row, projection: &[Expr]) -> io::Result<Vec<Value>> {
+    projection
+        .iter()
+        .map(|expr| evaluate(expr, row))
+        .collect::<Result<_, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
@@ -0,0 +1,553 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::sql_parser::Value;
+
+/// A scalar function callable from expressions. Arguments are evaluated
+/// before the call, so implementations only ever see values.
+pub struct ScalarFunction {
+    pub name: &'static str,
+    pub min_args: usize,
+    pub max_args: usize,
+    pub call: fn(&[Value]) -> Result<Value, String>,
+    /// False when a call may return something different each time, as
+    /// random() does and date('now') will tomorrow.
+    pub deterministic: bool,
+}
+
+/// A function added with `Database::register_function`.
+pub type UserFunction = Rc<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+/// The functions registered on one database, by lowercase name and number
+/// of arguments. They are only visible to expressions while one of that
+/// database's statements runs; see `with_user_functions`.
+#[derive(Clone, Default)]
+pub(crate) struct UserFunctions(Vec<(String, usize, UserFunction)>);
+
+/// A function an expression calls: built in, or registered by the program
+/// embedding the database.
+pub enum Function {
+    BuiltIn(&'static ScalarFunction),
+    User { name: String, call: UserFunction },
+}
+
+/// Every built-in scalar function, looked up by name. Adding a function
+/// only takes an entry here.
+static FUNCTIONS: &[ScalarFunction] = &[
+    ScalarFunction {
+        name: "abs",
+        min_args: 1,
+        max_args: 1,
+        call: abs,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "round",
+        min_args: 1,
+        max_args: 1,
+        call: round,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "min",
+        min_args: 2,
+        max_args: 2,
+        call: min,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "max",
+        min_args: 2,
+        max_args: 2,
+        call: max,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "random",
+        min_args: 0,
+        max_args: 0,
+        call: random,
+        deterministic: false,
+    },
+    ScalarFunction {
+        name: "typeof",
+        min_args: 1,
+        max_args: 1,
+        call: type_of,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "upper",
+        min_args: 1,
+        max_args: 1,
+        call: upper,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "lower",
+        min_args: 1,
+        max_args: 1,
+        call: lower,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "like",
+        min_args: 2,
+        max_args: 3,
+        call: like,
+        deterministic: true,
+    },
+    ScalarFunction {
+        name: "date",
+        min_args: 1,
+        max_args: 1,
+        call: date,
+        deterministic: false,
+    },
+    ScalarFunction {
+        name: "datetime",
+        min_args: 1,
+        max_args: 1,
+        call: datetime,
+        deterministic: false,
+    },
+];
+
+thread_local! {
+    /// State of the session's random number generator. It starts from the
+    /// clock and can be fixed with `PRAGMA random_seed = N` so that runs are
+    /// reproducible.
+    static RANDOM_STATE: Cell<u64> = Cell::new(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0),
+    );
+    /// Set when a function that is not deterministic is called; see
+    /// `take_nondeterministic_call`.
+    static NONDETERMINISTIC_CALL: Cell<bool> = const { Cell::new(false) };
+    /// The functions of the database whose statement is running.
+    static USER_FUNCTIONS: RefCell<Rc<UserFunctions>> = RefCell::default();
+}
+
+pub fn seed_random(seed: i64) {
+    RANDOM_STATE.with(|state| state.set(seed as u64));
+}
+
+/// Whether a function that is not deterministic was called since the last
+/// time this was asked, so the caller can tell whether running the same
+/// query again could give another result.
+pub fn take_nondeterministic_call() -> bool {
+    NONDETERMINISTIC_CALL.with(|called| called.replace(false))
+}
+
+impl UserFunctions {
+    /// Adds a function, replacing any registered before under the same name
+    /// and arity. Built-in functions and aggregates cannot be replaced.
+    pub(crate) fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        call: UserFunction,
+    ) -> Result<(), String> {
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("invalid function name: {}", name));
+        }
+        let name = name.to_lowercase();
+        if is_aggregate_name(&name, arity) || builtin(&name, arity).is_some() {
+            return Err(format!(
+                "cannot replace built-in function {}() with arity {}",
+                name, arity
+            ));
+        }
+        self.0.retain(|(n, a, _)| *n != name || *a != arity);
+        self.0.push((name, arity, call));
+        Ok(())
+    }
+}
+
+/// Runs `f` with a database's registered functions visible to the
+/// expressions it evaluates, and those of any other database hidden.
+pub(crate) fn with_user_functions<T>(functions: &Rc<UserFunctions>, f: impl FnOnce() -> T) -> T {
+    /// Puts back the functions that were visible before, even if `f`
+    /// panics.
+    struct Restore(Rc<UserFunctions>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            USER_FUNCTIONS.with(|current| current.replace(self.0.clone()));
+        }
+    }
+    let previous = USER_FUNCTIONS.with(|current| current.replace(functions.clone()));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// The function a call to `name` with `arity` arguments runs. Registered
+/// functions are checked first. A built-in is found whatever the number of
+/// arguments, and reports a mismatch when invoked.
+pub fn lookup(name: &str, arity: usize) -> Result<Function, String> {
+    let user = USER_FUNCTIONS.with(|functions| {
+        functions
+            .borrow()
+            .0
+            .iter()
+            .find(|(n, a, _)| n.eq_ignore_ascii_case(name) && *a == arity)
+            .map(|(n, _, call)| (n.clone(), call.clone()))
+    });
+    if let Some((name, call)) = user {
+        return Ok(Function::User { name, call });
+    }
+    if let Some(function) = FUNCTIONS.iter().find(|f| f.name.eq_ignore_ascii_case(name)) {
+        return Ok(Function::BuiltIn(function));
+    }
+    let registered = USER_FUNCTIONS.with(|functions| {
+        functions
+            .borrow()
+            .0
+            .iter()
+            .any(|(n, _, _)| n.eq_ignore_ascii_case(name))
+    });
+    if registered {
+        return Err(format!(
+            "wrong number of arguments to function {}()",
+            name.to_lowercase()
+        ));
+    }
+    Err(format!("no such function: {}", name))
+}
+
+/// Whether a call with this name and number of arguments is an aggregate.
+/// `min` and `max` are aggregates with one argument and scalar functions
+/// with two.
+pub fn is_aggregate_name(name: &str, arity: usize) -> bool {
+    match name.to_lowercase().as_str() {
+        "count" | "sum" | "avg" => true,
+        "min" | "max" => arity == 1,
+        _ => false,
+    }
+}
+
+fn builtin(name: &str, arity: usize) -> Option<&'static ScalarFunction> {
+    FUNCTIONS
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name) && (f.min_args..=f.max_args).contains(&arity))
+}
+
+impl Function {
+    /// Whether every call with the same arguments returns the same value.
+    /// Nothing is known of a registered function, so it is assumed not to.
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            Function::BuiltIn(function) => function.deterministic,
+            Function::User { .. } => false,
+        }
+    }
+
+    pub fn invoke(&self, args: &[Value]) -> Result<Value, String> {
+        match self {
+            Function::BuiltIn(function) => function.invoke(args),
+            Function::User { name, call } => {
+                NONDETERMINISTIC_CALL.with(|called| called.set(true));
+                call(args).map_err(|e| format!("{}(): {}", name, e))
+            }
+        }
+    }
+}
+
+impl ScalarFunction {
+    pub fn invoke(&self, args: &[Value]) -> Result<Value, String> {
+        if args.len() < self.min_args || args.len() > self.max_args {
+            return Err(format!(
+                "wrong number of arguments to function {}()",
+                self.name
+            ));
+        }
+        if !self.deterministic {
+            NONDETERMINISTIC_CALL.with(|called| called.set(true));
+        }
+        (self.call)(args)
+    }
+}
+
+/// Extracts integer arguments. Returns None when any argument is NULL,
+/// since every numeric function passes NULL through.
+fn integers(name: &str, args: &[Value]) -> Result<Option<Vec<i64>>, String> {
+    let mut integers = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Value::Integer(i) => integers.push(*i),
+            Value::Null => return Ok(None),
+            Value::Text(_) => return Err(format!("{}() expects a number, not text", name)),
+        }
+    }
+    Ok(Some(integers))
+}
+
+fn abs(args: &[Value]) -> Result<Value, String> {
+    match integers("abs", args)? {
+        // i64::MIN has no positive counterpart
+        Some(i) => i[0]
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or_else(|| "integer overflow".to_string()),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Integers are already round; this will do more once REAL values exist.
+fn round(args: &[Value]) -> Result<Value, String> {
+    Ok(match integers("round", args)? {
+        Some(i) => Value::Integer(i[0]),
+        None => Value::Null,
+    })
+}
+
+fn min(args: &[Value]) -> Result<Value, String> {
+    Ok(match integers("min", args)? {
+        Some(i) => Value::Integer(i[0].min(i[1])),
+        None => Value::Null,
+    })
+}
+
+fn max(args: &[Value]) -> Result<Value, String> {
+    Ok(match integers("max", args)? {
+        Some(i) => Value::Integer(i[0].max(i[1])),
+        None => Value::Null,
+    })
+}
+
+/// Draws the next value from a splitmix64 sequence, which is cheap, has no
+/// bad seeds, and is plenty for sampling and generating test data.
+fn random(_args: &[Value]) -> Result<Value, String> {
+    let next = RANDOM_STATE.with(|state| {
+        let seed = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state.set(seed);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    });
+    Ok(Value::Integer(next as i64))
+}
+
+/// Names the variant a value actually holds, which is not necessarily its
+/// column's declared type.
+fn type_of(args: &[Value]) -> Result<Value, String> {
+    let name = match args[0] {
+        Value::Integer(_) => "integer",
+        Value::Text(_) => "text",
+        Value::Null => "null",
+    };
+    Ok(Value::Text(name.to_string()))
+}
+
+/// Integers are converted to text first; NULL stays NULL.
+fn upper(args: &[Value]) -> Result<Value, String> {
+    Ok(match &args[0] {
+        Value::Integer(i) => Value::Text(i.to_string()),
+        Value::Text(s) => Value::Text(s.to_uppercase()),
+        Value::Null => Value::Null,
+    })
+}
+
+fn lower(args: &[Value]) -> Result<Value, String> {
+    Ok(match &args[0] {
+        Value::Integer(i) => Value::Text(i.to_string()),
+        Value::Text(s) => Value::Text(s.to_lowercase()),
+        Value::Null => Value::Null,
+    })
+}
+
+/// `like(pattern, value [, escape])`, which `value LIKE pattern ESCAPE
+/// escape` is parsed into, as in SQLite.
+fn like(args: &[Value]) -> Result<Value, String> {
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    let escape = match args.get(2) {
+        Some(escape) => {
+            let escape = text(escape);
+            let mut chars = escape.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => return Err("ESCAPE expression must be a single character".to_string()),
+            }
+        }
+        None => None,
+    };
+    let matched = like_matches(&text(&args[1]), &text(&args[0]), escape);
+    Ok(Value::Integer(matched as i64))
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Null => String::new(),
+    }
+}
+
+/// One element of a LIKE pattern.
+#[derive(Clone, Copy, PartialEq)]
+enum PatternChar {
+    /// `%`, any run of characters
+    AnyRun,
+    /// `_`, any single character
+    AnyOne,
+    Literal(char),
+}
+
+/// Matches a LIKE pattern, where `%` is any run of characters and `_` any
+/// single character, and `escape` makes the character after it literal.
+/// As in SQLite, ASCII letters match case-insensitively.
+///
+/// The match is greedy and only ever backtracks to the most recent `%`,
+/// so it takes at most time proportional to the value's length times the
+/// pattern's, however many `%` the pattern has.
+pub fn like_matches(value: &str, pattern: &str, escape: Option<char>) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let mut elements = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        elements.push(match c {
+            c if Some(c) == escape => match chars.next() {
+                Some(escaped) => PatternChar::Literal(escaped),
+                // A trailing escape character escapes nothing, so no value
+                // can match it
+                None => return false,
+            },
+            '%' => PatternChar::AnyRun,
+            '_' => PatternChar::AnyOne,
+            c => PatternChar::Literal(c),
+        });
+    }
+
+    let (mut v, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match elements.get(p) {
+            Some(PatternChar::AnyRun) => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(PatternChar::AnyOne) => {
+                p += 1;
+                v += 1;
+            }
+            Some(PatternChar::Literal(c)) if c.eq_ignore_ascii_case(&value[v]) => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    elements[p..].iter().all(|&e| e == PatternChar::AnyRun)
+}
+
+/// `date('now')` is the current UTC date as `YYYY-MM-DD`; given a date or
+/// datetime string, the date part of it. Anything else is NULL.
+fn date(args: &[Value]) -> Result<Value, String> {
+    Ok(match timestamp(&args[0]) {
+        Some((date, _)) => Value::Text(date),
+        None => Value::Null,
+    })
+}
+
+/// Like `date`, but as `YYYY-MM-DD HH:MM:SS`. A bare date is midnight.
+fn datetime(args: &[Value]) -> Result<Value, String> {
+    Ok(match timestamp(&args[0]) {
+        Some((date, time)) => Value::Text(format!("{} {}", date, time)),
+        None => Value::Null,
+    })
+}
+
+/// Splits `'now'` or an ISO-8601 string into its date and time parts.
+/// Times may be separated by a space or `T` and lose any fractional seconds.
+fn timestamp(value: &Value) -> Option<(String, String)> {
+    let text = match value {
+        Value::Text(text) => text,
+        _ => return None,
+    };
+    if text.eq_ignore_ascii_case("now") {
+        return Some(now_utc());
+    }
+
+    let (date, rest) = text.split_at_checked(10)?;
+    if !matches_pattern(date, "dddd-dd-dd") {
+        return None;
+    }
+    let time = match rest.as_bytes().first() {
+        None => "00:00:00".to_string(),
+        Some(b' ' | b'T') => {
+            let time = &rest[1..];
+            if time
+                .get(..8)
+                .is_some_and(|t| matches_pattern(t, "dd:dd:dd"))
+            {
+                time[..8].to_string()
+            } else if time.len() == 5 && matches_pattern(time, "dd:dd") {
+                format!("{}:00", time)
+            } else {
+                return None;
+            }
+        }
+        Some(_) => return None,
+    };
+    Some((date.to_string(), time))
+}
+
+/// Checks text against a pattern where `d` stands for any ASCII digit.
+fn matches_pattern(text: &str, pattern: &str) -> bool {
+    text.len() == pattern.len()
+        && text.bytes().zip(pattern.bytes()).all(|(c, p)| match p {
+            b'd' => c.is_ascii_digit(),
+            _ => c == p,
+        })
+}
+
+fn now_utc() -> (String, String) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // Converts days since 1970-01-01 to a civil date, counting in 400-year
+    // eras that start on March 1st so that leap days fall at the end
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        ),
+    )
+}
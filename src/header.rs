@@ -0,0 +1,202 @@
+use crate::checksum::CHECKSUM_SIZE;
+
+/// The first bytes of every table file.
+const MAGIC: &[u8; 16] = b"BugDB table file";
+/// The version of the file layout this build reads and writes. Files of
+/// any other version are refused.
+pub const FORMAT_VERSION: u32 = 7;
+
+/// Set when every page ends with a checksum of its contents, as every
+/// page this version writes does. A file without it is refused.
+const CHECKSUMS_FLAG: u32 = 1;
+/// Set in WAL mode, when pages are written to a log before the file.
+const WAL_FLAG: u32 = 2;
+
+const VERSION_OFFSET: usize = 16;
+const PAGE_SIZE_OFFSET: usize = 20;
+const ROW_COUNT_OFFSET: usize = 24;
+const FLAGS_OFFSET: usize = 32;
+const FREE_PAGE_OFFSET: usize = 36;
+const CHANGE_COUNTER_OFFSET: usize = 40;
+/// The schema's length, followed by the schema itself.
+const SCHEMA_LENGTH_OFFSET: usize = 48;
+const SCHEMA_OFFSET: usize = 52;
+
+/// The contents of a table file's first page, which holds no rows: what
+/// the file is, how it is laid out, and the table it holds.
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    pub page_size: usize,
+    pub row_count: usize,
+    pub wal: bool,
+    /// The first page of the free list, or 0 when it is empty. Each free
+    /// page leads on to the next.
+    pub free_page: usize,
+    /// How many times the file has been written to with changes, so a
+    /// tool can tell whether it changed since it last looked.
+    pub change_counter: u64,
+    /// The table's CREATE TABLE statement, left empty when it is too long
+    /// for the page. The catalog is what the table is opened from; this
+    /// lets the file be made sense of on its own.
+    pub schema: String,
+}
+
+impl Header {
+    /// The header of a new file.
+    pub fn new(page_size: usize, row_count: usize, schema: &str) -> Header {
+        Header {
+            page_size,
+            row_count: 0,
+            wal: false,
+            free_page: 0,
+            change_counter: 0,
+            schema: String::new(),
+        }
+        .updated(row_count, schema)
+    }
+
+    /// This header with a new row count and schema, for a file that keeps
+    /// its flags, free list and change counter.
+    pub fn updated(&self, row_count: usize, schema: &str) -> Header {
+        let room = self.page_size - SCHEMA_OFFSET - CHECKSUM_SIZE;
+        Header {
+            page_size: self.page_size,
+            row_count,
+            wal: self.wal,
+            free_page: self.free_page,
+            change_counter: self.change_counter,
+            schema: if schema.len() <= room {
+                schema.to_string()
+            } else {
+                String::new()
+            },
+        }
+    }
+
+    /// Reads a header page, refusing a file that is not a table file, whose
+    /// layout is of another version, or whose pages have no checksums.
+    pub fn read(page: &[u8]) -> Result<Header, String> {
+        if page.len() < SCHEMA_OFFSET || &page[..MAGIC.len()] != MAGIC {
+            return Err("not a BugDB table file".to_string());
+        }
+        let version = read_u32(page, VERSION_OFFSET);
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "file format version {} cannot be read by this version, which reads version {}",
+                version, FORMAT_VERSION
+            ));
+        }
+        let flags = read_u32(page, FLAGS_OFFSET);
+        if flags & CHECKSUMS_FLAG == 0 {
+            return Err("unsupported format: the file's pages have no checksums".to_string());
+        }
+        let schema_length = read_u32(page, SCHEMA_LENGTH_OFFSET) as usize;
+        let schema = page
+            .get(SCHEMA_OFFSET..SCHEMA_OFFSET + schema_length)
+            .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+            .ok_or("the header's schema is corrupt")?;
+        Ok(Header {
+            page_size: read_u32(page, PAGE_SIZE_OFFSET) as usize,
+            row_count: read_u64(page, ROW_COUNT_OFFSET) as usize,
+            wal: flags & WAL_FLAG != 0,
+            free_page: read_u32(page, FREE_PAGE_OFFSET) as usize,
+            change_counter: read_u64(page, CHANGE_COUNTER_OFFSET),
+            schema,
+        })
+    }
+
+    pub fn write(&self, page: &mut [u8]) {
+        page.fill(0);
+        page[..MAGIC.len()].copy_from_slice(MAGIC);
+        write_u32(page, VERSION_OFFSET, FORMAT_VERSION);
+        write_u32(page, PAGE_SIZE_OFFSET, self.page_size as u32);
+        write_u64(page, ROW_COUNT_OFFSET, self.row_count as u64);
+        let mut flags = CHECKSUMS_FLAG;
+        if self.wal {
+            flags |= WAL_FLAG;
+        }
+        write_u32(page, FLAGS_OFFSET, flags);
+        write_u32(page, FREE_PAGE_OFFSET, self.free_page as u32);
+        write_u64(page, CHANGE_COUNTER_OFFSET, self.change_counter);
+        write_u32(page, SCHEMA_LENGTH_OFFSET, self.schema.len() as u32);
+        page[SCHEMA_OFFSET..SCHEMA_OFFSET + self.schema.len()]
+            .copy_from_slice(self.schema.as_bytes());
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 4096;
+
+    #[test]
+    fn header_round_trips_through_a_page() {
+        let mut header = Header::new(PAGE_SIZE, 12, "create table t (id integer)");
+        header.wal = true;
+        header.free_page = 7;
+        header.change_counter = u64::MAX - 1;
+        let mut page = vec![0xFF; PAGE_SIZE];
+        header.write(&mut page);
+        assert_eq!(Header::read(&page).unwrap(), header);
+    }
+
+    #[test]
+    fn updated_keeps_the_flags_free_list_and_counter() {
+        let mut header = Header::new(PAGE_SIZE, 0, "");
+        header.wal = true;
+        header.free_page = 3;
+        header.change_counter = 41;
+        let updated = header.updated(5, "create table u (n integer)");
+        assert_eq!(updated.row_count, 5);
+        assert_eq!(updated.schema, "create table u (n integer)");
+        assert!(updated.wal);
+        assert_eq!((updated.free_page, updated.change_counter), (3, 41));
+    }
+
+    #[test]
+    fn schema_too_long_for_the_page_is_left_out() {
+        let fits = "x".repeat(PAGE_SIZE - SCHEMA_OFFSET - CHECKSUM_SIZE);
+        assert_eq!(Header::new(PAGE_SIZE, 0, &fits).schema, fits);
+        let too_long = format!("{}x", fits);
+        assert_eq!(Header::new(PAGE_SIZE, 0, &too_long).schema, "");
+    }
+
+    #[test]
+    fn other_files_and_versions_are_refused() {
+        let mut page = vec![0; PAGE_SIZE];
+        Header::new(PAGE_SIZE, 0, "").write(&mut page);
+        assert!(Header::read(&page[..SCHEMA_OFFSET - 1]).is_err());
+
+        let mut other_version = page.clone();
+        write_u32(&mut other_version, VERSION_OFFSET, FORMAT_VERSION + 1);
+        assert!(Header::read(&other_version)
+            .unwrap_err()
+            .contains("version"));
+
+        let mut without_checksums = page.clone();
+        write_u32(&mut without_checksums, FLAGS_OFFSET, 0);
+        assert!(Header::read(&without_checksums)
+            .unwrap_err()
+            .contains("unsupported format"));
+
+        page[0] = b'X';
+        assert!(Header::read(&page).is_err());
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, ErrorKind},
+    ops::Bound,
+};
+
+use crate::{
+    executor::evaluate,
+    sql_parser::{Collation, Expr, Row, Value},
+};
+
+/// Maps the values of one column, or of an expression over a row's
+/// columns, to the keys of the rows holding them, so an equality or range
+/// lookup need not scan the table. A row's key is its
+/// PRIMARY KEY value, or the number the table keys it by without one, so
+/// unlike its position it stays the same as other rows come and go. The
+/// index lives in memory and is written to its own file when the table
+/// closes.
+pub struct Index {
+    /// The name given by CREATE INDEX.
+    pub name: String,
+    /// What is indexed: a column, or an expression computed from each row,
+    /// with its column references unqualified.
+    pub expr: Expr,
+    /// The column's collation, or the expression's outermost COLLATE; keys hold values folded by it, so a lookup
+    /// finds every value the collation counts as equal.
+    pub collation: Collation,
+    path: String,
+    /// Keyed by `key`, which sorts as the values do, with the row keys
+    /// encoded the same way but never folded.
+    entries: BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    /// Whether the file on disk may match the entries. The file is deleted
+    /// on the first change after that, so a crash leaves no stale index
+    /// behind, only a missing one.
+    saved: bool,
+    /// The changes made since each open savepoint, the innermost last, so
+    /// they can be undone without copying the entries. A transaction
+    /// opens the outermost.
+    undo: Vec<Vec<Change>>,
+}
+
+/// A change to the entries, as the undo log records it.
+enum Change {
+    Inserted(Vec<u8>, Vec<u8>),
+    Removed(Vec<u8>, Vec<u8>),
+    /// Every entry, as they were before being cleared.
+    Cleared(BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>),
+}
+
+impl Index {
+    pub fn new(name: &str, expr: Expr, path: String) -> Index {
+        Index {
+            name: name.to_string(),
+            expr,
+            collation: Collation::Binary,
+            path,
+            entries: BTreeMap::new(),
+            saved: false,
+            undo: Vec::new(),
+        }
+    }
+
+    /// Reads the entries from the index file. Returns false, leaving the
+    /// index empty, when the file is missing or was written for a different
+    /// number of rows than the table has.
+    pub fn load(&mut self, num_rows: usize) -> io::Result<bool> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let path = &self.path;
+        let invalid = || io::Error::new(ErrorKind::InvalidData, format!("corrupt index {}", path));
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(FORMAT) {
+            // Written by an older version, with keys in another encoding
+            return Ok(false);
+        }
+        let rows: usize = lines
+            .next()
+            .and_then(|line| line.strip_prefix("rows "))
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid)?;
+        if rows != num_rows {
+            return Ok(false);
+        }
+
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let mut fields = line.split(' ');
+            let key = fields.next().and_then(decode_hex).ok_or_else(invalid)?;
+            let row_keys = fields
+                .map(|row_key| decode_hex(row_key).ok_or_else(invalid))
+                .collect::<io::Result<BTreeSet<Vec<u8>>>>()?;
+            entries.insert(key, row_keys);
+        }
+
+        self.entries = entries;
+        self.saved = true;
+        Ok(true)
+    }
+
+    /// Writes the entries out, unless the file already holds them. A copy
+    /// restored by ROLLBACK may have had its file deleted while it still
+    /// counts as saved, so the file must also be there to be skipped.
+    pub fn save(&mut self, num_rows: usize) -> io::Result<()> {
+        if self.saved && fs::metadata(&self.path).is_ok() {
+            return Ok(());
+        }
+        let mut contents = format!("{}\nrows {}\n", FORMAT, num_rows);
+        for (key, row_keys) in &self.entries {
+            contents.push_str(&encode_hex(key));
+            for row_key in row_keys {
+                contents.push(' ');
+                contents.push_str(&encode_hex(row_key));
+            }
+            contents.push('\n');
+        }
+        fs::write(&self.path, contents)?;
+        self.saved = true;
+        Ok(())
+    }
+
+    /// Deletes the index file, for when the index or its table goes.
+    pub fn remove_file(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The column indexed, or None for an index on an expression.
+    pub fn column(&self) -> Option<&str> {
+        match &self.expr {
+            Expr::Column(column) => Some(&column.column),
+            _ => None,
+        }
+    }
+
+    /// The value a row is indexed by. A column missing from the row is
+    /// NULL; an expression is evaluated, and may fail as it would in a
+    /// query.
+    pub fn value_of(&self, row: &Row) -> io::Result<Value> {
+        match &self.expr {
+            Expr::Column(column) => Ok(row
+                .values
+                .get(&column.column)
+                .cloned()
+                .unwrap_or(Value::Null)),
+            expr => evaluate(expr, row).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e)),
+        }
+    }
+
+    /// The number of distinct values indexed.
+    pub fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The keys of the rows holding `value`. NULL is never indexed, since
+    /// it equals nothing.
+    pub fn lookup(&self, value: &Value) -> Vec<Value> {
+        if value == &Value::Null {
+            return Vec::new();
+        }
+        self.entries
+            .get(&self.key(value))
+            .map_or(Vec::new(), decode_all)
+    }
+
+    /// The keys of the rows holding a value between the bounds. A NULL
+    /// bound matches nothing, as a comparison with NULL is never true.
+    pub fn range(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> Vec<Value> {
+        let bound = |bound: Bound<&Value>| match bound {
+            Bound::Included(Value::Null) | Bound::Excluded(Value::Null) => None,
+            Bound::Included(value) => Some(Bound::Included(self.key(value))),
+            Bound::Excluded(value) => Some(Bound::Excluded(self.key(value))),
+            // NULL is never indexed, so everything sorts above it
+            Bound::Unbounded => Some(Bound::Unbounded),
+        };
+        let (lower, upper) = match (bound(lower), bound(upper)) {
+            (Some(lower), Some(upper)) => (lower, upper),
+            _ => return Vec::new(),
+        };
+        // BTreeMap::range panics on bounds that cross
+        let empty = match (&lower, &upper) {
+            (Bound::Included(l), Bound::Included(u)) => l > u,
+            (Bound::Included(l) | Bound::Excluded(l), Bound::Included(u) | Bound::Excluded(u)) => {
+                l >= u
+            }
+            _ => false,
+        };
+        if empty {
+            return Vec::new();
+        }
+        self.entries
+            .range((lower, upper))
+            .flat_map(|(_, row_keys)| decode_all(row_keys))
+            .collect()
+    }
+
+    pub fn insert(&mut self, value: &Value, row_key: &Value) -> io::Result<()> {
+        // Even a row left out changes the row count the file was saved for
+        self.invalidate()?;
+        if value == &Value::Null {
+            return Ok(());
+        }
+        let (key, row_key) = (self.key(value), encode(row_key));
+        if self.add(key.clone(), row_key.clone()) {
+            self.log(Change::Inserted(key, row_key));
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, value: &Value, row_key: &Value) -> io::Result<()> {
+        self.invalidate()?;
+        let (key, row_key) = (self.key(value), encode(row_key));
+        if self.take(&key, &row_key) {
+            self.log(Change::Removed(key, row_key));
+        }
+        Ok(())
+    }
+
+    /// Forgets every row.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.invalidate()?;
+        let entries = std::mem::take(&mut self.entries);
+        self.log(Change::Cleared(entries));
+        Ok(())
+    }
+
+    /// Starts logging changes, to be kept by `commit` or undone by
+    /// `rollback`.
+    pub fn begin(&mut self) {
+        self.undo = vec![Vec::new()];
+    }
+
+    pub fn commit(&mut self) {
+        self.undo.clear();
+    }
+
+    /// Undoes every change since `begin`.
+    pub fn rollback(&mut self) {
+        while !self.undo.is_empty() {
+            self.restore();
+        }
+    }
+
+    /// Opens a savepoint inside the transaction, closed by `release` or
+    /// `restore`.
+    pub fn savepoint(&mut self) {
+        self.undo.push(Vec::new());
+    }
+
+    /// Keeps the changes since the innermost savepoint, handing them to the
+    /// one around it to undo if that is restored.
+    pub fn release(&mut self) {
+        if let Some(changes) = self.undo.pop() {
+            if let Some(outer) = self.undo.last_mut() {
+                outer.extend(changes);
+            }
+        }
+    }
+
+    /// Undoes the changes since the innermost savepoint, last first. Each
+    /// change deleted the file already, so it needs no deleting again.
+    pub fn restore(&mut self) {
+        let changes = self.undo.pop().unwrap_or_default();
+        for change in changes.into_iter().rev() {
+            match change {
+                Change::Inserted(key, row_key) => {
+                    self.take(&key, &row_key);
+                }
+                Change::Removed(key, row_key) => {
+                    self.add(key, row_key);
+                }
+                Change::Cleared(entries) => self.entries = entries,
+            }
+        }
+    }
+
+    /// Adds an entry, returning whether it was new.
+    fn add(&mut self, key: Vec<u8>, row_key: Vec<u8>) -> bool {
+        self.entries.entry(key).or_default().insert(row_key)
+    }
+
+    /// Removes an entry, returning whether it was there.
+    fn take(&mut self, key: &[u8], row_key: &[u8]) -> bool {
+        let row_keys = match self.entries.get_mut(key) {
+            Some(row_keys) => row_keys,
+            None => return false,
+        };
+        let removed = row_keys.remove(row_key);
+        if row_keys.is_empty() {
+            self.entries.remove(key);
+        }
+        removed
+    }
+
+    fn log(&mut self, change: Change) {
+        if let Some(changes) = self.undo.last_mut() {
+            changes.push(change);
+        }
+    }
+
+    /// A value folded by the index's collation, encoded for the map.
+    fn key(&self, value: &Value) -> Vec<u8> {
+        encode(&self.collation.fold(value))
+    }
+
+    fn invalidate(&mut self) -> io::Result<()> {
+        if self.saved {
+            self.remove_file()?;
+            self.saved = false;
+        }
+        Ok(())
+    }
+}
+
+/// The first line of an index file.
+const FORMAT: &str = "bugdb index 3";
+
+/// Encodes a value so that encodings compare bytewise as the values do:
+/// integers in order, then text. Flipping an integer's sign bit makes its
+/// big-endian bytes sort as the signed number does.
+fn encode(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Null => vec![0],
+        Value::Integer(i) => {
+            let mut key = vec![1];
+            key.extend_from_slice(&((*i as u64) ^ (1 << 63)).to_be_bytes());
+            key
+        }
+        Value::Text(s) => {
+            let mut key = vec![2];
+            key.extend_from_slice(s.as_bytes());
+            key
+        }
+    }
+}
+
+fn decode(bytes: &[u8]) -> Value {
+    match bytes.split_first() {
+        Some((1, rest)) => {
+            let bits = u64::from_be_bytes(rest.try_into().unwrap_or_default());
+            Value::Integer((bits ^ (1 << 63)) as i64)
+        }
+        Some((2, rest)) => Value::Text(String::from_utf8_lossy(rest).to_string()),
+        _ => Value::Null,
+    }
+}
+
+fn decode_all(row_keys: &BTreeSet<Vec<u8>>) -> Vec<Value> {
+    row_keys.iter().map(|row_key| decode(row_key)).collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::sql_parser::ColumnRef;
+
+    fn column(name: &str) -> Expr {
+        Expr::Column(ColumnRef {
+            table: None,
+            column: name.to_string(),
+        })
+    }
+
+    /// An index on `n` whose file is in a place of the test's own.
+    fn index(name: &str) -> Index {
+        let path = env::temp_dir().join(format!("bugdb-index-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        Index::new(name, column("n"), path.to_str().unwrap().to_string())
+    }
+
+    fn int(i: i64) -> Value {
+        Value::Integer(i)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    /// An index holding -5, 0, 0 and 7, for rows keyed 1 to 4.
+    fn filled(name: &str) -> Index {
+        let mut index = index(name);
+        for (value, row_key) in [(-5, 1), (0, 2), (0, 3), (7, 4)] {
+            index.insert(&int(value), &int(row_key)).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn lookup_and_range_return_row_keys_in_value_order() {
+        let mut index = filled("range");
+        index.insert(&Value::Null, &int(5)).unwrap();
+        index.insert(&text("a"), &int(6)).unwrap();
+
+        assert_eq!(index.lookup(&int(0)), [int(2), int(3)]);
+        assert!(index.lookup(&Value::Null).is_empty());
+        assert_eq!(index.key_count(), 4);
+        assert_eq!(
+            index.range(Bound::Excluded(&int(-5)), Bound::Unbounded),
+            [int(2), int(3), int(4), int(6)]
+        );
+        assert_eq!(
+            index.range(Bound::Unbounded, Bound::Included(&int(0))),
+            [int(1), int(2), int(3)]
+        );
+        // Bounds that cross, or a NULL bound, match nothing
+        assert!(index
+            .range(Bound::Included(&int(7)), Bound::Excluded(&int(7)))
+            .is_empty());
+        assert!(index
+            .range(Bound::Included(&Value::Null), Bound::Unbounded)
+            .is_empty());
+    }
+
+    #[test]
+    fn nocase_index_finds_every_spelling() {
+        let mut index = index("nocase");
+        index.collation = Collation::NoCase;
+        index.insert(&text("Alice"), &int(1)).unwrap();
+        index.insert(&text("ALICE"), &int(2)).unwrap();
+        assert_eq!(index.lookup(&text("alice")), [int(1), int(2)]);
+        index.remove(&text("aLiCe"), &int(1)).unwrap();
+        assert_eq!(index.lookup(&text("alice")), [int(2)]);
+    }
+
+    #[test]
+    fn restore_undoes_only_the_innermost_savepoint() {
+        let mut index = filled("savepoint");
+        index.begin();
+        index.insert(&int(1), &int(10)).unwrap();
+        index.savepoint();
+        index.remove(&int(0), &int(2)).unwrap();
+        index.insert(&int(2), &int(11)).unwrap();
+        index.clear().unwrap();
+        index.restore();
+        assert_eq!(index.lookup(&int(0)), [int(2), int(3)]);
+        assert!(index.lookup(&int(2)).is_empty());
+        assert_eq!(index.lookup(&int(1)), [int(10)]);
+
+        // A released savepoint's changes go with the transaction's
+        index.savepoint();
+        index.remove(&int(7), &int(4)).unwrap();
+        index.release();
+        index.rollback();
+        assert!(index.lookup(&int(1)).is_empty());
+        assert_eq!(index.lookup(&int(7)), [int(4)]);
+        assert_eq!(index.key_count(), 3);
+    }
+
+    #[test]
+    fn saved_entries_load_for_the_same_number_of_rows() {
+        let mut index = filled("save");
+        index.save(4).unwrap();
+
+        let mut loaded = Index::new("save", column("n"), index.path.clone());
+        assert!(!loaded.load(5).unwrap());
+        assert!(loaded.load(4).unwrap());
+        assert_eq!(
+            loaded.range(Bound::Unbounded, Bound::Unbounded),
+            [int(1), int(2), int(3), int(4)]
+        );
+
+        // The first change deletes the file, which is now out of date
+        loaded.insert(&int(3), &int(5)).unwrap();
+        assert!(!loaded.load(4).unwrap());
+    }
+
+    #[test]
+    fn value_of_reads_a_column_or_evaluates_an_expression() {
+        let mut row = Row::new();
+        row.values.insert("n".to_string(), int(6));
+        assert_eq!(index("value").value_of(&row).unwrap(), int(6));
+        assert_eq!(
+            Index::new("missing", column("m"), String::new())
+                .value_of(&row)
+                .unwrap(),
+            Value::Null
+        );
+
+        let doubled = Expr::Binary {
+            left: Box::new(column("n")),
+            op: crate::sql_parser::BinaryOp::Mul,
+            right: Box::new(Expr::Literal(int(2))),
+        };
+        let index = Index::new("doubled", doubled, String::new());
+        assert_eq!(index.value_of(&row).unwrap(), int(12));
+        assert!(index.value_of(&Row::new()).is_err());
+    }
+}
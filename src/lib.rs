@@ -0,0 +1,32 @@
+//! BugDB: a small SQL database kept in a file, with the shell that runs on
+//! it. `Database` opens one and runs statements on it. The SQL parser is
+//! exposed for tools that want to work with parsed statements without
+//! running them, and the scalar functions expressions call can be added
+//! to.
+
+pub mod functions;
+pub mod sql_parser;
+
+mod aggregates;
+mod btree;
+mod checksum;
+mod csv;
+mod database;
+mod executor;
+mod header;
+mod index;
+mod output;
+mod page_cache;
+mod pager;
+mod pipeline;
+mod planner;
+mod result_cache;
+pub mod shell;
+mod slow_log;
+mod sort;
+mod table;
+mod wal;
+
+pub use database::{Database, Options};
+pub use executor::ExecResult;
+pub use pager::Durability;
@@ -0,0 +1,204 @@
+use std::io;
+
+use crate::{csv, sql_parser::Value};
+
+/// How SELECT results are printed, chosen with `.mode`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// One `(1, 'alice')` tuple per row.
+    Tuple,
+    /// Columns aligned under a header row.
+    Column,
+    /// RFC 4180 CSV with a header line, for other tools to read.
+    Csv,
+    /// One JSON object per row, keyed by column name (NDJSON).
+    Json,
+}
+
+/// The widest a column grows in column mode; longer values are cut short.
+const MAX_COLUMN_WIDTH: usize = 40;
+
+impl OutputMode {
+    pub fn parse(name: &str) -> Option<OutputMode> {
+        match name {
+            "tuple" => Some(OutputMode::Tuple),
+            "column" => Some(OutputMode::Column),
+            "csv" => Some(OutputMode::Csv),
+            "json" => Some(OutputMode::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputMode::Tuple => "tuple",
+            OutputMode::Column => "column",
+            OutputMode::Csv => "csv",
+            OutputMode::Json => "json",
+        }
+    }
+}
+
+/// Prints a SELECT's result rows under the given column names as they
+/// arrive, and returns how many there were. Column mode is the exception,
+/// reading every row before printing any.
+pub fn print_rows(
+    mode: OutputMode,
+    columns: &[String],
+    rows: impl Iterator<Item = io::Result<Vec<Value>>>,
+) -> io::Result<usize> {
+    match mode {
+        OutputMode::Column => {
+            let rows = rows.collect::<io::Result<Vec<_>>>()?;
+            print_columns(columns, &rows);
+            return Ok(rows.len());
+        }
+        OutputMode::Csv => print_csv_header(columns),
+        OutputMode::Tuple | OutputMode::Json => {}
+    }
+    let mut count = 0;
+    for row in rows {
+        let row = row?;
+        match mode {
+            OutputMode::Csv => print_csv_row(&row),
+            OutputMode::Json => print_json_row(columns, &row),
+            _ => print_values(&row),
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// One object per line, so readers can handle rows one at a time.
+/// Integers are always JSON numbers, written exactly even past 2^53, where
+/// readers that parse numbers as doubles lose precision.
+fn print_json_row(columns: &[String], row: &[Value]) {
+    let members: Vec<String> = columns
+        .iter()
+        .zip(row)
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Integer(i) => i.to_string(),
+                Value::Text(s) => json_string(s),
+                Value::Null => "null".to_string(),
+            };
+            format!("{}:{}", json_string(name), value)
+        })
+        .collect();
+    println!("{{{}}}", members.join(","));
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn print_csv_header(columns: &[String]) {
+    let header: Vec<Option<String>> = columns.iter().cloned().map(Some).collect();
+    println!("{}", csv::format_record(&header));
+}
+
+/// Integers print bare and text is quoted only where CSV needs it. NULL is
+/// an empty field, while an empty string prints as `""`.
+fn print_csv_row(row: &[Value]) {
+    let fields: Vec<Option<String>> = row
+        .iter()
+        .map(|value| match value {
+            Value::Integer(i) => Some(i.to_string()),
+            Value::Text(s) => Some(s.clone()),
+            Value::Null => None,
+        })
+        .collect();
+    println!("{}", csv::format_record(&fields));
+}
+
+/// Column mode needs every row before printing anything, since each column
+/// is as wide as its widest value. Numbers are right-aligned, and NULL is
+/// shown as `NULL` where text would show without quotes.
+fn print_columns(columns: &[String], rows: &[Vec<Value>]) {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(cell).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([name.chars().count()])
+                .max()
+                .unwrap_or(0)
+                .min(MAX_COLUMN_WIDTH)
+        })
+        .collect();
+
+    let header: Vec<String> = columns
+        .iter()
+        .zip(&widths)
+        .map(|(name, &width)| format!("{:<width$}", truncate(name, width)))
+        .collect();
+    println!("{}", header.join("  ").trim_end());
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    println!("{}", rule.join("  "));
+    for (row, values) in cells.iter().zip(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(values)
+            .zip(&widths)
+            .map(|((text, value), &width)| match value {
+                Value::Integer(_) => format!("{:>width$}", truncate(text, width)),
+                _ => format!("{:<width$}", truncate(text, width)),
+            })
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Null => "NULL".to_string(),
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+fn print_values(values: &[Value]) {
+    println!("{}", format_values(values));
+}
+
+pub fn format_values(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(value_to_string).collect();
+    format!("({})", values.join(", "))
+}
+
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => format!("'{}'", s),
+        Value::Null => "NULL".to_string(),
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// How many pages a table keeps in memory unless `PRAGMA cache_size` says
+/// otherwise: 4 MB of them.
+pub const DEFAULT_CACHE_PAGES: usize = 1000;
+
+/// The pages of a table held in memory, up to a limit. The pager evicts
+/// the least recently used page to make room for another, writing it back
+/// first when it is dirty, that is, changed since it was last written.
+pub struct PageCache {
+    pages: HashMap<usize, CachedPage>,
+    /// Page numbers by when they were last used, least recent first.
+    recency: BTreeMap<u64, usize>,
+    clock: u64,
+    pub limit: usize,
+    pub stats: CacheStats,
+}
+
+struct CachedPage {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    /// Pages found in the cache.
+    pub hits: usize,
+    /// Pages read from the file, or started empty, to be cached.
+    pub misses: usize,
+    pub evictions: usize,
+    /// Dirty pages written to the file to be evicted.
+    pub writebacks: usize,
+}
+
+impl std::ops::AddAssign for CacheStats {
+    fn add_assign(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.evictions += other.evictions;
+        self.writebacks += other.writebacks;
+    }
+}
+
+impl PageCache {
+    pub fn new(limit: usize) -> PageCache {
+        PageCache {
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            limit,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// The cached pages' numbers, in no order.
+    pub fn page_nums(&self) -> Vec<usize> {
+        self.pages.keys().copied().collect()
+    }
+
+    pub fn contains(&self, page_num: usize) -> bool {
+        self.pages.contains_key(&page_num)
+    }
+
+    /// A cached page, made the most recently used.
+    pub fn get_mut(&mut self, page_num: usize) -> Option<&mut Vec<u8>> {
+        let page = self.pages.get_mut(&page_num)?;
+        self.recency.remove(&page.last_used);
+        self.clock += 1;
+        page.last_used = self.clock;
+        self.recency.insert(self.clock, page_num);
+        Some(&mut page.data)
+    }
+
+    /// A cached page as it is, without counting it as used.
+    pub fn peek(&self, page_num: usize) -> Option<&Vec<u8>> {
+        self.pages.get(&page_num).map(|page| &page.data)
+    }
+
+    /// Caches a page that was not cached, as the most recently used.
+    pub fn insert(&mut self, page_num: usize, data: Vec<u8>) {
+        self.clock += 1;
+        let page = CachedPage {
+            data,
+            dirty: false,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.pages.insert(page_num, page) {
+            self.recency.remove(&old.last_used);
+        }
+        self.recency.insert(self.clock, page_num);
+    }
+
+    pub fn remove(&mut self, page_num: usize) {
+        if let Some(page) = self.pages.remove(&page_num) {
+            self.recency.remove(&page.last_used);
+        }
+    }
+
+    /// Drops every page for which `keep` is false, dirty or not.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize, bool) -> bool) {
+        let recency = &mut self.recency;
+        self.pages.retain(|&page_num, page| {
+            let kept = keep(page_num, page.dirty);
+            if !kept {
+                recency.remove(&page.last_used);
+            }
+            kept
+        });
+    }
+
+    pub fn set_dirty(&mut self, page_num: usize, dirty: bool) {
+        if let Some(page) = self.pages.get_mut(&page_num) {
+            page.dirty = dirty;
+        }
+    }
+
+    pub fn is_dirty(&self, page_num: usize) -> bool {
+        self.pages.get(&page_num).is_some_and(|page| page.dirty)
+    }
+
+    /// The dirty pages, in page order.
+    pub fn dirty_pages(&self) -> Vec<usize> {
+        let mut dirty: Vec<usize> = self
+            .pages
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&page_num, _)| page_num)
+            .collect();
+        dirty.sort_unstable();
+        dirty
+    }
+
+    /// The least recently used page that is not pinned, to evict next.
+    pub fn victim(&self, pinned: impl Fn(usize) -> bool) -> Option<usize> {
+        self.recency
+            .values()
+            .copied()
+            .find(|&page_num| !pinned(page_num))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_of(page_nums: &[usize]) -> PageCache {
+        let mut cache = PageCache::new(page_nums.len());
+        for &page_num in page_nums {
+            cache.insert(page_num, vec![page_num as u8]);
+        }
+        cache
+    }
+
+    #[test]
+    fn least_recently_used_page_is_evicted_first() {
+        let mut cache = cache_of(&[1, 2, 3]);
+        assert_eq!(cache.victim(|_| false), Some(1));
+
+        // Using a page makes it the most recent; peeking does not
+        cache.get_mut(1);
+        cache.peek(2);
+        assert_eq!(cache.victim(|_| false), Some(2));
+
+        cache.remove(2);
+        assert_eq!(cache.victim(|_| false), Some(3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn pinned_pages_are_skipped() {
+        let cache = cache_of(&[1, 2, 3]);
+        assert_eq!(cache.victim(|page_num| page_num < 3), Some(3));
+        assert_eq!(cache.victim(|_| true), None);
+    }
+
+    #[test]
+    fn inserting_a_cached_page_replaces_it() {
+        let mut cache = cache_of(&[1, 2]);
+        cache.set_dirty(1, true);
+        cache.insert(1, vec![9]);
+        assert_eq!(cache.peek(1), Some(&vec![9]));
+        assert!(!cache.is_dirty(1));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.victim(|_| false), Some(2));
+    }
+
+    #[test]
+    fn dirty_pages_are_listed_in_order_and_kept_by_retain() {
+        let mut cache = cache_of(&[5, 1, 3, 2]);
+        for page_num in [5, 1, 3] {
+            cache.set_dirty(page_num, true);
+        }
+        cache.set_dirty(3, false);
+        assert_eq!(cache.dirty_pages(), [1, 5]);
+
+        cache.retain(|_, dirty| dirty);
+        let mut page_nums = cache.page_nums();
+        page_nums.sort_unstable();
+        assert_eq!(page_nums, [1, 5]);
+        assert_eq!(cache.victim(|_| false), Some(5));
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    checksum::{self, CHECKSUM_SIZE},
+    page_cache::{PageCache, DEFAULT_CACHE_PAGES},
+    wal::{Wal, CHECKPOINT_FRAMES},
+};
+
+pub const PAGE_SIZE: usize = 4096;
+/// A table file's first page holds its header rather than rows.
+pub const HEADER_PAGE: usize = 0;
+/// How hard the pager works to get what it writes onto the disk, trading
+/// safety from power failures for speed. Every level survives the process
+/// dying, as what it writes is then with the operating system.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Durability {
+    /// Never syncs, leaving it to the operating system when writes reach
+    /// the disk. A power failure can leave the file corrupt.
+    Off,
+    /// Syncs only what keeps the file sound after a power failure: the
+    /// journal before the file is written and the file before the journal
+    /// is deleted, or in WAL mode the file before the log is emptied. In
+    /// WAL mode a power failure can lose the latest commits; with the
+    /// rollback journal, every commit is synced anyway.
+    Normal,
+    /// Also syncs the write-ahead log after every commit, so that a commit
+    /// reported is kept in WAL mode too.
+    Full,
+}
+
+impl Durability {
+    pub fn parse(name: &str) -> io::Result<Durability> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Ok(Durability::Off),
+            "normal" => Ok(Durability::Normal),
+            "full" => Ok(Durability::Full),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown durability level: {}", name),
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Durability::Off => "off",
+            Durability::Normal => "normal",
+            Durability::Full => "full",
+        }
+    }
+}
+
+pub struct Pager {
+    file: File,
+    /// The pages read or written so far, by page number. The file can
+    /// have any number of pages, and only those used are held.
+    pub cache: PageCache,
+    pub file_length: usize,
+    /// Pages read from the file so far.
+    pub reads: usize,
+    /// Page writes made to the file so far.
+    pub writes: usize,
+    /// While a transaction is open, the pages written since BEGIN. Their
+    /// writes are held in memory until COMMIT, and `file_length` is the
+    /// length the file will have then.
+    pub pending: Option<BTreeSet<usize>>,
+    /// For each open savepoint, the innermost last, the pages changed
+    /// since it was taken. A page already changed by the transaction keeps
+    /// a copy of how it was; any other is as the file has it, and is
+    /// forgotten to be read again.
+    undo: Vec<BTreeMap<usize, Option<Vec<u8>>>>,
+    pub durability: Durability,
+    /// Where the old contents of the pages being written are saved, so
+    /// that a write cut short by a crash can be undone.
+    pub journal_path: String,
+    /// In WAL mode, the log that pages are written to in place of the
+    /// file until a checkpoint.
+    pub wal: Option<Wal>,
+    pub wal_path: String,
+}
+
+impl Pager {
+    pub fn new(filename: &str) -> io::Result<Pager> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(filename)?;
+
+        let mut pager = Pager {
+            file,
+            cache: PageCache::new(DEFAULT_CACHE_PAGES),
+            file_length: 0,
+            reads: 0,
+            writes: 0,
+            pending: None,
+            undo: Vec::new(),
+            durability: Durability::Full,
+            journal_path: format!("{}-journal", filename),
+            wal: None,
+            wal_path: format!("{}-wal", filename),
+        };
+        // A journal left behind means the last write never finished, and
+        // a log means the file was not closed since pages were logged
+        pager.play_back_journal()?;
+        if Path::new(&pager.wal_path).exists() {
+            let wal = Wal::open(&pager.wal_path)?;
+            if wal.num_pages().is_some() {
+                println!("Replaying {}", pager.wal_path);
+            }
+            pager.wal = Some(wal);
+            pager.close_wal()?;
+        }
+        pager.file_length = pager.file.metadata()?.len() as usize;
+        Ok(pager)
+    }
+
+    /// The bytes of a page that can hold a node, ahead of its checksum.
+    pub fn usable_size(&self) -> usize {
+        PAGE_SIZE - CHECKSUM_SIZE
+    }
+
+    /// The number of pages in the file, counting those allocated but not
+    /// yet written.
+    pub fn num_pages(&self) -> usize {
+        self.file_length.div_ceil(PAGE_SIZE)
+    }
+
+    /// Adds an empty page to the end of the file and returns its number.
+    /// The file only grows when the page is written, so a full disk is
+    /// reported then.
+    pub fn allocate_page(&mut self) -> io::Result<usize> {
+        let page_num = self.num_pages();
+        self.make_room()?;
+        self.cache.insert(page_num, vec![0; PAGE_SIZE]);
+        self.file_length = (page_num + 1) * PAGE_SIZE;
+        // Dirty from the start, so it is written before it can be evicted
+        self.mark_dirty(page_num);
+        Ok(page_num)
+    }
+
+    /// Records that a cached page is about to change, so it must be called
+    /// before the change rather than after. The page is written at COMMIT
+    /// inside a transaction, and otherwise when it is evicted or the table
+    /// is closed.
+    fn mark_dirty(&mut self, page_num: usize) {
+        self.save_before_image(page_num);
+        if let Some(pending) = &mut self.pending {
+            pending.insert(page_num);
+        }
+        self.cache.set_dirty(page_num, true);
+        self.file_length = self.file_length.max((page_num + 1) * PAGE_SIZE);
+    }
+
+    /// A page made ready to change: read in, and marked dirty first.
+    pub fn page_mut(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        self.get_page(page_num)?;
+        self.mark_dirty(page_num);
+        self.get_page(page_num)
+    }
+
+    /// Keeps a page as it is now, unless the innermost savepoint kept it
+    /// already, to put back if the savepoint is restored.
+    fn save_before_image(&mut self, page_num: usize) {
+        let undo = match self.undo.last_mut() {
+            Some(undo) if !undo.contains_key(&page_num) => undo,
+            _ => return,
+        };
+        let changed = self.pending.as_ref().is_some_and(|p| p.contains(&page_num));
+        let image = if changed {
+            self.cache.peek(page_num).cloned()
+        } else {
+            None
+        };
+        undo.insert(page_num, image);
+    }
+
+    /// Starts keeping the pages changed from now on, until `release` or
+    /// `restore`. Only taken inside a transaction, so that no page changed
+    /// since is written to the file.
+    pub fn savepoint(&mut self) {
+        self.undo.push(BTreeMap::new());
+    }
+
+    /// Keeps the changes since the innermost savepoint. The savepoint
+    /// around it takes over the pages it had not kept itself.
+    pub fn release(&mut self) {
+        if let Some(pages) = self.undo.pop() {
+            if let Some(outer) = self.undo.last_mut() {
+                for (page_num, image) in pages {
+                    outer.entry(page_num).or_insert(image);
+                }
+            }
+        }
+    }
+
+    /// Puts back every page changed since the innermost savepoint.
+    pub fn restore(&mut self) {
+        for (page_num, image) in self.undo.pop().unwrap_or_default() {
+            match image {
+                Some(page) => {
+                    self.cache.insert(page_num, page);
+                    self.cache.set_dirty(page_num, true);
+                }
+                None => {
+                    self.cache.remove(page_num);
+                    if let Some(pending) = &mut self.pending {
+                        pending.remove(&page_num);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A cached page as it is to be written, ending with its checksum.
+    fn image(&self, page_num: usize) -> Option<Vec<u8>> {
+        let mut page = self.cache.peek(page_num)?.clone();
+        let (contents, checksum) = page.split_at_mut(PAGE_SIZE - CHECKSUM_SIZE);
+        checksum.copy_from_slice(&checksum::crc32(contents).to_le_bytes());
+        Some(page)
+    }
+
+    /// Writes a cached page to the file, after which it is clean.
+    fn write_page(&mut self, page_num: usize) -> io::Result<()> {
+        let page = match self.image(page_num) {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+        self.file
+            .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+        self.file.write_all(&page).map_err(database_full)?;
+        self.writes += 1;
+        self.cache.set_dirty(page_num, false);
+        Ok(())
+    }
+
+    /// Writes every dirty page.
+    pub fn flush_dirty(&mut self) -> io::Result<()> {
+        let dirty = self.cache.dirty_pages();
+        self.write_pages(&dirty)
+    }
+
+    /// Appends pages to the write-ahead log as one commit, leaving the
+    /// file as it is. A cut short commit is ignored when the log is read,
+    /// so no journal is needed.
+    fn log_pages(&mut self, pages: &[usize]) -> io::Result<()> {
+        let images: Vec<(usize, Vec<u8>)> = pages
+            .iter()
+            .filter_map(|&page_num| Some((page_num, self.image(page_num)?)))
+            .collect();
+        if images.is_empty() {
+            return Ok(());
+        }
+        let num_pages = self.num_pages();
+        if let Some(wal) = &mut self.wal {
+            wal.append(&images, num_pages).map_err(database_full)?;
+        }
+        if let Some(wal) = &self.wal {
+            self.make_durable(wal.file(), Durability::Full)?;
+        }
+        for (page_num, _) in &images {
+            self.writes += 1;
+            self.cache.set_dirty(*page_num, false);
+        }
+        // Keeps the log from growing for as long as the table is open
+        if self
+            .wal
+            .as_ref()
+            .is_some_and(|wal| wal.frame_count() >= CHECKPOINT_FRAMES)
+        {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Copies the pages in the write-ahead log into the file and empties
+    /// the log, syncing the file in between.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let wal = match &mut self.wal {
+            Some(wal) if wal.num_pages().is_some() => wal,
+            _ => return Ok(()),
+        };
+        wal.copy_to(&mut self.file).map_err(database_full)?;
+        self.make_durable(&self.file, Durability::Normal)?;
+        match &mut self.wal {
+            Some(wal) => wal.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Syncs a file to the disk when the durability level is at least
+    /// `needed`. Every sync of a table's files goes through here:
+    /// `Normal` is asked for where a power failure could otherwise leave a
+    /// file that cannot be put right, and `Full` after each commit, so that
+    /// a commit once reported stays.
+    fn make_durable(&self, file: &File, needed: Durability) -> io::Result<()> {
+        if self.durability >= needed {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoints the write-ahead log and deletes it, leaving WAL mode.
+    pub fn close_wal(&mut self) -> io::Result<()> {
+        self.checkpoint()?;
+        match self.wal.take() {
+            Some(wal) => wal.remove(),
+            None => Ok(()),
+        }
+    }
+
+    /// The file's length as of the last write: the log's, in WAL mode
+    /// once it has a commit, and otherwise the file's own.
+    fn written_length(&self) -> io::Result<usize> {
+        match self.wal.as_ref().and_then(Wal::num_pages) {
+            Some(num_pages) => Ok(num_pages * PAGE_SIZE),
+            None => Ok(self.file.metadata()?.len() as usize),
+        }
+    }
+
+    /// Sets the file to its new length and writes the given pages, as one
+    /// change that a crash cannot leave half made. The old contents of the
+    /// pages, and of any cut off the end, are saved to the journal along
+    /// with the old length, and the journal is synced before the file is
+    /// touched. Once the file is synced in turn, the journal is deleted.
+    /// Should a write fail, the journal is played back at once, and the
+    /// pages stay dirty.
+    fn write_pages(&mut self, pages: &[usize]) -> io::Result<()> {
+        if self.wal.is_some() {
+            return self.log_pages(pages);
+        }
+        let old_length = self.file.metadata()?.len() as usize;
+        if pages.is_empty() && old_length == self.file_length {
+            return Ok(());
+        }
+        self.write_journal(pages, old_length)?;
+        let written = self.overwrite(pages);
+        if written.is_err() {
+            for &page_num in pages {
+                self.cache.set_dirty(page_num, true);
+            }
+            self.play_back_journal()?;
+        }
+        written?;
+        fs::remove_file(&self.journal_path)
+    }
+
+    fn write_journal(&mut self, pages: &[usize], old_length: usize) -> io::Result<()> {
+        let old_pages = old_length / PAGE_SIZE;
+        let saved: Vec<usize> = pages
+            .iter()
+            .copied()
+            .filter(|&page_num| page_num < old_pages)
+            .chain(self.num_pages()..old_pages)
+            .collect();
+        let mut journal = (old_length as u64).to_le_bytes().to_vec();
+        for page_num in saved {
+            let mut page = vec![0; PAGE_SIZE];
+            self.file
+                .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+            self.file.read_exact(&mut page)?;
+            journal.extend_from_slice(&(page_num as u32).to_le_bytes());
+            journal.extend_from_slice(&page);
+        }
+        journal.extend_from_slice(&checksum::crc32(&journal).to_le_bytes());
+        let mut file = File::create(&self.journal_path)?;
+        file.write_all(&journal).map_err(database_full)?;
+        self.make_durable(&file, Durability::Normal)
+    }
+
+    fn overwrite(&mut self, pages: &[usize]) -> io::Result<()> {
+        self.file
+            .set_len(self.file_length as u64)
+            .map_err(database_full)?;
+        for &page_num in pages {
+            self.write_page(page_num)?;
+        }
+        // The journal goes next, and the file must not be torn without it
+        self.make_durable(&self.file, Durability::Normal)
+    }
+
+    /// Puts back the pages and the length the journal saved, undoing a
+    /// write that did not finish, and deletes it. A journal that was never
+    /// completed fails its checksum and is only deleted, as the file is not
+    /// touched until its journal is synced.
+    fn play_back_journal(&mut self) -> io::Result<()> {
+        let journal = match fs::read(&self.journal_path) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if let Some(Journal { length, pages }) = read_journal(&journal) {
+            println!("Restoring {} pages from {}", pages.len(), self.journal_path);
+            for (page_num, page) in pages {
+                self.file
+                    .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+                self.file.write_all(page)?;
+            }
+            self.file.set_len(length as u64)?;
+            self.make_durable(&self.file, Durability::Normal)?;
+            self.file_length = length;
+        }
+        fs::remove_file(&self.journal_path)
+    }
+
+    /// Evicts pages until there is room for one more, least recently used
+    /// first. A page waiting on a commit stays, as the file must not change
+    /// before then, so a transaction can hold more pages than the limit.
+    fn make_room(&mut self) -> io::Result<()> {
+        self.evict_to(self.cache.limit.max(1) - 1)
+    }
+
+    fn evict_to(&mut self, size: usize) -> io::Result<()> {
+        while self.cache.len() > size {
+            let pending = &self.pending;
+            let victim = self
+                .cache
+                .victim(|page_num| pending.as_ref().is_some_and(|p| p.contains(&page_num)));
+            let page_num = match victim {
+                Some(page_num) => page_num,
+                None => break,
+            };
+            if self.cache.is_dirty(page_num) {
+                self.write_pages(&[page_num])?;
+                self.cache.stats.writebacks += 1;
+            }
+            self.cache.remove(page_num);
+            self.cache.stats.evictions += 1;
+        }
+        Ok(())
+    }
+
+    /// Cuts the file down to its first `num_pages` pages. The file itself
+    /// is cut with the next pages written, so that the pages cut off can be
+    /// journaled.
+    pub fn truncate(&mut self, num_pages: usize) {
+        self.file_length = num_pages * PAGE_SIZE;
+
+        // Forget cached pages past the new end of file, keeping them first
+        // for a savepoint that may put them back
+        for page_num in self.cache.page_nums() {
+            if page_num >= num_pages {
+                self.save_before_image(page_num);
+            }
+        }
+        self.cache.retain(|page_num, _| page_num < num_pages);
+    }
+
+    /// Holds back writes from now on, until `commit` or `rollback`. Dirty
+    /// pages are written first, so a rollback only drops the transaction's
+    /// own changes.
+    pub fn begin(&mut self) -> io::Result<()> {
+        self.flush_dirty()?;
+        self.pending = Some(BTreeSet::new());
+        self.undo.clear();
+        Ok(())
+    }
+
+    /// Writes every page changed since `begin`, all or none of them. The
+    /// file grows before any page is written, so a file system that
+    /// refuses the room fails the commit with the pages untouched.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        self.undo.clear();
+        let num_pages = self.num_pages();
+        let pages: Vec<usize> = pending.into_iter().filter(|&p| p < num_pages).collect();
+        self.write_pages(&pages)?;
+        // The transaction's pages are no longer held, so the cache goes
+        // back down to its limit
+        self.evict_to(self.cache.limit)
+    }
+
+    /// Forgets every change since `begin`. The file itself was not touched,
+    /// so dropping the changed pages and rereading its length restores it.
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.pending = None;
+        self.undo.clear();
+        self.cache.retain(|_, dirty| !dirty);
+        self.file_length = self.written_length()?;
+        let num_pages = self.num_pages();
+        self.cache.retain(|page_num, _| page_num < num_pages);
+        Ok(())
+    }
+
+    /// A page as the file has it, read without checking its checksum or
+    /// keeping it in the cache. The header is read this way first, so that
+    /// a file of another kind or format is refused for what it is.
+    pub fn read_unchecked(&mut self, page_num: usize) -> io::Result<Vec<u8>> {
+        let mut page = vec![0; PAGE_SIZE];
+        self.file
+            .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    pub fn get_page(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        if self.cache.contains(page_num) {
+            self.cache.stats.hits += 1;
+        } else {
+            self.make_room()?;
+            let mut page = vec![0; PAGE_SIZE];
+
+            let num_pages = self.num_pages();
+            let logged = match &mut self.wal {
+                Some(wal) if page_num < num_pages => wal.read_page(page_num)?,
+                _ => None,
+            };
+            if let Some(logged) = logged {
+                self.reads += 1;
+                page = logged;
+                check_page(page_num, &page)?;
+            } else if page_num < self.num_pages() {
+                self.reads += 1;
+                self.file
+                    .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+                let bytes_read = self.file.read(&mut page[..])?;
+                if bytes_read < PAGE_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Failed to read full page",
+                    ));
+                }
+                check_page(page_num, &page)?;
+            }
+
+            self.cache.insert(page_num, page);
+            self.cache.stats.misses += 1;
+        }
+
+        Ok(self.cache.get_mut(page_num).unwrap())
+    }
+}
+
+/// What a journal saved of a table file before writing to it.
+struct Journal<'a> {
+    length: usize,
+    /// Page numbers and their old contents.
+    pages: Vec<(usize, &'a [u8])>,
+}
+
+/// Reads a journal, or gives nothing when it was not written in full. A
+/// journal is the file's old length, then each page as its number and
+/// contents, then a checksum of all that.
+fn read_journal(journal: &[u8]) -> Option<Journal<'_>> {
+    let (contents, checksum) = journal.split_at(journal.len().checked_sub(CHECKSUM_SIZE)?);
+    if contents.len() < 8 || checksum::crc32(contents).to_le_bytes() != checksum {
+        return None;
+    }
+    let (length, saved) = contents.split_at(8);
+    let records = saved.chunks_exact(4 + PAGE_SIZE);
+    if !records.remainder().is_empty() {
+        return None;
+    }
+    let pages = records
+        .map(|record| {
+            let page_num = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+            (page_num, &record[4..])
+        })
+        .collect();
+    Some(Journal {
+        length: u64::from_le_bytes(length.try_into().unwrap()) as usize,
+        pages,
+    })
+}
+
+/// Fails when a page read from the file does not hold the checksum of its
+/// contents, that is, when it was damaged after it was written.
+pub fn check_page(page_num: usize, page: &[u8]) -> io::Result<()> {
+    if checksum::verify(page) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "page {} is corrupt: its checksum does not match its contents",
+                page_num
+            ),
+        ))
+    }
+}
+
+/// Reports a write the file system refused for want of space, or because
+/// the file would grow past what it allows, as the database being full.
+fn database_full(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::StorageFull | io::ErrorKind::FileTooLarge => {
+            io::Error::new(e.kind(), format!("database full: {}", e))
+        }
+        _ => e,
+    }
+}
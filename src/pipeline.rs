@@ -0,0 +1,127 @@
+use std::{collections::HashSet, io, rc::Rc};
+
+use crate::{
+    database::StatementStats,
+    executor::{is_distinct, matches_where_clause, output_row},
+    sql_parser::{Expr, Row, Statement, Value},
+    table::Table,
+};
+
+/// A stream of output rows. Each stage of a query wraps the one before it
+/// and pulls rows through one at a time, so nothing is held in memory
+/// unless a stage needs every row first, as sorting does.
+pub type Rows<'a> = Box<dyn Iterator<Item = io::Result<Vec<Value>>> + 'a>;
+
+/// Reads a table's rows at the given row numbers, in the order given.
+pub struct Scan<'a, N> {
+    table: &'a mut Table,
+    row_nums: N,
+}
+
+impl<'a, N: Iterator<Item = usize>> Scan<'a, N> {
+    pub fn new(table: &'a mut Table, row_nums: N) -> Scan<'a, N> {
+        Scan { table, row_nums }
+    }
+}
+
+impl<N: Iterator<Item = usize>> Iterator for Scan<'_, N> {
+    type Item = io::Result<Row>;
+
+    fn next(&mut self) -> Option<io::Result<Row>> {
+        loop {
+            let row_num = self.row_nums.next()?;
+            match self.table.read_row(row_num, None) {
+                Ok(Some(row)) => return Some(Ok(row)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Passes on the rows that satisfy a WHERE clause, counting the rows it
+/// examines and those that match.
+pub struct Filter<'s, I> {
+    input: I,
+    where_clause: Option<Expr>,
+    stats: &'s mut StatementStats,
+}
+
+impl<'s, I> Filter<'s, I> {
+    pub fn new(input: I, where_clause: Option<Expr>, stats: &'s mut StatementStats) -> Self {
+        Filter {
+            input,
+            where_clause,
+            stats,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Row>>> Iterator for Filter<'_, I> {
+    type Item = io::Result<Row>;
+
+    fn next(&mut self) -> Option<io::Result<Row>> {
+        loop {
+            let row = match self.input.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            self.stats.rows_examined += 1;
+            match matches_where_clause(&row, &self.where_clause) {
+                Ok(true) => {
+                    self.stats.rows_matched += 1;
+                    return Some(Ok(row));
+                }
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Evaluates the SELECT's output values for each row, along with any
+/// ORDER BY values a later sort needs.
+pub fn project<'a>(
+    input: impl Iterator<Item = io::Result<Row>> + 'a,
+    statement: Rc<Statement>,
+) -> impl Iterator<Item = io::Result<Vec<Value>>> + 'a {
+    input.map(move |row| row.and_then(|row| output_row(&row, &statement)))
+}
+
+/// Drops output rows seen before, for a DISTINCT SELECT. It remembers a
+/// key for every row passed on, but not the rows themselves.
+pub fn distinct<'a>(
+    input: impl Iterator<Item = io::Result<Vec<Value>>> + 'a,
+    statement: Rc<Statement>,
+) -> impl Iterator<Item = io::Result<Vec<Value>>> + 'a {
+    let mut seen = HashSet::new();
+    input.filter(move |row| match row {
+        Ok(values) => is_distinct(&statement, &mut seen, values),
+        Err(_) => true,
+    })
+}
+
+/// Drops the first `count` rows, for OFFSET. An error among them is still
+/// passed on rather than dropped.
+pub fn offset<'a, T>(
+    input: impl Iterator<Item = io::Result<T>> + 'a,
+    count: usize,
+) -> impl Iterator<Item = io::Result<T>> + 'a {
+    let mut remaining = count;
+    input.filter(move |row| {
+        if row.is_err() || remaining == 0 {
+            return true;
+        }
+        remaining -= 1;
+        false
+    })
+}
+
+/// Stops after `limit` rows, for LIMIT, without pulling any more from the
+/// stages before it.
+pub fn limit<'a>(
+    input: impl Iterator<Item = io::Result<Vec<Value>>> + 'a,
+    limit: Option<usize>,
+) -> impl Iterator<Item = io::Result<Vec<Value>>> + 'a {
+    input.take(limit.unwrap_or(usize::MAX))
+}
@@ -0,0 +1,289 @@
+use std::{io, ops::Bound};
+
+use crate::{
+    executor::{canonicalize, coerce, comparison_collation, strip_collate},
+    sql_parser::{BinaryOp, Collation, Expr, Value},
+    table::Table,
+};
+
+/// What reading one row through an index costs, relative to reading it in
+/// a scan: the index is searched, and the rows it returns are spread over
+/// the table rather than read page after page.
+const INDEX_ROW_COST: f64 = 4.0;
+
+/// Of the rows a range such as `id > 5` is assumed to match, with nothing
+/// better to go on. Each bound divides the table by this.
+const RANGE_SELECTIVITY: f64 = 3.0;
+
+/// How a statement reads the rows of a table that can match its WHERE
+/// clause. Every row read is still checked against the clause.
+pub enum Access {
+    /// Every row, in storage order.
+    Scan,
+    /// The rows an index holds between the bounds, or for the primary key
+    /// the rows the tree holds between them, already read as the column's
+    /// type.
+    Index {
+        /// The column, or the indexed expression, as EXPLAIN shows it.
+        column: String,
+        /// The index's name; None for a primary key's.
+        name: Option<String>,
+        lower: Bound<Value>,
+        upper: Bound<Value>,
+    },
+}
+
+/// The access chosen for a table, with the number of rows it expects to
+/// read.
+pub struct AccessPlan {
+    pub access: Access,
+    pub estimated_rows: usize,
+}
+
+/// Chooses between scanning a table and looking its rows up in an index,
+/// whichever is estimated to read less. The primary key, which the rows
+/// are stored in order of, or an index can answer a WHERE clause comparing
+/// its column with literals: an equality, a range such as `id > 5`,
+/// BETWEEN, or a lower and an upper bound AND-ed together, compared with the column's or index's collation. An index on
+/// an expression answers a comparison of the same expression, such as
+/// `lower(email) = 'x'`, matched node for node. An equality on a unique
+/// column matches one row, any other equality the average number of rows
+/// per key, and a range a third of the table for each bound, though never
+/// fewer than an equality would. The estimate is kept fractional until the
+/// choice is made, so a small table is not rounded down into looking cheap
+/// to search.
+pub fn plan_access(table: &Table, where_clause: &Option<Expr>) -> AccessPlan {
+    let scan = AccessPlan::scan(table);
+    let (operand, collation, lower, upper) = match where_clause.as_ref().and_then(index_bounds) {
+        Some(bounds) => bounds,
+        None => return scan,
+    };
+    let mut operand = operand.clone();
+    canonicalize(&mut operand);
+    // A column's definition gives its type and whether it is unique
+    let definition = match &operand {
+        Expr::Column(column) => match table.columns.iter().find(|c| c.name == column.column) {
+            Some(definition) => Some(definition),
+            None => return scan,
+        },
+        _ => None,
+    };
+    let (name, key_count) =
+        match definition.filter(|d| d.primary_key) {
+            Some(definition) => {
+                if definition.collation != collation {
+                    return scan;
+                }
+                (None, table.num_rows)
+            }
+            None => match table.indexes.iter().find(|index| {
+                index.collation == collation && strip_collate(&index.expr) == &operand
+            }) {
+                Some(index) => (Some(index.name.clone()), index.key_count()),
+                None => return scan,
+            },
+        };
+
+    let equality = matches!((&lower, &upper), (Bound::Included(l), Bound::Included(u)) if l == u);
+    let unique = definition.is_some_and(|d| d.primary_key || d.unique);
+    let table_rows = table.num_rows as f64;
+    let per_key = table_rows / key_count.max(1) as f64;
+    let estimate = if equality && unique {
+        1.0
+    } else if equality {
+        per_key
+    } else {
+        let bounds = [&lower, &upper]
+            .iter()
+            .filter(|bound| !matches!(bound, Bound::Unbounded))
+            .count() as i32;
+        (table_rows / RANGE_SELECTIVITY.powi(bounds)).max(per_key)
+    }
+    .max(1.0);
+    if estimate * INDEX_ROW_COST > table_rows {
+        return scan;
+    }
+    let estimated_rows = estimate.ceil() as usize;
+
+    // The bounds are read as the column's type, as a comparison reads them
+    let coerce_bound = |bound: Bound<&Value>| {
+        bound.map(|value| match definition {
+            Some(definition) => coerce(value, definition.data_type).unwrap_or(value.clone()),
+            None => value.clone(),
+        })
+    };
+    let column = match &operand {
+        Expr::Column(column) => column.column.clone(),
+        expr => expr.to_string(),
+    };
+    AccessPlan {
+        access: Access::Index {
+            column,
+            name,
+            lower: coerce_bound(lower.as_ref()),
+            upper: coerce_bound(upper.as_ref()),
+        },
+        estimated_rows,
+    }
+}
+
+impl AccessPlan {
+    /// A scan of every row, for a statement that reads them all anyway.
+    pub fn scan(table: &Table) -> AccessPlan {
+        AccessPlan {
+            access: Access::Scan,
+            estimated_rows: table.num_rows,
+        }
+    }
+
+    /// The rows to read, in row order, or None to scan the whole table.
+    pub fn row_nums(&self, table: &mut Table) -> io::Result<Option<Vec<usize>>> {
+        let (name, lower, upper) = match &self.access {
+            Access::Scan => return Ok(None),
+            Access::Index {
+                name, lower, upper, ..
+            } => (name, lower.as_ref(), upper.as_ref()),
+        };
+        let name = match name {
+            Some(name) => name,
+            None => return table.key_range(lower, upper).map(Some),
+        };
+        let keys = match table.indexes.iter().find(|index| index.name == *name) {
+            Some(index) => index.range(lower, upper),
+            None => return Ok(None),
+        };
+        table.rows_for_keys(&keys).map(Some)
+    }
+
+    /// A line for EXPLAIN QUERY PLAN, such as `SEARCH users USING PRIMARY
+    /// KEY (id=?) (~1 row)`.
+    pub fn describe(&self, table_name: &str) -> String {
+        let rows = estimate(self.estimated_rows);
+        let (column, name, lower, upper) = match &self.access {
+            Access::Scan => return format!("SCAN {} ({})", table_name, rows),
+            Access::Index {
+                column,
+                name,
+                lower,
+                upper,
+            } => (column, name, lower, upper),
+        };
+        let index = match name {
+            Some(name) => format!("INDEX {}", name),
+            None => "PRIMARY KEY".to_string(),
+        };
+        let condition = match (lower, upper) {
+            (Bound::Included(l), Bound::Included(u)) if l == u => format!("{}=?", column),
+            _ => {
+                let lower = match lower {
+                    Bound::Included(_) => Some(format!("{}>=?", column)),
+                    Bound::Excluded(_) => Some(format!("{}>?", column)),
+                    Bound::Unbounded => None,
+                };
+                let upper = match upper {
+                    Bound::Included(_) => Some(format!("{}<=?", column)),
+                    Bound::Excluded(_) => Some(format!("{}<?", column)),
+                    Bound::Unbounded => None,
+                };
+                lower
+                    .into_iter()
+                    .chain(upper)
+                    .collect::<Vec<_>>()
+                    .join(" AND ")
+            }
+        };
+        format!(
+            "SEARCH {} USING {} ({}) ({})",
+            table_name, index, condition, rows
+        )
+    }
+}
+
+/// A row estimate as EXPLAIN QUERY PLAN shows it.
+pub fn estimate(rows: usize) -> String {
+    match rows {
+        1 => "~1 row".to_string(),
+        n => format!("~{} rows", n),
+    }
+}
+
+/// The column or other expression a WHERE clause compares with literals,
+/// without its COLLATE, with the comparison's collation and the bounds the
+/// comparison puts on its values. None for a clause no index could answer.
+fn index_bounds(where_clause: &Expr) -> Option<(&Expr, Collation, Bound<Value>, Bound<Value>)> {
+    match where_clause {
+        // `id > 5 AND id < 10` reads the rows between the two. Otherwise the
+        // first conjunct with bounds is used, and the rest are checked row
+        // by row
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => match (index_bounds(left), index_bounds(right)) {
+            (Some(left), Some(right)) if left.0 == right.0 && left.1 == right.1 => {
+                let (operand, collation, lower, upper) = left;
+                let lower = match lower {
+                    Bound::Unbounded => right.2,
+                    lower => lower,
+                };
+                let upper = match upper {
+                    Bound::Unbounded => right.3,
+                    upper => upper,
+                };
+                Some((operand, collation, lower, upper))
+            }
+            (left, right) => left.or(right),
+        },
+        Expr::Binary { left, op, right } => {
+            let collation = comparison_collation(left, right);
+            // `5 < id` is read as `id > 5`
+            let (operand, op, value) = match (strip_collate(left), strip_collate(right)) {
+                (Expr::Literal(_), Expr::Literal(_)) => return None,
+                (operand, Expr::Literal(value)) => (operand, *op, value),
+                (Expr::Literal(value), operand) => {
+                    let op = match op {
+                        BinaryOp::Lt => BinaryOp::Gt,
+                        BinaryOp::LtEq => BinaryOp::GtEq,
+                        BinaryOp::Gt => BinaryOp::Lt,
+                        BinaryOp::GtEq => BinaryOp::LtEq,
+                        op => *op,
+                    };
+                    (operand, op, value)
+                }
+                _ => return None,
+            };
+            let value = value.clone();
+            let (lower, upper) = match op {
+                BinaryOp::Eq => (Bound::Included(value.clone()), Bound::Included(value)),
+                BinaryOp::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+                BinaryOp::LtEq => (Bound::Unbounded, Bound::Included(value)),
+                BinaryOp::Gt => (Bound::Excluded(value), Bound::Unbounded),
+                BinaryOp::GtEq => (Bound::Included(value), Bound::Unbounded),
+                _ => return None,
+            };
+            Some((operand, collation, lower, upper))
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } => {
+            let collation = comparison_collation(expr, low);
+            if comparison_collation(expr, high) != collation {
+                return None;
+            }
+            match (strip_collate(expr), strip_collate(low), strip_collate(high)) {
+                (Expr::Literal(_), _, _) => None,
+                (operand, Expr::Literal(low), Expr::Literal(high)) => Some((
+                    operand,
+                    collation,
+                    Bound::Included(low.clone()),
+                    Bound::Included(high.clone()),
+                )),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
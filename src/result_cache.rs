@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use crate::sql_parser::Value;
+
+const DEFAULT_MAX_ENTRIES: usize = 64;
+const DEFAULT_MAX_ROWS: usize = 10_000;
+
+/// The output of recent SELECTs, so that running one again can skip the
+/// scan. Entries are keyed by the parsed statement, so spacing and keyword
+/// case do not matter, and every statement that is not a SELECT empties
+/// the cache rather than working out which entries it could affect.
+pub struct ResultCache {
+    /// Turned on with `.cache on`; off to begin with.
+    pub enabled: bool,
+    pub max_entries: usize,
+    /// Results with more rows than this are not kept.
+    pub max_rows: usize,
+    /// Least recently used first.
+    entries: VecDeque<(String, CachedResult)>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+pub struct CachedResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultCache {
+    pub fn new() -> ResultCache {
+        ResultCache {
+            enabled: false,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_rows: DEFAULT_MAX_ROWS,
+            entries: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The cached result for a statement, counted as a hit or a miss. A hit
+    /// makes the entry the most recently used.
+    pub fn get(&mut self, key: &str) -> Option<&CachedResult> {
+        if !self.enabled {
+            return None;
+        }
+        match self.entries.iter().position(|(k, _)| k == key) {
+            Some(i) => {
+                self.hits += 1;
+                let entry = self.entries.remove(i)?;
+                self.entries.push_back(entry);
+                self.entries.back().map(|(_, result)| result)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps a result, evicting the least recently used entries to make
+    /// room.
+    pub fn insert(&mut self, key: String, result: CachedResult) {
+        if !self.enabled || result.rows.len() > self.max_rows {
+            return;
+        }
+        self.entries.retain(|(k, _)| *k != key);
+        self.entries.push_back((key, result));
+        self.evict();
+    }
+
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.evict();
+    }
+
+    pub fn set_max_rows(&mut self, max_rows: usize) {
+        self.max_rows = max_rows;
+        self.entries
+            .retain(|(_, result)| result.rows.len() <= max_rows);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    time::Instant,
+};
+
+use crate::{
+    csv,
+    database::{Database, Recovery},
+    executor::{build_insert_row, execute_statement, find_table, ExecResult},
+    output::{self, OutputMode},
+    page_cache::CacheStats,
+    sql_parser::{prepare_statement, PreparedStatement, Row, Value},
+    table::Table,
+};
+
+/// Runs the interactive shell on a database: reads SQL statements and
+/// dot-commands a line at a time from stdin, or from the scripts `.read`
+/// runs, and prints what they return, until `.exit` or the end of the
+/// input exits the process.
+pub fn run(mut db: Database) -> io::Result<()> {
+    // Aligned columns read best on screen; scripts keep the tuple format
+    db.output_mode = Some(if io::stdin().is_terminal() {
+        OutputMode::Column
+    } else {
+        OutputMode::Tuple
+    });
+
+    let mut lines = Input::default();
+    loop {
+        if lines.is_interactive() {
+            print_prompt();
+        }
+
+        let input = lines.read_line().unwrap_or_else(|| ".exit".to_string());
+
+        if input.starts_with('.') {
+            match do_meta_command(&input, &mut db, &mut lines) {
+                Ok(()) => continue,
+                Err(err) => {
+                    println!("Error executing meta command: {}", err);
+                    continue;
+                }
+            }
+        }
+        match prepare_statement(&input) {
+            Ok(statement) => {
+                let started = Instant::now();
+                match execute_statement(&statement, &mut db) {
+                    Ok(result) => print_result(&db, &result)?,
+                    Err(error) => println!("Error executing statement: {}", error),
+                }
+                if let Err(error) = db.slow_log.record(&input, started.elapsed(), &db.stats) {
+                    println!("Error writing slow query log: {}", error);
+                }
+            }
+            Err(error) => println!("Error: {}", error),
+        }
+    }
+}
+
+/// Prints what a statement returned, other than the SELECT rows printed as
+/// they were produced, and a line counting the rows it returned or changed.
+fn print_result(db: &Database, result: &ExecResult) -> io::Result<()> {
+    if !result.rows.is_empty() {
+        let mode = db.output_mode.unwrap_or(OutputMode::Tuple);
+        output::print_rows(mode, &result.columns, result.rows.iter().cloned().map(Ok))?;
+    }
+    for message in &result.messages {
+        println!("{}", message);
+    }
+    if let Some(summary) = result.summary() {
+        println!("{}", summary);
+    }
+    Ok(())
+}
+
+fn print_prompt() {
+    print!("db > ");
+    io::stdout().flush().unwrap();
+}
+
+/// Where the shell's lines come from: the scripts being run by `.read`,
+/// the innermost last, and then stdin.
+#[derive(Default)]
+struct Input {
+    scripts: Vec<std::vec::IntoIter<String>>,
+}
+
+impl Input {
+    /// Whether the next line comes from stdin, and so is prompted for.
+    fn is_interactive(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Reads one line from the innermost script, going back to the one that
+    /// ran it once it is done, or None once stdin is exhausted.
+    fn read_line(&mut self) -> Option<String> {
+        while let Some(script) = self.scripts.last_mut() {
+            match script.next() {
+                Some(line) => return Some(line.trim().to_string()),
+                None => {
+                    self.scripts.pop();
+                }
+            }
+        }
+        read_input()
+    }
+
+    /// Reads the lines of a script to run before going on.
+    fn push_script(&mut self, path: &str) -> io::Result<()> {
+        let lines: Vec<String> = fs::read_to_string(path)?
+            .lines()
+            .map(str::to_string)
+            .collect();
+        self.scripts.push(lines.into_iter());
+        Ok(())
+    }
+}
+
+/// Reads one line of input, or None once stdin is exhausted.
+fn read_input() -> Option<String> {
+    let mut input = String::new();
+    let bytes_read = io::stdin()
+        .read_line(&mut input)
+        .expect("Failed to read line");
+    if bytes_read == 0 {
+        return None;
+    }
+    Some(input.trim().to_string())
+}
+
+fn do_meta_command(input: &str, db: &mut Database, lines: &mut Input) -> io::Result<()> {
+    let args: Vec<&str> = input.split_whitespace().collect();
+    match args.as_slice() {
+        [".exit"] => {
+            db.close()?;
+            std::process::exit(0);
+        }
+        [".schema"] => {
+            for table in &db.tables {
+                println!("{}", table.schema_sql());
+                for sql in table.index_sql() {
+                    println!("{}", sql);
+                }
+            }
+            for view in &db.views {
+                println!("{}", view.schema_sql());
+            }
+            for trigger in &db.triggers {
+                println!("{}", trigger.schema_sql());
+            }
+            Ok(())
+        }
+        [".seed", table_name, columns, options @ ..] => {
+            let skip_errors = match options {
+                [] => false,
+                ["--skip-errors"] => true,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Usage: .seed TABLE COL1,COL2,... [--skip-errors]",
+                    ))
+                }
+            };
+            db.result_cache.clear();
+            seed_table(&mut db.tables, lines, table_name, columns, skip_errors)
+        }
+        [".read", path] => lines.push_script(path),
+        [".mode"] => {
+            println!("{}", db.output_mode.unwrap_or(OutputMode::Tuple).name());
+            Ok(())
+        }
+        [".mode", name] => {
+            db.output_mode = Some(OutputMode::parse(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Usage: .mode tuple|column|csv|json",
+                )
+            })?);
+            Ok(())
+        }
+        [".bulk"] => {
+            println!("{}", if db.bulk { "on" } else { "off" });
+            Ok(())
+        }
+        [".bulk", "on"] => {
+            db.set_bulk(true)?;
+            println!("Bulk mode on; rows are written at .bulk off or .exit, and lost if the process dies first");
+            Ok(())
+        }
+        [".bulk", "off"] => db.set_bulk(false),
+        [".cache"] => {
+            let cache = &db.result_cache;
+            println!("cache:   {}", if cache.enabled { "on" } else { "off" });
+            println!("entries: {} of {}", cache.len(), cache.max_entries);
+            println!("rows:    at most {} per entry", cache.max_rows);
+            println!("hits:    {}", cache.hits);
+            println!("misses:  {}", cache.misses);
+            Ok(())
+        }
+        [".cache", "on"] => {
+            db.result_cache.enabled = true;
+            Ok(())
+        }
+        [".cache", "off"] => {
+            db.result_cache.enabled = false;
+            db.result_cache.clear();
+            Ok(())
+        }
+        [".cache", setting @ ("entries" | "rows"), limit] => {
+            let limit = limit.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid {} limit: {}", setting, limit),
+                )
+            })?;
+            if *setting == "entries" {
+                db.result_cache.set_max_entries(limit);
+            } else {
+                db.result_cache.set_max_rows(limit);
+            }
+            Ok(())
+        }
+        [".cache", ..] => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: .cache [on|off|entries N|rows N]",
+        )),
+        [".pagecache"] => {
+            let mut stats = CacheStats::default();
+            let mut cached = 0;
+            for table in &db.tables {
+                stats += table.pager.cache.stats;
+                cached += table.pager.cache.len();
+            }
+            println!(
+                "pages:      {} cached, at most {} per table",
+                cached, db.cache_size
+            );
+            println!("hits:       {}", stats.hits);
+            println!("misses:     {}", stats.misses);
+            println!("evictions:  {}", stats.evictions);
+            println!("writebacks: {}", stats.writebacks);
+            Ok(())
+        }
+        [".stats"] => {
+            let stats = &db.stats;
+            println!("rows examined: {}", stats.rows_examined);
+            println!("rows matched:  {}", stats.rows_matched);
+            println!("rows returned: {}", stats.rows_returned);
+            println!("table scans:   {}", stats.table_scans);
+            println!(
+                "index used:    {}",
+                if stats.index_used { "yes" } else { "no" }
+            );
+            println!("pages read:    {}", stats.pages_read);
+            println!("pages written: {}", stats.pages_written);
+            Ok(())
+        }
+        [".run", sql_path] => run_prepared(db, sql_path, None),
+        [".run", sql_path, params_path] => run_prepared(db, sql_path, Some(params_path)),
+        [".checkpoint"] => db.checkpoint(),
+        [".dbinfo"] => print_db_info(&db.filename),
+        [".dbinfo", "--file", filename] => print_db_info(filename),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unrecognized command",
+        )),
+    }
+}
+
+/// Prepares the statement in a file once and runs it: once per record of
+/// the CSV file when one is given, with the record's fields bound to its
+/// placeholders, and otherwise once with none. A field that reads as an
+/// integer is bound as one and an empty unquoted field as NULL. Running
+/// stops at the first record that fails.
+fn run_prepared(db: &mut Database, sql_path: &str, params_path: Option<&str>) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let sql = fs::read_to_string(sql_path)?;
+    let prepared = PreparedStatement::new(sql.trim()).map_err(invalid)?;
+    let records = match params_path {
+        Some(path) => fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| csv::parse_record(line).map_err(invalid))
+            .collect::<io::Result<Vec<_>>>()?,
+        None => vec![Vec::new()],
+    };
+
+    let mut total: Option<ExecResult> = None;
+    for (i, record) in records.into_iter().enumerate() {
+        let params: Vec<Value> = record
+            .into_iter()
+            .map(|field| match field {
+                Some(field) => field.parse().map_or(Value::Text(field), Value::Integer),
+                None => Value::Null,
+            })
+            .collect();
+        let run = db
+            .execute_prepared(&prepared, &params)
+            .map_err(|e| invalid(format!("record {}: {}", i + 1, e)))?;
+        let total = total.get_or_insert_with(|| ExecResult::new(run.kind));
+        total.rows_returned += run.rows_returned;
+        total.rows_affected += run.rows_affected;
+        total.columns = run.columns;
+        total.rows.extend(run.rows);
+        total.messages.extend(run.messages);
+    }
+    match total {
+        Some(total) => print_result(db, &total),
+        None => Ok(()),
+    }
+}
+
+/// Reads CSV lines up to a lone "." and inserts them as one batch. Each
+/// field is text, or NULL when empty and unquoted, converted to its
+/// column's type as INSERT converts values. Unless errors are skipped, a
+/// malformed line or a rejected row leaves the table exactly as it was.
+fn seed_table(
+    tables: &mut [Table],
+    input: &mut Input,
+    table_name: &str,
+    columns: &str,
+    skip_errors: bool,
+) -> io::Result<()> {
+    let mut lines = Vec::new();
+    while let Some(line) = input.read_line() {
+        if line == "." {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let table = find_table(tables, table_name)?;
+    let columns: Vec<String> = columns.split(',').map(|c| c.trim().to_string()).collect();
+    if let Some(column) = columns
+        .iter()
+        .find(|column| !table.columns.iter().any(|c| &c.name == *column))
+    {
+        return Err(invalid(format!("no such column: {}", column)));
+    }
+
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+    for (line_num, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match seed_row(table, &columns, line) {
+            Ok(row) => rows.push(row),
+            Err(e) if skip_errors => {
+                println!("Skipping line {}: {}", line_num + 1, e);
+                skipped += 1;
+            }
+            Err(e) => return Err(invalid(format!("line {}: {}", line_num + 1, e))),
+        }
+    }
+
+    // Rows land in key order rather than at the end, so a failure is undone
+    // by holding every write back until the last row is in
+    let autocommit = table.pager.pending.is_none();
+    let savepoint = if autocommit {
+        table.begin()?;
+        None
+    } else {
+        Some(table.savepoint())
+    };
+    let mut inserted = 0;
+    for mut row in rows {
+        match table.insert(&mut row) {
+            Ok(_) => inserted += 1,
+            Err(e) if skip_errors => {
+                println!("Skipping row: {}", e);
+                skipped += 1;
+            }
+            Err(e) => {
+                match savepoint {
+                    Some(savepoint) => table.restore(savepoint),
+                    None => table.rollback()?,
+                }
+                return Err(invalid(format!("{}; no rows were seeded", e)));
+            }
+        }
+    }
+    match savepoint {
+        Some(savepoint) => table.release(savepoint),
+        None => table.commit()?,
+    }
+
+    println!("Seeded {} rows into {}", inserted, table.name);
+    if skipped > 0 {
+        println!("Skipped {} rows", skipped);
+    }
+    Ok(())
+}
+
+/// The row a line of CSV seeds, its fields read as INSERT reads values.
+fn seed_row(table: &Table, columns: &[String], line: &str) -> io::Result<Row> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let fields = csv::parse_record(line).map_err(invalid)?;
+    if fields.len() != columns.len() {
+        return Err(invalid(format!(
+            "expected {} fields but found {}",
+            columns.len(),
+            fields.len()
+        )));
+    }
+    let values: Vec<Value> = fields
+        .into_iter()
+        .map(|field| field.map_or(Value::Null, Value::Text))
+        .collect();
+    let mut row = build_insert_row(table, columns, &values)?;
+    table.apply_types(&mut row)?;
+    Ok(row)
+}
+
+fn print_db_info(filename: &str) -> io::Result<()> {
+    let info = Database::peek(filename)?;
+    println!("database:    {}", filename);
+    println!("format:      {}", info.format_version);
+    println!("page size:   {}", info.page_size);
+    println!("journal:     {}", if info.wal { "wal" } else { "rollback" });
+    println!("page count:  {}", info.page_count);
+    println!("table count: {}", info.table_count);
+    println!("changes:     {}", info.change_counter);
+    if info.recovery.is_empty() {
+        println!("recovery:    none");
+    }
+    for recovery in &info.recovery {
+        match recovery {
+            Recovery::Wal { path, frames } => {
+                println!("recovery:    {} frames in {}", frames, path)
+            }
+            Recovery::Journal { path } => println!("recovery:    journal {}", path),
+        }
+    }
+    Ok(())
+}
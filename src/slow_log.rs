@@ -0,0 +1,160 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::database::StatementStats;
+use crate::sql_parser::redact_literals;
+
+/// Once the log grows past this size it is rotated to `<path>.1`, so the
+/// log can be left enabled indefinitely.
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Records statements that take longer than a configurable threshold.
+pub struct SlowQueryLog {
+    /// Statements at or above this duration are logged; None disables the log.
+    pub threshold: Option<Duration>,
+    pub path: String,
+    /// Whether literal values are written as-is or replaced with `?`.
+    pub include_literals: bool,
+    pub max_bytes: u64,
+}
+
+impl SlowQueryLog {
+    pub fn new(path: String) -> SlowQueryLog {
+        SlowQueryLog {
+            threshold: None,
+            path,
+            include_literals: false,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    pub fn record(&self, sql: &str, elapsed: Duration, stats: &StatementStats) -> io::Result<()> {
+        match self.threshold {
+            Some(threshold) if elapsed >= threshold => {}
+            _ => return Ok(()),
+        }
+
+        self.rotate_if_full()?;
+
+        let sql = if self.include_literals {
+            sql.to_string()
+        } else {
+            redact_literals(sql)
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(
+            file,
+            "time={} duration_ms={:.3} rows_examined={} rows_returned={} pages_read={} pages_written={} index_used={} sql={:?}",
+            timestamp,
+            elapsed.as_secs_f64() * 1000.0,
+            stats.rows_examined,
+            stats.rows_returned,
+            stats.pages_read,
+            stats.pages_written,
+            if stats.index_used { "yes" } else { "no" },
+            sql
+        )
+    }
+
+    fn rotate_if_full(&self) -> io::Result<()> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() >= self.max_bytes => {
+                fs::rename(&self.path, format!("{}.1", self.path))
+            }
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// A log at a path of the test's own, with nothing there yet, that
+    /// records every statement.
+    fn log(name: &str) -> SlowQueryLog {
+        let path = env::temp_dir().join(format!("bugdb-slow-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(format!("{}.1", path.display()));
+        let mut log = SlowQueryLog::new(path.to_str().unwrap().to_string());
+        log.threshold = Some(Duration::ZERO);
+        log
+    }
+
+    fn record(log: &SlowQueryLog, sql: &str, elapsed: Duration) {
+        log.record(sql, elapsed, &StatementStats::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn only_statements_past_the_threshold_are_logged() {
+        let mut log = log("threshold");
+        log.threshold = None;
+        record(&log, "select 1", Duration::from_secs(10));
+        assert!(fs::metadata(&log.path).is_err());
+
+        log.threshold = Some(Duration::from_millis(50));
+        record(&log, "select 1", Duration::from_millis(49));
+        record(&log, "select 2", Duration::from_millis(50));
+        let contents = fs::read_to_string(&log.path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("duration_ms=50.000"), "{}", contents);
+        fs::remove_file(&log.path).unwrap();
+    }
+
+    #[test]
+    fn literals_are_redacted_unless_asked_for() {
+        let mut log = log("literals");
+        record(
+            &log,
+            "select * from users where name = 'secret' and id = 42",
+            Duration::ZERO,
+        );
+        log.include_literals = true;
+        record(&log, "select * from users where id = 42", Duration::ZERO);
+        let contents = fs::read_to_string(&log.path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert!(
+            lines[0].ends_with(r#"sql="select * from users where name = ? and id = ?""#),
+            "{}",
+            lines[0]
+        );
+        assert!(!lines[0].contains("secret"));
+        assert!(lines[1].ends_with(r#"sql="select * from users where id = 42""#));
+        fs::remove_file(&log.path).unwrap();
+    }
+
+    #[test]
+    fn a_full_log_is_rotated_before_the_next_entry() {
+        let log = log("rotate");
+        assert_eq!(log.max_bytes, 1024 * 1024);
+        fs::write(&log.path, vec![b'x'; 1024 * 1024 - 1]).unwrap();
+        record(&log, "select * from a", Duration::ZERO);
+        // Not yet full when the entry was written, so it was appended
+        assert!(fs::metadata(format!("{}.1", log.path)).is_err());
+
+        record(&log, "select * from b", Duration::ZERO);
+        let rotated = fs::read_to_string(format!("{}.1", log.path)).unwrap();
+        assert!(rotated.ends_with("sql=\"select * from a\"\n"));
+        let contents = fs::read_to_string(&log.path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("sql=\"select * from b\""));
+        fs::remove_file(&log.path).unwrap();
+        fs::remove_file(format!("{}.1", log.path)).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::PathBuf,
+    vec,
+};
+
+use crate::sql_parser::Value;
+
+/// How many bytes of rows a sort holds in memory before spilling them to a
+/// temporary file.
+const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+thread_local! {
+    /// Set with `PRAGMA sort_memory = BYTES`.
+    static MEMORY_BUDGET: Cell<usize> = const { Cell::new(DEFAULT_MEMORY_BUDGET) };
+    /// Numbers the temporary files, which are named after the process so
+    /// that shells sharing a temporary directory do not collide.
+    static NEXT_RUN: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn memory_budget() -> usize {
+    MEMORY_BUDGET.with(Cell::get)
+}
+
+pub fn set_memory_budget(bytes: usize) {
+    MEMORY_BUDGET.with(|budget| budget.set(bytes));
+}
+
+/// Sorts rows that may not fit in memory. Rows are gathered until they
+/// pass the memory budget, then sorted and written out as a run; `finish`
+/// merges the runs back. The sort is stable: rows that compare equal come
+/// out in the order they were pushed.
+pub struct Sorter<F> {
+    compare: F,
+    budget: usize,
+    rows: Vec<Vec<Value>>,
+    /// Roughly the memory `rows` takes up.
+    bytes: usize,
+    runs: Vec<Run>,
+}
+
+impl<F: Fn(&[Value], &[Value]) -> Ordering> Sorter<F> {
+    pub fn new(compare: F) -> Sorter<F> {
+        Sorter {
+            compare,
+            budget: memory_budget(),
+            rows: Vec::new(),
+            bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.bytes += row_bytes(&row);
+        self.rows.push(row);
+        if self.bytes > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// The rows in sorted order. When nothing was spilled they are sorted
+    /// in memory; otherwise the rows still held become one more run, read
+    /// from memory, and each row read takes the least of the runs' next
+    /// rows, the earliest run winning ties.
+    pub fn finish(mut self) -> io::Result<Merge<F>> {
+        self.rows.sort_by(|a, b| (self.compare)(a, b));
+        let mut sources: Vec<Source> = Vec::with_capacity(self.runs.len() + 1);
+        for run in self.runs.drain(..) {
+            sources.push(Source::File(run.open()?));
+        }
+        sources.push(Source::Memory(std::mem::take(&mut self.rows).into_iter()));
+        let mut heads = Vec::with_capacity(sources.len());
+        for source in &mut sources {
+            heads.push(source.next_row()?);
+        }
+        Ok(Merge {
+            compare: self.compare,
+            sources,
+            heads,
+        })
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.rows.sort_by(|a, b| (self.compare)(a, b));
+        let (run, file) = Run::create()?;
+        let mut writer = BufWriter::new(file);
+        for row in self.rows.drain(..) {
+            write_row(&mut writer, &row)?;
+        }
+        writer.flush()?;
+        self.runs.push(run);
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+/// Yields a finished sort's rows, merging its runs as it goes.
+pub struct Merge<F> {
+    compare: F,
+    sources: Vec<Source>,
+    /// The next row of each source, None once it is used up.
+    heads: Vec<Option<Vec<Value>>>,
+}
+
+impl<F: Fn(&[Value], &[Value]) -> Ordering> Iterator for Merge<F> {
+    type Item = io::Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<Value>>> {
+        let mut least: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some(row) = head else { continue };
+            let less = match least.and_then(|l| self.heads[l].as_ref()) {
+                Some(best) => (self.compare)(row, best) == Ordering::Less,
+                None => true,
+            };
+            if less {
+                least = Some(i);
+            }
+        }
+        let i = least?;
+        let row = self.heads[i].take();
+        match self.sources[i].next_row() {
+            Ok(next) => self.heads[i] = next,
+            Err(e) => return Some(Err(e)),
+        }
+        row.map(Ok)
+    }
+}
+
+/// A sorted run written to a temporary file, which is deleted when the run
+/// is dropped, whether the sort finished or failed part way.
+struct Run {
+    path: PathBuf,
+}
+
+impl Run {
+    fn create() -> io::Result<(Run, File)> {
+        let n = NEXT_RUN.with(|next| next.replace(next.get() + 1));
+        let path = std::env::temp_dir().join(format!("bugdb-sort-{}-{}", std::process::id(), n));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok((Run { path }, file))
+    }
+
+    fn open(self) -> io::Result<RunReader> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(RunReader { _run: self, reader })
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct RunReader {
+    /// Held so that the file is deleted along with the reader.
+    _run: Run,
+    reader: BufReader<File>,
+}
+
+enum Source {
+    File(RunReader),
+    Memory(vec::IntoIter<Vec<Value>>),
+}
+
+impl Source {
+    fn next_row(&mut self) -> io::Result<Option<Vec<Value>>> {
+        match self {
+            Source::File(file) => read_row(&mut file.reader),
+            Source::Memory(rows) => Ok(rows.next()),
+        }
+    }
+}
+
+/// An estimate of the memory a row takes up.
+fn row_bytes(row: &[Value]) -> usize {
+    row.iter()
+        .map(|value| match value {
+            Value::Text(s) => std::mem::size_of::<Value>() + s.len(),
+            _ => std::mem::size_of::<Value>(),
+        })
+        .sum::<usize>()
+        + std::mem::size_of::<Vec<Value>>()
+}
+
+/// Writes a row as its value count followed by each value: a type tag,
+/// then a little-endian integer or a length-prefixed string, as in a table
+/// slot. Unlike a slot the text may be of any length, since output values
+/// such as `upper(...)` results are not bound by a column's width.
+fn write_row(writer: &mut impl Write, row: &[Value]) -> io::Result<()> {
+    writer.write_all(&(row.len() as u32).to_le_bytes())?;
+    for value in row {
+        match value {
+            Value::Null => writer.write_all(&[0])?,
+            Value::Integer(i) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&i.to_le_bytes())?;
+            }
+            Value::Text(s) => {
+                writer.write_all(&[2])?;
+                writer.write_all(&(s.len() as u32).to_le_bytes())?;
+                writer.write_all(s.as_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a row written by `write_row`, or None at the end of the file.
+fn read_row(reader: &mut impl Read) -> io::Result<Option<Vec<Value>>> {
+    let mut count = [0; 4];
+    match reader.read_exact(&mut count) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let count = u32::from_le_bytes(count) as usize;
+    let mut row = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut tag = [0; 1];
+        reader.read_exact(&mut tag)?;
+        row.push(match tag[0] {
+            0 => Value::Null,
+            1 => {
+                let mut bytes = [0; 8];
+                reader.read_exact(&mut bytes)?;
+                Value::Integer(i64::from_le_bytes(bytes))
+            }
+            2 => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+                reader.read_exact(&mut bytes)?;
+                Value::Text(String::from_utf8(bytes).map_err(|e| {
+                    io::Error::new(ErrorKind::InvalidData, format!("corrupt sort run: {}", e))
+                })?)
+            }
+            tag => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("corrupt sort run: unknown value tag {}", tag),
+                ))
+            }
+        });
+    }
+    Ok(Some(row))
+}
@@ -1,33 +1,314 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StatementType {
     Insert,
     Select,
+    CreateTable,
+    DropTable,
+    CreateView,
+    DropView,
+    Truncate,
+    Update,
+    Delete,
+    CreateIndex,
+    DropIndex,
+    CreateTrigger,
+    DropTrigger,
+    Begin,
+    Commit,
+    Rollback,
+    Pragma,
+    Explain,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Integer(i64),
     Text(String),
+    Null,
 }
 
-#[derive(Clone)]
+impl Value {
+    /// Appends a canonical encoding of the value to `key`, for grouping
+    /// rows by value. A type tag comes first, so 1 and '1' differ, and text
+    /// is length-prefixed so a sequence of values encodes unambiguously.
+    pub fn encode_key(&self, key: &mut Vec<u8>) {
+        match self {
+            Value::Null => key.push(0),
+            Value::Integer(i) => {
+                key.push(1);
+                key.extend_from_slice(&i.to_be_bytes());
+            }
+            Value::Text(s) => {
+                key.push(2);
+                key.extend_from_slice(&(s.len() as u64).to_be_bytes());
+                key.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DataType {
+    Integer,
+    Text,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColumnDef {
+    pub name: String,
+    pub data_type: DataType,
+    pub primary_key: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// Evaluated for each insert that leaves the column out.
+    pub default: Option<Expr>,
+    pub autoincrement: bool,
+    pub check: Option<Expr>,
+    /// How the column's text compares, set with `COLLATE`.
+    pub collation: Collation,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Row {
     pub values: HashMap<String, Value>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Statement {
     pub statement_type: StatementType,
     pub table_name: String,
     pub columns: Vec<String>,
-    pub values: Vec<Value>,
-    pub where_clause: Option<WhereClause>,
+    /// The expressions of each of an INSERT's VALUES tuples, evaluated
+    /// without a row.
+    pub values: Vec<Vec<Expr>>,
+    pub where_clause: Option<Expr>,
+    pub column_defs: Vec<ColumnDef>,
+    /// IF NOT EXISTS on CREATE TABLE, or IF EXISTS on DROP TABLE.
+    pub if_exists: bool,
+    pub on_conflict: Option<OnConflict>,
+    /// An UPDATE's `SET column = expr` list, in the order written.
+    pub assignments: Vec<(String, Expr)>,
+    pub pragma: Option<Pragma>,
+    /// A second table joined to `table_name` by a SELECT.
+    pub join: Option<Join>,
+    /// The expressions a SELECT outputs; `*` is a column named `*`.
+    pub projection: Vec<Expr>,
+    /// SELECT DISTINCT, which drops repeats of an output row.
+    pub distinct: bool,
+    /// What RETURNING outputs, in the same form; empty when there is none.
+    pub returning: Vec<Expr>,
+    /// The SELECT whose rows an INSERT writes, in place of VALUES, or that
+    /// defines a view; the INSERT a trigger runs; or the statement EXPLAIN
+    /// QUERY PLAN describes.
+    pub query: Option<Box<Statement>>,
+    /// The text of a view's SELECT or a trigger's INSERT, as saved to the
+    /// catalog.
+    pub query_sql: String,
+    /// The index CREATE INDEX or DROP INDEX names. CREATE INDEX keeps the
+    /// table in `table_name` and the column or expression it indexes as the
+    /// one expression of `projection`.
+    pub index_name: String,
+    /// The trigger CREATE TRIGGER or DROP TRIGGER names. CREATE TRIGGER
+    /// keeps the table it watches in `table_name`.
+    pub trigger_name: String,
+    /// GROUP BY expressions; a SELECT with them outputs a row per group.
+    pub group_by: Vec<Expr>,
+    /// The condition a group must meet to be output, over its GROUP BY
+    /// values and aggregates. Without GROUP BY the whole table is one group.
+    pub having: Option<Expr>,
+    /// SELECTs combined with this one by UNION, applied left to right.
+    pub compound: Vec<Compound>,
+    /// ORDER BY terms, most significant first. They sort the output of the
+    /// whole statement, after any UNION.
+    pub order_by: Vec<OrderBy>,
+    /// The most rows a SELECT produces; None for no limit.
+    pub limit: Option<usize>,
+    /// Rows of output skipped before LIMIT applies.
+    pub offset: usize,
+}
+
+/// `UNION [ALL] SELECT ...`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Compound {
+    /// UNION ALL keeps duplicate rows; plain UNION removes them.
+    pub all: bool,
+    pub select: Statement,
+}
+
+/// One ORDER BY term.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderBy {
+    pub key: SortKey,
+    pub descending: bool,
+    /// Whether NULLs sort before other values. Without NULLS FIRST or
+    /// NULLS LAST they are the smallest values, so first unless DESC.
+    pub nulls_first: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SortKey {
+    /// A 1-based index into the select list, as in `ORDER BY 2`. The range
+    /// is checked when the statement runs.
+    Position(usize),
+    Expr(Expr),
 }
 
-pub struct WhereClause {
+/// `JOIN table ON condition`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Join {
+    pub table_name: String,
+    pub on: Expr,
+}
+
+/// `PRAGMA name` reads a setting, `PRAGMA name = value` changes it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pragma {
+    pub name: String,
+    pub value: Option<Value>,
+}
+
+/// What an INSERT does when it collides with an existing row on a primary
+/// key or UNIQUE column.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OnConflict {
+    Replace,
+    DoNothing,
+    DoUpdate {
+        /// Columns named in `ON CONFLICT (...)`; empty matches any conflict.
+        target: Vec<String>,
+        assignments: Vec<(String, Expr)>,
+    },
+}
+
+/// A column reference, optionally qualified with its table as `t.col`.
+/// `*` and `t.*` are represented with a column name of `*`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColumnRef {
+    pub table: Option<String>,
     pub column: String,
-    pub operator: String,
-    pub value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expr {
+    Column(ColumnRef),
+    Literal(Value),
+    /// A `?` or `?NNN` placeholder, numbered from 1, whose value
+    /// `PreparedStatement::bind` supplies.
+    Param(usize),
+    /// A parenthesized row value such as `(a, b)`, only meaningful as an
+    /// operand of a comparison or IN.
+    Tuple(Vec<Expr>),
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    In {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        /// NOT IN
+        negated: bool,
+    },
+    /// `expr IN (SELECT ...)`; `sql` is the subquery's text, kept for
+    /// display.
+    InSelect {
+        expr: Box<Expr>,
+        query: Box<Statement>,
+        sql: String,
+        negated: bool,
+    },
+    /// `EXISTS (SELECT ...)`
+    Exists {
+        query: Box<Statement>,
+        sql: String,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    /// `CASE [operand] WHEN .. THEN .. [ELSE ..] END`. Without an operand
+    /// each WHEN is a condition; with one, each WHEN is compared to it.
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        else_result: Option<Box<Expr>>,
+    },
+    /// A call such as `count(*)`; a `*` argument is a star column.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        /// `count(DISTINCT x)`: an aggregate fed each value only once.
+        distinct: bool,
+    },
+    /// `expr COLLATE name`, which has the value of `expr` but compares as
+    /// the collation says. References to a NOCASE column are wrapped in one
+    /// before a statement runs.
+    Collate {
+        expr: Box<Expr>,
+        collation: Collation,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Like,
+    NotLike,
+    Concat,
+}
+
+/// How text values compare, for a column or with `expr COLLATE name`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Ignoring case, by comparing the lowercase forms. Lowercasing is
+    /// Unicode-aware but maps one character at a time, so `'ß'` and `'SS'`
+    /// still differ.
+    NoCase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOp {
+    Neg,
+    Not,
 }
 
 impl Row {
@@ -38,227 +319,2243 @@ impl Row {
     }
 }
 
-pub fn prepare_statement(input: &str) -> Result<Statement, String> {
-    let tokens = tokenize(input);
-    println!("Tokens: {:?}", tokens);
+impl ColumnRef {
+    /// Parses `col`, `table.col`, `*` or `table.*`, where either name may
+    /// be double-quoted. Returns None for a token that is not a column.
+    fn parse(token: &str) -> Result<Option<ColumnRef>, String> {
+        let (table, column) = match split_qualified(token) {
+            Some((table, column)) => match name_part(table)? {
+                Some(table) => (Some(table), column),
+                None => return Ok(None),
+            },
+            None => (None, token),
+        };
+        if column == "*" {
+            return Ok(Some(ColumnRef {
+                table,
+                column: column.to_string(),
+            }));
+        }
+        Ok(name_part(column)?.map(|column| ColumnRef { table, column }))
+    }
 
-    match tokens.get(0).map(|s| s.to_lowercase()).as_deref() {
-        Some("insert") => parse_insert(&tokens),
-        Some("select") => parse_select(&tokens),
-        _ => Err("Unrecognized keyword at start of statement".to_string()),
+    pub fn is_star(&self) -> bool {
+        self.column == "*"
     }
 }
 
-fn tokenize(input: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut current_token = String::new();
-    let mut in_quotes = false;
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let column = if self.is_star() {
+            self.column.clone()
+        } else {
+            quote_name(&self.column)
+        };
+        match &self.table {
+            Some(table) => write!(f, "{}.{}", quote_name(table), column),
+            None => write!(f, "{}", column),
+        }
+    }
+}
 
-    for ch in input.chars() {
-        match ch {
-            '\'' => {
-                current_token.push(ch);
-                in_quotes = !in_quotes;
+impl Expr {
+    /// Whether this is the `*` or `t.*` of a projection.
+    pub fn is_star(&self) -> bool {
+        matches!(self, Expr::Column(column) if column.is_star())
+    }
+
+    /// Every column the expression refers to.
+    pub fn column_refs(&self) -> Vec<&ColumnRef> {
+        match self {
+            Expr::Column(column) => vec![column],
+            Expr::Literal(_) | Expr::Param(_) | Expr::Exists { .. } => Vec::new(),
+            Expr::Tuple(items) => items.iter().flat_map(|e| e.column_refs()).collect(),
+            Expr::Binary { left, right, .. } => {
+                let mut refs = left.column_refs();
+                refs.extend(right.column_refs());
+                refs
+            }
+            Expr::In { expr, list, .. } => {
+                let mut refs = expr.column_refs();
+                refs.extend(list.iter().flat_map(|e| e.column_refs()));
+                refs
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => [expr, low, high]
+                .into_iter()
+                .flat_map(|e| e.column_refs())
+                .collect(),
+            // The subquery's columns belong to its own table
+            Expr::InSelect { expr, .. } | Expr::Unary { expr, .. } | Expr::Collate { expr, .. } => {
+                expr.column_refs()
+            }
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => operand
+                .iter()
+                .map(|e| e.as_ref())
+                .chain(branches.iter().flat_map(|(when, then)| [when, then]))
+                .chain(else_result.iter().map(|e| e.as_ref()))
+                .flat_map(|e| e.column_refs())
+                .collect(),
+            Expr::Function { args, .. } => args
+                .iter()
+                .filter(|arg| !arg.is_star())
+                .flat_map(|arg| arg.column_refs())
+                .collect(),
+        }
+    }
+
+    /// The expressions directly inside this one. Subqueries are not
+    /// included, only the operand an IN (SELECT ...) tests.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Exists { .. } => Vec::new(),
+            Expr::Tuple(items) | Expr::Function { args: items, .. } => items.iter().collect(),
+            Expr::Binary { left, right, .. } => vec![left.as_ref(), right.as_ref()],
+            Expr::In { expr, list, .. } => std::iter::once(expr.as_ref()).chain(list).collect(),
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr.as_ref(), low.as_ref(), high.as_ref()],
+            Expr::InSelect { expr, .. } | Expr::Unary { expr, .. } | Expr::Collate { expr, .. } => {
+                vec![expr.as_ref()]
+            }
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => operand
+                .iter()
+                .map(|e| e.as_ref())
+                .chain(branches.iter().flat_map(|(when, then)| [when, then]))
+                .chain(else_result.iter().map(|e| e.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// As `children`, but mutable.
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Exists { .. } => Vec::new(),
+            Expr::Tuple(items) | Expr::Function { args: items, .. } => items.iter_mut().collect(),
+            Expr::Binary { left, right, .. } => vec![left.as_mut(), right.as_mut()],
+            Expr::In { expr, list, .. } => std::iter::once(expr.as_mut())
+                .chain(list.iter_mut())
+                .collect(),
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr.as_mut(), low.as_mut(), high.as_mut()],
+            Expr::InSelect { expr, .. } | Expr::Unary { expr, .. } | Expr::Collate { expr, .. } => {
+                vec![expr.as_mut()]
+            }
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => operand
+                .iter_mut()
+                .map(|e| e.as_mut())
+                .chain(branches.iter_mut().flat_map(|(when, then)| [when, then]))
+                .chain(else_result.iter_mut().map(|e| e.as_mut()))
+                .collect(),
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Like => "like",
+            BinaryOp::NotLike => "not like",
+            BinaryOp::Concat => "||",
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Concat => 4,
+            BinaryOp::Add | BinaryOp::Sub => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 7,
+            _ => 5,
+        }
+    }
+}
+
+impl UnaryOp {
+    /// NOT binds looser than comparisons and || but tighter than AND; unary
+    /// minus binds tighter than any binary operator.
+    fn precedence(&self) -> u8 {
+        match self {
+            UnaryOp::Not => 3,
+            UnaryOp::Neg => 8,
+        }
+    }
+}
+
+/// Renders the expression back into SQL that parses to the same tree, so
+/// it can be persisted with the schema and quoted in error messages.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Column(column) => write!(f, "{}", column),
+            Expr::Literal(value) => write!(f, "{}", value_to_sql(value)),
+            Expr::Param(number) => write!(f, "?{}", number),
+            Expr::Tuple(items) => write!(f, "({})", join_exprs(items)),
+            Expr::Binary { left, op, right } => {
+                write_operand(f, left, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                write_operand(f, right, op.precedence() + 1)
+            }
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => {
+                write_operand(f, expr, 6)?;
+                write!(f, " {}in ({})", not_prefix(*negated), join_exprs(list))
+            }
+            Expr::InSelect {
+                expr, sql, negated, ..
+            } => {
+                write_operand(f, expr, 6)?;
+                write!(f, " {}in ({})", not_prefix(*negated), sql)
             }
-            ' ' | ',' | '(' | ')' if !in_quotes => {
-                if !current_token.is_empty() {
-                    tokens.push(current_token);
-                    current_token = String::new();
+            Expr::Exists { sql, .. } => write!(f, "exists ({})", sql),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                write_operand(f, expr, 6)?;
+                write!(f, " {}between ", not_prefix(*negated))?;
+                write_operand(f, low, 6)?;
+                write!(f, " and ")?;
+                write_operand(f, high, 6)
+            }
+            Expr::Unary { op, expr } => {
+                match op {
+                    UnaryOp::Neg => write!(f, "- ")?,
+                    UnaryOp::Not => write!(f, "not ")?,
+                }
+                write_operand(f, expr, op.precedence())
+            }
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                write!(f, "case")?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand)?;
+                }
+                for (when, then) in branches {
+                    write!(f, " when {} then {}", when, then)?;
                 }
-                if ch != ' ' {
-                    tokens.push(ch.to_string());
+                if let Some(else_result) = else_result {
+                    write!(f, " else {}", else_result)?;
                 }
+                write!(f, " end")
+            }
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => {
+                let distinct = if *distinct { "distinct " } else { "" };
+                write!(f, "{}({}{})", name, distinct, join_exprs(args))
+            }
+            Expr::Collate { expr, collation } => {
+                // COLLATE binds tighter than any operator
+                write_operand(f, expr, u8::MAX)?;
+                write!(f, " collate {}", collation.name())
             }
-            _ => current_token.push(ch),
         }
     }
+}
 
-    if !current_token.is_empty() {
-        tokens.push(current_token);
+/// Writes a sub-expression, parenthesized when it binds more loosely than
+/// its surroundings require.
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, min_precedence: u8) -> fmt::Result {
+    let precedence = match expr {
+        Expr::Binary { op, .. } => op.precedence(),
+        Expr::In { .. } | Expr::InSelect { .. } | Expr::Between { .. } => 5,
+        Expr::Unary { op, .. } => op.precedence(),
+        _ => u8::MAX,
+    };
+    if precedence < min_precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
     }
+}
 
-    tokens
+fn not_prefix(negated: bool) -> &'static str {
+    if negated {
+        "not "
+    } else {
+        ""
+    }
 }
 
-fn parse_insert(tokens: &[String]) -> Result<Statement, String> {
-    println!("Parsing INSERT statement: {:?}", tokens);
+fn join_exprs(exprs: &[Expr]) -> String {
+    exprs
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    if tokens.len() < 7 || tokens[1].to_lowercase() != "into" {
-        return Err("Invalid Insert Statement".to_string());
+impl Statement {
+    /// Every expression written in the statement's own clauses. Those
+    /// nested inside them, and in subqueries, are not included.
+    pub fn exprs_mut(&mut self) -> impl Iterator<Item = &mut Expr> {
+        let assignments = match &mut self.on_conflict {
+            Some(OnConflict::DoUpdate { assignments, .. }) => assignments.as_mut_slice(),
+            _ => &mut [],
+        };
+        self.projection
+            .iter_mut()
+            .chain(self.where_clause.iter_mut())
+            .chain(self.join.iter_mut().map(|join| &mut join.on))
+            .chain(self.group_by.iter_mut())
+            .chain(self.having.iter_mut())
+            .chain(self.values.iter_mut().flatten())
+            .chain(assignments.iter_mut().map(|(_, expr)| expr))
+            .chain(self.assignments.iter_mut().map(|(_, expr)| expr))
+            .chain(self.returning.iter_mut())
+            .chain(
+                self.order_by
+                    .iter_mut()
+                    .filter_map(|term| match &mut term.key {
+                        SortKey::Expr(expr) => Some(expr),
+                        SortKey::Position(_) => None,
+                    }),
+            )
     }
 
-    let table_name = tokens[2].clone();
-    let mut i = 3;
+    fn new(statement_type: StatementType, table_name: String) -> Statement {
+        Statement {
+            statement_type,
+            table_name,
+            columns: vec![],
+            values: vec![],
+            where_clause: None,
+            column_defs: vec![],
+            if_exists: false,
+            on_conflict: None,
+            assignments: Vec::new(),
+            pragma: None,
+            join: None,
+            projection: Vec::new(),
+            distinct: false,
+            returning: Vec::new(),
+            query: None,
+            query_sql: String::new(),
+            index_name: String::new(),
+            trigger_name: String::new(),
+            compound: Vec::new(),
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: 0,
+        }
+    }
+}
 
-    // Ensure the next token is an opening parenthesis
-    if tokens[i] != "(" {
-        return Err("Expected '(' after table name".to_string());
+impl DataType {
+    fn parse(s: &str) -> Result<DataType, String> {
+        match s.to_lowercase().as_str() {
+            "integer" | "int" => Ok(DataType::Integer),
+            "text" => Ok(DataType::Text),
+            _ => Err(format!("Unknown column type: {}", s)),
+        }
     }
-    i += 1;
 
-    // Parse columns
-    let mut columns = Vec::new();
-    while i < tokens.len() && tokens[i] != ")" {
-        if tokens[i] != "," {
-            columns.push(tokens[i].clone());
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataType::Integer => "integer",
+            DataType::Text => "text",
         }
-        i += 1;
     }
+}
 
-    // Ensure we found the closing parenthesis
-    if i >= tokens.len() || tokens[i] != ")" {
-        return Err("Expected ')' after columns".to_string());
+impl Collation {
+    fn parse(s: &str) -> Result<Collation, String> {
+        match s.to_lowercase().as_str() {
+            "binary" => Ok(Collation::Binary),
+            "nocase" => Ok(Collation::NoCase),
+            _ => Err(format!("no such collation sequence: {}", s)),
+        }
     }
-    i += 1;
 
-    // Check for VALUES keyword
-    if i >= tokens.len() || tokens[i].to_lowercase() != "values" {
-        return Err("Expected 'VALUES' keyword".to_string());
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::NoCase => "nocase",
+        }
     }
-    i += 1;
 
-    // Ensure the next token is an opening parenthesis
-    if i >= tokens.len() || tokens[i] != "(" {
-        return Err("Expected '(' after VALUES".to_string());
+    /// The form of a value that compares bytewise as the collation
+    /// compares the value itself.
+    pub fn fold(&self, value: &Value) -> Value {
+        match (self, value) {
+            (Collation::NoCase, Value::Text(s)) => Value::Text(s.to_lowercase()),
+            _ => value.clone(),
+        }
     }
-    i += 1;
+}
 
-    // Parse values
-    let mut value_strings = Vec::new();
-    while i < tokens.len() && tokens[i] != ")" {
-        if tokens[i] != "," {
-            value_strings.push(tokens[i].clone());
+impl ColumnDef {
+    pub fn new(name: &str, data_type: DataType) -> ColumnDef {
+        ColumnDef {
+            name: name.to_string(),
+            data_type,
+            primary_key: false,
+            not_null: false,
+            unique: false,
+            default: None,
+            autoincrement: false,
+            check: None,
+            collation: Collation::Binary,
         }
-        i += 1;
     }
 
-    // Ensure we found the closing parenthesis
-    if i >= tokens.len() || tokens[i] != ")" {
-        return Err("Expected ')' after values".to_string());
+    /// Renders the definition back into the form accepted by CREATE TABLE.
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!("{} {}", quote_name(&self.name), self.data_type.name());
+        if self.primary_key {
+            sql.push_str(" primary key");
+        }
+        if self.autoincrement {
+            sql.push_str(" autoincrement");
+        }
+        if self.not_null {
+            sql.push_str(" not null");
+        }
+        if self.unique {
+            sql.push_str(" unique");
+        }
+        match &self.default {
+            Some(Expr::Literal(value)) => {
+                sql.push_str(&format!(" default {}", value_to_sql(value)))
+            }
+            Some(expr) => sql.push_str(&format!(" default ({})", expr)),
+            None => {}
+        }
+        if let Some(check) = &self.check {
+            sql.push_str(&format!(" check ({})", check));
+        }
+        if self.collation != Collation::Binary {
+            sql.push_str(&format!(" collate {}", self.collation.name()));
+        }
+        sql
     }
+}
 
-    let values = value_strings
-        .iter()
-        .map(|s| parse_value(s))
-        .collect::<Result<Vec<Value>, String>>()?;
+/// Why a statement could not be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+}
 
-    if columns.len() != values.len() {
-        return Err(format!(
-            "Number of columns ({}) doesn't match number of values ({})",
-            columns.len(),
-            values.len()
-        ));
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
     }
-
-    Ok(Statement {
-        statement_type: StatementType::Insert,
-        table_name,
-        columns,
-        values,
-        where_clause: None,
-    })
 }
 
-fn parse_select(tokens: &[String]) -> Result<Statement, String> {
-    if tokens.len() < 4 || tokens[tokens.len() - 2].to_lowercase() != "from" {
-        return Err("Invalid SELECT syntax".to_string());
+impl std::error::Error for ParseError {}
+
+/// Parses statements separated by semicolons. Unlike prepare_statement it
+/// prints nothing, so tools can use it to inspect SQL without running it.
+pub fn parse(input: &str) -> Result<Vec<Statement>, ParseError> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    for (i, ch) in input.char_indices().chain([(input.len(), ';')]) {
+        match ch {
+            '\'' | '"' if quote.is_none() => quote = Some(ch),
+            _ if quote == Some(ch) => quote = None,
+            ';' if quote.is_none() => {
+                let tokens = tokenize(&input[start..i]);
+                // A trigger's own statement ends in a semicolon before END
+                if i < input.len() && is_unfinished_trigger(&tokens) {
+                    continue;
+                }
+                if !tokens.is_empty() {
+                    let statement =
+                        parse_tokens(&tokens).map_err(|message| ParseError { message })?;
+                    statements.push(statement);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
     }
+    Ok(statements)
+}
 
-    let columns = if tokens[1] == "*" {
-        vec!["*".to_string()]
-    } else {
-        tokens[1..tokens.len() - 2]
-            .iter()
-            .filter(|&s| s != ",")
-            .map(|s| s.to_string())
-            .collect()
-    };
+/// Whether the tokens begin a CREATE TRIGGER that has not reached its END.
+fn is_unfinished_trigger(tokens: &[String]) -> bool {
+    starts_with_keywords(tokens, &["create", "trigger"])
+        && !tokens.last().is_some_and(|t| t.eq_ignore_ascii_case("end"))
+}
 
-    let table_name = tokens[tokens.len() - 1].to_string();
+/// Renders parsed statements as JSON.
+#[cfg(feature = "serde")]
+pub fn to_json(statements: &[Statement]) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(statements)
+}
 
-    // We're not handling WHERE clauses for now, but you can add that later
+pub fn prepare_statement(input: &str) -> Result<Statement, String> {
+    let mut tokens = tokenize(input);
+    number_params(&mut tokens);
+    parse_tokens(&tokens)
+}
 
-    Ok(Statement {
-        statement_type: StatementType::Select,
-        table_name,
-        columns,
-        values: vec![],
-        where_clause: None,
-    })
+/// A statement parsed once and run any number of times, each time with its
+/// `?` placeholders filled in by `bind`.
+pub struct PreparedStatement {
+    sql: String,
+    statement: Statement,
+    param_count: usize,
 }
 
-//helper functions
+impl PreparedStatement {
+    pub fn new(sql: &str) -> Result<PreparedStatement, String> {
+        let mut tokens = tokenize(sql);
+        number_params(&mut tokens);
+        let mut statement = parse_tokens(&tokens)?;
+        let mut param_count = 0;
+        visit_exprs(&mut statement, &mut |expr| {
+            if let Expr::Param(number) = expr {
+                param_count = param_count.max(*number);
+            }
+            Ok(())
+        })?;
+        Ok(PreparedStatement {
+            sql: sql.to_string(),
+            statement,
+            param_count,
+        })
+    }
 
-fn parse_parentheses_list(tokens: &[&str]) -> Result<Vec<String>, String> {
-    let mut result = vec![];
-    let mut current_item = String::new();
-    let mut depth = 0;
-    let mut in_quotes = false;
+    /// The statement as written, placeholders and all.
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
 
-    for token in tokens {
-        if token.starts_with('(') && !in_quotes {
-            depth += 1;
-            if depth == 1 {
-                continue; // Skip the opening parenthesis of the outermost level
+    /// The number of values `bind` expects: the highest placeholder number.
+    pub fn param_count(&self) -> usize {
+        self.param_count
+    }
+
+    /// The statement with `params[n - 1]` in place of each `?n`. Whether a
+    /// value suits the column it ends up in is checked when the statement
+    /// runs, as it is for literals.
+    pub fn bind(&self, params: &[Value]) -> Result<Statement, String> {
+        if params.len() != self.param_count {
+            return Err(format!(
+                "expected {} parameters but {} were given",
+                self.param_count,
+                params.len()
+            ));
+        }
+        let mut statement = self.statement.clone();
+        visit_exprs(&mut statement, &mut |expr| {
+            if let Expr::Param(number) = expr {
+                *expr = Expr::Literal(params[*number - 1].clone());
             }
+            Ok(())
+        })?;
+        Ok(statement)
+    }
+}
+
+/// Numbers bare `?` placeholders left to right, each one past the highest
+/// number so far, as SQLite does. `?NNN` keeps its number.
+fn number_params(tokens: &mut [String]) {
+    let mut highest = 0;
+    for token in tokens {
+        if token == "?" {
+            highest += 1;
+            *token = format!("?{}", highest);
+        } else if let Some(number) = param_number(token) {
+            highest = highest.max(number);
         }
+    }
+}
+
+fn param_number(token: &str) -> Option<usize> {
+    token.strip_prefix('?')?.parse().ok()
+}
 
-        if depth == 0 && !token.starts_with('(') {
-            break; // We've reached the end of the parentheses list
+/// Calls `f` on every expression of a statement, nested ones included, and
+/// on those of its subqueries, INSERT ... SELECT query and UNION arms.
+fn visit_exprs(
+    statement: &mut Statement,
+    f: &mut dyn FnMut(&mut Expr) -> Result<(), String>,
+) -> Result<(), String> {
+    fn visit_expr(
+        expr: &mut Expr,
+        f: &mut dyn FnMut(&mut Expr) -> Result<(), String>,
+    ) -> Result<(), String> {
+        f(expr)?;
+        if let Expr::InSelect { query, .. } | Expr::Exists { query, .. } = expr {
+            visit_exprs(query, f)?;
         }
+        for child in expr.children_mut() {
+            visit_expr(child, f)?;
+        }
+        Ok(())
+    }
+
+    for expr in statement.exprs_mut() {
+        visit_expr(expr, f)?;
+    }
+    if let Some(query) = &mut statement.query {
+        visit_exprs(query, f)?;
+    }
+    for arm in &mut statement.compound {
+        visit_exprs(&mut arm.select, f)?;
+    }
+    Ok(())
+}
+
+fn parse_tokens(tokens: &[String]) -> Result<Statement, String> {
+    match tokens.first().map(|s| s.to_lowercase()).as_deref() {
+        Some("insert") => parse_insert(tokens),
+        Some("select") => parse_select(tokens),
+        Some("create") => parse_create(tokens),
+        Some("drop") => parse_drop(tokens),
+        Some("truncate") => parse_truncate(tokens),
+        Some("update") => parse_update(tokens),
+        Some("delete") => parse_delete(tokens),
+        Some("begin") | Some("commit") | Some("rollback") => parse_transaction(tokens),
+        Some("pragma") => parse_pragma(tokens),
+        Some("explain") => parse_explain(tokens),
+        _ => Err("Unrecognized keyword at start of statement".to_string()),
+    }
+}
+
+/// Rewrites a statement with every literal replaced by `?`, for logging
+/// statements without the values they carry.
+pub fn redact_literals(sql: &str) -> String {
+    tokenize(sql)
+        .into_iter()
+        .map(|token| match parse_value(&token) {
+            Ok(Value::Null) | Err(_) => token,
+            Ok(_) => "?".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses a persisted CREATE TABLE, VIEW, INDEX or TRIGGER statement
+/// without the debug output of prepare_statement.
+pub fn parse_schema(sql: &str) -> Result<Statement, String> {
+    parse_create(&tokenize(sql))
+}
+
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current_token = String::new();
+    // The quote character of an open string literal or quoted name
+    let mut quote = None;
+    let mut chars = input.chars().peekable();
 
-        // Handle quotes
-        if token.starts_with('\'') {
-            in_quotes = true;
+    while let Some(ch) = chars.next() {
+        if quote.is_some_and(|q| q != ch) {
+            current_token.push(ch);
+            continue;
         }
-        if token.ends_with('\'') && !token.ends_with("\'\'") {
-            in_quotes = false;
+
+        match ch {
+            '\'' | '"' => {
+                current_token.push(ch);
+                quote = match quote {
+                    Some(_) => None,
+                    None => Some(ch),
+                };
+            }
+            ',' | '(' | ')' => {
+                flush_token(&mut tokens, &mut current_token);
+                tokens.push(ch.to_string());
+            }
+            // Tabs and line breaks separate tokens just like spaces
+            _ if ch.is_whitespace() => flush_token(&mut tokens, &mut current_token),
+            '=' | '<' | '>' | '!' => {
+                flush_token(&mut tokens, &mut current_token);
+                let mut operator = ch.to_string();
+                if let Some(next) = chars.next_if(|&next| next == '=' || (ch == '<' && next == '>'))
+                {
+                    operator.push(next);
+                }
+                tokens.push(operator);
+            }
+            '|' => {
+                flush_token(&mut tokens, &mut current_token);
+                let operator = if chars.next_if_eq(&'|').is_some() {
+                    "||"
+                } else {
+                    "|"
+                };
+                tokens.push(operator.to_string());
+            }
+            // A sign at the start of a token that is followed by a digit
+            // belongs to a numeric literal such as -5
+            '+' | '-'
+                if current_token.is_empty() && chars.peek().is_some_and(char::is_ascii_digit) =>
+            {
+                current_token.push(ch)
+            }
+            // `t.*` stays a single token
+            '*' if current_token.ends_with('.') => current_token.push(ch),
+            '+' | '-' | '*' | '/' | '%' => {
+                flush_token(&mut tokens, &mut current_token);
+                tokens.push(ch.to_string());
+            }
+            _ => current_token.push(ch),
         }
+    }
 
-        // Remove leading/trailing parentheses and commas, but only if not in quotes
-        let cleaned_token = if !in_quotes {
-            token.trim_matches(|c| c == '(' || c == ')' || c == ',')
-        } else {
-            token
+    flush_token(&mut tokens, &mut current_token);
+    tokens
+}
+
+fn flush_token(tokens: &mut Vec<String>, current_token: &mut String) {
+    if !current_token.is_empty() {
+        tokens.push(std::mem::take(current_token));
+    }
+}
+
+fn parse_insert(tokens: &[String]) -> Result<Statement, String> {
+    let (tokens, returning) = split_returning(tokens)?;
+
+    // INSERT OR REPLACE / INSERT OR IGNORE pick a conflict policy up front
+    let mut on_conflict = None;
+    let mut tokens = tokens.to_vec();
+    if tokens.len() > 2 && tokens[1].eq_ignore_ascii_case("or") {
+        on_conflict = match tokens[2].to_lowercase().as_str() {
+            "replace" => Some(OnConflict::Replace),
+            "ignore" => Some(OnConflict::DoNothing),
+            _ => return Err(format!("Unknown conflict policy: {}", tokens[2])),
         };
+        tokens.drain(1..3);
+    }
+
+    if tokens.len() < 4 || tokens[1].to_lowercase() != "into" {
+        return Err("Invalid Insert Statement".to_string());
+    }
+
+    let table_name = parse_name(&tokens[2])?;
+    let mut i = 3;
+
+    // The column list is optional; without it values are matched to the
+    // table's columns by position.
+    let mut columns = Vec::new();
+    let keyword = tokens[i].to_lowercase();
+    if keyword != "values" && keyword != "select" {
+        // Ensure the next token is an opening parenthesis
+        if tokens[i] != "(" {
+            return Err("Expected '(' after table name".to_string());
+        }
+        i += 1;
 
-        if !cleaned_token.is_empty() {
-            if !current_item.is_empty() && !in_quotes {
-                current_item.push(' ');
+        // Parse columns
+        while i < tokens.len() && tokens[i] != ")" {
+            if tokens[i] != "," {
+                columns.push(parse_name(&tokens[i])?);
             }
-            current_item.push_str(cleaned_token);
+            i += 1;
         }
 
-        if (token.ends_with(',') || token.ends_with(')')) && !in_quotes {
-            result.push(current_item.trim().to_string());
-            current_item.clear();
+        // Ensure we found the closing parenthesis
+        if i >= tokens.len() || tokens[i] != ")" {
+            return Err("Expected ')' after columns".to_string());
         }
+        i += 1;
+    }
+
+    // INSERT ... SELECT takes the rest of the statement as its query
+    if tokens
+        .get(i)
+        .is_some_and(|t| t.eq_ignore_ascii_case("select"))
+    {
+        let mut statement = Statement::new(StatementType::Insert, table_name);
+        statement.columns = columns;
+        statement.query = Some(Box::new(parse_select(&tokens[i..])?));
+        statement.on_conflict = on_conflict;
+        statement.returning = returning;
+        return Ok(statement);
+    }
+
+    // Check for VALUES keyword
+    if i >= tokens.len() || tokens[i].to_lowercase() != "values" {
+        return Err("Expected 'VALUES' keyword".to_string());
+    }
+    i += 1;
 
-        if token.ends_with(')') && !in_quotes {
-            depth -= 1;
-            if depth == 0 {
-                break; // We've reached the end of the list
+    // Parse one or more comma-separated tuples
+    let mut tuples = Vec::new();
+    loop {
+        if i >= tokens.len() || tokens[i] != "(" {
+            return Err("Expected '(' after VALUES".to_string());
+        }
+        let end = closing_paren(&tokens, i).ok_or("Expected ')' after values")?;
+        let mut values = Vec::new();
+        for group in split_top_level(&tokens[i + 1..end]) {
+            if group.is_empty() {
+                return Err("Expected a value in VALUES".to_string());
             }
+            values.push(parse_condition(group)?);
+        }
+        if let Some(first) = tuples.first().map(Vec::len).filter(|&n| n != values.len()) {
+            return Err(format!(
+                "all VALUES must have the same number of terms ({} and {})",
+                first,
+                values.len()
+            ));
+        }
+        tuples.push(values);
+        i = end + 1;
+        if tokens.get(i).is_some_and(|t| t == ",") {
+            i += 1;
+        } else {
+            break;
         }
     }
 
-    if depth != 0 {
-        return Err("Mismatched parentheses".to_string());
+    if i < tokens.len() {
+        if on_conflict.is_some() {
+            return Err(format!("Unexpected token after values: {}", tokens[i]));
+        }
+        on_conflict = Some(parse_on_conflict(&tokens[i..])?);
     }
 
-    if !current_item.is_empty() {
-        result.push(current_item.trim().to_string());
+    if !columns.is_empty() && columns.len() != tuples[0].len() {
+        return Err(format!(
+            "Number of columns ({}) doesn't match number of values ({})",
+            columns.len(),
+            tuples[0].len()
+        ));
     }
 
-    Ok(result)
+    let mut statement = Statement::new(StatementType::Insert, table_name);
+    statement.columns = columns;
+    statement.values = tuples;
+    statement.on_conflict = on_conflict;
+    statement.returning = returning;
+    Ok(statement)
 }
 
-fn parse_value(s: &str) -> Result<Value, String> {
-    if s.starts_with('\'') && s.ends_with('\'') {
-        Ok(Value::Text(s.trim_matches('\'').to_string()))
-    } else if let Ok(num) = s.parse::<i64>() {
-        Ok(Value::Integer(num))
-    } else {
-        Err(format!("Invalid value: {}", s))
+/// Splits a trailing `RETURNING col, ...` clause off a write statement.
+fn split_returning(tokens: &[String]) -> Result<(&[String], Vec<Expr>), String> {
+    let position = match keyword_position(tokens, "returning") {
+        Some(position) => position,
+        None => return Ok((tokens, Vec::new())),
+    };
+
+    let columns = parse_projection(&tokens[position + 1..], "RETURNING")?;
+    Ok((&tokens[..position], columns))
+}
+
+fn parse_on_conflict(tokens: &[String]) -> Result<OnConflict, String> {
+    let keyword = |i: usize, expected: &str| {
+        tokens
+            .get(i)
+            .is_some_and(|t| t.eq_ignore_ascii_case(expected))
+    };
+    if !keyword(0, "on") || !keyword(1, "conflict") {
+        return Err(format!("Unexpected token after values: {}", tokens[0]));
+    }
+
+    let mut i = 2;
+    let mut target = Vec::new();
+    if tokens.get(i).map(|t| t.as_str()) == Some("(") {
+        let end = closing_paren(tokens, i).ok_or("Expected ')' after conflict target")?;
+        target = tokens[i + 1..end]
+            .iter()
+            .filter(|t| *t != ",")
+            .map(|t| parse_name(t))
+            .collect::<Result<_, _>>()?;
+        i = end + 1;
+    }
+
+    if !keyword(i, "do") {
+        return Err("Expected DO after ON CONFLICT".to_string());
+    }
+    if keyword(i + 1, "nothing") && tokens.len() == i + 2 {
+        return Ok(OnConflict::DoNothing);
+    }
+    if !keyword(i + 1, "update") || !keyword(i + 2, "set") {
+        return Err("Expected DO NOTHING or DO UPDATE SET".to_string());
+    }
+
+    let assignments = parse_assignments(&tokens[i + 3..])?;
+    Ok(OnConflict::DoUpdate {
+        target,
+        assignments,
+    })
+}
+
+/// Parses `col = expr, col = expr, ...` as used by SET.
+fn parse_assignments(tokens: &[String]) -> Result<Vec<(String, Expr)>, String> {
+    let mut assignments = Vec::new();
+    for assignment in split_top_level(tokens) {
+        match assignment {
+            [column, eq, value @ ..] if eq == "=" => {
+                assignments.push((parse_name(column)?, parse_condition(value)?));
+            }
+            _ => return Err("Expected assignments of the form column = value".to_string()),
+        }
+    }
+    Ok(assignments)
+}
+
+/// Parses a SELECT and any UNION arms that follow it.
+fn parse_select(tokens: &[String]) -> Result<Statement, String> {
+    let limit = keyword_position(tokens, "limit");
+    let (tokens, limit) = tokens.split_at(limit.unwrap_or(tokens.len()));
+    let order = keyword_position(tokens, "order");
+    let (tokens, order_by) = tokens.split_at(order.unwrap_or(tokens.len()));
+    let first_end = keyword_position(tokens, "union").unwrap_or(tokens.len());
+    let mut statement = parse_simple_select(&tokens[..first_end])?;
+
+    let mut rest = &tokens[first_end..];
+    while !rest.is_empty() {
+        // `rest` starts at a UNION keyword
+        let all = rest.get(1).is_some_and(|t| t.eq_ignore_ascii_case("all"));
+        let arm = &rest[if all { 2 } else { 1 }..];
+        if !arm
+            .first()
+            .is_some_and(|t| t.eq_ignore_ascii_case("select"))
+        {
+            return Err("Expected SELECT after UNION".to_string());
+        }
+        let end = keyword_position(arm, "union").unwrap_or(arm.len());
+        statement.compound.push(Compound {
+            all,
+            select: parse_simple_select(&arm[..end])?,
+        });
+        rest = &arm[end..];
+    }
+
+    if !order_by.is_empty() {
+        statement.order_by = parse_order_by(order_by)?;
+    }
+    let (count, offset) = match limit {
+        [] => return Ok(statement),
+        [_, count] => (count, None),
+        [_, count, keyword, offset] if keyword.eq_ignore_ascii_case("offset") => {
+            (count, Some(offset))
+        }
+        // `LIMIT offset, count`
+        [_, offset, comma, count] if comma == "," => (count, Some(offset)),
+        _ => return Err("Expected a row count after LIMIT".to_string()),
+    };
+    statement.limit = Some(
+        count
+            .parse()
+            .map_err(|_| format!("Invalid LIMIT: {}", count))?,
+    );
+    if let Some(offset) = offset {
+        statement.offset = offset
+            .parse()
+            .map_err(|_| format!("Invalid OFFSET: {}", offset))?;
+    }
+    Ok(statement)
+}
+
+/// Parses `ORDER BY term [ASC | DESC] [NULLS FIRST | NULLS LAST], ...`. A
+/// bare integer refers to a column of the select list by position.
+fn parse_order_by(tokens: &[String]) -> Result<Vec<OrderBy>, String> {
+    if !tokens.get(1).is_some_and(|t| t.eq_ignore_ascii_case("by")) {
+        return Err("Expected BY after ORDER".to_string());
+    }
+    let mut terms = Vec::new();
+    for group in split_top_level(&tokens[2..]) {
+        let (nulls_first, group) = match group {
+            [rest @ .., nulls, position] if nulls.eq_ignore_ascii_case("nulls") => {
+                if position.eq_ignore_ascii_case("first") {
+                    (Some(true), rest)
+                } else if position.eq_ignore_ascii_case("last") {
+                    (Some(false), rest)
+                } else {
+                    return Err("Expected FIRST or LAST after NULLS".to_string());
+                }
+            }
+            _ => (None, group),
+        };
+        let (descending, group) = match group.split_last() {
+            Some((last, rest)) if last.eq_ignore_ascii_case("desc") => (true, rest),
+            Some((last, rest)) if last.eq_ignore_ascii_case("asc") => (false, rest),
+            _ => (false, group),
+        };
+        let key = match group {
+            [] => return Err("Expected an expression in ORDER BY".to_string()),
+            [token] if token.bytes().all(|b| b.is_ascii_digit()) => SortKey::Position(
+                token
+                    .parse()
+                    .map_err(|_| format!("Invalid ORDER BY position: {}", token))?,
+            ),
+            _ => SortKey::Expr(parse_condition(group)?),
+        };
+        terms.push(OrderBy {
+            key,
+            descending,
+            nulls_first: nulls_first.unwrap_or(!descending),
+        });
+    }
+    Ok(terms)
+}
+
+/// Parses a single SELECT. Without FROM the table name is left empty and
+/// the projection is evaluated once, as in `select 1 + 1`.
+fn parse_simple_select(tokens: &[String]) -> Result<Statement, String> {
+    let from = keyword_position(tokens, "from");
+    let projection_end = from
+        .or_else(|| keyword_position(tokens, "where"))
+        .unwrap_or(tokens.len());
+    if projection_end < 2 {
+        return Err("Invalid SELECT syntax".to_string());
+    }
+
+    let distinct = tokens[1].eq_ignore_ascii_case("distinct");
+    let projection_start = if distinct || tokens[1].eq_ignore_ascii_case("all") {
+        2
+    } else {
+        1
+    };
+    if projection_end <= projection_start {
+        return Err("Invalid SELECT syntax".to_string());
+    }
+    let projection = parse_projection(&tokens[projection_start..projection_end], "SELECT")?;
+    let (table_name, rest) = match from {
+        Some(from) if from + 1 < tokens.len() => {
+            (parse_name(&tokens[from + 1])?, &tokens[from + 2..])
+        }
+        Some(_) => return Err("Invalid SELECT syntax".to_string()),
+        None => (String::new(), &tokens[projection_end..]),
+    };
+
+    let mut statement = Statement::new(StatementType::Select, table_name);
+    statement.projection = projection;
+    statement.distinct = distinct;
+
+    let having = keyword_position(rest, "having");
+    let (rest, having) = rest.split_at(having.unwrap_or(rest.len()));
+    if !having.is_empty() {
+        if having.len() == 1 {
+            return Err("Expected a condition after HAVING".to_string());
+        }
+        statement.having = Some(parse_condition(&having[1..])?);
+    }
+
+    let group = keyword_position(rest, "group");
+    let (rest, group_by) = rest.split_at(group.unwrap_or(rest.len()));
+    if !group_by.is_empty() {
+        if !group_by
+            .get(1)
+            .is_some_and(|t| t.eq_ignore_ascii_case("by"))
+        {
+            return Err("Expected BY after GROUP".to_string());
+        }
+        statement.group_by = split_top_level(&group_by[2..])
+            .into_iter()
+            .map(parse_condition)
+            .collect::<Result<_, _>>()?;
+    }
+
+    let where_position = keyword_position(rest, "where");
+    let (join, rest) = rest.split_at(where_position.unwrap_or(rest.len()));
+    if !join.is_empty() {
+        statement.join = Some(parse_join(join)?);
+    }
+
+    if let Some(keyword) = rest.first() {
+        if !keyword.eq_ignore_ascii_case("where") {
+            return Err(format!("Unexpected token after table name: {}", keyword));
+        }
+        statement.where_clause = Some(parse_condition(&rest[1..])?);
+    }
+
+    Ok(statement)
+}
+
+/// Parses `[INNER] JOIN table ON condition`.
+fn parse_join(tokens: &[String]) -> Result<Join, String> {
+    let tokens = match tokens.first() {
+        Some(inner) if inner.eq_ignore_ascii_case("inner") => &tokens[1..],
+        _ => tokens,
+    };
+    match tokens {
+        [join, table_name, on, condition @ ..]
+            if join.eq_ignore_ascii_case("join") && on.eq_ignore_ascii_case("on") =>
+        {
+            Ok(Join {
+                table_name: parse_name(table_name)?,
+                on: parse_condition(condition)?,
+            })
+        }
+        [join, ..] if join.eq_ignore_ascii_case("join") => {
+            Err("Expected JOIN table ON condition".to_string())
+        }
+        [token, ..] => Err(format!("Unexpected token after table name: {}", token)),
+        [] => Err("Expected JOIN".to_string()),
+    }
+}
+
+/// Parses the comma-separated expressions a SELECT or RETURNING outputs.
+fn parse_projection(tokens: &[String], clause: &str) -> Result<Vec<Expr>, String> {
+    let mut items = Vec::new();
+    for group in split_top_level(tokens) {
+        match group {
+            [] => return Err(format!("Expected an expression in {}", clause)),
+            [token] => match ColumnRef::parse(token) {
+                Ok(Some(column)) if column.is_star() => items.push(Expr::Column(column)),
+                _ => items.push(parse_condition(group)?),
+            },
+            _ => items.push(parse_condition(group)?),
+        }
+    }
+    if items.len() > 1 && items.iter().any(Expr::is_star) {
+        return Err(format!(
+            "{} * cannot be combined with other columns",
+            clause
+        ));
+    }
+    Ok(items)
+}
+
+fn parse_condition(tokens: &[String]) -> Result<Expr, String> {
+    if tokens.is_empty() {
+        return Err("Expected a condition".to_string());
+    }
+
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        Some(token) => Err(format!("Unexpected token in condition: {}", token)),
+        None => Ok(expr),
+    }
+}
+
+/// Recursive-descent parser for expressions, lowest precedence first:
+/// OR, AND, NOT, ||, comparisons with IN, LIKE and BETWEEN, + and -, then
+/// * / and %, and finally unary minus.
+struct ExprParser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|t| t.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or("Unexpected end of condition")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token.eq_ignore_ascii_case(expected) => Ok(()),
+            token => Err(format!("Expected '{}' but found '{}'", expected, token)),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = binary(left, BinaryOp::Or, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = binary(left, BinaryOp::And, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if !self.peek_keyword("not") {
+            return self.parse_concat();
+        }
+        self.pos += 1;
+        let expr = self.parse_not()?;
+        Ok(Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_comparison()?;
+        while self.peek() == Some("||") {
+            self.pos += 1;
+            let right = self.parse_comparison()?;
+            left = binary(left, BinaryOp::Concat, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+
+        // NOT IN, NOT LIKE and NOT BETWEEN are single operators
+        let negated = self.peek_keyword("not")
+            && self.tokens.get(self.pos + 1).is_some_and(|t| {
+                ["in", "like", "between"]
+                    .iter()
+                    .any(|keyword| t.eq_ignore_ascii_case(keyword))
+            });
+        if negated {
+            self.pos += 1;
+        }
+
+        if self.peek_keyword("in") {
+            self.pos += 1;
+            self.expect("(")?;
+            if self.peek_keyword("select") {
+                return self.parse_in_select(left, negated);
+            }
+            let list = self.parse_list()?;
+            for item in &list {
+                check_arity(&left, item)?;
+            }
+            return Ok(Expr::In {
+                expr: Box::new(left),
+                list,
+                negated,
+            });
+        }
+
+        if self.peek_keyword("between") {
+            self.pos += 1;
+            let low = self.parse_additive()?;
+            self.expect("and")?;
+            let high = self.parse_additive()?;
+            check_arity(&left, &low)?;
+            check_arity(&left, &high)?;
+            return Ok(Expr::Between {
+                expr: Box::new(left),
+                low: Box::new(low),
+                high: Box::new(high),
+                negated,
+            });
+        }
+
+        let op = match self.peek() {
+            Some("=") => BinaryOp::Eq,
+            Some("!=") | Some("<>") => BinaryOp::NotEq,
+            Some("<") => BinaryOp::Lt,
+            Some("<=") => BinaryOp::LtEq,
+            Some(">") => BinaryOp::Gt,
+            Some(">=") => BinaryOp::GtEq,
+            Some(token) if token.eq_ignore_ascii_case("like") && negated => BinaryOp::NotLike,
+            Some(token) if token.eq_ignore_ascii_case("like") => BinaryOp::Like,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.parse_additive()?;
+        check_arity(&left, &right)?;
+        if matches!(op, BinaryOp::Like | BinaryOp::NotLike) && self.peek_keyword("escape") {
+            // The three-operand form is a call to like(), as in SQLite
+            self.pos += 1;
+            let escape = self.parse_additive()?;
+            let call = Expr::Function {
+                name: "like".to_string(),
+                args: vec![right, left, escape],
+                distinct: false,
+            };
+            return Ok(if negated {
+                Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(call),
+                }
+            } else {
+                call
+            });
+        }
+        Ok(binary(left, op, right))
+    }
+
+    /// Parses the `SELECT ...)` of `expr IN (SELECT ...)`.
+    fn parse_in_select(&mut self, left: Expr, negated: bool) -> Result<Expr, String> {
+        if matches!(left, Expr::Tuple(_)) {
+            return Err("row value misused".to_string());
+        }
+        let (query, sql) = self.parse_subquery()?;
+        Ok(Expr::InSelect {
+            expr: Box::new(left),
+            query,
+            sql,
+            negated,
+        })
+    }
+
+    /// Parses a SELECT up to and including the ')' that closes it, returning
+    /// the statement and its text.
+    fn parse_subquery(&mut self) -> Result<(Box<Statement>, String), String> {
+        if !self.peek_keyword("select") {
+            return Err("Expected SELECT in subquery".to_string());
+        }
+        let end = closing_paren(self.tokens, self.pos - 1).ok_or("Expected ')' after subquery")?;
+        let tokens = &self.tokens[self.pos..end];
+        let query = parse_select(tokens)?;
+        self.pos = end + 1;
+        Ok((Box::new(query), tokens.join(" ")))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some("+") => BinaryOp::Add,
+                Some("-") => BinaryOp::Sub,
+                // `id -5` tokenizes as an operand followed by a signed
+                // literal; it is the same as adding that literal
+                Some(token) if is_signed_number(token) => {
+                    let right = self.parse_multiplicative()?;
+                    left = binary(left, BinaryOp::Add, right);
+                    continue;
+                }
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_multiplicative()?;
+            left = binary(left, op, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some("*") => BinaryOp::Mul,
+                Some("/") => BinaryOp::Div,
+                Some("%") => BinaryOp::Mod,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = binary(left, op, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some("-") => {
+                self.pos += 1;
+                let expr = self.parse_unary()?;
+                Ok(Expr::Unary {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(expr),
+                })
+            }
+            Some("+") => {
+                self.pos += 1;
+                self.parse_unary()
+            }
+            _ => self.parse_collated(),
+        }
+    }
+
+    /// An operand and any `COLLATE name` after it.
+    fn parse_collated(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_operand()?;
+        while self.peek_keyword("collate") {
+            self.pos += 1;
+            let collation = Collation::parse(self.next()?)?;
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, String> {
+        let token = self.next()?.to_string();
+        if token == "(" {
+            // Either a grouped condition or a row value such as (a, b)
+            let mut items = self.parse_list()?;
+            if items.len() == 1 {
+                return Ok(items.remove(0));
+            }
+            return Ok(Expr::Tuple(items));
+        }
+
+        if token.eq_ignore_ascii_case("case") {
+            return self.parse_case();
+        }
+        if token.eq_ignore_ascii_case("exists") {
+            self.expect("(")?;
+            let (query, sql) = self.parse_subquery()?;
+            return Ok(Expr::Exists { query, sql });
+        }
+        if is_identifier(&token) && self.peek() == Some("(") {
+            self.pos += 1;
+            return self.parse_call(token.to_lowercase());
+        }
+
+        if let Some(number) = param_number(&token) {
+            if number == 0 {
+                return Err("parameter numbers start at ?1".to_string());
+            }
+            return Ok(Expr::Param(number));
+        }
+        match parse_value(&token) {
+            Ok(value) => Ok(Expr::Literal(value)),
+            Err(e) => match ColumnRef::parse(&token)? {
+                Some(column) if !column.is_star() => Ok(Expr::Column(column)),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Parses the rest of a CASE expression after the CASE keyword.
+    fn parse_case(&mut self) -> Result<Expr, String> {
+        let operand = if self.peek_keyword("when") {
+            None
+        } else {
+            Some(Box::new(self.parse_or()?))
+        };
+
+        let mut branches = Vec::new();
+        while self.peek_keyword("when") {
+            self.pos += 1;
+            let when = self.parse_or()?;
+            self.expect("then")?;
+            branches.push((when, self.parse_or()?));
+        }
+        if branches.is_empty() {
+            return Err("Expected WHEN in CASE".to_string());
+        }
+
+        let else_result = if self.peek_keyword("else") {
+            self.pos += 1;
+            Some(Box::new(self.parse_or()?))
+        } else {
+            None
+        };
+        self.expect("end")?;
+
+        Ok(Expr::Case {
+            operand,
+            branches,
+            else_result,
+        })
+    }
+
+    /// Parses a function's arguments after its opening '('.
+    fn parse_call(&mut self, name: String) -> Result<Expr, String> {
+        let distinct = self.peek_keyword("distinct");
+        if distinct {
+            self.pos += 1;
+        }
+        let args = match self.peek() {
+            Some(")" | "*") if distinct => {
+                return Err("DISTINCT aggregates must have exactly one argument".to_string())
+            }
+            Some(")") => {
+                self.pos += 1;
+                Vec::new()
+            }
+            Some("*") => {
+                self.pos += 1;
+                self.expect(")")?;
+                vec![Expr::Column(ColumnRef {
+                    table: None,
+                    column: "*".to_string(),
+                })]
+            }
+            _ => self.parse_list()?,
+        };
+        Ok(Expr::Function {
+            name,
+            args,
+            distinct,
+        })
+    }
+
+    /// Parses comma-separated expressions up to and including the closing ')'.
+    fn parse_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut items = vec![self.parse_or()?];
+        loop {
+            match self.next()? {
+                "," => items.push(self.parse_or()?),
+                ")" => return Ok(items),
+                token => return Err(format!("Expected ',' or ')' but found '{}'", token)),
+            }
+        }
+    }
+}
+
+/// Finds a keyword that is not nested inside parentheses, so that clauses
+/// of a subquery are not mistaken for the enclosing statement's.
+fn keyword_position(tokens: &[String], keyword: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.as_str() {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ if depth == 0 && token.eq_ignore_ascii_case(keyword) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a token list at commas that are not nested inside parentheses.
+fn split_top_level(tokens: &[String]) -> Vec<&[String]> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.as_str() {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            "," if depth == 0 => {
+                groups.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    groups.push(&tokens[start..]);
+    groups
+}
+
+/// Finds the index of the ')' matching the '(' at `open`.
+fn closing_paren(tokens: &[String], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.as_str() {
+            "(" => depth += 1,
+            ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+fn check_arity(left: &Expr, right: &Expr) -> Result<(), String> {
+    let arity = |expr: &Expr| match expr {
+        Expr::Tuple(items) => items.len(),
+        _ => 1,
+    };
+    if arity(left) != arity(right) {
+        return Err(format!(
+            "Row value misused: comparing {} values with {}",
+            arity(left),
+            arity(right)
+        ));
+    }
+    Ok(())
+}
+
+fn is_signed_number(token: &str) -> bool {
+    token.starts_with(['+', '-'])
+        && token.len() > 1
+        && token[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Words the parsers give a meaning to. Using one as a table or column
+/// name would make later statements parse differently, so it is rejected.
+const KEYWORDS: &[&str] = &[
+    "all",
+    "and",
+    "as",
+    "asc",
+    "between",
+    "by",
+    "case",
+    "collate",
+    "create",
+    "default",
+    "delete",
+    "desc",
+    "distinct",
+    "drop",
+    "else",
+    "end",
+    "exists",
+    "from",
+    "group",
+    "having",
+    "if",
+    "in",
+    "inner",
+    "insert",
+    "into",
+    "is",
+    "join",
+    "like",
+    "limit",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "primary",
+    "returning",
+    "select",
+    "set",
+    "table",
+    "then",
+    "truncate",
+    "union",
+    "update",
+    "values",
+    "view",
+    "when",
+    "where",
+];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS
+        .iter()
+        .any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+/// Rejects a table or column name that is a keyword.
+fn check_name(name: &str) -> Result<(), String> {
+    if is_keyword(name) {
+        return Err(format!(
+            "{} is a reserved keyword; double-quote it to use it as a name",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a table or column name. Double quotes allow any name, including
+/// keywords and ones with spaces, with `""` standing for a quote inside.
+fn parse_name(token: &str) -> Result<String, String> {
+    match unquote(token) {
+        Some(name) if name.is_empty() => Err("Names cannot be empty".to_string()),
+        Some(name) => Ok(name),
+        None => {
+            check_name(token)?;
+            Ok(token.to_string())
+        }
+    }
+}
+
+/// One part of a column reference: a quoted name, or an identifier that is
+/// not a keyword. None for anything else.
+fn name_part(part: &str) -> Result<Option<String>, String> {
+    if unquote(part).is_none() && !is_identifier(part) {
+        return Ok(None);
+    }
+    parse_name(part).map(Some)
+}
+
+fn unquote(token: &str) -> Option<String> {
+    let inner = token.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.replace("\"\"", "\""))
+}
+
+/// Splits `table.column` at the first dot outside double quotes.
+fn split_qualified(token: &str) -> Option<(&str, &str)> {
+    let mut quoted = false;
+    for (i, ch) in token.char_indices() {
+        match ch {
+            '"' => quoted = !quoted,
+            '.' if !quoted => return Some((&token[..i], &token[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Writes a name so that it parses back to itself, quoting it when it is
+/// not a plain identifier or is a keyword.
+pub fn quote_name(name: &str) -> String {
+    if is_identifier(name) && !is_keyword(name) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse_transaction(tokens: &[String]) -> Result<Statement, String> {
+    let keyword = tokens[0].to_lowercase();
+    let statement_type = match keyword.as_str() {
+        "begin" => StatementType::Begin,
+        "commit" => StatementType::Commit,
+        _ => StatementType::Rollback,
+    };
+
+    // The TRANSACTION keyword is optional noise
+    match &tokens[1..] {
+        [] => {}
+        [word] if word.eq_ignore_ascii_case("transaction") => {}
+        _ => return Err(format!("Invalid {} statement", keyword.to_uppercase())),
+    }
+
+    Ok(Statement::new(statement_type, String::new()))
+}
+
+/// Parses `EXPLAIN QUERY PLAN statement`. Plain EXPLAIN, which would show
+/// the steps a statement is compiled to, has nothing to show here.
+fn parse_explain(tokens: &[String]) -> Result<Statement, String> {
+    match tokens.get(1..3) {
+        Some([query, plan])
+            if query.eq_ignore_ascii_case("query") && plan.eq_ignore_ascii_case("plan") => {}
+        _ => return Err("Expected QUERY PLAN after EXPLAIN".to_string()),
+    }
+    if tokens.len() == 3 {
+        return Err("Expected a statement after EXPLAIN QUERY PLAN".to_string());
+    }
+    let mut statement = Statement::new(StatementType::Explain, String::new());
+    statement.query = Some(Box::new(parse_tokens(&tokens[3..])?));
+    Ok(statement)
+}
+
+fn parse_pragma(tokens: &[String]) -> Result<Statement, String> {
+    let value = match tokens {
+        [_, _] => None,
+        // Bare words such as ON or OFF are accepted as text
+        [_, _, eq, value] if eq == "=" => Some(parse_value(value).or_else(|e| {
+            if is_identifier(value) {
+                Ok(Value::Text(value.to_lowercase()))
+            } else {
+                Err(e)
+            }
+        })?),
+        _ => return Err("Expected PRAGMA name [= value]".to_string()),
+    };
+
+    let mut statement = Statement::new(StatementType::Pragma, String::new());
+    statement.pragma = Some(Pragma {
+        name: tokens[1].to_lowercase(),
+        value,
+    });
+    Ok(statement)
+}
+
+fn parse_create(tokens: &[String]) -> Result<Statement, String> {
+    match tokens.get(1) {
+        Some(word) if word.eq_ignore_ascii_case("view") => parse_create_view(tokens),
+        Some(word) if word.eq_ignore_ascii_case("index") => parse_create_index(tokens),
+        Some(word) if word.eq_ignore_ascii_case("trigger") => parse_create_trigger(tokens),
+        _ => parse_create_table(tokens),
+    }
+}
+
+/// Parses `CREATE INDEX [IF NOT EXISTS] name ON table (column)`, or with
+/// an expression such as `lower(email)` in place of the column.
+fn parse_create_index(tokens: &[String]) -> Result<Statement, String> {
+    let if_not_exists = starts_with_keywords(&tokens[2..], &["if", "not", "exists"]);
+    let tokens = if if_not_exists { &tokens[3..] } else { tokens };
+    match tokens {
+        [_, _, name, on, table_name, open, columns @ .., close]
+            if on.eq_ignore_ascii_case("on") && open == "(" && close == ")" =>
+        {
+            let indexed = match split_top_level(columns).as_slice() {
+                [[column]] => Expr::Column(ColumnRef {
+                    table: None,
+                    column: parse_name(column)?,
+                }),
+                [] | [[]] => return Err("Expected a column to index".to_string()),
+                [expr] => parse_condition(expr)?,
+                _ => return Err("indexes on more than one column are not supported".to_string()),
+            };
+            let mut statement = Statement::new(StatementType::CreateIndex, parse_name(table_name)?);
+            statement.index_name = parse_name(name)?;
+            statement.projection = vec![indexed];
+            statement.if_exists = if_not_exists;
+            Ok(statement)
+        }
+        _ => Err("Invalid CREATE INDEX statement".to_string()),
+    }
+}
+
+/// Parses `CREATE VIEW [IF NOT EXISTS] name AS SELECT ...`.
+fn parse_create_view(tokens: &[String]) -> Result<Statement, String> {
+    let if_not_exists = starts_with_keywords(&tokens[2..], &["if", "not", "exists"]);
+    let tokens = if if_not_exists { &tokens[3..] } else { tokens };
+    match tokens {
+        [_, _, name, as_, query @ ..] if as_.eq_ignore_ascii_case("as") => {
+            if !query
+                .first()
+                .is_some_and(|t| t.eq_ignore_ascii_case("select"))
+            {
+                return Err("Expected SELECT after AS".to_string());
+            }
+            let mut statement = Statement::new(StatementType::CreateView, parse_name(name)?);
+            statement.query = Some(Box::new(parse_select(query)?));
+            statement.query_sql = query.join(" ");
+            statement.if_exists = if_not_exists;
+            Ok(statement)
+        }
+        _ => Err("Invalid CREATE VIEW statement".to_string()),
+    }
+}
+
+/// Parses `CREATE TRIGGER [IF NOT EXISTS] name AFTER INSERT ON table [FOR
+/// EACH ROW] BEGIN insert ...; END`. The body must be a single INSERT.
+fn parse_create_trigger(tokens: &[String]) -> Result<Statement, String> {
+    let if_not_exists = starts_with_keywords(&tokens[2..], &["if", "not", "exists"]);
+    let tokens = if if_not_exists { &tokens[3..] } else { tokens };
+    let (name, table_name, rest) = match tokens {
+        [_, _, name, after, insert, on, table_name, rest @ ..]
+            if after.eq_ignore_ascii_case("after")
+                && insert.eq_ignore_ascii_case("insert")
+                && on.eq_ignore_ascii_case("on") =>
+        {
+            (name, table_name, rest)
+        }
+        [_, _, _, time, event, ..]
+            if ["before", "instead"]
+                .iter()
+                .any(|t| time.eq_ignore_ascii_case(t))
+                || ["update", "delete"]
+                    .iter()
+                    .any(|e| event.eq_ignore_ascii_case(e)) =>
+        {
+            return Err("only AFTER INSERT triggers are supported".to_string())
+        }
+        _ => return Err("Invalid CREATE TRIGGER statement".to_string()),
+    };
+    let rest = if starts_with_keywords(rest, &["for", "each", "row"]) {
+        &rest[3..]
+    } else {
+        rest
+    };
+    let mut body = match rest {
+        [begin, body @ .., end]
+            if begin.eq_ignore_ascii_case("begin") && end.eq_ignore_ascii_case("end") =>
+        {
+            body.to_vec()
+        }
+        _ => return Err("Expected BEGIN ... END around the trigger's statement".to_string()),
+    };
+    // The statement's semicolon may be a token of its own or end the last one
+    if body.last().is_some_and(|t| t == ";") {
+        body.pop();
+    } else if let Some(last) = body.last_mut() {
+        if let Some(stripped) = last.strip_suffix(';') {
+            *last = stripped.to_string();
+        }
+    }
+    if !body
+        .first()
+        .is_some_and(|t| t.eq_ignore_ascii_case("insert"))
+        || body.iter().any(|t| t.ends_with(';'))
+    {
+        return Err("a trigger's body must be a single INSERT statement".to_string());
+    }
+
+    let mut statement = Statement::new(StatementType::CreateTrigger, parse_name(table_name)?);
+    statement.trigger_name = parse_name(name)?;
+    statement.query = Some(Box::new(parse_insert(&body)?));
+    statement.query_sql = body.join(" ");
+    statement.if_exists = if_not_exists;
+    Ok(statement)
+}
+
+fn parse_create_table(tokens: &[String]) -> Result<Statement, String> {
+    if tokens.len() < 2 || tokens[1].to_lowercase() != "table" {
+        return Err("Invalid CREATE TABLE statement".to_string());
+    }
+    let if_not_exists = starts_with_keywords(&tokens[2..], &["if", "not", "exists"]);
+    let tokens = if if_not_exists { &tokens[3..] } else { tokens };
+    if tokens.len() < 5 {
+        return Err("Invalid CREATE TABLE statement".to_string());
+    }
+
+    let table_name = parse_name(&tokens[2])?;
+    if tokens[3] != "(" {
+        return Err("Expected '(' after table name".to_string());
+    }
+    if tokens[tokens.len() - 1] != ")" {
+        return Err("Expected ')' after column definitions".to_string());
+    }
+
+    let mut column_defs = Vec::new();
+    for definition in split_top_level(&tokens[4..tokens.len() - 1]) {
+        column_defs.push(parse_column_def(definition)?);
+    }
+
+    if column_defs.iter().filter(|c| c.primary_key).count() > 1 {
+        return Err(format!(
+            "Table {} has more than one primary key",
+            table_name
+        ));
+    }
+
+    let mut statement = Statement::new(StatementType::CreateTable, table_name);
+    statement.column_defs = column_defs;
+    statement.if_exists = if_not_exists;
+    Ok(statement)
+}
+
+/// Parses `DROP TABLE [IF EXISTS] name` or the same with VIEW, INDEX or
+/// TRIGGER.
+fn parse_drop(tokens: &[String]) -> Result<Statement, String> {
+    let kind = tokens.get(1).map(|t| t.to_lowercase());
+    let statement_type = match kind.as_deref() {
+        Some("table") => StatementType::DropTable,
+        Some("view") => StatementType::DropView,
+        Some("index") => StatementType::DropIndex,
+        Some("trigger") => StatementType::DropTrigger,
+        _ => return Err("Invalid DROP statement".to_string()),
+    };
+    let if_exists = starts_with_keywords(&tokens[2..], &["if", "exists"]);
+    let rest = if if_exists {
+        &tokens[4..]
+    } else {
+        &tokens[2..]
+    };
+    match rest {
+        [name] => {
+            let name = parse_name(name)?;
+            let mut statement = Statement::new(statement_type, String::new());
+            match statement_type {
+                StatementType::DropIndex => statement.index_name = name,
+                StatementType::DropTrigger => statement.trigger_name = name,
+                _ => statement.table_name = name,
+            }
+            statement.if_exists = if_exists;
+            Ok(statement)
+        }
+        [] => Err(format!(
+            "Expected {} name after DROP",
+            kind.unwrap_or_default()
+        )),
+        [_, token, ..] => Err(format!("Unexpected token after name: {}", token)),
+    }
+}
+
+/// Parses `TRUNCATE [TABLE] name`.
+fn parse_truncate(tokens: &[String]) -> Result<Statement, String> {
+    let rest = match tokens.get(1) {
+        Some(word) if word.eq_ignore_ascii_case("table") => &tokens[2..],
+        _ => &tokens[1..],
+    };
+    match rest {
+        [table_name] => Ok(Statement::new(
+            StatementType::Truncate,
+            parse_name(table_name)?,
+        )),
+        [] => Err("Expected table name after TRUNCATE".to_string()),
+        [_, token, ..] => Err(format!("Unexpected token after table name: {}", token)),
+    }
+}
+
+/// Parses `UPDATE table SET col = expr, ... [WHERE condition]`.
+fn parse_update(tokens: &[String]) -> Result<Statement, String> {
+    let table_name = tokens.get(1).ok_or("Expected table name after UPDATE")?;
+    if !tokens.get(2).is_some_and(|t| t.eq_ignore_ascii_case("set")) {
+        return Err("Expected SET after table name".to_string());
+    }
+
+    let mut statement = Statement::new(StatementType::Update, parse_name(table_name)?);
+    let where_position = keyword_position(tokens, "where").unwrap_or(tokens.len());
+    let (assignments, rest) = tokens[3..].split_at(where_position - 3);
+    if assignments.is_empty() {
+        return Err("Expected assignments after SET".to_string());
+    }
+    statement.assignments = parse_assignments(assignments)?;
+    if !rest.is_empty() {
+        statement.where_clause = Some(parse_condition(&rest[1..])?);
+    }
+    Ok(statement)
+}
+
+/// Parses `DELETE FROM table [WHERE condition]`.
+fn parse_delete(tokens: &[String]) -> Result<Statement, String> {
+    if !tokens
+        .get(1)
+        .is_some_and(|t| t.eq_ignore_ascii_case("from"))
+    {
+        return Err("Expected FROM after DELETE".to_string());
+    }
+    let table_name = tokens.get(2).ok_or("Expected table name after FROM")?;
+    let mut statement = Statement::new(StatementType::Delete, parse_name(table_name)?);
+    match tokens.get(3) {
+        None => {}
+        Some(keyword) if keyword.eq_ignore_ascii_case("where") => {
+            statement.where_clause = Some(parse_condition(&tokens[4..])?);
+        }
+        Some(token) => return Err(format!("Unexpected token after table name: {}", token)),
+    }
+    Ok(statement)
+}
+
+fn starts_with_keywords(tokens: &[String], keywords: &[&str]) -> bool {
+    tokens.len() >= keywords.len()
+        && tokens
+            .iter()
+            .zip(keywords)
+            .all(|(token, keyword)| token.eq_ignore_ascii_case(keyword))
+}
+
+fn parse_column_def(tokens: &[String]) -> Result<ColumnDef, String> {
+    if tokens.len() < 2 {
+        return Err("Expected column name and type".to_string());
+    }
+
+    let mut column = ColumnDef::new(&parse_name(&tokens[0])?, DataType::parse(&tokens[1])?);
+    let mut i = 2;
+    while i < tokens.len() {
+        match tokens[i].to_lowercase().as_str() {
+            "primary" => {
+                if tokens.get(i + 1).map(|t| t.to_lowercase()).as_deref() != Some("key") {
+                    return Err("Expected 'KEY' after 'PRIMARY'".to_string());
+                }
+                column.primary_key = true;
+                i += 2;
+            }
+            "not" => {
+                if tokens.get(i + 1).map(|t| t.to_lowercase()).as_deref() != Some("null") {
+                    return Err("Expected 'NULL' after 'NOT'".to_string());
+                }
+                column.not_null = true;
+                i += 2;
+            }
+            "unique" => {
+                column.unique = true;
+                i += 1;
+            }
+            "autoincrement" => {
+                column.autoincrement = true;
+                i += 1;
+            }
+            "collate" => {
+                let name = tokens
+                    .get(i + 1)
+                    .ok_or("Expected a collation after COLLATE")?;
+                column.collation = Collation::parse(name)?;
+                i += 2;
+            }
+            "check" => {
+                if tokens.get(i + 1).map(|t| t.as_str()) != Some("(") {
+                    return Err("Expected '(' after CHECK".to_string());
+                }
+                let end =
+                    closing_paren(tokens, i + 1).ok_or("Expected ')' after CHECK condition")?;
+                column.check = Some(parse_condition(&tokens[i + 2..end])?);
+                i = end + 1;
+            }
+            // A literal, or any expression in parentheses
+            "default" => match tokens.get(i + 1).map(|t| t.as_str()) {
+                Some("(") => {
+                    let end =
+                        closing_paren(tokens, i + 1).ok_or("Expected ')' after DEFAULT value")?;
+                    column.default = Some(parse_condition(&tokens[i + 2..end])?);
+                    i = end + 1;
+                }
+                Some(value) => {
+                    column.default = Some(Expr::Literal(parse_value(value)?));
+                    i += 2;
+                }
+                None => {
+                    return Err(format!(
+                        "Expected a value after DEFAULT for {}",
+                        column.name
+                    ))
+                }
+            },
+            _ => {
+                return Err(format!(
+                    "Unexpected token in definition of column {}: {}",
+                    column.name, tokens[i]
+                ))
+            }
+        }
+    }
+
+    if column.autoincrement && !(column.primary_key && column.data_type == DataType::Integer) {
+        return Err(format!(
+            "AUTOINCREMENT is only allowed on an integer primary key, not {}",
+            column.name
+        ));
+    }
+
+    Ok(column)
+}
+
+//helper functions
+
+fn value_to_sql(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => format!("'{}'", s),
+        Value::Null => "null".to_string(),
+    }
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if s.starts_with('\'') && s.ends_with('\'') {
+        Ok(Value::Text(s.trim_matches('\'').to_string()))
+    } else if s.eq_ignore_ascii_case("null") {
+        Ok(Value::Null)
+    } else if let Ok(num) = s.parse::<i64>() {
+        Ok(Value::Integer(num))
+    } else {
+        Err(format!("Invalid value: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn where_clause(condition: &str) -> Expr {
+        prepare_statement(&format!("select * from t where {}", condition))
+            .unwrap()
+            .where_clause
+            .unwrap()
+    }
+
+    #[test]
+    fn expressions_print_back_as_they_parse() {
+        for sql in [
+            "a or b and not c = 1",
+            "(a or b) and c",
+            "a - (b - c) * 2",
+            "x not between 1 and 10",
+            "name not like 'a%' || 'b'",
+            "t.name collate nocase in ('a', 'b')",
+            "lower(\"select\") = 'it''s'",
+            "case when n > 0 then 'pos' else null end = 'pos'",
+        ] {
+            let expr = where_clause(sql);
+            assert_eq!(expr.to_string(), sql);
+            assert_eq!(where_clause(&expr.to_string()), expr, "{}", sql);
+        }
+        // Parentheses that change nothing are dropped, and a minus sign is
+        // set apart from what it negates
+        assert_eq!(where_clause("a or (b and c)").to_string(), "a or b and c");
+        assert_eq!(where_clause("-n + 1 >= 5").to_string(), "- n + 1 >= 5");
+    }
+
+    #[test]
+    fn create_index_takes_a_column_or_an_expression() {
+        let statement = parse_schema("create index i on t (\"my col\")").unwrap();
+        assert_eq!(statement.index_name, "i");
+        assert_eq!(statement.table_name, "t");
+        assert_eq!(statement.projection.len(), 1);
+        assert!(matches!(&statement.projection[0], Expr::Column(c) if c.column == "my col"));
+
+        let statement = parse_schema("create index if not exists e on t (lower(email))").unwrap();
+        assert!(statement.if_exists);
+        assert_eq!(statement.projection[0].to_string(), "lower(email)");
+
+        for sql in [
+            "create index i on t ()",
+            "create index i on t (a, b)",
+            "create index i on t a",
+        ] {
+            assert!(parse_schema(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn parse_splits_on_semicolons_outside_quotes_and_triggers() {
+        let statements = parse(
+            "insert into t values ('a;b'); \
+             create trigger tr after insert on t begin insert into u values (1); end;\
+             select \";\" from t",
+        )
+        .unwrap();
+        let types: Vec<StatementType> = statements.iter().map(|s| s.statement_type).collect();
+        assert_eq!(
+            types,
+            [
+                StatementType::Insert,
+                StatementType::CreateTrigger,
+                StatementType::Select
+            ]
+        );
+        assert_eq!(
+            statements[0].values,
+            [[Expr::Literal(Value::Text("a;b".to_string()))]]
+        );
+        assert!(parse("select * from t; nonsense").is_err());
+    }
+
+    #[test]
+    fn parameters_are_numbered_and_bound() {
+        let prepared =
+            PreparedStatement::new("select * from t where a = ? and b = ?5 or c = ?").unwrap();
+        assert_eq!(prepared.param_count(), 6);
+        assert!(prepared.bind(&[Value::Null]).is_err());
+
+        let params: Vec<Value> = (1..=6).map(Value::Integer).collect();
+        let bound = prepared.bind(&params).unwrap();
+        assert_eq!(
+            bound.where_clause.unwrap().to_string(),
+            "a = 1 and b = 5 or c = 6"
+        );
+    }
+
+    #[test]
+    fn redact_literals_hides_values_but_not_names_or_null() {
+        assert_eq!(
+            redact_literals("select name from t where id = 5 and note = 'secret' or x is null"),
+            "select name from t where id = ? and note = ? or x is null"
+        );
     }
 }
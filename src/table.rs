@@ -0,0 +1,1441 @@
+use std::{cmp::Ordering, fs, io, ops::Bound};
+
+use crate::{
+    btree::{self, Node},
+    executor::{coerce, collation_of, compare_collated, evaluate, truth},
+    header::Header,
+    index::Index,
+    output::value_to_string,
+    pager::{Pager, HEADER_PAGE, PAGE_SIZE},
+    sql_parser::{quote_name, Collation, ColumnDef, Row, Value},
+    wal::Wal,
+};
+
+/// The tag ahead of each of a row's values, and of the key ahead of them.
+/// Tags 3 and 4 are kept for real and blob values.
+const NULL_TAG: u8 = 0;
+const INTEGER_TAG: u8 = 1;
+const TEXT_TAG: u8 = 2;
+/// Takes the place of the key's tag in a cell too big for its leaf, or a
+/// key too big for an internal cell. The length of the key and values
+/// follows, then the first of the overflow pages holding the rest of them,
+/// then as many of their bytes as fit in the node.
+const OVERFLOW_TAG: u8 = 5;
+const OVERFLOW_STUB_SIZE: usize = 9;
+/// Every table's tree is entered from the page after the header.
+const ROOT_PAGE: usize = 1;
+
+/// A position in a table's rows, which it reads page by page in key
+/// order.
+pub struct Cursor<'a> {
+    pub table: &'a mut Table,
+    page_num: usize,
+    cell_num: usize,
+    pub end_of_table: bool,
+}
+
+/// An existing row that an insert collides with.
+pub struct Conflict {
+    pub row_num: usize,
+    pub column: String,
+    value: Value,
+}
+
+pub struct Table {
+    pub pager: Pager,
+    pub num_rows: usize,
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// The file the rows are stored in.
+    pub path: String,
+    /// The indexes made with CREATE INDEX. The PRIMARY KEY needs none, as
+    /// the rows are stored in its order.
+    pub indexes: Vec<Index>,
+    pub sequence_path: String,
+    /// Largest key handed out to an AUTOINCREMENT column so far.
+    sequence: i64,
+    /// Whether the first column is kept unique when no column is declared
+    /// PRIMARY KEY, set with `PRAGMA implicit_key`.
+    pub implicit_key: bool,
+}
+
+/// A table as it stood partway through a transaction, to return to when a
+/// statement inside the transaction fails. The pages and index entries
+/// changed since are kept by the pager and the indexes.
+pub struct Savepoint {
+    file_length: usize,
+    num_rows: usize,
+    sequence: i64,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn table_start(table: &'a mut Table) -> io::Result<Cursor<'a>> {
+        let page_num = table.first_leaf()?;
+        let mut cursor = Cursor {
+            table,
+            page_num,
+            cell_num: 0,
+            end_of_table: false,
+        };
+        cursor.skip_empty_pages()?;
+        Ok(cursor)
+    }
+
+    /// Moves back to the first row, to scan the table again.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.page_num = self.table.first_leaf()?;
+        self.cell_num = 0;
+        self.end_of_table = false;
+        self.skip_empty_pages()
+    }
+
+    pub fn advance(&mut self) -> io::Result<()> {
+        self.cell_num += 1;
+        self.skip_empty_pages()
+    }
+
+    /// Moves on to the next leaf while the cursor is past the cells of its
+    /// leaf, setting `end_of_table` after the last.
+    fn skip_empty_pages(&mut self) -> io::Result<()> {
+        loop {
+            let page = self.table.leaf_page(self.page_num)?;
+            if self.cell_num < btree::cell_count(page) {
+                return Ok(());
+            }
+            match btree::next_leaf(page) {
+                0 => {
+                    self.end_of_table = true;
+                    return Ok(());
+                }
+                next => {
+                    self.page_num = next;
+                    self.cell_num = 0;
+                }
+            }
+        }
+    }
+
+    pub fn value(&mut self) -> io::Result<Option<Row>> {
+        self.partial_value(None)
+    }
+
+    /// Like `value`, but deserializes only the given columns.
+    pub fn partial_value(&mut self, only: Option<&[String]>) -> io::Result<Option<Row>> {
+        if self.end_of_table {
+            Ok(None)
+        } else {
+            self.table
+                .read_cell(self.page_num, self.cell_num, only)
+                .map(Some)
+        }
+    }
+}
+
+/// The first overflow page of a cell whose row is too big for its leaf, or
+/// of a key too big for its internal cell.
+fn first_overflow_page(stub: &[u8]) -> Option<usize> {
+    (stub.first() == Some(&OVERFLOW_TAG))
+        .then(|| u32::from_le_bytes(stub[5..9].try_into().unwrap()) as usize)
+}
+
+/// Reads the largest AUTOINCREMENT key handed out, kept beside the table's
+/// file. A table that has never generated one has no sequence file.
+fn read_sequence(path: &str) -> io::Result<i64> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+impl Table {
+    pub fn new(filename: &str, name: &str, columns: Vec<ColumnDef>) -> io::Result<Table> {
+        let pager: Pager = Pager::new(filename)?;
+
+        let sequence_path = format!("{}.sequence", filename);
+        let sequence = read_sequence(&sequence_path)?;
+
+        let mut table = Table {
+            pager,
+            num_rows: 0,
+            name: name.to_string(),
+            columns,
+            path: filename.to_string(),
+            indexes: Vec::new(),
+            sequence_path,
+            sequence,
+            implicit_key: false,
+        };
+        table.open_file()?;
+        Ok(table)
+    }
+
+    /// Writes the header and an empty root to a new file, or checks the
+    /// header of an existing one and takes the row count from it.
+    fn open_file(&mut self) -> io::Result<()> {
+        let path = self.path.clone();
+        let invalid = |message: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, message))
+        };
+        if self.pager.num_pages() == 0 {
+            self.sync_header()?;
+            self.pager.page_mut(ROOT_PAGE)?;
+            return self.pager.flush_dirty();
+        }
+
+        if self.pager.file_length < PAGE_SIZE {
+            return Err(invalid("too short to be a BugDB table file".to_string()));
+        }
+        let header = Header::read(&self.pager.read_unchecked(HEADER_PAGE)?).map_err(invalid)?;
+        if header.page_size != PAGE_SIZE {
+            return Err(invalid(format!(
+                "written with {}-byte pages, but this version uses {}-byte pages",
+                header.page_size, PAGE_SIZE
+            )));
+        }
+        // Only checked once the header says the file is one to check
+        self.pager
+            .get_page(HEADER_PAGE)
+            .map_err(|e| invalid(e.to_string()))?;
+        if header.wal {
+            self.pager.wal = Some(Wal::open(&self.pager.wal_path)?);
+        }
+        self.num_rows = self.stored_row_count()?;
+        if header.row_count != self.num_rows {
+            return Err(invalid(format!(
+                "the header counts {} rows, but the pages hold {}",
+                header.row_count, self.num_rows
+            )));
+        }
+        Ok(())
+    }
+
+    /// Brings the header up to date with the row count and the schema,
+    /// marking it dirty only when it changes so that a session that only
+    /// reads writes nothing. The change counter goes up once for each
+    /// write of changed pages that it precedes.
+    fn sync_header(&mut self) -> io::Result<()> {
+        let schema = self.schema_sql();
+        let wal = self.pager.wal.is_some();
+        let changed = self
+            .pager
+            .cache
+            .dirty_pages()
+            .iter()
+            .any(|&page_num| page_num != HEADER_PAGE);
+        let page = self.pager.get_page(HEADER_PAGE)?;
+        let current = Header::read(page).ok();
+        let mut header = match &current {
+            Some(current) => current.updated(self.num_rows, &schema),
+            None => Header::new(PAGE_SIZE, self.num_rows, &schema),
+        };
+        header.wal = wal;
+        if changed {
+            header.change_counter += 1;
+        }
+        if current.as_ref() != Some(&header) {
+            header.write(self.pager.page_mut(HEADER_PAGE)?);
+        }
+        Ok(())
+    }
+
+    /// Switches between WAL mode and the rollback journal, recording the
+    /// mode in the header. Leaving WAL mode checkpoints the log first.
+    pub fn set_wal(&mut self, on: bool) -> io::Result<()> {
+        if on == self.pager.wal.is_some() {
+            return Ok(());
+        }
+        self.pager.flush_dirty()?;
+        if on {
+            self.pager.wal = Some(Wal::open(&self.pager.wal_path)?);
+        } else {
+            self.pager.close_wal()?;
+        }
+        self.sync_header()?;
+        self.pager.flush_dirty()
+    }
+
+    /// Adds an index to the table, loading its entries from its file or,
+    /// when that is missing or out of date, building them from the rows.
+    pub fn attach_index(&mut self, mut index: Index) -> io::Result<()> {
+        index.collation = match index.column() {
+            Some(column) => self
+                .columns
+                .iter()
+                .find(|c| c.name == column)
+                .map_or(Collation::Binary, |c| c.collation),
+            None => collation_of(&index.expr).unwrap_or_default(),
+        };
+        if !index.load(self.num_rows)? {
+            let only: Vec<String> = index
+                .expr
+                .column_refs()
+                .iter()
+                .map(|column| column.column.clone())
+                .collect();
+            for row_num in 0..self.num_rows {
+                if let Some(row) = self.read_row(row_num, Some(&only))? {
+                    index.insert(&index.value_of(&row)?, &self.row_key(row_num)?)?;
+                }
+            }
+        }
+        // Built inside a transaction, it is rolled back with the rows
+        if self.pager.pending.is_some() {
+            index.begin();
+        }
+        self.indexes.push(index);
+        Ok(())
+    }
+
+    pub fn index_path(&self, name: &str) -> String {
+        format!("{}.{}.index", self.path, name)
+    }
+
+    /// The CREATE INDEX statements of the table's named indexes.
+    pub fn index_sql(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .map(|index| {
+                format!(
+                    "create index {} on {} ({})",
+                    quote_name(&index.name),
+                    quote_name(&self.name),
+                    index.expr
+                )
+            })
+            .collect()
+    }
+
+    fn find_index(&self, column: &str) -> Option<&Index> {
+        self.indexes
+            .iter()
+            .find(|index| index.column() == Some(column))
+    }
+
+    pub fn remove_index_files(&self) -> io::Result<()> {
+        for index in &self.indexes {
+            index.remove_file()?;
+        }
+        Ok(())
+    }
+
+    pub fn begin(&mut self) -> io::Result<()> {
+        self.pager.begin()?;
+        for index in &mut self.indexes {
+            index.begin();
+        }
+        Ok(())
+    }
+
+    /// Writes the transaction's changes, or, when the file cannot take
+    /// them, rolls back to BEGIN and reports why.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let committed = self.sync_header().and_then(|()| self.pager.commit());
+        if let Err(e) = committed {
+            self.rollback()?;
+            return Err(e);
+        }
+        for index in &mut self.indexes {
+            index.commit();
+        }
+        if self.sequence > read_sequence(&self.sequence_path)? {
+            fs::write(&self.sequence_path, self.sequence.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Marks where a statement inside a transaction starts, so that it can
+    /// be undone alone. Every savepoint is closed by `release` or
+    /// `restore`, innermost first.
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.pager.savepoint();
+        for index in &mut self.indexes {
+            index.savepoint();
+        }
+        Savepoint {
+            file_length: self.pager.file_length,
+            num_rows: self.num_rows,
+            sequence: self.sequence,
+        }
+    }
+
+    /// Keeps everything since `savepoint`, for the transaction to commit
+    /// or roll back.
+    pub fn release(&mut self, _savepoint: Savepoint) {
+        self.pager.release();
+        for index in &mut self.indexes {
+            index.release();
+        }
+    }
+
+    /// Undoes everything since `savepoint`. Nothing reaches the file inside
+    /// a transaction, so the pages kept since hold every change to undo.
+    pub fn restore(&mut self, savepoint: Savepoint) {
+        self.pager.restore();
+        self.pager.file_length = savepoint.file_length;
+        self.num_rows = savepoint.num_rows;
+        self.sequence = savepoint.sequence;
+        for index in &mut self.indexes {
+            index.restore();
+        }
+    }
+
+    /// Puts the table back as it was at BEGIN: the row count comes from the
+    /// untouched file, the sequence from its file and the indexes from
+    /// undoing their changes.
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.pager.rollback()?;
+        self.num_rows = self.stored_row_count()?;
+        self.sequence = read_sequence(&self.sequence_path)?;
+        for index in &mut self.indexes {
+            index.rollback();
+        }
+        Ok(())
+    }
+
+    fn leaf(&self) -> Node {
+        Node::leaf(self.pager.usable_size())
+    }
+
+    fn internal(&self) -> Node {
+        Node::internal(self.pager.usable_size())
+    }
+
+    /// The column whose values key the rows, which are kept in its order.
+    /// A table without a PRIMARY KEY keys its rows by a number of their own
+    /// instead, counting up, so they stay in the order they were inserted.
+    fn key_column(&self) -> Option<&ColumnDef> {
+        self.columns.iter().find(|c| c.primary_key)
+    }
+
+    fn compare_keys(&self, a: &Value, b: &Value) -> Ordering {
+        let collation = self.key_column().map_or(Collation::Binary, |c| c.collation);
+        compare_collated(a, b, collation).unwrap_or(Ordering::Equal)
+    }
+
+    /// A page of the table, checked to be a node of the tree.
+    fn node_page(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        let page = self.pager.get_page(page_num)?;
+        if !matches!(btree::node_type(page), btree::LEAF | btree::INTERNAL) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "page {} of table {} is not a tree node",
+                    page_num, self.name
+                ),
+            ));
+        }
+        Ok(page)
+    }
+
+    /// A node of the tree made ready to change; see `Pager::page_mut`.
+    fn node_page_mut(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        self.node_page(page_num)?;
+        self.pager.page_mut(page_num)
+    }
+
+    /// A leaf made ready to change; see `Pager::page_mut`.
+    fn leaf_page_mut(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        self.leaf_page(page_num)?;
+        self.pager.page_mut(page_num)
+    }
+
+    /// A page of the table, checked to be a leaf.
+    fn leaf_page(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        let page = self.pager.get_page(page_num)?;
+        if btree::node_type(page) != btree::LEAF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {} of table {} is not a leaf", page_num, self.name),
+            ));
+        }
+        Ok(page)
+    }
+
+    /// The children of an internal page: each one's page, number of rows
+    /// and greatest key.
+    fn children(&mut self, page_num: usize) -> io::Result<Vec<(usize, usize, Value)>> {
+        let internal = self.internal();
+        let count = btree::cell_count(self.node_page(page_num)?);
+        let mut children = Vec::with_capacity(count);
+        for i in 0..count {
+            let cell = internal.cell(self.node_page(page_num)?, i);
+            let (child_page, rows) = (btree::child_page(cell), btree::child_rows(cell));
+            let key = match Self::key_in_node(btree::child_key(cell))? {
+                Some(key) => key,
+                None => {
+                    let stub = btree::child_key(cell).to_vec();
+                    self.read_key(&stub)?
+                }
+            };
+            children.push((child_page, rows, key));
+        }
+        Ok(children)
+    }
+
+    /// The number of rows in a node and the nodes below it, read from its
+    /// header or, for an internal node, its children's counts.
+    fn node_rows(&mut self, page_num: usize) -> io::Result<usize> {
+        let page = self.node_page(page_num)?;
+        if btree::node_type(page) == btree::LEAF {
+            return Ok(btree::cell_count(page));
+        }
+        Ok(self
+            .children(page_num)?
+            .iter()
+            .map(|(_, rows, _)| rows)
+            .sum())
+    }
+
+    /// The greatest key in a node: its last row's for a leaf, and its last
+    /// child's for an internal node.
+    fn node_key(&mut self, page_num: usize) -> io::Result<Value> {
+        let page = self.node_page(page_num)?;
+        let count = btree::cell_count(page);
+        if btree::node_type(page) == btree::LEAF {
+            return self.cell_key(page_num, count - 1);
+        }
+        let (_, _, key) = self.children(page_num)?.pop().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "internal page without children")
+        })?;
+        Ok(key)
+    }
+
+    /// Whether a leaf has no room for another cell of `cell_size` bytes.
+    /// An internal node keeps room for the three cells an insert below it
+    /// may add or grow: a child split off, and a longer key for two others.
+    fn node_is_full(&mut self, page_num: usize, cell_size: usize) -> io::Result<bool> {
+        let (node, size) = match btree::node_type(self.node_page(page_num)?) {
+            btree::LEAF => (self.leaf(), cell_size),
+            _ => (self.internal(), 3 * self.internal().max_cell_size()),
+        };
+        Ok(!node.has_room(self.node_page(page_num)?, size))
+    }
+
+    fn stored_row_count(&mut self) -> io::Result<usize> {
+        self.node_rows(ROOT_PAGE)
+    }
+
+    /// The page and cell holding a row, or None past the last row. Each
+    /// internal node's row counts say which child the row is under.
+    fn locate(&mut self, row_num: usize) -> io::Result<Option<(usize, usize)>> {
+        let mut page_num = ROOT_PAGE;
+        let mut remaining = row_num;
+        loop {
+            let page = self.node_page(page_num)?;
+            if btree::node_type(page) == btree::LEAF {
+                return Ok((remaining < btree::cell_count(page)).then_some((page_num, remaining)));
+            }
+            let mut child = None;
+            for (child_page, rows, _) in self.children(page_num)? {
+                if remaining < rows {
+                    child = Some(child_page);
+                    break;
+                }
+                remaining -= rows;
+            }
+            match child {
+                Some(child) => page_num = child,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// The leftmost leaf, where a scan starts.
+    fn first_leaf(&mut self) -> io::Result<usize> {
+        let mut page_num = ROOT_PAGE;
+        while btree::node_type(self.node_page(page_num)?) == btree::INTERNAL {
+            page_num = self.children(page_num)?[0].0;
+        }
+        Ok(page_num)
+    }
+
+    fn cell_key(&mut self, page_num: usize, cell_num: usize) -> io::Result<Value> {
+        let leaf = self.leaf();
+        let cell = leaf.cell(self.leaf_page(page_num)?, cell_num);
+        match Self::key_in_node(cell)? {
+            Some(key) => Ok(key),
+            None => {
+                let stub = cell.to_vec();
+                self.read_key(&stub)
+            }
+        }
+    }
+
+    /// A key read from the start of a leaf cell or from an internal cell,
+    /// or None when some of it is only on its overflow pages.
+    fn key_in_node(bytes: &[u8]) -> io::Result<Option<Value>> {
+        if first_overflow_page(bytes).is_none() {
+            return Ok(Some(Self::untag_value(bytes)?.0));
+        }
+        let in_node = &bytes[OVERFLOW_STUB_SIZE..];
+        if Self::tagged_size(in_node) > in_node.len() {
+            return Ok(None);
+        }
+        Ok(Some(Self::untag_value(in_node)?.0))
+    }
+
+    /// A key whose bytes may go on to overflow pages, read in full.
+    fn read_key(&mut self, bytes: &[u8]) -> io::Result<Value> {
+        if let Some(key) = Self::key_in_node(bytes)? {
+            return Ok(key);
+        }
+        Ok(Self::untag_value(&self.read_overflow(bytes)?)?.0)
+    }
+
+    /// The key a row is stored under, which the indexes refer to it by.
+    fn row_key(&mut self, row_num: usize) -> io::Result<Value> {
+        let (page_num, cell_num) = self.locate(row_num)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "row missing from table")
+        })?;
+        self.cell_key(page_num, cell_num)
+    }
+
+    /// The number of rows whose key is less than `key`, or not greater
+    /// than it when `past_equal` is set. The search descends from the
+    /// root, passing over children whose greatest key is below the bound.
+    /// A child's key may be stale after deletes, but it is never less
+    /// than the keys under it, so the search still finds the right leaf.
+    fn rank(&mut self, key: &Value, past_equal: bool) -> io::Result<usize> {
+        let below = |table: &Self, other: &Value| match table.compare_keys(other, key) {
+            Ordering::Less => true,
+            Ordering::Equal => past_equal,
+            Ordering::Greater => false,
+        };
+        let mut page_num = ROOT_PAGE;
+        let mut rank = 0;
+        loop {
+            if btree::node_type(self.node_page(page_num)?) == btree::LEAF {
+                let (mut low, mut high) = (0, btree::cell_count(self.leaf_page(page_num)?));
+                while low < high {
+                    let middle = (low + high) / 2;
+                    let middle_key = self.cell_key(page_num, middle)?;
+                    if below(self, &middle_key) {
+                        low = middle + 1;
+                    } else {
+                        high = middle;
+                    }
+                }
+                return Ok(rank + low);
+            }
+            let mut child = None;
+            for (child_page, rows, child_key) in self.children(page_num)? {
+                if !below(self, &child_key) {
+                    child = Some(child_page);
+                    break;
+                }
+                rank += rows;
+            }
+            match child {
+                Some(child) => page_num = child,
+                None => return Ok(rank),
+            }
+        }
+    }
+
+    /// The row stored under a key, if there is one.
+    fn find_row(&mut self, key: &Value) -> io::Result<Option<usize>> {
+        // NULL compares equal to nothing, so no search could find it
+        if key == &Value::Null {
+            return Ok(None);
+        }
+        let row_num = self.rank(key, false)?;
+        if row_num >= self.num_rows {
+            return Ok(None);
+        }
+        let found = self.row_key(row_num)?;
+        Ok((self.compare_keys(&found, key) == Ordering::Equal).then_some(row_num))
+    }
+
+    /// The rows whose keys lie between the bounds, found by searching the
+    /// tree for each end. A NULL bound matches nothing.
+    pub fn key_range(
+        &mut self,
+        lower: Bound<&Value>,
+        upper: Bound<&Value>,
+    ) -> io::Result<Vec<usize>> {
+        let start = match lower {
+            Bound::Included(Value::Null) | Bound::Excluded(Value::Null) => return Ok(Vec::new()),
+            Bound::Included(key) => self.rank(key, false)?,
+            Bound::Excluded(key) => self.rank(key, true)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match upper {
+            Bound::Included(Value::Null) | Bound::Excluded(Value::Null) => return Ok(Vec::new()),
+            Bound::Included(key) => self.rank(key, true)?,
+            Bound::Excluded(key) => self.rank(key, false)?,
+            Bound::Unbounded => self.num_rows,
+        };
+        Ok((start..end.max(start)).collect())
+    }
+
+    /// The rows stored under the given keys, in row order. Keys no row has
+    /// any more are passed over.
+    pub fn rows_for_keys(&mut self, keys: &[Value]) -> io::Result<Vec<usize>> {
+        let mut row_nums = Vec::new();
+        for key in keys {
+            if let Some(row_num) = self.find_row(key)? {
+                row_nums.push(row_num);
+            }
+        }
+        row_nums.sort_unstable();
+        Ok(row_nums)
+    }
+
+    /// The key for the next row of a table without a PRIMARY KEY: one more
+    /// than the greatest the root knows of. Once the root is an internal
+    /// node, keys of deleted rows at the end are not handed out again.
+    fn next_row_key(&mut self) -> io::Result<i64> {
+        if btree::cell_count(self.node_page(ROOT_PAGE)?) == 0 {
+            return Ok(1);
+        }
+        match self.node_key(ROOT_PAGE)? {
+            Value::Integer(key) => Ok(key + 1),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("row key of table {} is not a number", self.name),
+            )),
+        }
+    }
+
+    pub fn schema_sql(&self) -> String {
+        let definitions: Vec<String> = self.columns.iter().map(|c| c.to_sql()).collect();
+        format!(
+            "create table {} ({})",
+            quote_name(&self.name),
+            definitions.join(", ")
+        )
+    }
+
+    /// Writes the pages changed since they were last written, and no
+    /// others, so a session that only reads leaves the file as it was.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.sync_header()?;
+        self.pager.flush_dirty()?;
+        // A closed table is its file alone, with the log folded into it
+        self.pager.close_wal()?;
+
+        for index in &mut self.indexes {
+            index.save(self.num_rows)?;
+        }
+        Ok(())
+    }
+
+    pub fn row_slot(&mut self, row_num: usize) -> io::Result<Option<Row>> {
+        self.read_row(row_num, None)
+    }
+
+    /// Reads a row, deserializing only the named columns when `only` is
+    /// given.
+    pub fn read_row(&mut self, row_num: usize, only: Option<&[String]>) -> io::Result<Option<Row>> {
+        match self.locate(row_num)? {
+            Some((page_num, cell_num)) => self.read_cell(page_num, cell_num, only).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn read_cell(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        only: Option<&[String]>,
+    ) -> io::Result<Row> {
+        let leaf = self.leaf();
+        let overflowing;
+        let cell = if self.overflow_chain(page_num, cell_num)?.is_some() {
+            let stub = leaf.cell(self.pager.get_page(page_num)?, cell_num).to_vec();
+            overflowing = self.read_overflow(&stub)?;
+            &overflowing
+        } else {
+            // Borrowing the pager alone leaves the columns free to read
+            let page = self.pager.get_page(page_num)?;
+            leaf.cell(page, cell_num)
+        };
+        let values = &cell[Self::tagged_size(cell)..];
+        let mut row = Row::new();
+        let mut value_offset = 0;
+        for column in &self.columns {
+            let (value, size) = Self::untag_value(&values[value_offset..])?;
+            value_offset += size;
+            if only.is_none_or(|only| only.contains(&column.name)) {
+                row.values.insert(column.name.clone(), value);
+            }
+        }
+        Ok(row)
+    }
+
+    /// Adds a row and returns the key generated for an AUTOINCREMENT
+    /// column, if the row did not supply one. On success `row` holds what
+    /// was written, including that key.
+    pub fn insert(&mut self, row: &mut Row) -> io::Result<Option<i64>> {
+        self.apply_types(row)?;
+        let generated_key = self.assign_autoincrement(row)?;
+        self.check_row(row, None)?;
+        self.store_row(row)?;
+        self.advance_sequence(row)?;
+
+        Ok(generated_key)
+    }
+
+    /// Writes a row that has passed its checks to its place in key order
+    /// and adds it to the indexes, returning its row number. Rows that now
+    /// come after it move up a row number.
+    ///
+    /// The insert descends from the root, splitting every full node on the
+    /// way before entering it, so the leaf it reaches has room and each
+    /// split has room in the parent above.
+    fn store_row(&mut self, row: &Row) -> io::Result<usize> {
+        let key = match self.key_column() {
+            Some(column) => row.values.get(&column.name).cloned().unwrap_or(Value::Null),
+            None => Value::Integer(self.next_row_key()?),
+        };
+        // Worked out first, so an expression that fails leaves nothing
+        // written
+        let indexed = self
+            .indexes
+            .iter()
+            .map(|index| index.value_of(row))
+            .collect::<io::Result<Vec<_>>>()?;
+        let cell = self.serialize_cell(&key, row);
+        let cell = self.spill(cell, self.leaf().max_cell_size())?;
+        let (leaf, internal) = (self.leaf(), self.internal());
+        if self.node_is_full(ROOT_PAGE, cell.len())? {
+            self.grow_root()?;
+        }
+
+        let mut page_num = ROOT_PAGE;
+        let mut row_num = 0;
+        while btree::node_type(self.node_page(page_num)?) == btree::INTERNAL {
+            let mut child_num = self.choose_child(page_num, &key)?;
+            let (child_page, _, _) = self.children(page_num)?[child_num];
+            if self.node_is_full(child_page, cell.len())? {
+                self.split_child(page_num, child_num)?;
+                child_num = self.choose_child(page_num, &key)?;
+            }
+
+            let children = self.children(page_num)?;
+            let (child_page, rows, child_key) = &children[child_num];
+            row_num += children[..child_num]
+                .iter()
+                .map(|(_, rows, _)| rows)
+                .sum::<usize>();
+            if self.compare_keys(&key, child_key) == Ordering::Greater {
+                // The node kept room for the longer key when it was entered
+                let raised = btree::child_cell(*child_page, rows + 1, &self.spill_key(&key)?);
+                let page = self.node_page_mut(page_num)?;
+                let old_chain =
+                    first_overflow_page(btree::child_key(internal.cell(page, child_num)));
+                internal.replace(page, child_num, &raised);
+                if let Some(old_chain) = old_chain {
+                    self.free_overflow(old_chain)?;
+                }
+            } else {
+                let page = self.node_page_mut(page_num)?;
+                btree::set_child_rows(internal.cell_mut(page, child_num), rows + 1);
+            }
+            page_num = *child_page;
+        }
+
+        // Binary search for the first cell with a greater key
+        let count = btree::cell_count(self.leaf_page(page_num)?);
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let middle = (low + high) / 2;
+            let middle_key = self.cell_key(page_num, middle)?;
+            if self.compare_keys(&middle_key, &key) == Ordering::Greater {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        let page = self.leaf_page_mut(page_num)?;
+        leaf.insert(page, low, &cell);
+
+        self.num_rows += 1;
+        for (index, value) in self.indexes.iter_mut().zip(indexed) {
+            index.insert(&value, &key)?;
+        }
+        Ok(row_num + low)
+    }
+
+    /// The child of an internal page whose rows a key belongs among: the
+    /// first whose greatest key is not less than it, or else the last.
+    fn choose_child(&mut self, page_num: usize, key: &Value) -> io::Result<usize> {
+        let children = self.children(page_num)?;
+        Ok(children
+            .iter()
+            .position(|(_, _, child_key)| self.compare_keys(child_key, key) != Ordering::Less)
+            .unwrap_or(children.len() - 1))
+    }
+
+    /// Moves the full root to a new page and makes the root an internal
+    /// node with it as the only child, which the insert then splits. The
+    /// root stays on its page so it can always be found.
+    fn grow_root(&mut self) -> io::Result<()> {
+        let rows = self.node_rows(ROOT_PAGE)?;
+        let key = self.node_key(ROOT_PAGE)?;
+        let key = self.spill_key(&key)?;
+        let new_page_num = self.allocate_page()?;
+        let old_root = self.node_page(ROOT_PAGE)?.clone();
+        *self.pager.page_mut(new_page_num)? = old_root;
+
+        let mut root = btree::internal_page();
+        let cell = btree::child_cell(new_page_num, rows, &key);
+        self.internal().insert(&mut root, 0, &cell);
+        *self.pager.page_mut(ROOT_PAGE)? = root;
+        Ok(())
+    }
+
+    /// Splits a full child of an internal page in two, adding the new
+    /// upper half to the parent after it. The upper half keeps the old
+    /// child's greatest key; the lower half takes its own last key.
+    fn split_child(&mut self, page_num: usize, child_num: usize) -> io::Result<()> {
+        let (child_page, _, _) = self.children(page_num)?[child_num];
+        let node = match btree::node_type(self.node_page(child_page)?) {
+            btree::LEAF => self.leaf(),
+            _ => self.internal(),
+        };
+        let new_page_num = self.allocate_page()?;
+        let new_page = node.split(self.node_page_mut(child_page)?, new_page_num);
+        *self.pager.page_mut(new_page_num)? = new_page;
+
+        let lower_key = self.node_key(child_page)?;
+        let lower = btree::child_cell(
+            child_page,
+            self.node_rows(child_page)?,
+            &self.spill_key(&lower_key)?,
+        );
+        let internal = self.internal();
+        let page = self.node_page(page_num)?;
+        let upper_key = btree::child_key(internal.cell(page, child_num)).to_vec();
+        let upper = btree::child_cell(new_page_num, self.node_rows(new_page_num)?, &upper_key);
+        // The parent kept room for both when it was entered
+        let page = self.node_page_mut(page_num)?;
+        internal.replace(page, child_num, &lower);
+        internal.insert(page, child_num + 1, &upper);
+        Ok(())
+    }
+
+    /// Overwrites an existing row, as UPDATE, INSERT OR REPLACE and
+    /// ON CONFLICT DO UPDATE do, and returns its row number afterwards. A
+    /// row whose key changes is moved to keep the rows in key order.
+    pub fn replace_row(&mut self, row_num: usize, row: &mut Row) -> io::Result<usize> {
+        self.apply_types(row)?;
+        self.check_row(row, Some(row_num))?;
+        // The old values are needed to take the row out of the indexes
+        let old = self.row_slot(row_num)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "row missing from table")
+        })?;
+        let moved = self.key_column().is_some_and(|column| {
+            let (old, new) = (&old.values[&column.name], &row.values[&column.name]);
+            self.compare_keys(old, new) != Ordering::Equal
+        });
+        let old_key = self.row_key(row_num)?;
+        let indexed = self
+            .indexes
+            .iter()
+            .map(|index| Ok((index.value_of(&old)?, index.value_of(row)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        if !moved && self.replace_cell(row_num, row)? {
+            // The key may be respelled, though it still sorts the same
+            let new_key = self.row_key(row_num)?;
+            for (index, (old_value, new_value)) in self.indexes.iter_mut().zip(indexed) {
+                index.remove(&old_value, &old_key)?;
+                index.insert(&new_value, &new_key)?;
+            }
+            self.advance_sequence(row)?;
+            return Ok(row_num);
+        }
+
+        // A row that moves, or grows too big for its leaf, is taken out and
+        // stored again
+        self.delete_rows(&[(row_num, old)])?;
+        let new_row_num = self.store_row(row)?;
+        self.advance_sequence(row)?;
+        Ok(new_row_num)
+    }
+
+    /// Overwrites a row's cell where it is, or returns false, changing
+    /// nothing, when the new cell does not fit in its leaf.
+    fn replace_cell(&mut self, row_num: usize, row: &Row) -> io::Result<bool> {
+        let (page_num, cell_num) = self.locate(row_num)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "row missing from table")
+        })?;
+        // The key is rewritten too, since a key equal under its collation
+        // may still be spelled differently
+        let key = match self.key_column() {
+            Some(column) => row.values[&column.name].clone(),
+            None => self.cell_key(page_num, cell_num)?,
+        };
+        let old_chain = self.overflow_chain(page_num, cell_num)?;
+        let cell = self.serialize_cell(&key, row);
+        let cell = self.spill(cell, self.leaf().max_cell_size())?;
+        let new_chain = first_overflow_page(&cell);
+        let leaf = self.leaf();
+        // Marked dirty even when the cell does not fit, as the row is then
+        // taken out of the same leaf
+        let page = self.leaf_page_mut(page_num)?;
+        if !leaf.replace(page, cell_num, &cell) {
+            if let Some(new_chain) = new_chain {
+                self.free_overflow(new_chain)?;
+            }
+            return Ok(false);
+        }
+        if let Some(old_chain) = old_chain {
+            self.free_overflow(old_chain)?;
+        }
+        Ok(true)
+    }
+
+    /// Moves the bytes of a cell or key longer than `max_size` out to a
+    /// chain of overflow pages, leaving a stub in their place with as many
+    /// of them as fit. Shorter ones are returned as they are.
+    fn spill(&mut self, bytes: Vec<u8>, max_size: usize) -> io::Result<Vec<u8>> {
+        if bytes.len() <= max_size {
+            return Ok(bytes);
+        }
+        let (in_node, rest) = bytes.split_at(max_size - OVERFLOW_STUB_SIZE);
+        // Last first, so each page knows the one after it
+        let capacity = btree::overflow_capacity(self.pager.usable_size());
+        let mut first_page = 0;
+        for part in rest.chunks(capacity).rev() {
+            let page_num = self.allocate_page()?;
+            *self.pager.page_mut(page_num)? = btree::overflow_page(part, first_page);
+            first_page = page_num;
+        }
+
+        let mut stub = Vec::with_capacity(max_size);
+        stub.push(OVERFLOW_TAG);
+        stub.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        stub.extend_from_slice(&(first_page as u32).to_le_bytes());
+        stub.extend_from_slice(in_node);
+        Ok(stub)
+    }
+
+    /// A key as an internal cell holds it, spilling to overflow pages when
+    /// it is too long for the node.
+    fn spill_key(&mut self, key: &Value) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        Self::tag_value(&mut bytes, Some(key));
+        self.spill(bytes, self.internal().max_key_size())
+    }
+
+    /// The first overflow page of a row, if it is too big for its leaf.
+    fn overflow_chain(&mut self, page_num: usize, cell_num: usize) -> io::Result<Option<usize>> {
+        let leaf = self.leaf();
+        let page = self.leaf_page(page_num)?;
+        Ok(first_overflow_page(leaf.cell(page, cell_num)))
+    }
+
+    /// Puts a cell or key back together from its stub, which starts with
+    /// the overflow tag, and its overflow pages.
+    fn read_overflow(&mut self, stub: &[u8]) -> io::Result<Vec<u8>> {
+        let length = u32::from_le_bytes(stub[1..5].try_into().unwrap()) as usize;
+        let mut page_num = u32::from_le_bytes(stub[5..9].try_into().unwrap()) as usize;
+        let capacity = btree::overflow_capacity(self.pager.usable_size());
+        let mut values = Vec::with_capacity(length);
+        values.extend_from_slice(&stub[OVERFLOW_STUB_SIZE..]);
+        while values.len() < length {
+            let page = self.overflow_page(page_num)?;
+            let part = btree::overflow_part(page, (length - values.len()).min(capacity));
+            values.extend_from_slice(part);
+            page_num = btree::next_page(page);
+        }
+        Ok(values)
+    }
+
+    /// A page of the table, checked to be an overflow page.
+    fn overflow_page(&mut self, page_num: usize) -> io::Result<&mut Vec<u8>> {
+        let page = self.pager.get_page(page_num)?;
+        if btree::node_type(page) != btree::OVERFLOW {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "page {} of table {} is not an overflow page",
+                    page_num, self.name
+                ),
+            ));
+        }
+        Ok(page)
+    }
+
+    /// Puts every page of an overflow chain on the free list.
+    fn free_overflow(&mut self, mut page_num: usize) -> io::Result<()> {
+        let mut free_page = self.header()?.free_page;
+        while page_num != 0 {
+            let next_page = btree::next_page(self.overflow_page(page_num)?);
+            *self.pager.page_mut(page_num)? = btree::free_page(free_page);
+            free_page = page_num;
+            page_num = next_page;
+        }
+        self.set_free_page(free_page)
+    }
+
+    /// A page for the tree or an overflow chain: the first on the free list,
+    /// emptied, or else a new one at the end of the file.
+    pub fn allocate_page(&mut self) -> io::Result<usize> {
+        let page_num = self.header()?.free_page;
+        if page_num == 0 {
+            return self.pager.allocate_page();
+        }
+        let page = self.pager.get_page(page_num)?;
+        if btree::node_type(page) != btree::FREE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "page {} of table {} is not a free page",
+                    page_num, self.name
+                ),
+            ));
+        }
+        let next_page = btree::next_page(page);
+        self.pager.page_mut(page_num)?.fill(0);
+        self.set_free_page(next_page)?;
+        Ok(page_num)
+    }
+
+    fn header(&mut self) -> io::Result<Header> {
+        Header::read(self.pager.get_page(HEADER_PAGE)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Makes `page_num` the first page of the free list.
+    fn set_free_page(&mut self, page_num: usize) -> io::Result<()> {
+        let mut header = self.header()?;
+        header.free_page = page_num;
+        header.write(self.pager.page_mut(HEADER_PAGE)?);
+        Ok(())
+    }
+
+    /// Runs every constraint against a candidate row. `replacing` names the
+    /// row it will overwrite, which is exempt from the uniqueness checks.
+    fn check_row(&mut self, row: &Row, replacing: Option<usize>) -> io::Result<()> {
+        self.check_not_null(row)?;
+        self.check_conditions(row)?;
+        self.check_unique(row, replacing)
+    }
+
+    pub fn assign_autoincrement(&self, row: &mut Row) -> io::Result<Option<i64>> {
+        let column = match self.columns.iter().find(|c| c.autoincrement) {
+            Some(column) => column,
+            None => return Ok(None),
+        };
+        if !matches!(row.values.get(&column.name), None | Some(Value::Null)) {
+            return Ok(None);
+        }
+
+        let key = self.sequence.checked_add(1).ok_or_else(|| {
+            io::Error::other(format!(
+                "AUTOINCREMENT exhausted for {}.{}",
+                self.name, column.name
+            ))
+        })?;
+        row.values.insert(column.name.clone(), Value::Integer(key));
+        Ok(Some(key))
+    }
+
+    /// Records the key of a newly written row so that generated keys keep
+    /// increasing past explicitly supplied ones, even across restarts.
+    fn advance_sequence(&mut self, row: &Row) -> io::Result<()> {
+        let column = match self.columns.iter().find(|c| c.autoincrement) {
+            Some(column) => column,
+            None => return Ok(()),
+        };
+        if let Some(Value::Integer(key)) = row.values.get(&column.name) {
+            if *key > self.sequence {
+                self.sequence = *key;
+                // A transaction writes the sequence when it commits
+                if self.pager.pending.is_none() {
+                    fs::write(&self.sequence_path, key.to_string())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards every row by cutting the file back to nothing.
+    pub fn clear_rows(&mut self) -> io::Result<()> {
+        self.num_rows = 0;
+        for index in &mut self.indexes {
+            index.clear()?;
+        }
+        // Only the header is kept, and the root starts over as an empty leaf
+        self.pager.truncate(ROOT_PAGE);
+        self.pager.page_mut(ROOT_PAGE)?;
+        self.set_free_page(0)?;
+        Ok(())
+    }
+
+    /// Removes the given rows, listed in row order with their values. Each
+    /// leaves its page, and every later row moves down a row number.
+    pub fn delete_rows(&mut self, deleted: &[(usize, Row)]) -> io::Result<()> {
+        if !self.indexes.is_empty() {
+            for (row_num, row) in deleted {
+                let key = self.row_key(*row_num)?;
+                for index in &mut self.indexes {
+                    index.remove(&index.value_of(row)?, &key)?;
+                }
+            }
+        }
+
+        // Last first, so the rows still to go keep their numbers
+        for (row_num, _) in deleted.iter().rev() {
+            self.remove_row(*row_num)?;
+        }
+        self.num_rows -= deleted.len();
+        Ok(())
+    }
+
+    /// Takes a row out of its leaf, counting it out of every internal node
+    /// above. Nodes are left as they are however few rows they keep.
+    fn remove_row(&mut self, row_num: usize) -> io::Result<()> {
+        let (leaf, internal) = (self.leaf(), self.internal());
+        let missing = || io::Error::new(io::ErrorKind::UnexpectedEof, "row missing from table");
+        let mut page_num = ROOT_PAGE;
+        let mut remaining = row_num;
+        while btree::node_type(self.node_page(page_num)?) == btree::INTERNAL {
+            let children = self.children(page_num)?;
+            let mut child_num = 0;
+            while remaining >= children.get(child_num).ok_or_else(missing)?.1 {
+                remaining -= children[child_num].1;
+                child_num += 1;
+            }
+            let page = self.node_page_mut(page_num)?;
+            btree::set_child_rows(
+                internal.cell_mut(page, child_num),
+                children[child_num].1 - 1,
+            );
+            page_num = children[child_num].0;
+        }
+        if remaining >= btree::cell_count(self.leaf_page(page_num)?) {
+            return Err(missing());
+        }
+        let chain = self.overflow_chain(page_num, remaining)?;
+        leaf.remove(self.leaf_page_mut(page_num)?, remaining);
+        if let Some(chain) = chain {
+            self.free_overflow(chain)?;
+        }
+        Ok(())
+    }
+
+    /// A column holds values of its own type, so a value of another type
+    /// is converted when that loses nothing (see `coerce`) and rejected
+    /// otherwise.
+    pub fn apply_types(&self, row: &mut Row) -> io::Result<()> {
+        for column in &self.columns {
+            let value = match row.values.get_mut(&column.name) {
+                Some(value) => value,
+                None => continue,
+            };
+            match coerce(value, column.data_type) {
+                Some(converted) => *value = converted,
+                None => {
+                    let found = match value {
+                        Value::Integer(_) => "integer",
+                        _ => "text",
+                    };
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "cannot store {} in {} column {}",
+                            found,
+                            column.data_type.name(),
+                            column.name
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_not_null(&self, row: &Row) -> io::Result<()> {
+        for column in self.columns.iter().filter(|c| c.not_null) {
+            if matches!(row.values.get(&column.name), None | Some(Value::Null)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("NOT NULL constraint failed: {}.{}", self.name, column.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_conditions(&self, row: &Row) -> io::Result<()> {
+        for check in self.columns.iter().filter_map(|c| c.check.as_ref()) {
+            let value =
+                evaluate(check, row).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            // Only a definite false fails the check; NULL lets the row through
+            if truth(&value) == Some(false) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CHECK constraint failed: {}", check),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_unique(&mut self, row: &Row, replacing: Option<usize>) -> io::Result<()> {
+        // Primary keys may never be NULL; other unique columns may hold any
+        // number of NULLs, which never compare equal to each other.
+        for column in self.columns.iter().filter(|c| c.primary_key) {
+            if matches!(row.values.get(&column.name), None | Some(Value::Null)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("UNIQUE constraint failed: {}.{}", self.name, column.name),
+                ));
+            }
+        }
+
+        match self.find_conflict(row, replacing)? {
+            Some(conflict) => Err(self.unique_violation(&conflict)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn unique_violation(&self, conflict: &Conflict) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "UNIQUE constraint failed: {}.{} (duplicate value {})",
+                self.name,
+                conflict.column,
+                value_to_string(&conflict.value)
+            ),
+        )
+    }
+
+    /// Finds the first existing row that shares a primary key or UNIQUE
+    /// value with `row`, skipping the row at `ignore`.
+    pub fn find_conflict(
+        &mut self,
+        row: &Row,
+        ignore: Option<usize>,
+    ) -> io::Result<Option<Conflict>> {
+        let implicit_key = self.implicit_key && !self.columns.iter().any(|c| c.primary_key);
+        let mut checked = Vec::new();
+        for (i, column) in self.columns.iter().enumerate() {
+            let key = column.primary_key || column.unique || (implicit_key && i == 0);
+            if !key {
+                continue;
+            }
+            match row.values.get(&column.name) {
+                Some(Value::Null) | None => {}
+                Some(value) => checked.push((column.name.clone(), value.clone(), column.collation)),
+            }
+        }
+        if checked.is_empty() {
+            return Ok(None);
+        }
+
+        // The key column is searched for in the tree, and an indexed column
+        // looked up in its index
+        let mut scanned = Vec::new();
+        for (column, value, collation) in checked {
+            let row_nums = if self.key_column().is_some_and(|c| c.name == column) {
+                self.find_row(&value)?.into_iter().collect()
+            } else if let Some(index) = self.find_index(&column) {
+                let keys = index.lookup(&value);
+                self.rows_for_keys(&keys)?
+            } else {
+                scanned.push((column, value, collation));
+                continue;
+            };
+            if let Some(&row_num) = row_nums.iter().find(|&&n| Some(n) != ignore) {
+                return Ok(Some(Conflict {
+                    row_num,
+                    column,
+                    value,
+                }));
+            }
+        }
+        let checked = scanned;
+        if checked.is_empty() {
+            return Ok(None);
+        }
+
+        // Other columns are checked with a full scan, reading in pages that
+        // are not cached
+        for row_num in 0..self.num_rows {
+            if Some(row_num) == ignore {
+                continue;
+            }
+            if let Some(existing) = self.row_slot(row_num)? {
+                for (column, value, collation) in &checked {
+                    let equal = existing.values.get(column).is_some_and(|existing| {
+                        compare_collated(existing, value, *collation) == Some(Ordering::Equal)
+                    });
+                    if equal {
+                        return Ok(Some(Conflict {
+                            row_num,
+                            column: column.clone(),
+                            value: value.clone(),
+                        }));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// A leaf cell: the key, then the row's values, each after its tag.
+    fn serialize_cell(&self, key: &Value, row: &Row) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        Self::tag_value(&mut buffer, Some(key));
+        for column in &self.columns {
+            Self::tag_value(&mut buffer, row.values.get(&column.name));
+        }
+        buffer
+    }
+
+    /// Appends a value after a tag saying its type: nothing more for NULL,
+    /// eight bytes for an integer, or a text's length and its bytes.
+    fn tag_value(buffer: &mut Vec<u8>, value: Option<&Value>) {
+        match value {
+            Some(Value::Integer(i)) => {
+                buffer.push(INTEGER_TAG);
+                buffer.extend_from_slice(&i.to_le_bytes());
+            }
+            Some(Value::Text(s)) => {
+                buffer.push(TEXT_TAG);
+                buffer.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(s.as_bytes());
+            }
+            Some(Value::Null) | None => buffer.push(NULL_TAG),
+        }
+    }
+
+    /// Reads back a tagged value, returning it with the bytes it took.
+    fn untag_value(buffer: &[u8]) -> io::Result<(Value, usize)> {
+        match buffer[0] {
+            NULL_TAG => Ok((Value::Null, 1)),
+            INTEGER_TAG => {
+                let bytes = buffer[1..9].try_into().unwrap();
+                Ok((Value::Integer(i64::from_le_bytes(bytes)), 9))
+            }
+            TEXT_TAG => {
+                let len = u32::from_le_bytes(buffer[1..5].try_into().unwrap()) as usize;
+                let text = String::from_utf8_lossy(&buffer[5..5 + len]).to_string();
+                Ok((Value::Text(text), 5 + len))
+            }
+            tag => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown value type tag {}", tag),
+            )),
+        }
+    }
+
+    /// The bytes a tagged value takes, read from its tag and length.
+    fn tagged_size(buffer: &[u8]) -> usize {
+        match buffer[0] {
+            INTEGER_TAG => 9,
+            TEXT_TAG => 5 + u32::from_le_bytes(buffer[1..5].try_into().unwrap()) as usize,
+            _ => 1,
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{checksum, pager::PAGE_SIZE};
+
+/// A frame starts with a checksum of the rest of it, then the page's
+/// number, then the table file's length in pages when the frame ends a
+/// commit and 0 otherwise. The page follows.
+const CHECKSUM_OFFSET: usize = 0;
+const PAGE_NUM_OFFSET: usize = 4;
+const COMMIT_OFFSET: usize = 8;
+const FRAME_HEADER_SIZE: usize = 12;
+const FRAME_SIZE: usize = FRAME_HEADER_SIZE + PAGE_SIZE;
+
+/// How many frames the log may grow to before a commit checkpoints it.
+pub const CHECKPOINT_FRAMES: usize = 1000;
+
+/// A write-ahead log. In WAL mode the pages written to a table are
+/// appended here instead of to its file, and a page is read from its
+/// latest frame here before the file. A checkpoint copies the pages into
+/// the file and empties the log.
+pub struct Wal {
+    file: File,
+    path: String,
+    /// Where the latest committed frame of each page starts.
+    frames: HashMap<usize, u64>,
+    /// The table file's length in pages as of the last commit, if any.
+    num_pages: Option<usize>,
+    /// Where the next frame goes, after the last commit.
+    length: u64,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it when it is missing, and finds
+    /// every commit in it. Frames after the last whole commit, the remains
+    /// of one a crash cut short, are ignored and later written over.
+    pub fn open(path: &str) -> io::Result<Wal> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let mut wal = Wal {
+            file,
+            path: path.to_string(),
+            frames: HashMap::new(),
+            num_pages: None,
+            length: 0,
+        };
+        wal.find_commits(&log);
+        Ok(wal)
+    }
+
+    /// Reads the log at `path` without opening it for writing, for a look
+    /// at what opening its table would replay. Returns the number of
+    /// committed frames, none for a missing log, and the latest committed
+    /// contents of `page_num`, if the log holds any.
+    pub fn peek(path: &str, page_num: usize) -> io::Result<(usize, Option<Vec<u8>>)> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, None)),
+            Err(e) => return Err(e),
+        };
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let mut wal = Wal {
+            file,
+            path: path.to_string(),
+            frames: HashMap::new(),
+            num_pages: None,
+            length: 0,
+        };
+        wal.find_commits(&log);
+        let page = wal.frames.get(&page_num).map(|&offset| {
+            let start = offset as usize + FRAME_HEADER_SIZE;
+            log[start..start + PAGE_SIZE].to_vec()
+        });
+        Ok((wal.frame_count(), page))
+    }
+
+    /// Finds every commit in the log's contents. Frames after the last
+    /// whole commit are left out.
+    fn find_commits(&mut self, log: &[u8]) {
+        let mut uncommitted = Vec::new();
+        for (frame_num, frame) in log.chunks_exact(FRAME_SIZE).enumerate() {
+            if checksum::crc32(&frame[PAGE_NUM_OFFSET..]) as usize
+                != read_u32(frame, CHECKSUM_OFFSET)
+            {
+                break;
+            }
+            let offset = (frame_num * FRAME_SIZE) as u64;
+            uncommitted.push((read_u32(frame, PAGE_NUM_OFFSET), offset));
+            let num_pages = read_u32(frame, COMMIT_OFFSET);
+            if num_pages > 0 {
+                self.frames.extend(uncommitted.drain(..));
+                self.num_pages = Some(num_pages);
+                self.length = offset + FRAME_SIZE as u64;
+            }
+        }
+    }
+
+    /// The table file's length in pages as of the last commit, if there
+    /// has been one since the log was last emptied.
+    pub fn num_pages(&self) -> Option<usize> {
+        self.num_pages
+    }
+
+    /// The number of frames in the log, up to the last commit.
+    pub fn frame_count(&self) -> usize {
+        self.length as usize / FRAME_SIZE
+    }
+
+    /// The log's file, for the pager to sync.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Appends pages as one commit after which the table file is
+    /// `num_pages` long. Only the last frame marks the commit, so the pages
+    /// count as written once all of them are.
+    pub fn append(&mut self, pages: &[(usize, Vec<u8>)], num_pages: usize) -> io::Result<()> {
+        let mut frames = Vec::with_capacity(pages.len() * FRAME_SIZE);
+        for (i, (page_num, page)) in pages.iter().enumerate() {
+            let commit = if i + 1 == pages.len() { num_pages } else { 0 };
+            let mut frame = vec![0; FRAME_HEADER_SIZE];
+            write_u32(&mut frame, PAGE_NUM_OFFSET, *page_num);
+            write_u32(&mut frame, COMMIT_OFFSET, commit);
+            frame.extend_from_slice(page);
+            let checksum = checksum::crc32(&frame[PAGE_NUM_OFFSET..]) as usize;
+            write_u32(&mut frame, CHECKSUM_OFFSET, checksum);
+            frames.extend_from_slice(&frame);
+        }
+        self.file.seek(SeekFrom::Start(self.length))?;
+        self.file.write_all(&frames)?;
+
+        for (i, (page_num, _)) in pages.iter().enumerate() {
+            let offset = self.length + (i * FRAME_SIZE) as u64;
+            self.frames.insert(*page_num, offset);
+        }
+        self.length += frames.len() as u64;
+        self.num_pages = Some(num_pages);
+        Ok(())
+    }
+
+    /// The latest committed contents of a page, if the log holds any.
+    pub fn read_page(&mut self, page_num: usize) -> io::Result<Option<Vec<u8>>> {
+        let offset = match self.frames.get(&page_num) {
+            Some(&offset) => offset,
+            None => return Ok(None),
+        };
+        let mut page = vec![0; PAGE_SIZE];
+        self.file
+            .seek(SeekFrom::Start(offset + FRAME_HEADER_SIZE as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(Some(page))
+    }
+
+    /// Copies every page in the log into the table file and sets the
+    /// file's length. Cut short, it is simply done again, as long as the
+    /// log is only cleared once the file is synced.
+    pub fn copy_to(&mut self, table_file: &mut File) -> io::Result<()> {
+        let num_pages = match self.num_pages {
+            Some(num_pages) => num_pages,
+            None => return Ok(()),
+        };
+        let mut frames: Vec<(usize, u64)> = self.frames.iter().map(|(&p, &o)| (p, o)).collect();
+        frames.sort_unstable();
+        for (page_num, _) in frames {
+            if page_num < num_pages {
+                let page = self.read_page(page_num)?.unwrap();
+                table_file.seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
+                table_file.write_all(&page)?;
+            }
+        }
+        table_file.set_len((num_pages * PAGE_SIZE) as u64)
+    }
+
+    /// Empties the log, once its pages are in the table file.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.frames.clear();
+        self.num_pages = None;
+        self.length = 0;
+        Ok(())
+    }
+
+    /// Deletes the log, which a checkpoint has emptied.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// A path of the test's own, with nothing there yet.
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("bugdb-wal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; PAGE_SIZE]
+    }
+
+    #[test]
+    fn committed_pages_are_found_on_reopen() {
+        let path = temp_path("reopen");
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.num_pages(), None);
+        wal.append(&[(1, page(1)), (2, page(2))], 3).unwrap();
+        wal.append(&[(1, page(9))], 4).unwrap();
+
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.num_pages(), Some(4));
+        assert_eq!(wal.frame_count(), 3);
+        assert_eq!(wal.read_page(1).unwrap(), Some(page(9)));
+        assert_eq!(wal.read_page(2).unwrap(), Some(page(2)));
+        assert_eq!(wal.read_page(3).unwrap(), None);
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn a_commit_cut_short_is_ignored_and_written_over() {
+        let path = temp_path("torn");
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&[(1, page(1))], 2).unwrap();
+        wal.append(&[(1, page(5)), (2, page(6))], 3).unwrap();
+        // The second commit's first frame is whole, its last is not
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((2 * FRAME_SIZE + 10) as u64).unwrap();
+
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.num_pages(), Some(2));
+        assert_eq!(wal.frame_count(), 1);
+        assert_eq!(wal.read_page(1).unwrap(), Some(page(1)));
+        assert_eq!(wal.read_page(2).unwrap(), None);
+
+        wal.append(&[(2, page(8))], 3).unwrap();
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.frame_count(), 2);
+        assert_eq!(wal.read_page(2).unwrap(), Some(page(8)));
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn a_corrupt_frame_ends_the_log() {
+        let path = temp_path("corrupt");
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&[(1, page(1))], 2).unwrap();
+        wal.append(&[(1, page(2))], 2).unwrap();
+        let mut log = fs::read(&path).unwrap();
+        log[FRAME_SIZE + FRAME_HEADER_SIZE] ^= 1;
+        fs::write(&path, log).unwrap();
+
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.frame_count(), 1);
+        assert_eq!(wal.read_page(1).unwrap(), Some(page(1)));
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn peek_reads_without_creating_or_changing_the_log() {
+        let path = temp_path("peek");
+        assert_eq!(Wal::peek(&path, 0).unwrap(), (0, None));
+        assert!(fs::metadata(&path).is_err());
+
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&[(0, page(3))], 1).unwrap();
+        wal.append(&[(0, page(4)), (1, page(5))], 2).unwrap();
+        let before = fs::read(&path).unwrap();
+        assert_eq!(Wal::peek(&path, 0).unwrap(), (3, Some(page(4))));
+        assert_eq!(Wal::peek(&path, 7).unwrap(), (3, None));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn copy_to_writes_the_latest_pages_and_length() {
+        let (path, table_path) = (temp_path("copy"), temp_path("copy-table"));
+        fs::write(&table_path, [page(0), page(0), page(0), page(0)].concat()).unwrap();
+        let mut table = OpenOptions::new().write(true).open(&table_path).unwrap();
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&[(1, page(1)), (2, page(2))], 3).unwrap();
+        wal.append(&[(2, page(7))], 3).unwrap();
+
+        wal.copy_to(&mut table).unwrap();
+        assert_eq!(
+            fs::read(&table_path).unwrap(),
+            [page(0), page(1), page(7)].concat()
+        );
+        wal.clear().unwrap();
+        assert_eq!((wal.num_pages(), wal.frame_count()), (None, 0));
+        wal.remove().unwrap();
+        fs::remove_file(&table_path).unwrap();
+    }
+}
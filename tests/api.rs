@@ -0,0 +1,364 @@
+mod common;
+
+use bugdb::{
+    sql_parser::{PreparedStatement, StatementType, Value},
+    Database, Durability, Options,
+};
+use common::TestDb;
+
+fn open(db: &TestDb) -> Database {
+    Database::open(&db.path).unwrap()
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+#[test]
+fn results_report_rows_and_counts() {
+    let dir = TestDb::new("api-results");
+    let mut db = open(&dir);
+    let created = db
+        .execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    assert_eq!(created.kind, StatementType::CreateTable);
+    assert_eq!(created.messages, ["Created table t"]);
+
+    let inserted = db
+        .execute("insert into t values (1, 'a'), (2, 'b'), (3, 'c')")
+        .unwrap();
+    assert_eq!(inserted.kind, StatementType::Insert);
+    assert_eq!(inserted.rows_affected, 3);
+    assert!(inserted.rows.is_empty());
+
+    let selected = db.execute("select name, id from t where id > 1").unwrap();
+    assert_eq!(selected.rows_returned, 2);
+    assert_eq!(selected.columns, ["name", "id"]);
+    assert_eq!(
+        selected.rows,
+        [
+            vec![text("b"), Value::Integer(2)],
+            vec![text("c"), Value::Integer(3)]
+        ]
+    );
+    assert_eq!(selected.scanned, Some((2, 3)));
+
+    // Zero rows are a count like any other
+    let none = db.execute("select * from t where id > 10").unwrap();
+    assert_eq!(none.rows_returned, 0);
+    assert_eq!(
+        none.summary().unwrap(),
+        "0 rows returned (0 of 3 rows scanned matched)"
+    );
+
+    assert_eq!(
+        db.execute("update t set name = 'z' where id < 3")
+            .unwrap()
+            .rows_affected,
+        2
+    );
+    assert_eq!(db.execute("delete from t").unwrap().rows_affected, 3);
+    db.close().unwrap();
+}
+
+#[test]
+fn returning_rows_and_pragma_values_come_back_in_the_result() {
+    let dir = TestDb::new("api-returning");
+    let mut db = open(&dir);
+    db.execute("create table t (id integer primary key autoincrement, name text)")
+        .unwrap();
+    let inserted = db
+        .execute("insert into t (name) values ('a'), ('b') returning id, name")
+        .unwrap();
+    assert_eq!(inserted.rows_affected, 2);
+    assert_eq!(inserted.columns, ["id", "name"]);
+    assert_eq!(
+        inserted.rows,
+        [
+            vec![Value::Integer(1), text("a")],
+            vec![Value::Integer(2), text("b")]
+        ]
+    );
+
+    assert_eq!(db.execute("pragma cache_size").unwrap().messages.len(), 1);
+    let plan = db.execute("explain query plan select * from t where id = 1");
+    assert_eq!(plan.unwrap().messages[0], "QUERY PLAN");
+}
+
+#[test]
+fn errors_come_back_as_errors() {
+    let dir = TestDb::new("api-errors");
+    let mut db = open(&dir);
+    assert!(db.execute("selec 1").is_err());
+    assert!(db.execute("select * from missing").is_err());
+    // The database is still usable afterwards
+    assert_eq!(
+        db.execute("select 1").unwrap().rows,
+        [vec![Value::Integer(1)]]
+    );
+}
+
+#[test]
+fn data_written_through_the_library_is_read_by_the_shell() {
+    let dir = TestDb::new("api-shell");
+    let mut db = open(&dir);
+    db.execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    db.execute("insert into t values (1, 'from the library')")
+        .unwrap();
+    db.close().unwrap();
+    drop(db);
+    assert_eq!(dir.rows("select * from t"), ["(1, 'from the library')"]);
+}
+
+#[test]
+fn prepared_statements_run_with_each_set_of_parameters() {
+    let dir = TestDb::new("api-prepared");
+    let mut db = open(&dir);
+    db.execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    let insert = PreparedStatement::new("insert into t values (?, ?)").unwrap();
+    for (id, name) in [(1, "a"), (2, "b"), (3, "c")] {
+        let result = db
+            .execute_prepared(&insert, &[Value::Integer(id), text(name)])
+            .unwrap();
+        assert_eq!(result.rows_affected, 1);
+    }
+
+    // A wrong number of parameters, or one of the wrong type, fails
+    assert!(db.execute_prepared(&insert, &[Value::Integer(4)]).is_err());
+    assert!(db
+        .execute_prepared(&insert, &[text("four"), text("d")])
+        .is_err());
+
+    let select =
+        PreparedStatement::new("select name from t where id >= ?1 and id < ?1 + 2").unwrap();
+    let result = db.execute_prepared(&select, &[Value::Integer(2)]).unwrap();
+    assert_eq!(result.rows, [vec![text("b")], vec![text("c")]]);
+    let result = db.execute_prepared(&select, &[Value::Integer(3)]).unwrap();
+    assert_eq!(result.rows, [vec![text("c")]]);
+}
+
+fn slugify(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Text(s) => Ok(Value::Text(s.to_lowercase().replace(' ', "-"))),
+        Value::Integer(_) => Err("expected text".to_string()),
+        Value::Null => Ok(Value::Null),
+    }
+}
+
+#[test]
+fn registered_functions_are_called_from_sql() {
+    let dir = TestDb::new("api-functions");
+    let mut db = open(&dir);
+    db.register_function("slugify", 1, slugify).unwrap();
+    db.execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    db.execute("insert into t values (1, 'Hello World'), (2, 'Other Name'), (3, NULL)")
+        .unwrap();
+
+    let result = db.execute("select slugify(name) from t").unwrap();
+    assert_eq!(
+        result.rows,
+        [
+            vec![text("hello-world")],
+            vec![text("other-name")],
+            vec![Value::Null]
+        ]
+    );
+    let result = db
+        .execute("select id from t where SLUGIFY(name) = 'other-name'")
+        .unwrap();
+    assert_eq!(result.rows, [vec![Value::Integer(2)]]);
+
+    // A closure that captures state is called once per row
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = calls.clone();
+    db.register_function("tick", 0, move |_| {
+        counter.set(counter.get() + 1);
+        Ok(Value::Integer(counter.get()))
+    })
+    .unwrap();
+    db.execute("select tick() from t").unwrap();
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn registering_a_built_in_name_is_refused() {
+    let dir = TestDb::new("api-functions-built-in");
+    let mut db = open(&dir);
+    for (name, arity) in [
+        ("upper", 1),
+        ("LOWER", 1),
+        ("count", 1),
+        ("min", 1),
+        ("abs", 1),
+    ] {
+        assert!(
+            db.register_function(name, arity, slugify).is_err(),
+            "{}/{}",
+            name,
+            arity
+        );
+    }
+    assert!(db.register_function("not a name", 1, slugify).is_err());
+    // The built-in still runs
+    let result = db.execute("select upper('a')").unwrap();
+    assert_eq!(result.rows, [vec![text("A")]]);
+}
+
+#[test]
+fn a_closure_error_fails_the_statement_with_the_function_name() {
+    let dir = TestDb::new("api-functions-error");
+    let mut db = open(&dir);
+    db.register_function("slugify", 1, slugify).unwrap();
+    let error = db.execute("select slugify(42)").err().unwrap();
+    assert!(error.to_string().contains("slugify"), "{}", error);
+    assert!(error.to_string().contains("expected text"), "{}", error);
+    let error = db.execute("select slugify('a', 'b')").err().unwrap();
+    assert!(error.to_string().contains("slugify"), "{}", error);
+}
+
+#[test]
+fn functions_belong_to_the_database_they_were_registered_on() {
+    let first = TestDb::new("api-functions-first");
+    let second = TestDb::new("api-functions-second");
+    let mut with = open(&first);
+    let mut without = open(&second);
+    with.register_function("slugify", 1, slugify).unwrap();
+    assert!(with.execute("select slugify('A B')").is_ok());
+    assert!(without.execute("select slugify('A B')").is_err());
+    // Nor does the shell, on the same file, see them
+    let output = first.run("select slugify('A B')");
+    assert_eq!(common::errors(&output).len(), 1, "{}", output);
+}
+
+#[test]
+fn durability_and_journal_mode_are_chosen_when_opening() {
+    let dir = TestDb::new("api-options");
+    let options = Options {
+        durability: Durability::Off,
+        wal: true,
+    };
+    let mut db = Database::open_with(&dir.path, options).unwrap();
+    let setting = |db: &mut Database, name: &str| db.execute(name).unwrap().messages;
+    assert_eq!(setting(&mut db, "pragma durability"), ["off"]);
+    assert_eq!(setting(&mut db, "pragma journal_mode"), ["wal"]);
+    db.execute("create table t (id integer primary key)")
+        .unwrap();
+    db.execute("insert into t values (1)").unwrap();
+    db.close().unwrap();
+    drop(db);
+
+    // The journal mode is kept in the file; the durability is not
+    let mut db = Database::open(&dir.path).unwrap();
+    assert_eq!(setting(&mut db, "pragma durability"), ["full"]);
+    assert_eq!(setting(&mut db, "pragma journal_mode"), ["wal"]);
+    assert_eq!(
+        db.execute("select * from t").unwrap().rows,
+        [vec![Value::Integer(1)]]
+    );
+    db.close().unwrap();
+    drop(db);
+
+    let options = Options {
+        durability: Durability::Normal,
+        ..Options::default()
+    };
+    let mut db = Database::open_with(&dir.path, options).unwrap();
+    assert_eq!(setting(&mut db, "pragma durability"), ["normal"]);
+}
+
+#[test]
+fn a_file_without_checksums_is_refused() {
+    let dir = TestDb::new("api-no-checksums");
+    let mut db = open(&dir);
+    db.execute("create table t (id integer primary key)")
+        .unwrap();
+    db.close().unwrap();
+    drop(db);
+
+    // Clear the header's flags, where the checksums flag is kept
+    let mut file = std::fs::read(&dir.path).unwrap();
+    file[32..36].copy_from_slice(&0u32.to_le_bytes());
+    std::fs::write(&dir.path, file).unwrap();
+
+    let error = Database::open(&dir.path).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(
+        error.to_string().contains("unsupported format"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn uncommitted_writes_are_seen_only_by_their_own_database() {
+    let dir = TestDb::new("api-isolation");
+    let mut writer = open(&dir);
+    writer
+        .execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    writer
+        .execute("insert into t values (1, 'committed')")
+        .unwrap();
+    let count = |db: &mut Database| db.execute("select count(*) from t").unwrap().rows;
+
+    writer.execute("begin").unwrap();
+    writer
+        .execute("insert into t values (2, 'pending')")
+        .unwrap();
+    writer
+        .execute("update t set name = 'changed' where id = 1")
+        .unwrap();
+    // The writer reads its own writes; a reader opened now reads the file,
+    // which has none of them
+    assert_eq!(count(&mut writer), [vec![Value::Integer(2)]]);
+    let mut reader = open(&dir);
+    assert_eq!(count(&mut reader), [vec![Value::Integer(1)]]);
+    assert_eq!(
+        reader.execute("select name from t").unwrap().rows,
+        [vec![text("committed")]]
+    );
+    drop(reader);
+
+    writer.execute("commit").unwrap();
+    let mut reader = open(&dir);
+    assert_eq!(count(&mut reader), [vec![Value::Integer(2)]]);
+    drop(reader);
+
+    writer.execute("begin").unwrap();
+    writer.execute("delete from t").unwrap();
+    writer.execute("rollback").unwrap();
+    let mut reader = open(&dir);
+    assert_eq!(count(&mut reader), [vec![Value::Integer(2)]]);
+}
+
+#[test]
+fn statements_past_the_threshold_are_logged_with_their_counts() {
+    let dir = TestDb::new("api-slow-log");
+    let log = format!("{}-slow.log", dir.path);
+    let mut db = open(&dir);
+    db.execute("create table t (id integer primary key, name text)")
+        .unwrap();
+    db.execute("insert into t values (1, 'a'), (2, 'b'), (3, 'c')")
+        .unwrap();
+    db.execute(&format!("pragma slow_query_log = '{}'", log))
+        .unwrap();
+    db.execute("pragma slow_query_ms = 0").unwrap();
+    db.execute("select * from t where name = 'b'").unwrap();
+    db.execute("pragma slow_query_ms = off").unwrap();
+    db.execute("select * from t").unwrap();
+
+    let contents = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    // Logged from the pragma turning it on to the one turning it off
+    assert_eq!(lines.len(), 2, "{}", contents);
+    assert!(lines[0].ends_with(r#"sql="pragma slow_query_ms = ?""#));
+    assert!(
+        lines[1].contains("rows_examined=3 rows_returned=1"),
+        "{}",
+        lines[1]
+    );
+    assert!(lines[1].ends_with(r#"sql="select * from t where name = ?""#));
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use common::TestDb;
+
+const ROWS: usize = 2000;
+
+/// Set at the start of every session: a cache of a few pages, so that the
+/// tree is read back from the file as it grows, and no syncing, which the
+/// test has no use for.
+const SMALL_CACHE: &str = "pragma cache_size = 3\npragma durability = off\n";
+
+/// Every key below `ROWS` once, in an order that jumps about the tree.
+fn scrambled() -> impl Iterator<Item = usize> {
+    (0..ROWS).map(|i| i * 7919 % ROWS)
+}
+
+/// Rows big enough that a leaf holds only a few, for a tree of many pages.
+fn name(key: usize) -> String {
+    format!("{:0>200}", key)
+}
+
+fn ids(keys: impl Iterator<Item = usize>) -> Vec<String> {
+    keys.map(|key| format!("({})", key)).collect()
+}
+
+fn setup(db: &TestDb) {
+    let mut script = format!(
+        "{}create table t (id integer primary key, name text)\n",
+        SMALL_CACHE
+    );
+    for key in scrambled() {
+        script.push_str(&format!(
+            "insert into t values ({}, '{}')\n",
+            key,
+            name(key)
+        ));
+    }
+    db.run(&script);
+}
+
+#[test]
+fn rows_inserted_out_of_order_come_back_in_key_order() {
+    let db = TestDb::new("btree-insert");
+    setup(&db);
+    let query = format!("{}select id from t", SMALL_CACHE);
+    assert_eq!(db.rows(&query), ids(0..ROWS));
+    for key in [0, 1, ROWS / 2, ROWS - 1] {
+        assert_eq!(
+            db.rows(&format!(
+                "{}select name from t where id = {}",
+                SMALL_CACHE, key
+            )),
+            [format!("('{}')", name(key))]
+        );
+    }
+    assert_eq!(
+        db.rows(&format!(
+            "{}select id from t where id >= 995 and id < 1005",
+            SMALL_CACHE
+        )),
+        ids(995..1005)
+    );
+}
+
+#[test]
+fn deletes_and_updates_keep_the_tree_in_order() {
+    let db = TestDb::new("btree-delete");
+    setup(&db);
+    db.run(&format!(
+        "{}delete from t where id % 3 = 0\nupdate t set id = id + {} where id % 3 = 1",
+        SMALL_CACHE, ROWS
+    ));
+    let expected: Vec<usize> = (0..ROWS)
+        .filter(|key| key % 3 == 2)
+        .chain((0..ROWS).filter(|key| key % 3 == 1).map(|key| key + ROWS))
+        .collect();
+    assert_eq!(
+        db.rows(&format!("{}select id from t", SMALL_CACHE)),
+        ids(expected.into_iter())
+    );
+    assert_eq!(
+        db.rows(&format!(
+            "{}select name from t where id = {}",
+            SMALL_CACHE,
+            ROWS + 4
+        )),
+        [format!("('{}')", name(4))]
+    );
+
+    // Pages the deleted rows freed are reused before the file grows
+    let size = db.file_size();
+    let mut script = SMALL_CACHE.to_string();
+    for key in (0..ROWS).filter(|key| key % 3 == 0) {
+        script.push_str(&format!(
+            "insert into t values ({}, '{}')\n",
+            key,
+            name(key)
+        ));
+    }
+    db.run(&script);
+    assert!(
+        db.file_size() <= size + 4096 * 8,
+        "{} > {}",
+        db.file_size(),
+        size
+    );
+}
+
+#[test]
+fn a_table_grows_past_a_hundred_pages() {
+    let db = TestDb::new("btree-large");
+    setup(&db);
+    assert!(db.file_size() > 100 * 4096, "{}", db.file_size());
+    assert_eq!(
+        db.rows(&format!("{}select count(*) from t", SMALL_CACHE)),
+        [format!("({})", ROWS)]
+    );
+    assert_eq!(
+        db.rows(&format!(
+            "{}select name from t where id = {}",
+            SMALL_CACHE,
+            ROWS - 1
+        )),
+        [format!("('{}')", name(ROWS - 1))]
+    );
+}
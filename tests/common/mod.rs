@@ -0,0 +1,147 @@
+//! Runs the BugDB shell on a database in a directory of its own, feeding it
+//! a script on stdin the way a user at the prompt would.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+const PROMPT: &str = "db > ";
+
+pub struct TestDb {
+    dir: PathBuf,
+    pub path: String,
+}
+
+impl TestDb {
+    /// A database that does not exist yet, in a fresh directory that is
+    /// removed when the test is done with it.
+    pub fn new(name: &str) -> TestDb {
+        let dir = env::temp_dir().join(format!("bugdb-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.db").to_str().unwrap().to_string();
+        TestDb { dir, path }
+    }
+
+    /// Runs a script, one statement per line, and then exits cleanly,
+    /// returning everything printed.
+    pub fn run(&self, script: &str) -> String {
+        self.run_with_args(script, &[])
+    }
+
+    pub fn run_with_args(&self, script: &str, args: &[&str]) -> String {
+        self.finish(self.spawn(args, &[]), script)
+    }
+
+    /// Like `run`, with environment variables set for the shell.
+    pub fn run_with_env(&self, script: &str, vars: &[(&str, &str)]) -> String {
+        self.finish(self.spawn(&[], vars), script)
+    }
+
+    /// Feeds a script to a shell and waits for it to exit.
+    fn finish(&self, mut child: Child, script: &str) -> String {
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(script.as_bytes()).unwrap();
+        stdin.write_all(b"\n.exit\n").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Runs a script and then kills the process the way a crash would,
+    /// once every statement in it has answered, so nothing is written at
+    /// exit.
+    pub fn run_and_kill(&self, script: &str) -> String {
+        let mut child = self.spawn(&[], &[]);
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(script.as_bytes()).unwrap();
+        stdin.write_all(b"\n").unwrap();
+        stdin.flush().unwrap();
+
+        // One prompt is printed before each line is read, so the line after
+        // the last has been asked for once they have all run
+        let statements = script.lines().count();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut output = String::new();
+        let mut buffer = [0; 4096];
+        while output.matches(PROMPT).count() <= statements {
+            let read = stdout.read(&mut buffer).unwrap();
+            assert!(read > 0, "the shell exited early:\n{}", output);
+            output.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+        output
+    }
+
+    /// The rows a query returns, as the shell prints them.
+    pub fn rows(&self, query: &str) -> Vec<String> {
+        rows(&self.run(query))
+    }
+
+    /// Whether a file beside the database, named by adding `suffix` to
+    /// its name, exists.
+    pub fn has_file(&self, suffix: &str) -> bool {
+        fs::metadata(format!("{}{}", self.path, suffix)).is_ok()
+    }
+
+    pub fn file_size(&self) -> u64 {
+        fs::metadata(&self.path).unwrap().len()
+    }
+
+    /// The directory the database is in, which nothing else uses.
+    pub fn dir(&self) -> &str {
+        self.dir.to_str().unwrap()
+    }
+
+    fn spawn(&self, args: &[&str], vars: &[(&str, &str)]) -> Child {
+        Command::new(env!("CARGO_BIN_EXE_BugDB"))
+            .arg(&self.path)
+            .args(args)
+            .envs(vars.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The rows in a shell's output, each as a tuple such as `(1, 'a')`.
+pub fn rows(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| line.trim_start_matches(PROMPT))
+        .filter(|line| line.starts_with('('))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The lines of a shell's output that report an error.
+pub fn errors(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| line.trim_start_matches(PROMPT))
+        .filter(|line| line.starts_with("Error"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// A counter printed by `.stats` for the statement before it.
+pub fn stat(output: &str, name: &str) -> usize {
+    output
+        .lines()
+        .find_map(|line| line.trim_start_matches(PROMPT).strip_prefix(name))
+        .and_then(|rest| rest.trim_start_matches(':').trim().parse().ok())
+        .unwrap_or_else(|| panic!("no {} in {}", name, output))
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::{rows, TestDb};
+
+#[test]
+fn union_tells_rows_apart_with_the_first_selects_collation() {
+    let db = TestDb::new("union-nocase");
+    let output = db.run(
+        "create table a (name text collate nocase)\n\
+         create table b (name text)\n\
+         insert into a values ('Alice'), ('bob'), (NULL)\n\
+         insert into b values ('ALICE'), ('Bob'), ('carol'), (NULL)\n\
+         select name from a union select name from b\n\
+         select name from b union select name from a\n\
+         select name from a union all select name from b",
+    );
+    let rows = rows(&output);
+    // Case aside, and with NULLs equal, each name once, as DISTINCT would
+    assert_eq!(rows[..4], ["('Alice')", "('bob')", "(NULL)", "('carol')"]);
+    // A binary first column keeps every spelling
+    assert_eq!(
+        rows[4..10],
+        [
+            "('ALICE')",
+            "('Bob')",
+            "('carol')",
+            "(NULL)",
+            "('Alice')",
+            "('bob')"
+        ]
+    );
+    assert_eq!(rows[10..].len(), 7);
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{errors, TestDb};
+
+/// Enough rows that the planner prefers an index to a scan.
+fn setup() -> String {
+    let mut script =
+        "create table users (id integer primary key, email text, n integer)\n".to_string();
+    for i in 1..=40 {
+        script.push_str(&format!(
+            "insert into users values ({}, 'User{}@Example.com', {})\n",
+            i,
+            i,
+            i % 5
+        ));
+    }
+    script.push_str("create index le on users (lower(email))");
+    script
+}
+
+fn plan(db: &TestDb, query: &str) -> String {
+    db.run(&format!("explain query plan {}", query))
+}
+
+#[test]
+fn updates_move_rows_between_expression_values() {
+    let db = TestDb::new("expression-index-update");
+    db.run(&setup());
+    let find = |email: &str| {
+        db.rows(&format!(
+            "select id from users where lower(email) = '{email}'"
+        ))
+    };
+    assert_eq!(find("user7@example.com"), ["(7)"]);
+
+    // Changing the case leaves the indexed value as it was
+    db.run("update users set email = 'USER7@EXAMPLE.COM' where id = 7");
+    assert_eq!(find("user7@example.com"), ["(7)"]);
+
+    db.run("update users set email = 'Someone@Else.org' where id = 7");
+    assert!(find("user7@example.com").is_empty());
+    assert_eq!(find("someone@else.org"), ["(7)"]);
+
+    // Moving the row to a new key takes its entry along
+    db.run("update users set id = 100 where id = 7");
+    assert_eq!(find("someone@else.org"), ["(100)"]);
+
+    db.run("begin\nupdate users set email = 'x@y.com' where id = 100\nrollback");
+    assert_eq!(find("someone@else.org"), ["(100)"]);
+    assert!(find("x@y.com").is_empty());
+
+    db.run("delete from users where id = 100");
+    assert!(find("someone@else.org").is_empty());
+    assert!(errors(&db.run("drop index le")).is_empty());
+}
+
+#[test]
+fn only_the_indexed_expression_uses_the_index() {
+    let db = TestDb::new("expression-index-plan");
+    db.run(&setup());
+    for query in [
+        "select id from users where lower(email) = 'user3@example.com'",
+        "select id from users where LOWER(users.email) = 'user3@example.com'",
+        "select id from users where 'user3@example.com' = lower(email)",
+    ] {
+        assert!(
+            plan(&db, query).contains("SEARCH users USING INDEX le (lower(email)=?)"),
+            "{}",
+            query
+        );
+    }
+    for near_miss in [
+        "select id from users where upper(email) = 'USER3@EXAMPLE.COM'",
+        "select id from users where email = 'user3@example.com'",
+        "select id from users where lower(email || '') = 'user3@example.com'",
+    ] {
+        assert!(plan(&db, near_miss).contains("SCAN users"), "{}", near_miss);
+    }
+    assert!(db
+        .rows("select id from users where upper(email) = 'USER3@EXAMPLE.COM'")
+        .contains(&"(3)".to_string()));
+}
+
+#[test]
+fn expression_index_survives_a_reopen() {
+    let db = TestDb::new("expression-index-reopen");
+    db.run(&setup());
+    db.run("create index odd on users (n * 2 + 1)");
+    assert_eq!(
+        db.rows("select id from users where n * 2 + 1 = 9"),
+        ["(4)", "(9)", "(14)", "(19)", "(24)", "(29)", "(34)", "(39)"]
+    );
+    assert!(plan(&db, "select id from users where n * 2 + 1 = 9").contains("INDEX odd"));
+}
+
+#[test]
+fn non_deterministic_expressions_are_refused() {
+    let db = TestDb::new("expression-index-refused");
+    db.run(&setup());
+    for sql in [
+        "create index r on users (random() + n)",
+        "create index d on users (date('now'))",
+        "create index c on users (count(n))",
+        "create index x on users (lower(emial))",
+    ] {
+        assert_eq!(errors(&db.run(sql)).len(), 1, "{}", sql);
+    }
+    assert!(plan(&db, "select id from users where random() + n = 1").contains("SCAN users"));
+}
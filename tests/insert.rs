@@ -0,0 +1,112 @@
+mod common;
+
+use common::{errors, rows, stat, TestDb};
+
+#[test]
+fn upserts_leave_one_row_with_the_new_values() {
+    let db = TestDb::new("insert-upsert");
+    let output = db.run(
+        "create table users (id integer primary key, username text)\n\
+         insert into users values (1, 'old')\n\
+         insert or replace into users (id, username) values (1, 'replaced')\n\
+         insert into users values (1, 'ignored') on conflict (id) do nothing\n\
+         insert or ignore into users values (1, 'ignored too')\n\
+         select * from users\n\
+         insert into users values (1, 'x') on conflict (id) do update set username = 'updated'\n\
+         insert into users values (2, 'new') on conflict (id) do update set username = 'never'\n\
+         select * from users",
+    );
+    assert!(errors(&output).is_empty(), "{}", output);
+    assert_eq!(
+        rows(&output),
+        ["(1, 'replaced')", "(1, 'updated')", "(2, 'new')"]
+    );
+    assert_eq!(
+        db.rows("select * from users"),
+        ["(1, 'updated')", "(2, 'new')"]
+    );
+}
+
+#[test]
+fn invalid_inserts_are_rejected_before_anything_is_written() {
+    let db = TestDb::new("insert-validation");
+    db.run(
+        "create table users (id integer primary key, username text, age integer)\n\
+         insert into users values (1, 'a', 30)",
+    );
+    let output = db.run(
+        "insert into users (id, usernme) values (2, 'b')\n\
+         insert into users (id, id) values (3, 4)\n\
+         insert into users (id, age) values (4, 'old')\n\
+         insert into users values (5, 'e', 1), (6, 'f', 'bad')\n\
+         .stats",
+    );
+    assert_eq!(
+        errors(&output),
+        [
+            "Error executing statement: table users has no column named usernme",
+            "Error executing statement: column id is listed more than once",
+            "Error executing statement: cannot store text in integer column age",
+            "Error executing statement: cannot store text in integer column age; no rows were inserted",
+        ]
+    );
+    assert_eq!(stat(&output, "pages written"), 0);
+    assert_eq!(db.rows("select * from users"), ["(1, 'a', 30)"]);
+    assert_eq!(
+        db.rows("insert into users values (2, 'b')\nselect * from users where id = 2"),
+        ["(2, 'b', NULL)"]
+    );
+}
+
+#[test]
+fn do_update_sees_the_existing_and_the_excluded_row() {
+    let db = TestDb::new("insert-do-update");
+    db.run(
+        "create table t (id integer primary key, n integer, name text)\n\
+         insert into t values (1, 5, 'short'), (2, 1, 'other')\n\
+         insert into t values (1, 3, 'a much longer name') \
+         on conflict (id) do update set n = n + excluded.n, name = excluded.name",
+    );
+    // reopened, so the longer text was written intact
+    assert_eq!(
+        db.rows("select * from t"),
+        ["(1, 8, 'a much longer name')", "(2, 1, 'other')"]
+    );
+    assert_eq!(
+        db.rows(
+            "insert into t values (1, 0, 'x') \
+             on conflict (id) do update set n = t.n * 10, name = 'y' returning *"
+        ),
+        ["(1, 80, 'y')"]
+    );
+    let output =
+        db.run("insert into t values (2, 0, 'x') on conflict do update set name = excluded.nope");
+    assert_eq!(
+        errors(&output),
+        ["Error executing statement: no such column: excluded.nope"]
+    );
+    assert_eq!(
+        db.rows("select * from t"),
+        ["(1, 80, 'y')", "(2, 1, 'other')"]
+    );
+}
+
+#[test]
+fn omitted_columns_take_their_default_or_null_unless_not_null() {
+    let db = TestDb::new("insert-defaults");
+    let output = db.run(
+        "create table t (id integer primary key, status text default 'new', \
+         n integer default 7, note text, owner text not null)\n\
+         insert into t (id, owner) values (1, 'me')\n\
+         insert into t (id) values (2)\n\
+         insert into t (id, owner, status) values (3, 'you', NULL)",
+    );
+    assert_eq!(
+        errors(&output),
+        ["Error executing statement: NOT NULL constraint failed: t.owner"]
+    );
+    assert_eq!(
+        db.rows("select * from t"),
+        ["(1, 'new', 7, NULL, 'me')", "(3, NULL, 7, NULL, 'you')"]
+    );
+}
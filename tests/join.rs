@@ -0,0 +1,89 @@
+mod common;
+
+use common::{rows, stat, TestDb};
+
+/// Tables `a` and `b` with keys `k` from 1 up, `b` padded over many pages.
+fn setup(db: &TestDb, a: usize, b: usize) {
+    let mut script = String::from(
+        "create table a (id integer primary key, k integer, x text)\n\
+         create table b (id integer primary key, k integer, y text)\n",
+    );
+    for id in 1..=a {
+        script.push_str(&format!(
+            "insert into a values ({}, {}, 'a{}')\n",
+            id, id, id
+        ));
+    }
+    for id in 1..=b {
+        script.push_str(&format!(
+            "insert into b values ({}, {}, '{:0>100}')\n",
+            id, id, id
+        ));
+    }
+    db.run(&script);
+}
+
+#[test]
+fn nested_loop_join_reads_the_inner_table_from_disk_once() {
+    let db = TestDb::new("join-nested");
+    setup(&db, 20, 200);
+    let scan_a = stat(
+        &db.run("select * from a where x <> ''\n.stats"),
+        "pages read",
+    );
+    let scan_b = stat(
+        &db.run("select * from b where y <> ''\n.stats"),
+        "pages read",
+    );
+
+    let output =
+        db.run("select a.x, b.id from a join b on a.k + 100 = b.k where b.id < 110\n.stats");
+    let expected: Vec<String> = (1..10)
+        .map(|id| format!("('a{}', {})", id, id + 100))
+        .collect();
+    assert_eq!(rows(&output), expected);
+    assert_eq!(stat(&output, "table scans"), 21);
+    assert_eq!(stat(&output, "rows examined"), 20 * 200);
+    assert_eq!(stat(&output, "pages read"), scan_a + scan_b);
+}
+
+#[test]
+fn hash_join_matches_the_nested_loop_and_scans_each_table_once() {
+    let db = TestDb::new("join-hash");
+    db.run(
+        "create table a (id integer primary key, k integer, x text)\n\
+         create table b (id integer primary key, k integer, y text)\n\
+         create table c (id integer primary key, k text)\n\
+         insert into a values (1, 1, 'a1'), (2, 2, 'a2'), (3, NULL, 'a3'), (4, 2, 'a4')\n\
+         insert into b values (1, 2, 'b1'), (2, 2, 'b2'), (3, NULL, 'b3'), (4, 1, 'b4'), (5, 9, 'b5')\n\
+         insert into c values (1, '2'), (2, 'x')",
+    );
+    let query = "select a.x, b.y from a join b on a.k = b.k order by a.x, b.y";
+    let output = db.run(&format!("{}\n.stats", query));
+    // duplicate keys give a row per pair, and NULL matches nothing
+    assert_eq!(
+        rows(&output),
+        [
+            "('a1', 'b4')",
+            "('a2', 'b1')",
+            "('a2', 'b2')",
+            "('a4', 'b1')",
+            "('a4', 'b2')"
+        ]
+    );
+    assert_eq!(stat(&output, "table scans"), 2);
+
+    // An OR is no equality join, so this runs as a nested loop
+    let nested = db.run(&format!(
+        "{}\n.stats",
+        query.replace("a.k = b.k", "a.k = b.k or 0 = 1")
+    ));
+    assert_eq!(rows(&nested), rows(&output));
+    assert_eq!(stat(&nested, "table scans"), 5);
+
+    // an integer key never equals text, whichever way the join runs
+    assert!(db.rows("select * from a join c on a.k = c.k").is_empty());
+    assert!(db
+        .rows("select * from a join c on a.k = c.k or 0 = 1")
+        .is_empty());
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::TestDb;
+
+/// Two operands, each true, false or unknown, with their AND and OR.
+type Case = (Option<bool>, Option<bool>, Option<bool>, Option<bool>);
+
+/// SQL's three-valued AND and OR for every pair of operands, written out
+/// case by case.
+const TRUTH_TABLE: [Case; 9] = [
+    // a, b, a AND b, a OR b
+    (Some(true), Some(true), Some(true), Some(true)),
+    (Some(true), Some(false), Some(false), Some(true)),
+    (Some(true), None, None, Some(true)),
+    (Some(false), Some(true), Some(false), Some(true)),
+    (Some(false), Some(false), Some(false), Some(false)),
+    (Some(false), None, Some(false), None),
+    (None, Some(true), None, Some(true)),
+    (None, Some(false), Some(false), None),
+    (None, None, None, None),
+];
+
+const NOT: [(Option<bool>, Option<bool>); 3] = [
+    (Some(true), Some(false)),
+    (Some(false), Some(true)),
+    (None, None),
+];
+
+fn sql(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "1",
+        Some(false) => "0",
+        None => "NULL",
+    }
+}
+
+/// A table with a row for each pair of operands, keyed by its position in
+/// `TRUTH_TABLE`.
+fn setup(name: &str) -> TestDb {
+    let db = TestDb::new(name);
+    let mut script = "create table v (id integer primary key, a integer, b integer)\n".to_string();
+    for (id, (a, b, _, _)) in TRUTH_TABLE.iter().enumerate() {
+        script.push_str(&format!(
+            "insert into v values ({}, {}, {})\n",
+            id,
+            sql(*a),
+            sql(*b)
+        ));
+    }
+    db.run(&script);
+    db
+}
+
+/// The ids of the rows a WHERE clause returns.
+fn matching(db: &TestDb, condition: &str) -> Vec<usize> {
+    db.rows(&format!("select id from v where {}", condition))
+        .iter()
+        .map(|row| row.trim_matches(|c| c == '(' || c == ')').parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn and_or_not_follow_the_truth_table() {
+    let db = setup("null-logic-values");
+    let rows = db.rows("select a and b, a or b from v");
+    for (row, (a, b, and, or)) in rows.iter().zip(TRUTH_TABLE) {
+        assert_eq!(
+            *row,
+            format!("({}, {})", sql(and), sql(or)),
+            "a = {}, b = {}",
+            sql(a),
+            sql(b)
+        );
+    }
+    for (a, not) in NOT {
+        assert_eq!(
+            db.rows(&format!("select not {}", sql(a))),
+            [format!("({})", sql(not))],
+            "not {}",
+            sql(a)
+        );
+    }
+}
+
+#[test]
+fn where_keeps_only_rows_that_are_definitely_true() {
+    let db = setup("null-logic-where");
+    let ids = |wanted: fn(&Case) -> bool| {
+        TRUTH_TABLE
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| wanted(row))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(matching(&db, "a and b"), ids(|row| row.2 == Some(true)));
+    assert_eq!(matching(&db, "a or b"), ids(|row| row.3 == Some(true)));
+    // Negating an unknown result leaves it unknown, so NOT drops it too
+    assert_eq!(
+        matching(&db, "not (a and b)"),
+        ids(|row| row.2 == Some(false))
+    );
+    assert_eq!(
+        matching(&db, "not (a or b)"),
+        ids(|row| row.3 == Some(false))
+    );
+    assert_eq!(matching(&db, "not a"), ids(|row| row.0 == Some(false)));
+
+    // A comparison with NULL is unknown whichever way it is asked
+    assert_eq!(matching(&db, "a = 1"), ids(|row| row.0 == Some(true)));
+    assert_eq!(
+        matching(&db, "not (a = 1)"),
+        ids(|row| row.0 == Some(false))
+    );
+    assert_eq!(matching(&db, "a <> 1"), ids(|row| row.0 == Some(false)));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::TestDb;
+
+const SETUP: &str = "create table t (id integer primary key, name text, note text)\n\
+                     insert into t values (1, 'a, b', 'say \"hi\"'), (2, 'plain', NULL)";
+
+/// The lines the shell printed for a query, without prompts or summary.
+fn lines(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .map(|line| line.trim_start_matches("db > "))
+        .filter(|line| !line.is_empty() && !line.contains("returned"))
+        .collect()
+}
+
+#[test]
+fn csv_mode_prints_a_header_and_quotes_where_needed() {
+    let db = TestDb::new("output-csv");
+    db.run(SETUP);
+    assert_eq!(
+        lines(&db.run(".mode csv\nselect * from t")),
+        ["id,name,note", "1,\"a, b\",\"say \"\"hi\"\"\"", "2,plain,"]
+    );
+    assert_eq!(
+        lines(&db.run(".mode csv\nselect note, id from t where id = 1")),
+        ["note,id", "\"say \"\"hi\"\"\",1"]
+    );
+}
+
+#[test]
+fn json_mode_prints_one_escaped_object_per_row() {
+    let db = TestDb::new("output-json");
+    db.run(&format!(
+        "{}\ninsert into t values (9007199254740993, 'back\\slash', 'tab\t')",
+        SETUP
+    ));
+    assert_eq!(
+        lines(&db.run(".mode json\nselect * from t")),
+        [
+            r#"{"id":1,"name":"a, b","note":"say \"hi\""}"#,
+            r#"{"id":2,"name":"plain","note":null}"#,
+            // integers past 2^53 are still printed as numbers
+            r#"{"id":9007199254740993,"name":"back\\slash","note":"tab\t"}"#,
+        ]
+    );
+    assert_eq!(
+        lines(&db.run(".mode json\nselect note from t where id = 2")),
+        [r#"{"note":null}"#]
+    );
+}
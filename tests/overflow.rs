@@ -0,0 +1,135 @@
+mod common;
+
+use common::TestDb;
+
+/// A string of `len` bytes that does not repeat within a page, so a part
+/// put back in the wrong place shows.
+fn text(len: usize, seed: u64) -> String {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (b'a' + (state >> 59) as u8 % 26) as char
+        })
+        .collect()
+}
+
+#[test]
+fn large_text_round_trips_through_a_reopen() {
+    let db = TestDb::new("overflow-round-trip");
+    let big = text(20 * 1024, 1);
+    db.run(&format!(
+        "create table t (id integer primary key, body text, n integer)\n\
+         insert into t values (1, '{}', 7)\n\
+         insert into t values (2, 'small', 8)",
+        big
+    ));
+    assert_eq!(
+        db.rows("select * from t"),
+        [format!("(1, '{}', 7)", big), "(2, 'small', 8)".to_string()]
+    );
+}
+
+#[test]
+fn freed_overflow_pages_are_reused() {
+    let db = TestDb::new("overflow-reuse");
+    let first = text(20 * 1024, 2);
+    db.run(&format!(
+        "create table t (id integer primary key, body text)\n\
+         insert into t values (1, '{}')",
+        first
+    ));
+    let size = db.file_size();
+
+    db.run("delete from t where id = 1");
+    assert!(db.rows("select * from t").is_empty());
+
+    // A row of the same size fits in the pages the first one let go
+    let second = text(20 * 1024, 3);
+    db.run(&format!("insert into t values (2, '{}')", second));
+    assert_eq!(db.file_size(), size);
+    assert_eq!(db.rows("select * from t"), [format!("(2, '{}')", second)]);
+}
+
+#[test]
+fn updates_grow_and_shrink_overflowing_rows() {
+    let db = TestDb::new("overflow-update");
+    db.run(
+        "create table t (id integer primary key, body text)\n\
+         insert into t values (1, 'short')",
+    );
+    for (i, len) in [9000, 30000, 5000, 3, 12000].into_iter().enumerate() {
+        let body = text(len, i as u64);
+        db.run(&format!("update t set body = '{}' where id = 1", body));
+        assert_eq!(db.rows("select * from t"), [format!("(1, '{}')", body)]);
+    }
+}
+
+#[test]
+fn rollback_restores_an_overflowing_row() {
+    let db = TestDb::new("overflow-rollback");
+    let big = text(10000, 4);
+    db.run(&format!(
+        "create table t (id integer primary key, body text)\n\
+         insert into t values (1, '{}')",
+        big
+    ));
+    db.run(&format!(
+        "begin\nupdate t set body = 'x' where id = 1\ninsert into t values (2, '{}')\nrollback",
+        text(15000, 5)
+    ));
+    assert_eq!(db.rows("select * from t"), [format!("(1, '{}')", big)]);
+}
+
+#[test]
+fn long_text_keys_round_trip_through_a_reopen() {
+    let db = TestDb::new("overflow-text-keys");
+    // Keys of every size, from a few bytes to several pages, and enough of
+    // them that the greatest keys of the leaves spill from internal cells
+    let mut keys: Vec<String> = (0..120)
+        .map(|i| format!("{:03}{}", i, text([20, 300, 1500, 9000][i % 4], i as u64)))
+        .collect();
+    let mut script = "create table t (name text primary key, n integer)\n".to_string();
+    for (i, key) in keys.iter().enumerate().rev() {
+        script.push_str(&format!("insert into t values ('{}', {})\n", key, i));
+    }
+    db.run(&script);
+
+    keys.sort();
+    let expected: Vec<String> = keys
+        .iter()
+        .map(|key| format!("('{}', {})", key, key[..3].parse::<usize>().unwrap()))
+        .collect();
+    assert_eq!(db.rows("select * from t"), expected);
+    for i in [0, 3, 61, 119] {
+        assert_eq!(
+            db.rows(&format!("select n from t where name = '{}'", keys[i])),
+            [format!("({})", i)]
+        );
+    }
+    assert_eq!(
+        db.rows(&format!(
+            "select n from t where name > '{}' and name <= '{}'",
+            keys[10], keys[13]
+        )),
+        ["(11)", "(12)", "(13)"]
+    );
+
+    db.run(&format!("delete from t where name = '{}'", keys[3]));
+    db.run(&format!(
+        "update t set n = 1000 where name = '{}'",
+        keys[119]
+    ));
+    let output = db.run(&format!("insert into t values ('{}', 0)", keys[0]));
+    assert_eq!(common::errors(&output).len(), 1, "{}", output);
+    assert_eq!(db.rows("select count(*) from t"), ["(119)"]);
+    assert_eq!(
+        db.rows(&format!("select n from t where name = '{}'", keys[119])),
+        ["(1000)"]
+    );
+    assert!(db
+        .rows(&format!("select n from t where name = '{}'", keys[3]))
+        .is_empty());
+}
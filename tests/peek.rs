@@ -0,0 +1,119 @@
+mod common;
+
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use common::{errors, TestDb};
+
+/// The values `.dbinfo --file` reports for `field` about another
+/// database, one per line it prints, as a shell that never opens it sees
+/// them.
+fn peek(db: &TestDb, field: &str) -> Vec<String> {
+    static OBSERVERS: AtomicUsize = AtomicUsize::new(0);
+    let number = OBSERVERS.fetch_add(1, Ordering::Relaxed);
+    let observer = TestDb::new(&format!("peek-observer-{}", number));
+    observer
+        .run(&format!(".dbinfo --file {}", db.path))
+        .lines()
+        .map(|line| line.trim_start_matches("db > "))
+        .filter_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+/// Every file of the database, with its contents.
+fn files(db: &TestDb) -> Vec<(String, Vec<u8>)> {
+    let dir = std::path::Path::new(&db.path).parent().unwrap();
+    let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (path.display().to_string(), fs::read(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn change_counter_counts_commits_that_write() {
+    let db = TestDb::new("peek-counter");
+    db.run("create table t (id integer primary key, n integer)");
+    let start: u64 = peek(&db, "changes")[0].parse().unwrap();
+
+    db.run("insert into t values (1, 1)\ninsert into t values (2, 2)");
+    assert_eq!(peek(&db, "changes"), [(start + 2).to_string()]);
+
+    // Reading, or a transaction rolled back, changes nothing
+    db.run("select * from t\nbegin\ninsert into t values (3, 3)\nrollback");
+    assert_eq!(peek(&db, "changes"), [(start + 2).to_string()]);
+
+    db.run("begin\ninsert into t values (3, 3)\nupdate t set n = 0\ncommit");
+    assert_eq!(peek(&db, "changes"), [(start + 3).to_string()]);
+}
+
+#[test]
+fn table_count_comes_from_the_catalog() {
+    let db = TestDb::new("peek-tables");
+    db.run(".exit");
+    assert_eq!(peek(&db, "table count"), ["0"]);
+
+    db.run(
+        "create table a (id integer primary key)\n\
+         create table b (id integer primary key)\n\
+         create index ia on a (id)\n\
+         create view v as select * from a\n\
+         create trigger tr after insert on a begin insert into b values (1); end",
+    );
+    assert_eq!(peek(&db, "table count"), ["2"]);
+
+    fs::write(format!("{}.schema", db.path), "create table (\n").unwrap();
+    let observer = TestDb::new("peek-tables-observer");
+    let output = observer.run(&format!(".dbinfo --file {}", db.path));
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+}
+
+#[test]
+fn peek_reports_a_log_to_replay_without_replaying_it() {
+    let db = TestDb::new("peek-wal");
+    db.run(
+        "create table t (id integer primary key, n integer)\n\
+         pragma journal_mode = wal",
+    );
+    assert_eq!(peek(&db, "recovery"), ["none"]);
+    let start: u64 = peek(&db, "changes")[0].parse().unwrap();
+
+    db.run_and_kill("insert into t values (1, 10)\ninsert into t values (2, 20)");
+    let before = files(&db);
+    let recovery = peek(&db, "recovery");
+    assert_eq!(recovery.len(), 1);
+    assert!(recovery[0].ends_with("test.db-wal"), "{:?}", recovery);
+    // The header logged by the last commit is the one reported
+    assert_eq!(peek(&db, "changes"), [(start + 2).to_string()]);
+    assert_eq!(files(&db), before);
+
+    assert_eq!(db.rows("select * from t"), ["(1, 10)", "(2, 20)"]);
+    assert_eq!(peek(&db, "recovery"), ["none"]);
+}
+
+#[test]
+fn peek_reports_a_leftover_journal_of_any_table() {
+    let db = TestDb::new("peek-journal");
+    db.run(
+        "create table a (id integer primary key)\n\
+         create table b (id integer primary key)\n\
+         insert into b values (1)",
+    );
+    fs::write(format!("{}.b-journal", db.path), b"cut short").unwrap();
+    let before = files(&db);
+    assert_eq!(
+        peek(&db, "recovery"),
+        [format!("journal {}.b-journal", db.path)]
+    );
+    assert_eq!(files(&db), before);
+
+    assert_eq!(db.rows("select * from b"), ["(1)"]);
+    assert!(!db.has_file(".b-journal"));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::TestDb;
+
+/// A table of `rows` rows whose `qty` takes `keys` values, indexed.
+fn setup(db: &TestDb, rows: usize, keys: usize) {
+    let mut script = "create table items (id integer primary key, qty integer)\n".to_string();
+    for i in 0..rows {
+        script.push_str(&format!("insert into items values ({}, {})\n", i, i % keys));
+    }
+    script.push_str("create index by_qty on items (qty)");
+    db.run(&script);
+}
+
+fn plan(db: &TestDb, query: &str) -> String {
+    db.run(&format!("explain query plan {}", query))
+}
+
+#[test]
+fn a_small_table_is_scanned_for_equalities_and_ranges_alike() {
+    let db = TestDb::new("planner-small");
+    setup(&db, 5, 3);
+    for query in [
+        "select * from items where qty = 2",
+        "select * from items where qty > 1",
+        "select * from items where qty between 1 and 2",
+    ] {
+        assert!(
+            plan(&db, query).contains("SCAN items (~5 rows)"),
+            "{}",
+            query
+        );
+    }
+    assert_eq!(db.rows("select id from items where qty = 2"), ["(2)"]);
+}
+
+#[test]
+fn a_large_table_is_searched_when_few_rows_match() {
+    let db = TestDb::new("planner-large");
+    setup(&db, 300, 100);
+    assert!(plan(&db, "select * from items where qty = 3")
+        .contains("SEARCH items USING INDEX by_qty (qty=?) (~3 rows)"));
+    assert!(plan(&db, "select * from items where qty between 10 and 20")
+        .contains("SEARCH items USING INDEX by_qty (qty>=? AND qty<=?) (~34 rows)"));
+    // A third of the table is too many to look up one by one
+    assert!(plan(&db, "select * from items where qty > 3").contains("SCAN items (~300 rows)"));
+    assert_eq!(
+        db.rows("select id from items where qty = 3"),
+        ["(3)", "(103)", "(203)"]
+    );
+}
+
+#[test]
+fn a_lower_and_an_upper_bound_are_searched_together() {
+    let db = TestDb::new("planner-and");
+    setup(&db, 300, 100);
+    assert!(plan(&db, "select * from items where qty > 10 and qty < 20")
+        .contains("SEARCH items USING INDEX by_qty (qty>? AND qty<?) (~34 rows)"));
+    assert!(
+        plan(&db, "select * from items where id < 110 and 100 <= id")
+            .contains("SEARCH items USING PRIMARY KEY (id>=? AND id<?) (~34 rows)")
+    );
+
+    assert_eq!(
+        db.rows("select id from items where qty > 97 and qty <= 98 and id > 100"),
+        ["(198)", "(298)"]
+    );
+    assert_eq!(
+        db.rows("select id from items where id < 105 and 100 <= id"),
+        ["(100)", "(101)", "(102)", "(103)", "(104)"]
+    );
+    assert!(db
+        .rows("select id from items where qty > 20 and qty < 10")
+        .is_empty());
+}
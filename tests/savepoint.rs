@@ -0,0 +1,56 @@
+mod common;
+
+use common::{errors, rows, TestDb};
+
+/// A statement that fails inside a transaction is undone alone: the
+/// statements before it stay, and so do the index and overflow pages they
+/// changed.
+#[test]
+fn failed_statement_is_undone_alone() {
+    let db = TestDb::new("savepoint-statement");
+    let big = "x".repeat(9000);
+    db.run(
+        "create table t (id integer primary key, n integer, body text)\n\
+         create index tn on t (n)\n\
+         insert into t values (1, 10, 'a')\n\
+         insert into t values (2, 20, 'b')",
+    );
+    let output = db.run(&format!(
+        "begin\n\
+         insert into t values (3, 30, '{big}')\n\
+         update t set n = 11 where id = 1\n\
+         insert into t values (4, 40, '{big}'), (5, 50, 'c'), (2, 0, 'dup')\n\
+         update t set id = 6 where id = 3\n\
+         commit"
+    ));
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert_eq!(
+        db.rows("select id, n from t"),
+        ["(1, 11)", "(2, 20)", "(6, 30)"]
+    );
+    assert!(db.rows("select id from t where n = 40").is_empty());
+    assert_eq!(db.rows("select id from t where n = 30"), ["(6)"]);
+    assert_eq!(
+        db.rows("select body from t where id = 6"),
+        [format!("('{}')", big)]
+    );
+}
+
+/// Undoing a failed statement leaves the earlier ones to ROLLBACK.
+#[test]
+fn rollback_after_a_failed_statement() {
+    let db = TestDb::new("savepoint-rollback");
+    db.run(
+        "create table t (id integer primary key, n integer unique)\n\
+         insert into t values (1, 10)",
+    );
+    let output = db.run(
+        "begin\n\
+         insert into t values (2, 20)\n\
+         update t set n = 20 where id = 1\n\
+         select * from t\n\
+         rollback",
+    );
+    assert_eq!(rows(&output), ["(1, 10)", "(2, 20)"]);
+    assert_eq!(db.rows("select * from t"), ["(1, 10)"]);
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::{errors, rows, TestDb};
+
+const SETUP: &str = "create table a (id integer primary key, x text)\n\
+                     insert into a values (1, 'one')\n\
+                     create table b (id integer primary key, y integer)\n\
+                     insert into b values (5, 50)\n\
+                     create index iy on b (y)";
+
+#[test]
+fn rollback_undoes_schema_changes() {
+    let db = TestDb::new("schema-rollback");
+    db.run(SETUP);
+    let output = db.run(
+        "begin\n\
+         create table c (id integer primary key)\n\
+         insert into c values (7)\n\
+         drop table a\n\
+         drop index iy\n\
+         create view v as select * from b\n\
+         create trigger t after insert on b begin insert into c values (8); end\n\
+         select * from c\n\
+         rollback",
+    );
+    assert_eq!(rows(&output), ["(7)"]);
+    assert!(errors(&output).is_empty(), "{}", output);
+
+    assert_eq!(db.rows("select * from a"), ["(1, 'one')"]);
+    assert_eq!(db.rows("select * from b where y = 50"), ["(5, 50)"]);
+    assert!(!db.has_file(".c"));
+    let output = db.run("select * from c\nselect * from v\ninsert into b values (6, 60)");
+    assert_eq!(errors(&output).len(), 2, "{}", output);
+    assert!(db.run("drop index iy").contains("Dropped index iy"));
+}
+
+#[test]
+fn commit_keeps_schema_changes() {
+    let db = TestDb::new("schema-commit");
+    db.run(SETUP);
+    db.run(
+        "begin\n\
+         create table c (id integer primary key)\n\
+         insert into c values (7)\n\
+         drop table a\n\
+         create trigger t after insert on b begin insert into c values (8); end\n\
+         commit",
+    );
+    // b took the dropped first table's place in the database file
+    assert!(!db.has_file(".b"));
+    assert_eq!(db.rows("select * from b"), ["(5, 50)"]);
+    assert_eq!(db.rows("select * from b where y = 50"), ["(5, 50)"]);
+    assert_eq!(
+        db.rows("insert into b values (6, 60)\nselect * from c"),
+        ["(7)", "(8)"]
+    );
+    assert_eq!(errors(&db.run("select * from a")).len(), 1);
+}
+
+#[test]
+fn schema_changes_are_lost_with_the_transaction() {
+    let db = TestDb::new("schema-crash");
+    db.run(SETUP);
+    db.run_and_kill("begin\ndrop table b\ncreate table c (id integer primary key)");
+    assert_eq!(db.rows("select * from b"), ["(5, 50)"]);
+    assert_eq!(errors(&db.run("select * from c")).len(), 1);
+}
+
+#[test]
+fn dropped_table_cannot_be_created_again_before_commit() {
+    let db = TestDb::new("schema-recreate");
+    db.run(SETUP);
+    let output = db.run("begin\ndrop table b\ncreate table b (id integer primary key)\nrollback");
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert_eq!(db.rows("select * from b"), ["(5, 50)"]);
+}
+
+#[test]
+fn primary_key_rejects_duplicate_and_missing_keys() {
+    let db = TestDb::new("schema-primary-key");
+    let output = db.run(
+        "create table t (id integer primary key, name text)\n\
+         insert into t values (1, 'a')\n\
+         insert into t values (1, 'b')\n\
+         insert into t (name) values ('c')\n\
+         insert into t values (NULL, 'd')\n\
+         create table u (a integer primary key, b integer primary key)",
+    );
+    assert_eq!(
+        errors(&output),
+        [
+            "Error executing statement: UNIQUE constraint failed: t.id (duplicate value 1)",
+            "Error executing statement: UNIQUE constraint failed: t.id",
+            "Error executing statement: UNIQUE constraint failed: t.id",
+            "Error: Table u has more than one primary key",
+        ]
+    );
+    // The key is still enforced once the table is opened again
+    let output = db.run("insert into t values (1, 'e')\nselect * from t");
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert_eq!(rows(&output), ["(1, 'a')"]);
+}
+
+#[test]
+fn if_not_exists_and_if_exists_skip_instead_of_failing() {
+    let db = TestDb::new("schema-if-exists");
+    db.run(SETUP);
+    let output = db.run(
+        "create table a (id integer primary key)\n\
+         create table if not exists a (other text)\n\
+         drop table missing\n\
+         drop table if exists missing",
+    );
+    assert_eq!(errors(&output).len(), 2, "{}", output);
+    assert!(
+        output.contains("Table a already exists, skipped"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("Table missing does not exist, skipped"),
+        "{}",
+        output
+    );
+    assert_eq!(db.rows("select * from a"), ["(1, 'one')"]);
+
+    let output = db.run("drop table if exists a\ncreate table if not exists a (name text)");
+    assert!(errors(&output).is_empty(), "{}", output);
+    assert!(output.contains("Dropped table a"), "{}", output);
+    assert!(output.contains("Created table a"), "{}", output);
+    assert!(db.rows("select * from a").is_empty());
+}
+
+#[test]
+fn quoted_and_unicode_names_work_in_every_statement() {
+    let db = TestDb::new("schema-names");
+    db.run(
+        "create table \"my table\" (id integer primary key, \"first name\" text)\n\
+         insert into \"my table\" values (1, 'a')\n\
+         create table användare (id integer primary key, 名前 text)\n\
+         insert into användare (id, 名前) values (1, 'å')\n\
+         insert into användare (id, 名前) values (2, 'b')\n\
+         create index i名 on användare (名前)\n\
+         update användare set 名前 = '名' where id = 1",
+    );
+    // reopened, so the names also came back out of the schema
+    assert_eq!(
+        db.rows("select \"first name\" from \"my table\""),
+        ["('a')"]
+    );
+    assert_eq!(
+        db.rows("select id, 名前 from användare where 名前 = '名'"),
+        ["(1, '名')"]
+    );
+    db.run("delete from användare where 名前 = '名'\ndrop index i名");
+    assert_eq!(db.rows("select * from användare"), ["(2, 'b')"]);
+    let schema = db.run(".schema");
+    assert!(
+        schema.contains("create table \"my table\" (id integer primary key, \"first name\" text)"),
+        "{}",
+        schema
+    );
+    assert!(
+        schema.contains("create table användare (id integer primary key, 名前 text)"),
+        "{}",
+        schema
+    );
+}
+
+#[test]
+fn select_star_lists_columns_in_declared_order() {
+    let db = TestDb::new("schema-column-order");
+    db.run(
+        "create table t (zeta text, id integer primary key, alpha integer, mid text)\n\
+         insert into t (alpha, id, mid, zeta) values (1, 2, 'm', 'z')\n\
+         create table u (id integer primary key, b text, a text)\n\
+         insert into u values (2, 'b', 'a')",
+    );
+    for _ in 0..3 {
+        assert_eq!(db.rows("select * from t"), ["('z', 2, 1, 'm')"]);
+    }
+    assert_eq!(
+        db.rows("select * from t join u on t.id = u.id"),
+        ["('z', 2, 1, 'm', 2, 'b', 'a')"]
+    );
+    assert!(db
+        .run(".mode json\nselect * from t")
+        .contains(r#"{"zeta":"z","id":2,"alpha":1,"mid":"m"}"#));
+}
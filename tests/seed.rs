@@ -0,0 +1,73 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{errors, rows, TestDb};
+
+/// Writes a script beside the database, returning its path.
+fn script(db: &TestDb, name: &str, contents: &str) -> String {
+    let path = Path::new(&db.path).with_file_name(name);
+    fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn a_script_seeds_rows_with_quoted_fields() {
+    let db = TestDb::new("seed-script");
+    let path = script(
+        &db,
+        "seed.sql",
+        "create table t (id integer primary key, name text, note text)\n\
+         .seed t id,name,note\n\
+         1,\"Smith, John\",\"said \"\"hi\"\"\"\n\
+         2,,\"\"\n\
+         \n\
+         \"3\",plain,x\n\
+         .\n\
+         select count(*) from t\n",
+    );
+    let output = db.run(&format!(".read {}\nselect * from t", path));
+    assert!(output.contains("Seeded 3 rows into t"), "{}", output);
+    // The script's statements run, and then the lines after `.read`
+    assert_eq!(
+        rows(&output),
+        [
+            "(3)",
+            "(1, 'Smith, John', 'said \"hi\"')",
+            "(2, NULL, '')",
+            "(3, 'plain', 'x')"
+        ]
+    );
+}
+
+#[test]
+fn a_bad_row_reports_its_line_and_seeds_nothing() {
+    let db = TestDb::new("seed-bad-row");
+    db.run("create table t (id integer primary key, name text)");
+    let data = "1,a\ntwo,b\n3,c\n.\n";
+    let path = script(&db, "bad.sql", &format!(".seed t id,name\n{}", data));
+    let output = db.run(&format!(".read {}", path));
+    assert!(
+        output.contains("line 2: cannot store text in integer column id"),
+        "{}",
+        output
+    );
+    assert!(db.rows("select * from t").is_empty());
+
+    let path = script(
+        &db,
+        "skip.sql",
+        &format!(".seed t id,name --skip-errors\n{}", data),
+    );
+    let output = db.run(&format!(".read {}", path));
+    assert!(output.contains("Skipping line 2"), "{}", output);
+    assert_eq!(db.rows("select id from t"), ["(1)", "(3)"]);
+}
+
+#[test]
+fn a_missing_script_is_an_error() {
+    let db = TestDb::new("seed-missing");
+    let output = db.run(".read no-such-file.sql\nselect 1");
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert_eq!(rows(&output), ["(1)"]);
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use common::{errors, rows, stat, TestDb};
+
+#[test]
+fn count_star_is_answered_without_a_scan() {
+    let db = TestDb::new("select-count");
+    db.run(
+        "create table t (id integer primary key, name text)\n\
+         insert into t values (1, 'a'), (2, 'b'), (3, NULL)\n\
+         delete from t where id = 2",
+    );
+    let output = db.run("select count(*) from t\n.stats");
+    assert_eq!(rows(&output), ["(2)"]);
+    assert_eq!(stat(&output, "table scans"), 0);
+    assert_eq!(stat(&output, "rows examined"), 0);
+
+    // A WHERE clause, or another aggregate, still reads the rows
+    let output = db.run("select count(*) from t where id > 1\n.stats");
+    assert_eq!(rows(&output), ["(1)"]);
+    assert_eq!(stat(&output, "table scans"), 1);
+    assert_eq!(db.rows("select count(*), count(name) from t"), ["(2, 1)"]);
+    assert_eq!(errors(&db.run("select count(*) from missing")).len(), 1);
+}
+
+#[test]
+fn where_keeps_only_the_matching_rows() {
+    let db = TestDb::new("select-where");
+    db.run(
+        "create table t (id integer primary key, name text, n integer)\n\
+         insert into t values (1, 'a', 10), (2, 'b', 20), (3, 'c', 30), (4, 'd', NULL)",
+    );
+    let output = db.run("select name from t where n >= 20");
+    assert_eq!(rows(&output), ["('b')", "('c')"]);
+    assert!(
+        output.contains("2 rows returned (2 of 4 rows scanned matched)"),
+        "{}",
+        output
+    );
+    assert_eq!(
+        db.rows("select id from t where name = 'd' or n < 20"),
+        ["(1)", "(4)"]
+    );
+    assert!(db.rows("select * from t where n > 100").is_empty());
+
+    let output = db.run("update t set n = 0 where id = 4\nselect * from t where n = 0");
+    assert!(
+        output.contains("(1 of 4 rows scanned matched)"),
+        "{}",
+        output
+    );
+    assert_eq!(rows(&output), ["(4, 'd', 0)"]);
+}
+
+/// A table `logs` of `count` rows, padded so that it spans many pages.
+fn logs(db: &TestDb, count: usize) {
+    let mut script = String::from("create table logs (id integer primary key, msg text)\n");
+    for id in 1..=count {
+        script.push_str(&format!(
+            "insert into logs values ({}, '{:0>100}')\n",
+            id, id
+        ));
+    }
+    db.run(&script);
+}
+
+#[test]
+fn order_by_rowid_desc_reads_only_the_last_pages() {
+    let db = TestDb::new("select-reverse");
+    logs(&db, 1000);
+    let output = db.run("select id from logs order by rowid desc limit 3\n.stats");
+    assert_eq!(rows(&output)[..3], ["(1000)", "(999)", "(998)"]);
+    assert_eq!(stat(&output, "rows examined"), 3);
+    let tail = stat(&output, "pages read");
+
+    let output = db.run("select id from logs\n.stats");
+    assert!(tail <= 2 && stat(&output, "pages read") > 10, "{}", output);
+    let all = db.rows("select id from logs order by rowid desc");
+    assert_eq!(all.len(), 1000);
+    assert_eq!([all[0].as_str(), all[999].as_str()], ["(1000)", "(1)"]);
+}
+
+#[test]
+fn limit_stops_the_scan_early() {
+    let db = TestDb::new("select-limit");
+    logs(&db, 1000);
+    let output = db.run("select id from logs limit 1\n.stats");
+    assert_eq!(rows(&output), ["(1)"]);
+    assert_eq!(stat(&output, "rows examined"), 1);
+    assert_eq!(stat(&output, "pages read"), 1);
+
+    // skipped rows are not examined either
+    let output = db.run("select id from logs limit 2 offset 5\n.stats");
+    assert_eq!(rows(&output), ["(6)", "(7)"]);
+    assert_eq!(stat(&output, "rows examined"), 2);
+    assert_eq!(
+        db.rows("select id from logs limit 5 offset 998"),
+        ["(999)", "(1000)"]
+    );
+    assert!(db.rows("select id from logs limit 0").is_empty());
+}
+
+#[test]
+fn an_equality_on_an_index_reads_one_page() {
+    let db = TestDb::new("select-point");
+    logs(&db, 1000);
+    db.run("create index by_msg on logs (msg)");
+    let key = format!("'{:0>100}'", 500);
+    for query in [
+        "select id from logs where id = 500".to_string(),
+        format!("select id from logs where msg = {}", key),
+    ] {
+        let output = db.run(&format!("{}\n.stats", query));
+        assert_eq!(rows(&output), ["(500)"]);
+        assert_eq!(stat(&output, "table scans"), 0, "{}", query);
+        assert_eq!(stat(&output, "pages read"), 1, "{}", query);
+    }
+
+    // any other shape of predicate falls back to the scan
+    let output = db.run(&format!(
+        "select id from logs where (msg || '') = {}\n.stats",
+        key
+    ));
+    assert_eq!(rows(&output), ["(500)"]);
+    assert_eq!(stat(&output, "table scans"), 1);
+    assert!(stat(&output, "pages read") > 10, "{}", output);
+    assert!(db.rows("select id from logs where id = 1001").is_empty());
+}
+
+#[test]
+fn like_handles_wildcards_escapes_and_long_values() {
+    let db = TestDb::new("select-like");
+    db.run(&format!(
+        "create table t (id integer primary key, s text)\n\
+         insert into t values (1, ''), (2, 'ab'), (3, '100%'), (4, '1000'), \
+         (5, 'snö_bo'), (6, 'snöxbo'), (7, '{}')",
+        "a".repeat(5000)
+    ));
+    let ids = |pattern: &str| db.rows(&format!("select id from t where s like {}", pattern));
+    assert_eq!(ids("''"), ["(1)"]);
+    assert_eq!(ids("'%%'").len(), 7);
+    assert_eq!(ids("'__'"), ["(2)"]);
+    assert_eq!(ids(r"'100\%' escape '\'"), ["(3)"]);
+    assert_eq!(ids("'sn_!_bo' escape '!'"), ["(5)"]);
+    // only ASCII letters ignore case
+    assert_eq!(ids("'SNö%'"), ["(5)", "(6)"]);
+    assert!(ids("'SNÖ%'").is_empty());
+    // would take exponential time with naive backtracking
+    assert!(ids("'%a%a%a%a%a%a%a%b'").is_empty());
+
+    let output = db.run("select id from t where s like 'x' escape 'ab'");
+    assert_eq!(
+        errors(&output),
+        ["Error executing statement: ESCAPE expression must be a single character"]
+    );
+}
+
+#[test]
+fn order_by_spills_to_temporary_files_past_the_memory_budget() {
+    let db = TestDb::new("select-external-sort");
+    let mut script = String::from("create table t (id integer primary key, s text)\n");
+    for id in 0..3000 {
+        script.push_str(&format!(
+            "insert into t values ({}, 'v{:04}')\n",
+            id,
+            id * 7919 % 3000
+        ));
+    }
+    db.run(&script);
+    let query = "pragma sort_memory = 4096\nselect s from t order by s desc";
+    let expected: Vec<String> = (0..3000).rev().map(|n| format!("('v{:04}')", n)).collect();
+
+    let tmp = db.dir().to_string();
+    assert_eq!(rows(&db.run_with_env(query, &[("TMPDIR", &tmp)])), expected);
+    let leftover = std::fs::read_dir(&tmp)
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with("bugdb-sort")
+        })
+        .count();
+    assert_eq!(leftover, 0);
+
+    // With nowhere to write its runs the sort fails, unless it fits
+    let missing = format!("{}/missing", tmp);
+    let output = db.run_with_env(query, &[("TMPDIR", &missing)]);
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    let output = db.run_with_env("select s from t order by s desc", &[("TMPDIR", &missing)]);
+    assert_eq!(rows(&output), expected);
+}
+
+#[test]
+fn rows_are_printed_as_they_are_produced() {
+    let db = TestDb::new("select-streaming");
+    logs(&db, 10);
+    // Rows before the failing one were already printed, so none were held
+    // back; ORDER BY has to see every row first, so it prints none
+    let output = db.run("select id, 100 / (id - 5) from logs");
+    assert_eq!(
+        rows(&output),
+        ["(1, -25)", "(2, -33)", "(3, -50)", "(4, -100)"]
+    );
+    assert_eq!(
+        errors(&output),
+        ["Error executing statement: division by zero"]
+    );
+    let output = db.run("select id, 100 / (id - 5) from logs order by id desc");
+    assert!(rows(&output).is_empty(), "{}", output);
+    assert_eq!(errors(&output).len(), 1);
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use std::{fs, time::SystemTime};
+
+use common::{rows, TestDb};
+
+const PAGE_SIZE: usize = 4096;
+
+const SETUP: &str = "create table t (id integer primary key, name text)\n\
+                     insert into t values (1, 'kept')";
+
+#[test]
+fn rollback_undoes_writes_in_memory_and_on_disk() {
+    let db = TestDb::new("transaction-rollback");
+    db.run(SETUP);
+    let size = db.file_size();
+    let mut script = "begin\n".to_string();
+    for id in 2..200 {
+        script.push_str(&format!("insert into t values ({}, 'gone {}')\n", id, id));
+    }
+    script.push_str("update t set name = 'changed' where id = 1\nrollback\nselect * from t");
+    assert_eq!(rows(&db.run(&script)), ["(1, 'kept')"]);
+
+    assert_eq!(db.rows("select * from t"), ["(1, 'kept')"]);
+    assert_eq!(db.file_size(), size);
+}
+
+#[test]
+fn a_crash_before_commit_loses_the_transaction() {
+    let db = TestDb::new("transaction-crash");
+    db.run(SETUP);
+    db.run_and_kill("begin\ninsert into t values (2, 'lost')\ndelete from t where id = 1");
+    assert_eq!(db.rows("select * from t"), ["(1, 'kept')"]);
+    assert!(!db.has_file("-journal"));
+}
+
+#[test]
+fn commits_in_the_log_are_replayed_after_a_crash() {
+    let db = TestDb::new("wal-replay");
+    db.run(&format!("{}\npragma journal_mode = wal", SETUP));
+    let size = db.file_size();
+    db.run_and_kill(
+        "insert into t values (2, 'logged')\n\
+         begin\n\
+         update t set name = 'also logged' where id = 1\n\
+         insert into t values (3, 'logged too')\n\
+         commit\n\
+         begin\n\
+         insert into t values (4, 'never committed')",
+    );
+    // The commits are only in the log until it is replayed
+    assert!(db.has_file("-wal"));
+    assert_eq!(db.file_size(), size);
+
+    let output = db.run("select * from t");
+    assert!(output.contains("Replaying"), "{}", output);
+    assert_eq!(
+        rows(&output),
+        ["(1, 'also logged')", "(2, 'logged')", "(3, 'logged too')"]
+    );
+    assert!(!db.has_file("-wal"));
+    // The mode is kept in the file's header, so the replay leaves it on
+    assert!(db.run("pragma journal_mode").contains("wal"));
+}
+
+#[test]
+fn bulk_mode_writes_each_page_once() {
+    let db = TestDb::new("bulk-writes");
+    db.run(SETUP);
+    let mut script = ".bulk on\n".to_string();
+    for id in 2..=500 {
+        script.push_str(&format!("insert into t values ({}, '{:0>100}')\n", id, id));
+    }
+    script.push_str(".bulk off");
+    let output = db.run(&script);
+    let written: usize = output
+        .split("Wrote ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next())
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_else(|| panic!("no page count in {}", output));
+
+    // Every row changed a page, but each page reached the file only once
+    let pages = db.file_size() as usize / 4096;
+    assert!(pages > 10, "{} pages", pages);
+    assert!(written <= pages, "{} writes for {} pages", written, pages);
+    assert_eq!(db.rows("select count(*) from t"), ["(500)"]);
+}
+
+#[test]
+fn a_transaction_reads_its_own_writes_and_commits_survive_a_crash() {
+    let db = TestDb::new("wal-read-your-writes");
+    db.run(&format!("{}\npragma journal_mode = wal", SETUP));
+    let output = db.run_and_kill(
+        "begin\n\
+         insert into t values (2, 'mine')\n\
+         update t set name = 'changed' where id = 1\n\
+         select * from t\n\
+         commit\n\
+         insert into t values (3, 'autocommit')\n\
+         insert into t values (4, 'autocommit')\n\
+         begin\n\
+         delete from t where id = 2\n\
+         select count(*) from t",
+    );
+    // Inside the transaction its changes show before COMMIT
+    assert_eq!(rows(&output), ["(1, 'changed')", "(2, 'mine')", "(3)"]);
+    // Every commit that answered is there after the crash; the open
+    // transaction is not
+    assert_eq!(
+        db.rows("select * from t"),
+        [
+            "(1, 'changed')",
+            "(2, 'mine')",
+            "(3, 'autocommit')",
+            "(4, 'autocommit')"
+        ]
+    );
+}
+
+/// Every file of the database, with its contents and when it was written.
+fn files(db: &TestDb) -> Vec<(String, Vec<u8>, SystemTime)> {
+    let mut files: Vec<_> = fs::read_dir(db.dir())
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let modified = fs::metadata(&path).unwrap().modified().unwrap();
+            (
+                path.display().to_string(),
+                fs::read(&path).unwrap(),
+                modified,
+            )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn a_session_that_only_reads_writes_nothing() {
+    let db = TestDb::new("transaction-read-only");
+    let mut script = format!("{}\ncreate index by_name on t (name)\n", SETUP);
+    for id in 2..300 {
+        script.push_str(&format!("insert into t values ({}, 'row {}')\n", id, id));
+    }
+    db.run(&script);
+    let before = files(&db);
+    let output = db.run(
+        "select count(*) from t where name <> ''\n\
+         select * from t where name = 'row 7'\n\
+         select id from t order by rowid desc limit 1\n\
+         .stats",
+    );
+    assert_eq!(rows(&output)[1..], ["(7, 'row 7')", "(299)"]);
+    assert_eq!(files(&db), before);
+}
+
+/// CRC-32 as journals are checksummed with, computed bit by bit.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// A journal saving `old`'s length and its pages that `new` changed.
+fn journal(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut journal = (old.len() as u64).to_le_bytes().to_vec();
+    for (page_num, page) in old.chunks(PAGE_SIZE).enumerate() {
+        if new.get(page_num * PAGE_SIZE..(page_num + 1) * PAGE_SIZE) != Some(page) {
+            journal.extend_from_slice(&(page_num as u32).to_le_bytes());
+            journal.extend_from_slice(page);
+        }
+    }
+    let checksum = crc32(&journal);
+    journal.extend_from_slice(&checksum.to_le_bytes());
+    journal
+}
+
+#[test]
+fn a_journal_left_by_a_torn_write_is_played_back() {
+    let db = TestDb::new("transaction-journal");
+    let mut script = format!("{}\n", SETUP);
+    for id in 2..100 {
+        script.push_str(&format!("insert into t values ({}, 'old {}')\n", id, id));
+    }
+    db.run(&script);
+    let old = fs::read(&db.path).unwrap();
+    let expected = db.rows("select * from t");
+
+    let mut script = "update t set name = 'new' where id % 2 = 0\n".to_string();
+    for id in 100..300 {
+        script.push_str(&format!("insert into t values ({}, 'new {}')\n", id, id));
+    }
+    db.run(&script);
+    let new = fs::read(&db.path).unwrap();
+    assert_ne!(old, new);
+
+    // The crash came part way through writing the pages: the file has its
+    // new length, but only its first changed page has the new contents
+    let first_changed = (0..)
+        .find(|&n| {
+            old[n * PAGE_SIZE..(n + 1) * PAGE_SIZE] != new[n * PAGE_SIZE..(n + 1) * PAGE_SIZE]
+        })
+        .unwrap();
+    let mut torn = new.clone();
+    let rest = (first_changed + 1) * PAGE_SIZE;
+    torn[rest..old.len()].copy_from_slice(&old[rest..]);
+    fs::write(&db.path, &torn).unwrap();
+    let journal_path = format!("{}-journal", db.path);
+    fs::write(&journal_path, journal(&old, &new)).unwrap();
+
+    let output = db.run("select * from t");
+    assert!(output.contains("Restoring"), "{}", output);
+    assert_eq!(rows(&output), expected);
+    assert!(!db.has_file("-journal"));
+    assert_eq!(fs::read(&db.path).unwrap(), old);
+
+    // A journal cut short was written before the file was touched, so it
+    // is only deleted
+    let mut cut = journal(&old, &new);
+    cut.truncate(cut.len() / 2);
+    fs::write(&journal_path, cut).unwrap();
+    let output = db.run("select * from t");
+    assert!(!output.contains("Restoring"), "{}", output);
+    assert_eq!(rows(&output), expected);
+    assert!(!db.has_file("-journal"));
+}
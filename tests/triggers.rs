@@ -0,0 +1,60 @@
+mod common;
+
+use common::{errors, rows, TestDb};
+
+/// A trigger that writes a fixed key fails from its second firing on.
+const SETUP: &str = "create table t (id integer primary key, n integer)\n\
+                     create table audit (id integer primary key, note text)\n\
+                     create trigger log after insert on t begin insert into audit values (1, 'x'); end";
+
+#[test]
+fn failed_trigger_undoes_the_insert() {
+    let db = TestDb::new("trigger-autocommit");
+    db.run(SETUP);
+    let output = db.run("insert into t values (1, 10)\ninsert into t values (2, 20)");
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert_eq!(db.rows("select * from t"), ["(1, 10)"]);
+    assert_eq!(db.rows("select * from audit"), ["(1, 'x')"]);
+}
+
+#[test]
+fn failed_trigger_inside_a_transaction_undoes_only_its_statement() {
+    let db = TestDb::new("trigger-transaction");
+    db.run(SETUP);
+    let output = db.run(
+        "begin\n\
+         insert into t values (1, 10)\n\
+         insert into t values (2, 20), (3, 30)\n\
+         select * from t\n\
+         commit",
+    );
+    assert_eq!(rows(&output), ["(1, 10)"]);
+    assert_eq!(db.rows("select * from t"), ["(1, 10)"]);
+    assert_eq!(db.rows("select * from audit"), ["(1, 'x')"]);
+}
+
+#[test]
+fn nested_trigger_failure_undoes_every_level() {
+    let db = TestDb::new("trigger-nested");
+    db.run(
+        "create table a (id integer primary key)\n\
+         create table b (id integer primary key)\n\
+         create table c (id integer primary key)\n\
+         create trigger ab after insert on a begin insert into b values (1); end\n\
+         create trigger bc after insert on b begin insert into c values (1); end\n\
+         insert into c values (1)",
+    );
+    let output = db.run("insert into a values (1)");
+    assert_eq!(errors(&output).len(), 1, "{}", output);
+    assert!(db.rows("select * from a").is_empty());
+    assert!(db.rows("select * from b").is_empty());
+}
+
+#[test]
+fn trigger_writes_are_committed_with_the_insert() {
+    let db = TestDb::new("trigger-crash");
+    db.run(SETUP);
+    db.run_and_kill("insert into t values (1, 10)");
+    assert_eq!(db.rows("select * from t"), ["(1, 10)"]);
+    assert_eq!(db.rows("select * from audit"), ["(1, 'x')"]);
+}
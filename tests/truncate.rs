@@ -0,0 +1,43 @@
+mod common;
+
+use common::{rows, TestDb};
+
+const SETUP: &str = "create table t (id integer primary key autoincrement, name text)\n\
+                     insert into t (name) values ('a')\n\
+                     insert into t (name) values ('b')\n\
+                     insert into t (name) values ('c')";
+
+#[test]
+fn truncate_is_kept_after_a_crash() {
+    let db = TestDb::new("truncate-crash");
+    db.run(SETUP);
+    let output = db.run_and_kill("truncate table t");
+    assert!(output.contains("3 rows deleted"), "{}", output);
+
+    assert!(db.rows("select * from t").is_empty());
+    // The sequence survives, so ids are not handed out again
+    assert_eq!(
+        db.rows("insert into t (name) values ('d')\nselect * from t"),
+        ["(4, 'd')"]
+    );
+}
+
+#[test]
+fn rollback_undoes_truncate() {
+    let db = TestDb::new("truncate-rollback");
+    db.run(SETUP);
+    let output = db.run("begin\ntruncate table t\nselect * from t\nrollback\nselect * from t");
+    assert_eq!(rows(&output), ["(1, 'a')", "(2, 'b')", "(3, 'c')"]);
+    assert_eq!(db.rows("select * from t").len(), 3);
+}
+
+#[test]
+fn truncate_in_a_transaction_waits_for_commit() {
+    let db = TestDb::new("truncate-uncommitted");
+    db.run(SETUP);
+    db.run_and_kill("begin\ntruncate table t");
+    assert_eq!(db.rows("select name from t"), ["('a')", "('b')", "('c')"]);
+
+    db.run("begin\ntruncate table t\ncommit");
+    assert!(db.rows("select * from t").is_empty());
+}